@@ -1,8 +1,9 @@
 use anyhow::Result;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use htx::api::{HtxServer, HtxServerConfig, MuxConfig, ProbeGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpStream;
 use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
@@ -14,21 +15,58 @@ async fn main() -> Result<()> {
         .with_target(false)
         .compact()
         .init();
-    // Config via env: BIND=0.0.0.0:4443, HTX_TLS_CERT, HTX_TLS_KEY
+    // Config via env: BIND=0.0.0.0:4443, HTX_TLS_CERT, HTX_TLS_KEY (re-read on SIGHUP),
+    // EDGE_DRAIN_GRACE_SECS=30 (how long in-flight streams get on SIGTERM/Ctrl-C),
+    // EDGE_HANDSHAKE_TIMEOUT_SECS=10, EDGE_MAX_HANDSHAKES=64, EDGE_ACCEPT_QUEUE=128,
+    // HTX_KNOCK_KEY (64 hex) + HTX_COVER_ORIGIN=host:port to send non-HTX clients to a cover site
     let bind = std::env::var("BIND").unwrap_or_else(|_| "0.0.0.0:4443".to_string());
-    info!(%bind, "edge-gateway starting");
-    loop {
-        // Block to accept a single outer TLS connection and establish inner mux
-        let conn = match htx::api::accept(&bind) {
-            Ok(c) => c,
-            Err(e) => {
-                error!(error=?e, "accept failed");
-                continue;
-            }
-        };
+    let grace = Duration::from_secs(
+        std::env::var("EDGE_DRAIN_GRACE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30),
+    );
+    let mux_cfg = MuxConfig::from_env();
+    info!(%bind, grace_secs = grace.as_secs(), ?mux_cfg, "edge-gateway starting");
+    let env_num = |k: &str| std::env::var(k).ok().and_then(|v| v.parse::<u64>().ok());
+    let mut server_cfg = HtxServerConfig::default().mux(mux_cfg);
+    if let Some(secs) = env_num("EDGE_HANDSHAKE_TIMEOUT_SECS") {
+        server_cfg = server_cfg.handshake_timeout(Duration::from_secs(secs.max(1)));
+    }
+    if let Some(n) = env_num("EDGE_MAX_HANDSHAKES") {
+        server_cfg = server_cfg.max_handshakes(n as usize);
+    }
+    if let Some(n) = env_num("EDGE_ACCEPT_QUEUE") {
+        server_cfg = server_cfg.accept_queue(n as usize);
+    }
+    match ProbeGuard::from_env().map_err(|e| anyhow::anyhow!("probe guard: {e:?}"))? {
+        Some(guard) => server_cfg = server_cfg.probe_guard(guard),
+        None => warn!("HTX_KNOCK_KEY/HTX_COVER_ORIGIN unset; probers will see the HTX edge"),
+    }
+    let server = HtxServer::bind_from_env(&bind, server_cfg)
+        .map_err(|e| anyhow::anyhow!("listen on {bind}: {e:?}"))?;
+    let server = Arc::new(server);
+    let live: Arc<Mutex<Vec<htx::api::Conn>>> = Arc::new(Mutex::new(Vec::new()));
+    let draining = Arc::new(AtomicBool::new(false));
+    tokio::spawn(drain_on_signal(live.clone(), draining.clone(), grace));
+    tokio::spawn(reload_on_sighup(server.clone()));
+    // Outer TLS handshakes run concurrently inside the server; this loop only
+    // picks up connections whose inner mux is ready
+    while let Some(conn) = server.accept() {
+        if draining.load(Ordering::SeqCst) {
+            // Restart in progress: tell the client to go elsewhere right away
+            info!("draining; refusing new connection with GOAWAY");
+            std::thread::spawn(move || conn.shutdown(Duration::ZERO));
+            continue;
+        }
         info!("outer TLS accepted; serving inner streams");
         // Observability: log encryption epoch to validate mux is initialized
         info!(epoch = conn.encryption_epoch(), "mux ready");
+        {
+            let mut live = live.lock().unwrap();
+            live.retain(|c| !c.is_closed());
+            live.push(conn.clone());
+        }
         // Handle inner streams until the peer disconnects
         let conn_cloned = conn.clone();
         std::thread::spawn(move || {
@@ -40,6 +78,17 @@ async fn main() -> Result<()> {
                             error!(error=?e, "inner stream error");
                         }
                     });
+                } else if conn_cloned.is_closed() {
+                    let keys = conn_cloned.key_metrics();
+                    info!(
+                        epoch = keys.epoch,
+                        tx_epoch = keys.tx_epoch,
+                        rx_epoch = keys.rx_epoch,
+                        auto_key_updates = keys.auto_updates,
+                        srtt_ms = conn_cloned.rtt().map(|r| r.smoothed.as_millis() as u64),
+                        "outer connection closed"
+                    );
+                    break;
                 } else {
                     // timeout; continue waiting
                     tracing::debug!("accept_stream timeout; no incoming stream yet");
@@ -47,7 +96,85 @@ async fn main() -> Result<()> {
             }
         });
     }
+    Ok(())
+}
+
+// Certificate rotation: SIGHUP re-reads HTX_TLS_CERT/HTX_TLS_KEY. New handshakes use
+// the new pair; live connections keep theirs. A bad pair is logged and ignored.
+async fn reload_on_sighup(server: Arc<HtxServer>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut hup) = signal(SignalKind::hangup()) else {
+            return;
+        };
+        while hup.recv().await.is_some() {
+            match server.reload() {
+                Ok(()) => info!(reloads = server.stats().reloads, "TLS certificate reloaded"),
+                Err(e) => error!(error=?e, "TLS certificate reload failed; keeping the old one"),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = server;
+    }
+}
+
+// Rolling restarts: on SIGTERM/Ctrl-C send GOAWAY on every live connection so clients
+// stop opening streams here, give in-flight streams `grace` to finish, then exit.
+async fn drain_on_signal(
+    live: Arc<Mutex<Vec<htx::api::Conn>>>,
+    draining: Arc<AtomicBool>,
+    grace: Duration,
+) {
+    shutdown_signal().await;
+    draining.store(true, Ordering::SeqCst);
+    let conns: Vec<htx::api::Conn> = live.lock().unwrap().drain(..).collect();
+    info!(
+        connections = conns.len(),
+        grace_secs = grace.as_secs(),
+        "shutdown requested; draining"
+    );
+    let drains: Vec<_> = conns
+        .into_iter()
+        .map(|c| tokio::task::spawn_blocking(move || c.shutdown(grace)))
+        .collect();
+    let mut forced = 0usize;
+    for d in drains {
+        if !d.await.unwrap_or(false) {
+            forced += 1;
+        }
+    }
+    if forced > 0 {
+        warn!(forced, "grace period expired; remaining streams were reset");
+    }
+    info!("drained; exiting");
+    std::process::exit(0);
 }
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
 fn read_connect_prelude_from_secure(ss: &htx::api::SecureStream) -> Result<String> {
     // Accumulate bytes until we see CRLFCRLF (end of headers)
     let mut buf: Vec<u8> = Vec::with_capacity(2048);
@@ -106,52 +233,16 @@ fn handle_inner_stream(ss: htx::api::SecureStream) -> Result<()> {
     })
 }
 
-async fn bridge_tcp_secure(stream: &mut TcpStream, ss: htx::api::SecureStream) -> Result<()> {
-    use std::sync::mpsc;
-    use std::time::Duration;
-
-    let (mut ri, mut wi) = stream.split();
-    let (to_tcp_tx, mut to_tcp_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(32);
-    let (to_htx_tx, to_htx_rx) = mpsc::channel::<Vec<u8>>();
-
-    let h = std::thread::spawn(move || {
-        let mut idle = 0u32;
-        loop {
-            let mut progressed = false;
-            while let Ok(buf) = to_htx_rx.try_recv() {
-                ss.write(&buf);
-                progressed = true;
-            }
-            if let Some(buf) = ss.try_read() {
-                if to_tcp_tx.blocking_send(buf).is_err() {
-                    break;
-                }
-                progressed = true;
-            }
-            if !progressed {
-                idle = idle.saturating_add(1);
-                std::thread::sleep(Duration::from_millis(2.min(idle as u64)));
-            } else {
-                idle = 0;
-            }
-        }
-    });
-
-    let mut tmp = vec![0u8; 8192];
-    loop {
-        tokio::select! {
-            maybe = to_tcp_rx.recv() => {
-                match maybe {
-                    Some(buf) => { if wi.write_all(&buf).await.is_err() { break; } }
-                    None => break,
-                }
-            }
-            res = ri.read(&mut tmp) => {
-                match res { Ok(0) => break, Ok(n) => { if to_htx_tx.send(tmp[..n].to_vec()).is_err() { break; } }, Err(_) => break }
-            }
+async fn bridge_tcp_secure(stream: &mut TcpStream, mut ss: htx::api::SecureStream) -> Result<()> {
+    // copy_bidirectional shuts down each writer when the opposite reader hits EOF,
+    // so a TCP FIN becomes an HTX FIN and vice versa; dropping `ss` releases the stream.
+    match tokio::io::copy_bidirectional(stream, &mut ss).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            // Propagate aborts as aborts: RST the HTX stream and make the socket close with RST
+            ss.reset(htx::api::ResetCode::CONNECT_ERROR);
+            let _ = stream.set_linger(Some(Duration::ZERO));
+            Err(e.into())
         }
     }
-    drop(to_htx_tx);
-    let _ = h.join();
-    Ok(())
 }
@@ -1,4 +1,5 @@
 use anyhow::{anyhow, bail, Context, Result};
+use htx::api::MuxConfig;
 use serde::{Deserialize, Serialize};
 use std::io::Write as _;
 use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
@@ -354,7 +355,7 @@ async fn main() -> Result<()> {
 
     // Optional HTX loopback HTTP echo mode
     let htx_client = if cfg.mode == Mode::HtxHttpEcho {
-        let (client, server) = htx::api::dial_inproc_secure();
+        let (client, server) = htx::api::dial_inproc_secure_with(mux_config());
         // Spawn a server thread that accepts streams and replies with a minimal HTTP 200
         std::thread::spawn(move || {
             loop {
@@ -729,6 +730,12 @@ impl Config {
     }
 }
 
+// Parsed once; every HTX connection from this process shares it.
+fn mux_config() -> MuxConfig {
+    static CFG: std::sync::OnceLock<MuxConfig> = std::sync::OnceLock::new();
+    CFG.get_or_init(MuxConfig::from_env).clone()
+}
+
 fn apply_mode(cfg: &mut Config, raw: &str) {
     let m = raw.to_ascii_lowercase();
     cfg.mode = match m.as_str() {
@@ -3187,7 +3194,7 @@ async fn handle_client(
                     ms.attempts = ms.attempts.saturating_add(1);
                 }
             }
-            let conn = match htx::api::dial(&origin) {
+            let conn = match htx::api::dial_with(&origin, mux_config()) {
                 Ok(c) => c,
                 Err(e) => {
                     if let Some(app) = &app_state {
@@ -3297,78 +3304,18 @@ enum TargetMeta {
     Domain,
 }
 
-async fn bridge_tcp_secure(stream: &mut TcpStream, ss: htx::api::SecureStream) -> Result<()> {
-    use std::sync::mpsc;
-    use std::time::Duration;
-
-    // Split TCP stream
-    let (mut ri, mut wi) = stream.split();
-
-    // Channels:
-    //  - to_tcp (tokio mpsc): from HTX thread -> async writer
-    //  - to_htx (std mpsc): from async reader -> HTX thread
-    let (to_tcp_tx, mut to_tcp_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(32);
-    let (to_htx_tx, to_htx_rx) = mpsc::channel::<Vec<u8>>();
-
-    // Spawn HTX thread owning the SecureStream
-    let h = std::thread::spawn(move || {
-        let mut idle = 0u32;
-        loop {
-            let mut progressed = false;
-
-            // Drain writes from TCP -> HTX
-            while let Ok(buf) = to_htx_rx.try_recv() {
-                ss.write(&buf);
-                progressed = true;
-            }
-
-            // Read from HTX -> TCP
-            if let Some(buf) = ss.try_read() {
-                // If receiver gone, exit
-                if to_tcp_tx.blocking_send(buf).is_err() {
-                    break;
-                }
-                progressed = true;
-            }
-
-            if !progressed {
-                idle = idle.saturating_add(1);
-                // Back off a bit when idle
-                std::thread::sleep(Duration::from_millis(2.min(idle as u64)));
-            } else {
-                idle = 0;
-            }
-        }
-    });
-
-    // Single async loop to forward in both directions without spawning 'static tasks
-    let mut tmp = vec![0u8; 8192];
-    loop {
-        tokio::select! {
-            maybe = to_tcp_rx.recv() => {
-                match maybe {
-                    Some(buf) => {
-                        if wi.write_all(&buf).await.is_err() { break; }
-                    }
-                    None => { break; }
-                }
-            }
-            res = ri.read(&mut tmp) => {
-                match res {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        if to_htx_tx.send(tmp[..n].to_vec()).is_err() { break; }
-                    }
-                    Err(_) => break,
-                }
-            }
+async fn bridge_tcp_secure(stream: &mut TcpStream, mut ss: htx::api::SecureStream) -> Result<()> {
+    // copy_bidirectional shuts down each writer when the opposite reader hits EOF,
+    // so a TCP FIN becomes an HTX FIN and vice versa; dropping `ss` releases the stream.
+    match tokio::io::copy_bidirectional(stream, &mut ss).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            // Propagate aborts as aborts: RST the HTX stream and make the socket close with RST
+            ss.reset(htx::api::ResetCode::CONNECT_ERROR);
+            let _ = stream.set_linger(Some(std::time::Duration::ZERO));
+            Err(e.into())
         }
     }
-
-    // Drop sender to signal HTX thread completion
-    drop(to_htx_tx);
-    let _ = h.join();
-    Ok(())
 }
 
 async fn read_u8(s: &mut TcpStream) -> Result<u8> {
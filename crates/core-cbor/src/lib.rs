@@ -1,6 +1,6 @@
 //! Deterministic CBOR helpers
 
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::BTreeMap;
 
 // Encode any Serialize deterministically. For maps, prefer BTreeMap to ensure key order.
@@ -11,6 +11,11 @@ pub fn to_det_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, serde_cbor::Error
     Ok(ser.into_inner())
 }
 
+// Decode bytes produced by `to_det_cbor` (the self-describe tag is skipped).
+pub fn from_det_cbor<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, serde_cbor::Error> {
+    serde_cbor::from_slice(bytes)
+}
+
 // Convenience: encode a BTreeMap (keys are ordered) to deterministic CBOR bytes.
 pub fn encode_map<K, V>(map: &BTreeMap<K, V>) -> Result<Vec<u8>, serde_cbor::Error>
 where
@@ -32,9 +37,9 @@ pub fn compute_template_id<T: Serialize>(params: &T) -> [u8; 32] {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde::Serialize;
+    use serde::{Deserialize, Serialize};
 
-    #[derive(Serialize)]
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
     struct Params {
         ver: u8,
         opts: BTreeMap<String, u64>,
@@ -81,4 +86,14 @@ mod tests {
         let id2 = compute_template_id(&p2);
         assert_ne!(id1, id2, "TemplateID must change when params change");
     }
+
+    #[test]
+    fn det_cbor_decodes_back() {
+        let mut opts = BTreeMap::new();
+        opts.insert("k".to_string(), 7);
+        let p = Params { ver: 2, opts };
+        let bytes = to_det_cbor(&p).unwrap();
+        assert_eq!(from_det_cbor::<Params>(&bytes).unwrap(), p);
+        assert!(from_det_cbor::<Params>(&bytes[..bytes.len() - 1]).is_err());
+    }
 }
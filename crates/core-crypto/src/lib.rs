@@ -1,4 +1,4 @@
-//! Core cryptographic primitives (thin wrappers around ring)
+//! Core cryptographic primitives (thin wrappers around ring, plus ML-KEM-768)
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
@@ -113,6 +113,8 @@ pub mod ed25519 {
     }
 }
 
+pub mod mlkem;
+
 pub mod x25519 {
     use crate::Error;
     use ring::{agreement, rand::SystemRandom};
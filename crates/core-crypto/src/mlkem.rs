@@ -0,0 +1,557 @@
+//! ML-KEM-768 (FIPS 203) key encapsulation.
+//!
+//! A straight implementation of the standard on top of SHA-3: key generation,
+//! encapsulation and decapsulation with implicit rejection. The deterministic
+//! `*_from_seed` / `encapsulate_with` entry points exist for known-answer tests;
+//! everything else draws from the system RNG.
+//!
+//! Arithmetic on secret coefficients never divides: reductions mod q are Barrett
+//! reductions and compression multiplies by a fixed-point 1/q, so no hardware
+//! `div` with secret operands appears whatever the target or opt-level
+//! (KyberSlash).
+
+use crate::Error;
+use ring::rand::{SecureRandom, SystemRandom};
+use sha3::digest::{ExtendableOutput, Update, XofReader};
+use sha3::{Digest, Sha3_256, Sha3_512, Shake128, Shake256};
+
+const N: usize = 256;
+const Q: u32 = 3329;
+const K: usize = 3;
+const ETA: usize = 2;
+const DU: u32 = 10;
+const DV: u32 = 4;
+
+const POLY_BYTES: usize = 384;
+pub const ENCAPS_KEY_LEN: usize = POLY_BYTES * K + 32;
+pub const DECAPS_KEY_LEN: usize = 2 * POLY_BYTES * K + 96;
+pub const CIPHERTEXT_LEN: usize = 32 * (DU as usize * K + DV as usize);
+pub const SHARED_SECRET_LEN: usize = 32;
+
+/// Public key a peer encapsulates to.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncapsulationKey(Vec<u8>);
+
+/// Private key; never printed.
+#[derive(Clone)]
+pub struct DecapsulationKey(Vec<u8>);
+
+impl std::fmt::Debug for EncapsulationKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EncapsulationKey({:02x?}..)", &self.0[..8])
+    }
+}
+
+impl std::fmt::Debug for DecapsulationKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DecapsulationKey([REDACTED])")
+    }
+}
+
+impl EncapsulationKey {
+    /// Parse and run the FIPS 203 modulus check (every coefficient below q).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != ENCAPS_KEY_LEN {
+            return Err(Error::Crypto);
+        }
+        for i in 0..K {
+            let chunk = &bytes[i * POLY_BYTES..(i + 1) * POLY_BYTES];
+            if byte_encode(&byte_decode(chunk, 12), 12) != chunk {
+                return Err(Error::Crypto);
+            }
+        }
+        Ok(Self(bytes.to_vec()))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl DecapsulationKey {
+    /// Parse and check that the embedded public-key hash matches.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != DECAPS_KEY_LEN {
+            return Err(Error::Crypto);
+        }
+        let ek = &bytes[POLY_BYTES * K..2 * POLY_BYTES * K + 32];
+        let h = &bytes[2 * POLY_BYTES * K + 32..2 * POLY_BYTES * K + 64];
+        if h != hash_h(ek) {
+            return Err(Error::Crypto);
+        }
+        Ok(Self(bytes.to_vec()))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn encapsulation_key(&self) -> EncapsulationKey {
+        EncapsulationKey(self.0[POLY_BYTES * K..2 * POLY_BYTES * K + 32].to_vec())
+    }
+}
+
+fn random<const L: usize>() -> [u8; L] {
+    let mut out = [0u8; L];
+    SystemRandom::new().fill(&mut out).expect("system rng");
+    out
+}
+
+pub fn generate_keypair() -> (DecapsulationKey, EncapsulationKey) {
+    keypair_from_seed(&random(), &random())
+}
+
+/// ML-KEM.KeyGen_internal(d, z).
+pub fn keypair_from_seed(d: &[u8; 32], z: &[u8; 32]) -> (DecapsulationKey, EncapsulationKey) {
+    let (ek, dk_pke) = pke_keygen(d);
+    let mut dk = Vec::with_capacity(DECAPS_KEY_LEN);
+    dk.extend_from_slice(&dk_pke);
+    dk.extend_from_slice(&ek);
+    dk.extend_from_slice(&hash_h(&ek));
+    dk.extend_from_slice(z);
+    (DecapsulationKey(dk), EncapsulationKey(ek))
+}
+
+/// Ciphertext and shared secret for `ek`.
+pub fn encapsulate(ek: &EncapsulationKey) -> (Vec<u8>, [u8; 32]) {
+    encapsulate_with(ek, &random())
+}
+
+/// ML-KEM.Encaps_internal(ek, m).
+pub fn encapsulate_with(ek: &EncapsulationKey, m: &[u8; 32]) -> (Vec<u8>, [u8; 32]) {
+    let (k, r) = hash_g(&[m, &hash_h(&ek.0)]);
+    (pke_encrypt(&ek.0, m, &r), k)
+}
+
+/// Shared secret for `ct`. A ciphertext that was not produced for this key
+/// yields a pseudorandom secret instead of an error (implicit rejection).
+pub fn decapsulate(dk: &DecapsulationKey, ct: &[u8]) -> Result<[u8; 32], Error> {
+    if ct.len() != CIPHERTEXT_LEN {
+        return Err(Error::Crypto);
+    }
+    let dk = &dk.0;
+    let dk_pke = &dk[..POLY_BYTES * K];
+    let ek = &dk[POLY_BYTES * K..2 * POLY_BYTES * K + 32];
+    let h = &dk[2 * POLY_BYTES * K + 32..2 * POLY_BYTES * K + 64];
+    let z = &dk[2 * POLY_BYTES * K + 64..];
+
+    let m = pke_decrypt(dk_pke, ct);
+    let (k, r) = hash_g(&[&m, h]);
+    let mut rejected = [0u8; 32];
+    Shake256::default()
+        .chain(z)
+        .chain(ct)
+        .finalize_xof()
+        .read(&mut rejected);
+    let ct2 = pke_encrypt(ek, &m, &r);
+    // Constant-time select between k and the rejection secret
+    let diff = ct.iter().zip(&ct2).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    let mask = ((diff as u16).wrapping_sub(1) >> 8) as u8; // 0xFF when equal
+    let mut out = [0u8; 32];
+    for i in 0..32 {
+        out[i] = (k[i] & mask) | (rejected[i] & !mask);
+    }
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------
+// K-PKE
+// ---------------------------------------------------------------------------
+
+type Poly = [u16; N];
+
+fn pke_keygen(d: &[u8; 32]) -> (Vec<u8>, Vec<u8>) {
+    let (rho, sigma) = hash_g(&[d, &[K as u8]]);
+    let a = matrix(&rho);
+    let mut s = [[0u16; N]; K];
+    let mut e = [[0u16; N]; K];
+    for (nonce, p) in s.iter_mut().chain(e.iter_mut()).enumerate() {
+        *p = ntt(cbd(&prf(&sigma, nonce as u8)));
+    }
+    let mut ek = Vec::with_capacity(ENCAPS_KEY_LEN);
+    let mut dk = Vec::with_capacity(POLY_BYTES * K);
+    for i in 0..K {
+        let t = add(&e[i], &dot(a[i].iter(), &s));
+        ek.extend_from_slice(&byte_encode(&t, 12));
+        dk.extend_from_slice(&byte_encode(&s[i], 12));
+    }
+    ek.extend_from_slice(&rho);
+    (ek, dk)
+}
+
+fn pke_encrypt(ek: &[u8], m: &[u8; 32], r: &[u8; 32]) -> Vec<u8> {
+    let t: Vec<Poly> = (0..K)
+        .map(|i| byte_decode(&ek[i * POLY_BYTES..(i + 1) * POLY_BYTES], 12))
+        .collect();
+    let rho: [u8; 32] = ek[POLY_BYTES * K..].try_into().expect("rho");
+    let a = matrix(&rho);
+    let y: Vec<Poly> = (0..K as u8).map(|n| ntt(cbd(&prf(r, n)))).collect();
+    let mut ct = Vec::with_capacity(CIPHERTEXT_LEN);
+    for i in 0..K {
+        // A transposed
+        let u = dot(a.iter().map(|row| &row[i]), &y);
+        let u = add(&ntt_inverse(u), &cbd(&prf(r, (K + i) as u8)));
+        ct.extend_from_slice(&byte_encode(&compress(&u, DU), DU));
+    }
+    let v = ntt_inverse(dot(t.iter(), &y));
+    let mu = decompress(&byte_decode(m, 1), 1);
+    let v = add(&add(&v, &cbd(&prf(r, 2 * K as u8))), &mu);
+    ct.extend_from_slice(&byte_encode(&compress(&v, DV), DV));
+    ct
+}
+
+fn pke_decrypt(dk: &[u8], ct: &[u8]) -> [u8; 32] {
+    let c1 = 32 * DU as usize;
+    let mut w = decompress(&byte_decode(&ct[c1 * K..], DV), DV);
+    let s: Vec<Poly> = dk.chunks(POLY_BYTES).map(|c| byte_decode(c, 12)).collect();
+    let u: Vec<Poly> = ct[..c1 * K]
+        .chunks(c1)
+        .map(|c| ntt(decompress(&byte_decode(c, DU), DU)))
+        .collect();
+    let su = ntt_inverse(dot(s.iter(), &u));
+    for (w, s) in w.iter_mut().zip(su) {
+        *w = csub(*w as u32 + Q - s as u32);
+    }
+    byte_encode(&compress(&w, 1), 1)
+        .try_into()
+        .expect("32 bytes")
+}
+
+// A_hat[i][j] = SampleNTT(rho || j || i)
+fn matrix(rho: &[u8; 32]) -> [[Poly; K]; K] {
+    let mut a = [[[0u16; N]; K]; K];
+    for (i, row) in a.iter_mut().enumerate() {
+        for (j, p) in row.iter_mut().enumerate() {
+            *p = sample_ntt(rho, j as u8, i as u8);
+        }
+    }
+    a
+}
+
+// ---------------------------------------------------------------------------
+// Hashes and sampling
+// ---------------------------------------------------------------------------
+
+fn hash_h(data: &[u8]) -> [u8; 32] {
+    Sha3_256::digest(data).into()
+}
+
+fn hash_g(parts: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+    let mut g = Sha3_512::new();
+    for p in parts {
+        Digest::update(&mut g, p);
+    }
+    let out = g.finalize();
+    (
+        out[..32].try_into().expect("32"),
+        out[32..].try_into().expect("32"),
+    )
+}
+
+fn prf(seed: &[u8; 32], nonce: u8) -> [u8; 64 * ETA] {
+    let mut out = [0u8; 64 * ETA];
+    Shake256::default()
+        .chain(seed)
+        .chain([nonce])
+        .finalize_xof()
+        .read(&mut out);
+    out
+}
+
+fn sample_ntt(rho: &[u8; 32], j: u8, i: u8) -> Poly {
+    let mut xof = Shake128::default().chain(rho).chain([j, i]).finalize_xof();
+    let mut a = [0u16; N];
+    let mut n = 0;
+    let mut c = [0u8; 3];
+    while n < N {
+        xof.read(&mut c);
+        let d1 = c[0] as u16 | ((c[1] as u16 & 0x0F) << 8);
+        let d2 = (c[1] as u16 >> 4) | ((c[2] as u16) << 4);
+        for d in [d1, d2] {
+            if (d as u32) < Q && n < N {
+                a[n] = d;
+                n += 1;
+            }
+        }
+    }
+    a
+}
+
+// SamplePolyCBD_2
+fn cbd(b: &[u8; 64 * ETA]) -> Poly {
+    let bit = |k: usize| ((b[k / 8] >> (k % 8)) & 1) as u32;
+    let mut f = [0u16; N];
+    for (i, c) in f.iter_mut().enumerate() {
+        let x: u32 = (0..ETA).map(|j| bit(2 * i * ETA + j)).sum();
+        let y: u32 = (0..ETA).map(|j| bit(2 * i * ETA + ETA + j)).sum();
+        *c = csub(x + Q - y);
+    }
+    f
+}
+
+// ---------------------------------------------------------------------------
+// Arithmetic in R_q and T_q
+// ---------------------------------------------------------------------------
+
+// 17^BitRev7(i) mod q
+const ZETAS: [u16; 128] = zetas();
+// 17^(2 BitRev7(i) + 1) mod q, for BaseCaseMultiply
+const GAMMAS: [u16; 128] = gammas();
+// floor(2^32 / q), for Barrett reduction
+const BARRETT_M: u64 = (1 << 32) / Q as u64;
+// ceil(2^48 / q): floor(x * COMPRESS_M / 2^48) = floor(x / q) for x < 2^24
+const COMPRESS_M: u64 = (1 << 48) / Q as u64 + 1;
+
+const fn bitrev7(i: u32) -> u32 {
+    let mut r = 0;
+    let mut k = 0;
+    while k < 7 {
+        r |= ((i >> k) & 1) << (6 - k);
+        k += 1;
+    }
+    r
+}
+
+const fn pow17(mut e: u32) -> u32 {
+    let (mut base, mut acc) = (17u32, 1u32);
+    while e > 0 {
+        if e & 1 == 1 {
+            acc = acc * base % Q;
+        }
+        base = base * base % Q;
+        e >>= 1;
+    }
+    acc
+}
+
+const fn zetas() -> [u16; 128] {
+    let mut z = [0u16; 128];
+    let mut i = 0;
+    while i < 128 {
+        z[i] = pow17(bitrev7(i as u32)) as u16;
+        i += 1;
+    }
+    z
+}
+
+const fn gammas() -> [u16; 128] {
+    let mut g = [0u16; 128];
+    let mut i = 0;
+    while i < 128 {
+        g[i] = pow17(2 * bitrev7(i as u32) + 1) as u16;
+        i += 1;
+    }
+    g
+}
+
+// a - q when a >= q, without a branch; a < 2q
+fn csub(a: u32) -> u16 {
+    let t = a.wrapping_sub(Q);
+    t.wrapping_add(Q & (t >> 31).wrapping_neg()) as u16
+}
+
+// a mod q for any u32: the Barrett quotient is floor(a / q) or one less
+fn reduce(a: u32) -> u16 {
+    let quot = ((a as u64 * BARRETT_M) >> 32) as u32;
+    csub(a - quot * Q)
+}
+
+fn add(a: &Poly, b: &Poly) -> Poly {
+    let mut out = [0u16; N];
+    for i in 0..N {
+        out[i] = csub(a[i] as u32 + b[i] as u32);
+    }
+    out
+}
+
+// Sum of a[i] * b[i] in the NTT domain
+fn dot<'a>(a: impl Iterator<Item = &'a Poly>, b: &[Poly]) -> Poly {
+    a.zip(b)
+        .fold([0u16; N], |acc, (x, y)| add(&acc, &multiply_ntts(x, y)))
+}
+
+fn ntt(mut f: Poly) -> Poly {
+    let mut i = 1;
+    let mut len = 128;
+    while len >= 2 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = ZETAS[i] as u32;
+            i += 1;
+            for j in start..start + len {
+                let t = reduce(zeta * f[j + len] as u32) as u32;
+                f[j + len] = csub(f[j] as u32 + Q - t);
+                f[j] = csub(f[j] as u32 + t);
+            }
+        }
+        len /= 2;
+    }
+    f
+}
+
+fn ntt_inverse(mut f: Poly) -> Poly {
+    let mut i = 127;
+    let mut len = 2;
+    while len <= 128 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = ZETAS[i] as u32;
+            i -= 1;
+            for j in start..start + len {
+                let t = f[j] as u32;
+                f[j] = csub(t + f[j + len] as u32);
+                f[j + len] = reduce(zeta * csub(f[j + len] as u32 + Q - t) as u32);
+            }
+        }
+        len *= 2;
+    }
+    // 128^-1 mod q
+    for c in f.iter_mut() {
+        *c = reduce(*c as u32 * 3303);
+    }
+    f
+}
+
+fn multiply_ntts(f: &Poly, g: &Poly) -> Poly {
+    let mut h = [0u16; N];
+    for i in 0..128 {
+        let gamma = GAMMAS[i] as u32;
+        let (a0, a1) = (f[2 * i] as u32, f[2 * i + 1] as u32);
+        let (b0, b1) = (g[2 * i] as u32, g[2 * i + 1] as u32);
+        h[2 * i] = reduce(a0 * b0 + reduce(a1 * b1) as u32 * gamma);
+        h[2 * i + 1] = reduce(a0 * b1 + a1 * b0);
+    }
+    h
+}
+
+// round(2^d x / q) mod 2^d, with the division done as a multiply and shift
+fn compress(f: &Poly, d: u32) -> Poly {
+    let mut out = [0u16; N];
+    for (o, &x) in out.iter_mut().zip(f) {
+        let n = ((x as u64) << d) + (Q / 2) as u64;
+        *o = (((n * COMPRESS_M) >> 48) & ((1 << d) - 1)) as u16;
+    }
+    out
+}
+
+fn decompress(f: &Poly, d: u32) -> Poly {
+    let mut out = [0u16; N];
+    for (o, &y) in out.iter_mut().zip(f) {
+        *o = ((y as u32 * Q + (1 << (d - 1))) >> d) as u16;
+    }
+    out
+}
+
+// Little-endian bit packing of d-bit coefficients
+fn byte_encode(f: &Poly, d: u32) -> Vec<u8> {
+    let mut out = vec![0u8; 32 * d as usize];
+    let mut bit = 0;
+    for &c in f {
+        for k in 0..d {
+            out[bit / 8] |= (((c >> k) & 1) as u8) << (bit % 8);
+            bit += 1;
+        }
+    }
+    out
+}
+
+// For d = 12 the result is reduced mod q, as FIPS 203 specifies
+fn byte_decode(b: &[u8], d: u32) -> Poly {
+    let mut f = [0u16; N];
+    let mut bit = 0;
+    for c in f.iter_mut() {
+        for k in 0..d {
+            *c |= (((b[bit / 8] >> (bit % 8)) & 1) as u16) << k;
+            bit += 1;
+        }
+        if d == 12 {
+            *c = csub(*c as u32);
+        }
+    }
+    f
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(b: &[u8]) -> String {
+        b.iter().map(|x| format!("{x:02x}")).collect()
+    }
+
+    // Values cross-checked against OpenSSL 3.5 (seed d || z = 00..3f, m = 5a..5a)
+    #[test]
+    fn known_answer() {
+        let d: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let z: [u8; 32] = std::array::from_fn(|i| 32 + i as u8);
+        let (dk, ek) = keypair_from_seed(&d, &z);
+        assert_eq!(
+            hex(&hash_h(ek.as_bytes())),
+            "a24e16d8f8f9383a95b77050f4d9fd2f5733eec1d63ef3c23ebf9918173669a7"
+        );
+        let (ct, k) = encapsulate_with(&ek, &[0x5a; 32]);
+        assert_eq!(
+            hex(&hash_h(&ct)),
+            "6e2c896887c5cd1929b419a6ca2db683f01e24f7f19c6fd79ed0cb705af8be45"
+        );
+        assert_eq!(
+            hex(&k),
+            "1345ffad8176a59e2c87a2f2118c7313a6e04ddd06ca857555f6156d4c9f4d95"
+        );
+        assert_eq!(decapsulate(&dk, &ct), Ok(k));
+        // Implicit rejection: a flipped bit gives J(z || c), not an error
+        let mut bad = ct.clone();
+        bad[0] ^= 1;
+        assert_eq!(
+            hex(&decapsulate(&dk, &bad).unwrap()),
+            "29cca2645acf134ef3b019d68739fd5c9ebf9311f3c5604bbd4dba2ef08ae512"
+        );
+    }
+
+    #[test]
+    fn reductions_match_division() {
+        // Every value the NTT and base-case multiply can produce, and the u32 edges
+        for a in (0..2 * (Q - 1) * (Q - 1) + Q).chain(u32::MAX - 2 * Q..=u32::MAX) {
+            assert_eq!(reduce(a) as u32, a % Q, "reduce({a})");
+        }
+        for a in 0..2 * Q {
+            assert_eq!(csub(a) as u32, a % Q, "csub({a})");
+        }
+        for d in 1..=12 {
+            for x in 0..Q {
+                let want = (((x << d) + Q / 2) / Q) & ((1 << d) - 1);
+                assert_eq!(compress(&[x as u16; N], d)[0] as u32, want, "d={d} x={x}");
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_keys_and_ciphertexts_are_rejected() {
+        let (dk, ek) = generate_keypair();
+        assert_eq!(EncapsulationKey::from_bytes(ek.as_bytes()), Ok(ek.clone()));
+        assert!(DecapsulationKey::from_bytes(dk.as_bytes()).is_ok());
+        // Coefficient 0xFFF is not below q
+        let mut big = ek.as_bytes().to_vec();
+        big[0] = 0xFF;
+        big[1] |= 0x0F;
+        assert!(EncapsulationKey::from_bytes(&big).is_err());
+        assert!(EncapsulationKey::from_bytes(&ek.as_bytes()[1..]).is_err());
+        let mut tampered = dk.as_bytes().to_vec();
+        tampered[POLY_BYTES * K] ^= 1;
+        assert!(DecapsulationKey::from_bytes(&tampered).is_err());
+        let (ct, _) = encapsulate(&ek);
+        assert!(decapsulate(&dk, &ct[1..]).is_err());
+    }
+
+    #[test]
+    fn sizes_and_roundtrip() {
+        assert_eq!(ENCAPS_KEY_LEN, 1184);
+        assert_eq!(DECAPS_KEY_LEN, 2400);
+        assert_eq!(CIPHERTEXT_LEN, 1088);
+        for _ in 0..8 {
+            let (dk, ek) = generate_keypair();
+            assert_eq!(dk.encapsulation_key(), ek);
+            let (ct, k) = encapsulate(&ek);
+            assert_eq!(ct.len(), CIPHERTEXT_LEN);
+            assert_eq!(decapsulate(&dk, &ct), Ok(k));
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Learn a traffic profile from a text trace and write it as CBOR.
+//!
+//! cargo run -p core-framing --example learn_profile -- browse.txt browse.cbor [name]
+
+use core_framing::profile::{parse_trace, TrafficProfile};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let (Some(trace_path), Some(out_path)) = (args.next(), args.next()) else {
+        eprintln!("usage: learn_profile <trace.txt> <out.cbor> [name]");
+        std::process::exit(2);
+    };
+    let name = args.next().unwrap_or_else(|| "learned".into());
+    let text = std::fs::read_to_string(&trace_path).expect("read trace");
+    let trace = parse_trace(&text).expect("parse trace");
+    let profile = TrafficProfile::learn(&name, &trace).expect("learn profile");
+    std::fs::write(&out_path, profile.to_cbor()).expect("write profile");
+    println!(
+        "{name}: {} records, sizes {}..={} B, gaps {}..={} us -> {out_path}",
+        trace.len(),
+        profile.sizes.edges[0],
+        profile.sizes.edges[profile.sizes.edges.len() - 1],
+        profile.gaps_us.edges[0],
+        profile.gaps_us.edges[profile.gaps_us.edges.len() - 1],
+    );
+}
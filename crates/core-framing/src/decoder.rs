@@ -0,0 +1,328 @@
+//! Incremental frame decoding from arbitrary byte chunks.
+//!
+//! [`FrameDecoder`] buffers whatever the carrier hands it and yields frames
+//! once the u24 length says they are complete. The length is checked against
+//! a configurable maximum before any room is reserved, so a peer cannot make
+//! us allocate by announcing a huge frame.
+
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::{Error, Frame, KeyCtx, RecordLayout};
+
+/// Largest length the u24 header can express.
+pub const MAX_WIRE_LEN: usize = 0xFF_FFFF;
+
+/// Supplies the AEAD nonce for each successive frame.
+pub trait NonceSource {
+    fn next_nonce(&mut self) -> [u8; 12];
+}
+
+impl<F: FnMut() -> [u8; 12]> NonceSource for F {
+    fn next_nonce(&mut self) -> [u8; 12] {
+        self()
+    }
+}
+
+/// Per-frame counter nonce: four zero bytes then the counter, little-endian,
+/// the layout the HTX mux uses.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CounterNonce {
+    next: u64,
+}
+
+impl CounterNonce {
+    pub fn new(start: u64) -> Self {
+        Self { next: start }
+    }
+}
+
+impl NonceSource for CounterNonce {
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut n = [0u8; 12];
+        n[4..12].copy_from_slice(&self.next.to_le_bytes());
+        self.next += 1;
+        n
+    }
+}
+
+enum Mode {
+    Plain,
+    Aead {
+        key: KeyCtx,
+        layout: RecordLayout,
+        nonces: Box<dyn NonceSource + Send>,
+    },
+}
+
+/// Reassembles and decodes frames from a byte stream.
+///
+/// Feed it with [`FrameDecoder::extend`] and drain it with
+/// [`FrameDecoder::next_frame`], or hand it to `tokio_util::codec::FramedRead`.
+pub struct FrameDecoder {
+    buf: BytesMut,
+    max_len: usize,
+    mode: Mode,
+}
+
+impl FrameDecoder {
+    /// Decoder for unencrypted frames ([`Frame::decode_plain`]).
+    pub fn plain() -> Self {
+        Self {
+            buf: BytesMut::new(),
+            max_len: MAX_WIRE_LEN,
+            mode: Mode::Plain,
+        }
+    }
+
+    /// Decoder for AEAD frames ([`crate::decode`]); each complete frame takes the next nonce.
+    pub fn aead(key: KeyCtx, nonces: impl NonceSource + Send + 'static) -> Self {
+        Self {
+            buf: BytesMut::new(),
+            max_len: MAX_WIRE_LEN,
+            mode: Mode::Aead {
+                key,
+                layout: RecordLayout::Clear,
+                nonces: Box::new(nonces),
+            },
+        }
+    }
+
+    /// Open sealed frames with `layout` instead of the clear-header one. No effect on
+    /// plain decoders.
+    pub fn record_layout(mut self, layout: RecordLayout) -> Self {
+        if let Mode::Aead { layout: l, .. } = &mut self.mode {
+            *l = layout;
+        }
+        self
+    }
+
+    /// Reject frames whose length field (type + payload, plus tag when sealed)
+    /// exceeds `len`. Capped at what the u24 header can carry.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_len = len.min(MAX_WIRE_LEN);
+        self
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Bytes held that do not yet form a complete frame.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Next complete frame from the internal buffer, decoded.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, Error> {
+        let mut buf = std::mem::take(&mut self.buf);
+        let res = self.decode_from(&mut buf);
+        self.buf = buf;
+        res
+    }
+
+    /// Next complete wire frame from the internal buffer, left encoded
+    /// (for callers that manage keys themselves).
+    pub fn next_raw(&mut self) -> Result<Option<Bytes>, Error> {
+        Ok(split_frame(&mut self.buf, self.max_len)?.map(BytesMut::freeze))
+    }
+
+    // Frames of an unknown ignorable type are dropped here; a sealed one still
+    // used up its nonce.
+    fn decode_from(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, Error> {
+        loop {
+            let Some(wire) = split_frame(src, self.max_len)? else {
+                return Ok(None);
+            };
+            let res = match &mut self.mode {
+                Mode::Plain => Frame::decode_plain(&wire),
+                Mode::Aead {
+                    key,
+                    layout,
+                    nonces,
+                } => layout.open(&wire, *key, nonces.next_nonce()),
+            };
+            match res {
+                Err(Error::Ignored(_)) => continue,
+                other => return other.map(Some),
+            }
+        }
+    }
+}
+
+// Split one whole wire frame off the front of `src`, or reserve room for it.
+fn split_frame(src: &mut BytesMut, max_len: usize) -> Result<Option<BytesMut>, Error> {
+    if src.len() < 3 {
+        return Ok(None);
+    }
+    let len = ((src[0] as usize) << 16) | ((src[1] as usize) << 8) | (src[2] as usize);
+    if len == 0 {
+        // Every frame carries at least its type byte
+        return Err(Error::InvalidLen);
+    }
+    if len > max_len {
+        return Err(Error::TooLarge(len));
+    }
+    let total = 3 + len;
+    if src.remaining() < total {
+        src.reserve(total - src.len());
+        return Ok(None);
+    }
+    Ok(Some(src.split_to(total)))
+}
+
+impl tokio_util::codec::Decoder for FrameDecoder {
+    type Item = Frame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, Error> {
+        self.decode_from(src)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode, encode_type, FrameType};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn frames() -> Vec<Frame> {
+        (0..20u8)
+            .map(|i| Frame {
+                ty: if i % 2 == 0 {
+                    FrameType::Stream
+                } else {
+                    FrameType::Ping
+                },
+                payload: vec![i; i as usize * 97],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plain_frames_survive_arbitrary_chunking() {
+        let mut wire = Vec::new();
+        for f in frames() {
+            wire.extend_from_slice(&f.encode_plain());
+        }
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut dec = FrameDecoder::plain();
+        let mut out = Vec::new();
+        let mut rest = &wire[..];
+        while !rest.is_empty() {
+            let n = rng.gen_range(1..=rest.len().min(300));
+            dec.extend(&rest[..n]);
+            rest = &rest[n..];
+            while let Some(f) = dec.next_frame().unwrap() {
+                out.push(f);
+            }
+        }
+        assert_eq!(out, frames());
+        assert_eq!(dec.buffered(), 0);
+    }
+
+    #[test]
+    fn aead_frames_take_successive_nonces() {
+        let key = KeyCtx { key: [9u8; 32] };
+        let mut nonces = CounterNonce::default();
+        let mut wire = Vec::new();
+        for f in frames() {
+            wire.extend_from_slice(&encode(&f, key, nonces.next_nonce()));
+        }
+        let mut dec = FrameDecoder::aead(key, CounterNonce::default());
+        let mut out = Vec::new();
+        for chunk in wire.chunks(7) {
+            dec.extend(chunk);
+            while let Some(f) = dec.next_frame().unwrap() {
+                out.push(f);
+            }
+        }
+        assert_eq!(out, frames());
+
+        // A decoder that is one nonce off rejects the first frame
+        let mut off = FrameDecoder::aead(key, CounterNonce::new(1));
+        off.extend(&wire);
+        assert_eq!(off.next_frame(), Err(Error::Crypto));
+    }
+
+    #[test]
+    fn oversized_length_is_rejected_before_allocating() {
+        let mut dec = FrameDecoder::plain().max_frame_len(1024);
+        dec.extend(&[0xFF, 0xFF, 0xFF, 0x10]);
+        assert_eq!(dec.next_frame(), Err(Error::TooLarge(MAX_WIRE_LEN)));
+        assert!(dec.buf.capacity() < 1024);
+
+        let mut dec = FrameDecoder::plain().max_frame_len(1024);
+        dec.extend(&[0x00, 0x04, 0x00, 0x10]);
+        assert_eq!(dec.next_frame(), Ok(None));
+        assert!(dec.buf.capacity() >= 3 + 1024);
+    }
+
+    #[test]
+    fn ignorable_types_are_skipped_and_keep_the_nonce_sequence() {
+        let key = KeyCtx { key: [4u8; 32] };
+        let mut nonces = CounterNonce::default();
+        let mut wire = Vec::new();
+        wire.extend_from_slice(&encode_type(
+            0x5A,
+            b"from the future",
+            key,
+            nonces.next_nonce(),
+        ));
+        for f in frames() {
+            wire.extend_from_slice(&encode(&f, key, nonces.next_nonce()));
+        }
+        let mut dec = FrameDecoder::aead(key, CounterNonce::default());
+        dec.extend(&wire);
+        let mut out = Vec::new();
+        while let Some(f) = dec.next_frame().unwrap() {
+            out.push(f);
+        }
+        assert_eq!(out, frames());
+
+        // Outside the ignorable range an unknown type is still an error
+        let mut dec = FrameDecoder::plain();
+        dec.extend(&[0x00, 0x00, 0x01, 0x20]);
+        assert_eq!(dec.next_frame(), Err(Error::UnknownType(0x20)));
+    }
+
+    #[test]
+    fn hidden_records_decode_incrementally() {
+        let key = KeyCtx { key: [3u8; 32] };
+        let layout = RecordLayout::hidden(256);
+        let mut nonces = CounterNonce::default();
+        let mut wire = Vec::new();
+        for f in frames() {
+            wire.extend_from_slice(&layout.seal(&f, key, nonces.next_nonce()));
+        }
+        let mut dec = FrameDecoder::aead(key, CounterNonce::default()).record_layout(layout);
+        let mut out = Vec::new();
+        for chunk in wire.chunks(100) {
+            dec.extend(chunk);
+            while let Some(f) = dec.next_frame().unwrap() {
+                out.push(f);
+            }
+        }
+        assert_eq!(out, frames());
+    }
+
+    #[tokio::test]
+    async fn works_as_a_tokio_codec() {
+        use futures::StreamExt;
+        use tokio::io::AsyncWriteExt;
+        use tokio_util::codec::FramedRead;
+
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let expected = frames();
+        let sent = expected.clone();
+        tokio::spawn(async move {
+            for f in sent {
+                tx.write_all(&f.encode_plain()).await.unwrap();
+            }
+        });
+        let got: Vec<Frame> = FramedRead::new(rx, FrameDecoder::plain().max_frame_len(4096))
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(got, expected);
+    }
+}
@@ -3,9 +3,17 @@
 use bytes::{BufMut, Bytes, BytesMut};
 use core_crypto as crypto;
 
+pub mod decoder;
+pub mod profile;
+pub mod record;
+pub use decoder::{CounterNonce, FrameDecoder, NonceSource};
+pub use record::RecordLayout;
+
 #[cfg(feature = "stealth-mode")]
 pub mod sizing {
+    use crate::profile::TrafficProfile;
     use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::sync::Arc;
 
     #[derive(Debug, Clone, Copy)]
     pub enum Profile {
@@ -14,19 +22,45 @@ pub mod sizing {
         Bursty,
     }
 
+    enum Shape {
+        Builtin(Profile),
+        Learned(Arc<TrafficProfile>),
+    }
+
     pub struct Sizer {
         rng: StdRng,
-        prof: Profile,
+        shape: Shape,
+    }
+
+    // Fixed seeds reproduce a run; without one every sizer gets fresh OS randomness.
+    pub(crate) fn seeded_rng(seed: Option<u64>) -> StdRng {
+        seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)
     }
 
     impl Sizer {
         pub fn new(prof: Profile, seed: Option<u64>) -> Self {
-            let rng = StdRng::seed_from_u64(seed.unwrap_or(0x5eed_5eed));
-            Self { rng, prof }
+            Self {
+                rng: seeded_rng(seed),
+                shape: Shape::Builtin(prof),
+            }
         }
+
+        /// Record sizes drawn from a learned profile instead of a built-in range.
+        pub fn from_profile(profile: Arc<TrafficProfile>, seed: Option<u64>) -> Self {
+            Self {
+                rng: seeded_rng(seed),
+                shape: Shape::Learned(profile),
+            }
+        }
+
         pub fn choose_len(&mut self, payload_len: usize) -> usize {
             let cap = payload_len.max(1);
-            match self.prof {
+            let prof = match &self.shape {
+                Shape::Builtin(p) => *p,
+                // Learned sizes are whole records; a larger payload spans several
+                Shape::Learned(p) => return p.sample_size(&mut self.rng).clamp(1, 64 * 1024),
+            };
+            match prof {
                 Profile::Small => {
                     let extra = self.rng.gen_range(0..=1024); // 0-1KiB
                     (cap + extra).min(64 * 1024)
@@ -53,7 +87,10 @@ pub mod sizing {
 
 #[cfg(feature = "stealth-mode")]
 pub mod jitter {
-    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use crate::profile::TrafficProfile;
+    use crate::sizing::seeded_rng;
+    use rand::{rngs::StdRng, Rng};
+    use std::sync::Arc;
     use std::time::Duration;
 
     #[derive(Debug, Clone, Copy)]
@@ -62,18 +99,38 @@ pub mod jitter {
         Webby,
     }
 
+    enum Shape {
+        Builtin(Profile),
+        Learned(Arc<TrafficProfile>),
+    }
+
     pub struct Jitter {
         rng: StdRng,
-        prof: Profile,
+        shape: Shape,
     }
 
     impl Jitter {
         pub fn new(prof: Profile, seed: Option<u64>) -> Self {
-            let rng = StdRng::seed_from_u64(seed.unwrap_or(0x5151_7171));
-            Self { rng, prof }
+            Self {
+                rng: seeded_rng(seed),
+                shape: Shape::Builtin(prof),
+            }
+        }
+
+        /// Delays drawn from the intra-burst gaps of a learned profile.
+        pub fn from_profile(profile: Arc<TrafficProfile>, seed: Option<u64>) -> Self {
+            Self {
+                rng: seeded_rng(seed),
+                shape: Shape::Learned(profile),
+            }
         }
+
         pub fn delay(&mut self) -> Duration {
-            match self.prof {
+            let prof = match &self.shape {
+                Shape::Builtin(p) => *p,
+                Shape::Learned(p) => return p.sample_gap(&mut self.rng),
+            };
+            match prof {
                 Profile::Small => Duration::from_millis(self.rng.gen_range(1..=5)),
                 Profile::Webby => Duration::from_millis(self.rng.gen_range(2..=15)),
             }
@@ -88,18 +145,72 @@ pub enum Error {
     InvalidLen,
     TooShort,
     UnknownType(u8),
+    /// A type from [`IGNORABLE_TYPES`] this build does not know. The frame was
+    /// well formed (and authenticated, when sealed); callers skip it.
+    Ignored(u8),
     Crypto,
+    /// Length field above the decoder's limit; nothing was buffered for it.
+    TooLarge(usize),
+    /// The carrier failed underneath a streaming decoder.
+    Io(std::io::ErrorKind),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.kind())
+    }
 }
 
+/// Wire type bytes a receiver may skip when it does not know them.
+///
+/// Core types live in 0x10..=0x3F and an unknown one there is a protocol error.
+/// Optional extensions take a type from this range, so older peers drop them
+/// instead of tearing the connection down.
+pub const IGNORABLE_TYPES: std::ops::RangeInclusive<u8> = 0x40..=0x7F;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FrameType {
     Stream = 0x10,
     WindowUpdate = 0x11,
     Ping = 0x12,
     KeyUpdate = 0x13,
+    RstStream = 0x14,
+    GoAway = 0x15,
+    Priority = 0x16,
+    Datagram = 0x17,
+    /// Filler with no meaning; receivers discard the payload.
+    Padding = 0x18,
+    /// Role and initial flow-control windows, sent once as each end's first frame.
+    Settings = 0x19,
     Close = 0x1F,
 }
 
+impl FrameType {
+    pub const ALL: [FrameType; 11] = [
+        FrameType::Stream,
+        FrameType::WindowUpdate,
+        FrameType::Ping,
+        FrameType::KeyUpdate,
+        FrameType::RstStream,
+        FrameType::GoAway,
+        FrameType::Priority,
+        FrameType::Datagram,
+        FrameType::Padding,
+        FrameType::Settings,
+        FrameType::Close,
+    ];
+
+    /// Look up a wire type byte: `Ignored` for unknown types in
+    /// [`IGNORABLE_TYPES`], `UnknownType` for anything else unrecognised.
+    pub fn from_u8(ty: u8) -> Result<FrameType, Error> {
+        match Self::ALL.iter().find(|t| **t as u8 == ty) {
+            Some(t) => Ok(*t),
+            None if IGNORABLE_TYPES.contains(&ty) => Err(Error::Ignored(ty)),
+            None => Err(Error::UnknownType(ty)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Frame {
     pub ty: FrameType,
@@ -127,14 +238,7 @@ impl Frame {
             return Err(Error::InvalidLen);
         }
         let ty = src.first().copied().ok_or(Error::TooShort)?;
-        let ty = match ty {
-            0x10 => FrameType::Stream,
-            0x11 => FrameType::WindowUpdate,
-            0x12 => FrameType::Ping,
-            0x13 => FrameType::KeyUpdate,
-            0x1F => FrameType::Close,
-            x => return Err(Error::UnknownType(x)),
-        };
+        let ty = FrameType::from_u8(ty)?;
         let payload = src[1..len].to_vec();
         Ok(Frame { ty, payload })
     }
@@ -166,15 +270,20 @@ pub struct KeyCtx {
 }
 
 pub fn encode(frame: &Frame, key: KeyCtx, nonce: [u8; 12]) -> Bytes {
+    encode_type(frame.ty as u8, &frame.payload, key, nonce)
+}
+
+/// [`encode`] for a raw type byte, e.g. an extension from [`IGNORABLE_TYPES`]
+/// that has no [`FrameType`] variant in this build.
+pub fn encode_type(typ: u8, payload: &[u8], key: KeyCtx, nonce: [u8; 12]) -> Bytes {
     // AAD is the wire header [Len(u24) | Type]. Len = 1 + payload.len() + TAG_LEN.
-    let wire_len = 1u32 + (frame.payload.len() + TAG_LEN) as u32;
-    let typ = frame.ty as u8;
+    let wire_len = 1u32 + (payload.len() + TAG_LEN) as u32;
     let mut aad = [0u8; 4];
     aad[0] = ((wire_len >> 16) & 0xff) as u8;
     aad[1] = ((wire_len >> 8) & 0xff) as u8;
     aad[2] = (wire_len & 0xff) as u8;
     aad[3] = typ;
-    let ct = crypto::aead::seal(&key.key, &nonce, &aad, &frame.payload);
+    let ct = crypto::aead::seal(&key.key, &nonce, &aad, payload);
     let mut out = BytesMut::with_capacity(4 + 1 + ct.len());
     put_u24(&mut out, wire_len);
     out.put_u8(typ);
@@ -223,14 +332,7 @@ pub fn decode(mut src: &[u8], key: KeyCtx, nonce: [u8; 12]) -> Result<Frame, Err
     aad[3] = typ;
     let ct = &src[1..wire_len];
     let payload = crypto::aead::open(&key.key, &nonce, &aad, ct).map_err(|_| Error::Crypto)?;
-    let ty = match typ {
-        0x10 => FrameType::Stream,
-        0x11 => FrameType::WindowUpdate,
-        0x12 => FrameType::Ping,
-        0x13 => FrameType::KeyUpdate,
-        0x1F => FrameType::Close,
-        x => return Err(Error::UnknownType(x)),
-    };
+    let ty = FrameType::from_u8(typ)?;
     Ok(Frame { ty, payload })
 }
 
@@ -264,6 +366,26 @@ mod tests {
         assert_eq!(f, g);
     }
 
+    #[test]
+    fn type_registry_and_ignorable_range() {
+        for t in FrameType::ALL {
+            assert_eq!(FrameType::from_u8(t as u8), Ok(t));
+            assert!(!IGNORABLE_TYPES.contains(&(t as u8)));
+        }
+        assert_eq!(FrameType::from_u8(0x40), Err(Error::Ignored(0x40)));
+        assert_eq!(FrameType::from_u8(0x7F), Err(Error::Ignored(0x7F)));
+        assert_eq!(FrameType::from_u8(0x3F), Err(Error::UnknownType(0x3F)));
+        assert_eq!(FrameType::from_u8(0x80), Err(Error::UnknownType(0x80)));
+
+        // A sealed ignorable frame is only reported as such once it authenticates
+        let key = KeyCtx { key: [1u8; 32] };
+        let w = encode_type(0x41, b"ext", key, [0u8; 12]);
+        assert_eq!(decode(&w, key, [0u8; 12]), Err(Error::Ignored(0x41)));
+        assert_eq!(decode(&w, key, [1u8; 12]), Err(Error::Crypto));
+        let plain = [0x00, 0x00, 0x02, 0x41, 0xAA];
+        assert_eq!(Frame::decode_plain(&plain), Err(Error::Ignored(0x41)));
+    }
+
     #[test]
     fn aead_protected_encode_decode_and_negative() {
         let mut rng = StdRng::seed_from_u64(123);
@@ -276,11 +398,17 @@ mod tests {
             let payload_len = (rng.next_u32() % 2048) as usize;
             let mut payload = vec![0u8; payload_len];
             rng.fill_bytes(&mut payload);
-            let ty = match rng.next_u32() % 5 {
+            let ty = match rng.next_u32() % 11 {
                 0 => FrameType::Stream,
                 1 => FrameType::WindowUpdate,
                 2 => FrameType::Ping,
                 3 => FrameType::KeyUpdate,
+                4 => FrameType::RstStream,
+                5 => FrameType::GoAway,
+                6 => FrameType::Priority,
+                7 => FrameType::Datagram,
+                8 => FrameType::Padding,
+                9 => FrameType::Settings,
                 _ => FrameType::Close,
             };
             let f = Frame { ty, payload };
@@ -405,4 +533,38 @@ mod stealth_tests {
             assert!(ms >= 2 && ms <= 15, "ms={} out of bounds", ms);
         }
     }
+
+    #[test]
+    fn learned_profile_drives_sizer_and_jitter() {
+        use crate::jitter::Jitter;
+        use crate::profile::{TraceRecord, TrafficProfile};
+        use crate::sizing::Sizer;
+        use std::sync::Arc;
+
+        let trace: Vec<TraceRecord> = (0..100)
+            .map(|i| TraceRecord {
+                at: i as f64 * 0.002,
+                len: if i % 2 == 0 { 1200 } else { 9000 },
+            })
+            .collect();
+        let p = Arc::new(TrafficProfile::learn("t", &trace).unwrap());
+        let mut s = Sizer::from_profile(p.clone(), Some(3));
+        let mut j = Jitter::from_profile(p, Some(3));
+        for _ in 0..256 {
+            let len = s.choose_len(10);
+            assert!((1200..=9000).contains(&len), "len={len}");
+            let d = j.delay().as_micros();
+            assert!((1990..=2010).contains(&d), "gap={d}us");
+        }
+    }
+
+    #[test]
+    fn unseeded_shapers_do_not_share_a_sequence() {
+        use crate::sizing::{Profile, Sizer};
+        let mut a = Sizer::new(Profile::Webby, None);
+        let mut b = Sizer::new(Profile::Webby, None);
+        let a: Vec<usize> = (0..16).map(|_| a.choose_len(1500)).collect();
+        let b: Vec<usize> = (0..16).map(|_| b.choose_len(1500)).collect();
+        assert_ne!(a, b);
+    }
 }
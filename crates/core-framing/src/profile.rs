@@ -0,0 +1,304 @@
+//! Traffic profiles learned from recorded traces.
+//!
+//! A [`TrafficProfile`] holds two equi-depth histograms: record sizes and the
+//! gaps between records inside a burst. Learn one from a capture of real
+//! browsing with [`TrafficProfile::learn`], ship it as deterministic CBOR and
+//! hand it to the stealth sizer and jitter, which then sample from it.
+//!
+//! Traces are plain text, one line per packet: the capture time in seconds and
+//! the TLS record lengths it carried, comma separated. That is what
+//!
+//! ```text
+//! tshark -r browse.pcap -Y tls.record -T fields -e frame.time_relative -e tls.record.length
+//! ```
+//!
+//! prints. Blank lines and lines starting with `#` are skipped.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Format version written by this build.
+pub const PROFILE_VERSION: u32 = 1;
+/// Buckets per learned histogram.
+pub const LEARN_BUCKETS: usize = 32;
+/// Gaps longer than this are idle time between bursts, not pacing, and are left out.
+pub const IDLE_GAP: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileError {
+    /// Trace line (1-based) that is not `<seconds> <len>[,<len>...]`.
+    BadTrace {
+        line: usize,
+    },
+    /// Not enough records to learn from.
+    EmptyTrace,
+    /// CBOR did not decode or the histograms are inconsistent.
+    Invalid,
+    UnsupportedVersion(u32),
+    Io(std::io::ErrorKind),
+}
+
+impl From<std::io::Error> for ProfileError {
+    fn from(e: std::io::Error) -> Self {
+        ProfileError::Io(e.kind())
+    }
+}
+
+/// One TLS record seen in a trace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceRecord {
+    /// Seconds since the start of the capture.
+    pub at: f64,
+    pub len: u32,
+}
+
+/// Parse a text trace (see the module docs).
+pub fn parse_trace(text: &str) -> Result<Vec<TraceRecord>, ProfileError> {
+    let mut out = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = ProfileError::BadTrace { line: i + 1 };
+        let mut fields = line.split_whitespace();
+        let at: f64 = match fields.next().map(str::parse) {
+            Some(Ok(t)) if f64::is_finite(t) => t,
+            _ => return Err(bad),
+        };
+        let lens = fields.next().ok_or(bad.clone())?;
+        for len in lens.split(',').filter(|l| !l.is_empty()) {
+            let len = len.parse().map_err(|_| bad.clone())?;
+            out.push(TraceRecord { at, len });
+        }
+    }
+    Ok(out)
+}
+
+/// Equi-depth histogram: bucket `i` spans `edges[i]..=edges[i + 1]` and holds
+/// `counts[i]` samples.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub edges: Vec<u64>,
+    pub counts: Vec<u64>,
+}
+
+impl Histogram {
+    /// Split `values` into up to `buckets` buckets of roughly equal count.
+    pub fn learn(values: &[u64], buckets: usize) -> Option<Self> {
+        if values.is_empty() || buckets == 0 {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        let edges: Vec<u64> = (0..=buckets)
+            .map(|i| sorted[i * (n - 1) / buckets])
+            .collect();
+        let mut counts = vec![0u64; buckets];
+        for v in &sorted {
+            let lo = edges.partition_point(|&e| e < *v);
+            let hi = edges.partition_point(|&e| e <= *v);
+            // A value repeated across several edges owns a zero-width bucket; keep it
+            // there so sampling gives it back exactly instead of smearing it out
+            let b = if hi - lo >= 2 {
+                lo
+            } else {
+                hi.saturating_sub(1)
+            };
+            counts[b.min(buckets - 1)] += 1;
+        }
+        Some(Self { edges, counts })
+    }
+
+    /// Pick a bucket by weight, then a value uniformly inside it.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> u64 {
+        let total: u64 = self.counts.iter().sum();
+        let mut r = rng.gen_range(0..total);
+        for (i, &c) in self.counts.iter().enumerate() {
+            if r < c {
+                return rng.gen_range(self.edges[i]..=self.edges[i + 1]);
+            }
+            r -= c;
+        }
+        unreachable!("r < total")
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.counts.is_empty()
+            && self.edges.len() == self.counts.len() + 1
+            && self.edges.windows(2).all(|w| w[0] <= w[1])
+            // sample() draws below the total, so it must be non-zero and fit a u64
+            && self
+                .counts
+                .iter()
+                .try_fold(0u64, |a, &c| a.checked_add(c))
+                .is_some_and(|total| total > 0)
+    }
+}
+
+/// Record sizes and intra-burst gaps learned from a trace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficProfile {
+    pub version: u32,
+    pub name: String,
+    /// Record sizes in bytes.
+    pub sizes: Histogram,
+    /// Gaps between consecutive records of a burst, in microseconds.
+    pub gaps_us: Histogram,
+}
+
+impl TrafficProfile {
+    pub fn learn(name: &str, trace: &[TraceRecord]) -> Result<Self, ProfileError> {
+        let sizes: Vec<u64> = trace
+            .iter()
+            .filter(|r| r.len > 0)
+            .map(|r| r.len as u64)
+            .collect();
+        let mut times: Vec<f64> = trace.iter().map(|r| r.at).collect();
+        times.sort_by(f64::total_cmp);
+        let idle = IDLE_GAP.as_micros() as u64;
+        let gaps: Vec<u64> = times
+            .windows(2)
+            .map(|w| ((w[1] - w[0]) * 1e6) as u64)
+            .filter(|&g| g <= idle)
+            .collect();
+        Ok(Self {
+            version: PROFILE_VERSION,
+            name: name.to_string(),
+            sizes: Histogram::learn(&sizes, LEARN_BUCKETS).ok_or(ProfileError::EmptyTrace)?,
+            gaps_us: Histogram::learn(&gaps, LEARN_BUCKETS).ok_or(ProfileError::EmptyTrace)?,
+        })
+    }
+
+    pub fn sample_size<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        self.sizes.sample(rng) as usize
+    }
+
+    pub fn sample_gap<R: Rng + ?Sized>(&self, rng: &mut R) -> Duration {
+        Duration::from_micros(self.gaps_us.sample(rng))
+    }
+
+    pub fn to_cbor(&self) -> Vec<u8> {
+        core_cbor::to_det_cbor(self).expect("det-cbor")
+    }
+
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, ProfileError> {
+        let p: Self = core_cbor::from_det_cbor(bytes).map_err(|_| ProfileError::Invalid)?;
+        if p.version != PROFILE_VERSION {
+            return Err(ProfileError::UnsupportedVersion(p.version));
+        }
+        if !p.sizes.is_valid() || !p.gaps_us.is_valid() {
+            return Err(ProfileError::Invalid);
+        }
+        Ok(p)
+    }
+
+    /// Read a profile written by [`TrafficProfile::to_cbor`].
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, ProfileError> {
+        Self::from_cbor(&std::fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // Browsing-like trace: bursts of full records with small gaps, a few short
+    // records, and idle pauses between bursts.
+    fn trace() -> Vec<TraceRecord> {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut at = 0.0;
+        let mut out = Vec::new();
+        for _ in 0..200 {
+            for _ in 0..10 {
+                at += rng.gen_range(0.0001..0.004);
+                let len = if rng.gen_bool(0.7) {
+                    16_401
+                } else {
+                    rng.gen_range(100..1500)
+                };
+                out.push(TraceRecord { at, len });
+            }
+            at += rng.gen_range(0.5..3.0);
+        }
+        out
+    }
+
+    #[test]
+    fn learned_profile_reproduces_the_trace() {
+        let p = TrafficProfile::learn("browse", &trace()).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let n = 10_000;
+        let mut full = 0;
+        for _ in 0..n {
+            let s = p.sample_size(&mut rng);
+            assert!((100..=16_401).contains(&s), "size {s}");
+            full += (s == 16_401) as usize;
+            let g = p.sample_gap(&mut rng);
+            assert!(g >= Duration::from_micros(100) && g < Duration::from_millis(4));
+        }
+        // 70% of records were full-size in the trace
+        assert!((6_500..7_500).contains(&full), "full={full}");
+    }
+
+    #[test]
+    fn cbor_roundtrip_and_validation() {
+        let p = TrafficProfile::learn("browse", &trace()).unwrap();
+        let bytes = p.to_cbor();
+        assert_eq!(bytes, p.to_cbor());
+        assert_eq!(TrafficProfile::from_cbor(&bytes), Ok(p.clone()));
+
+        let mut old = p.clone();
+        old.version = 9;
+        assert_eq!(
+            TrafficProfile::from_cbor(&old.to_cbor()),
+            Err(ProfileError::UnsupportedVersion(9))
+        );
+        let mut broken = p;
+        broken.sizes.counts = vec![0; broken.sizes.counts.len()];
+        assert_eq!(
+            TrafficProfile::from_cbor(&broken.to_cbor()),
+            Err(ProfileError::Invalid)
+        );
+        assert_eq!(
+            TrafficProfile::from_cbor(b"garbage"),
+            Err(ProfileError::Invalid)
+        );
+    }
+
+    #[test]
+    fn counts_summing_past_u64_are_rejected() {
+        let mut p = TrafficProfile::learn("browse", &trace()).unwrap();
+        p.sizes = Histogram {
+            edges: vec![0, 10, 20],
+            counts: vec![u64::MAX, 1],
+        };
+        assert_eq!(
+            TrafficProfile::from_cbor(&p.to_cbor()),
+            Err(ProfileError::Invalid)
+        );
+    }
+
+    #[test]
+    fn parses_tshark_style_traces() {
+        let text = "# t len\n0.000100\t517\n\n0.031\t1200,16401\n0.2 90";
+        let t = parse_trace(text).unwrap();
+        assert_eq!(
+            t.iter().map(|r| r.len).collect::<Vec<_>>(),
+            vec![517, 1200, 16_401, 90]
+        );
+        assert_eq!(t[2].at, 0.031);
+        assert_eq!(
+            parse_trace("0.1 10\nnope 5"),
+            Err(ProfileError::BadTrace { line: 2 })
+        );
+        assert_eq!(parse_trace("0.1"), Err(ProfileError::BadTrace { line: 1 }));
+        assert_eq!(
+            TrafficProfile::learn("x", &[]),
+            Err(ProfileError::EmptyTrace)
+        );
+    }
+}
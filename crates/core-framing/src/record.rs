@@ -0,0 +1,206 @@
+//! Record layouts for sealed frames.
+//!
+//! The clear layout ([`crate::encode`]) authenticates the frame type and exact
+//! length but sends them in the open. The hidden layout moves both inside the
+//! ciphertext and pads the plaintext up to a multiple of the record size:
+//!
+//! ```text
+//! wire:      [Len(u24) | ciphertext_with_tag]      AAD = [Len(u24)]
+//! plaintext: [Type(u8) | PayloadLen(u24) | payload | zero padding]
+//! ```
+//!
+//! An observer then only learns how many records of `record_size` a frame
+//! took. Both ends must use the same layout; the HTX inner key binding makes
+//! a mismatch fail authentication instead of being misread.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use core_crypto as crypto;
+
+use crate::decoder::MAX_WIRE_LEN;
+use crate::{decode, encode_type, put_u24, Error, Frame, FrameType, KeyCtx, TAG_LEN};
+
+// Type byte and u24 payload length at the front of the hidden plaintext
+const INNER_HDR: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordLayout {
+    /// Type and length in the clear, authenticated as AAD.
+    #[default]
+    Clear,
+    /// Type and length sealed, plaintext padded to a multiple of `record_size`.
+    Hidden { record_size: usize },
+}
+
+impl RecordLayout {
+    pub const MIN_RECORD: usize = 64;
+    /// One TLS record's worth of plaintext.
+    pub const MAX_RECORD: usize = 16 * 1024;
+
+    /// Hidden layout with `record_size` clamped to
+    /// [`MIN_RECORD`](Self::MIN_RECORD)..=[`MAX_RECORD`](Self::MAX_RECORD).
+    pub fn hidden(record_size: usize) -> Self {
+        RecordLayout::Hidden {
+            record_size: record_size.clamp(Self::MIN_RECORD, Self::MAX_RECORD),
+        }
+    }
+
+    pub fn seal(&self, frame: &Frame, key: KeyCtx, nonce: [u8; 12]) -> Bytes {
+        self.seal_type(frame.ty as u8, &frame.payload, key, nonce)
+    }
+
+    /// [`RecordLayout::seal`] for a raw type byte (see [`encode_type`]).
+    pub fn seal_type(&self, typ: u8, payload: &[u8], key: KeyCtx, nonce: [u8; 12]) -> Bytes {
+        match *self {
+            RecordLayout::Clear => encode_type(typ, payload, key, nonce),
+            RecordLayout::Hidden { record_size } => {
+                seal_hidden(typ, payload, record_size, key, nonce)
+            }
+        }
+    }
+
+    pub fn open(&self, src: &[u8], key: KeyCtx, nonce: [u8; 12]) -> Result<Frame, Error> {
+        match self {
+            RecordLayout::Clear => decode(src, key, nonce),
+            RecordLayout::Hidden { .. } => open_hidden(src, key, nonce),
+        }
+    }
+}
+
+fn seal_hidden(typ: u8, payload: &[u8], record_size: usize, key: KeyCtx, nonce: [u8; 12]) -> Bytes {
+    let used = INNER_HDR + payload.len();
+    let padded = used.div_ceil(record_size) * record_size;
+    // Never pad past what the u24 length can carry
+    let padded = padded.min(MAX_WIRE_LEN - TAG_LEN).max(used);
+    let mut pt = BytesMut::with_capacity(padded);
+    pt.put_u8(typ);
+    put_u24(&mut pt, payload.len() as u32);
+    pt.extend_from_slice(payload);
+    pt.resize(padded, 0);
+
+    let wire_len = (padded + TAG_LEN) as u32;
+    let mut aad = BytesMut::with_capacity(3);
+    put_u24(&mut aad, wire_len);
+    let ct = crypto::aead::seal(&key.key, &nonce, &aad, &pt);
+    let mut out = BytesMut::with_capacity(3 + ct.len());
+    out.extend_from_slice(&aad);
+    out.extend_from_slice(&ct);
+    out.freeze()
+}
+
+fn open_hidden(src: &[u8], key: KeyCtx, nonce: [u8; 12]) -> Result<Frame, Error> {
+    if src.len() < 3 {
+        return Err(Error::TooShort);
+    }
+    let wire_len = ((src[0] as usize) << 16) | ((src[1] as usize) << 8) | (src[2] as usize);
+    if src.len() - 3 < wire_len {
+        return Err(Error::InvalidLen);
+    }
+    if wire_len < INNER_HDR + TAG_LEN {
+        return Err(Error::TooShort);
+    }
+    let pt = crypto::aead::open(&key.key, &nonce, &src[..3], &src[3..3 + wire_len])
+        .map_err(|_| Error::Crypto)?;
+    let typ = pt[0];
+    let len = ((pt[1] as usize) << 16) | ((pt[2] as usize) << 8) | (pt[3] as usize);
+    if len > pt.len() - INNER_HDR {
+        return Err(Error::InvalidLen);
+    }
+    let ty = FrameType::from_u8(typ)?;
+    Ok(Frame {
+        ty,
+        payload: pt[INNER_HDR..INNER_HDR + len].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    const KEY: KeyCtx = KeyCtx { key: [7u8; 32] };
+
+    #[test]
+    fn hidden_roundtrip_for_every_type_and_size() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let layout = RecordLayout::hidden(512);
+        for (i, ty) in FrameType::ALL.into_iter().cycle().take(200).enumerate() {
+            let mut payload = vec![0u8; (rng.next_u32() % 5000) as usize];
+            rng.fill_bytes(&mut payload);
+            let f = Frame { ty, payload };
+            let nonce = [i as u8; 12];
+            let w = layout.seal(&f, KEY, nonce);
+            assert_eq!((w.len() - 3 - TAG_LEN) % 512, 0);
+            assert_eq!(layout.open(&w, KEY, nonce), Ok(f));
+        }
+        // Clear layout is the plain AEAD encoding
+        let f = Frame {
+            ty: FrameType::Ping,
+            payload: vec![1; 9],
+        };
+        let w = RecordLayout::Clear.seal(&f, KEY, [0; 12]);
+        assert_eq!(w, crate::encode(&f, KEY, [0; 12]));
+        assert_eq!(RecordLayout::Clear.open(&w, KEY, [0; 12]), Ok(f));
+    }
+
+    #[test]
+    fn hidden_records_do_not_reveal_type_or_length() {
+        let layout = RecordLayout::hidden(1024);
+        let small = Frame {
+            ty: FrameType::WindowUpdate,
+            payload: vec![0; 8],
+        };
+        let big = Frame {
+            ty: FrameType::Stream,
+            payload: vec![0; 1000],
+        };
+        let a = layout.seal(&small, KEY, [1; 12]);
+        let b = layout.seal(&big, KEY, [2; 12]);
+        assert_eq!(a.len(), b.len());
+        assert_eq!(a[..3], b[..3]);
+    }
+
+    #[test]
+    fn hidden_records_reject_tampering() {
+        let layout = RecordLayout::hidden(256);
+        let f = Frame {
+            ty: FrameType::Stream,
+            payload: b"secret".to_vec(),
+        };
+        let nonce = [5u8; 12];
+        let w = layout.seal(&f, KEY, nonce).to_vec();
+        for i in 0..w.len() {
+            let mut bad = w.clone();
+            bad[i] ^= 0x20;
+            assert!(
+                layout.open(&bad, KEY, nonce).is_err(),
+                "flip at {i} accepted"
+            );
+        }
+        assert_eq!(
+            layout.open(&w[..w.len() - 1], KEY, nonce),
+            Err(Error::InvalidLen)
+        );
+        assert_eq!(layout.open(&w, KEY, [6u8; 12]), Err(Error::Crypto));
+        // Neither layout accepts the other's records
+        assert!(RecordLayout::Clear.open(&w, KEY, nonce).is_err());
+        let clear = RecordLayout::Clear.seal(&f, KEY, nonce);
+        assert!(layout.open(&clear, KEY, nonce).is_err());
+    }
+
+    #[test]
+    fn inner_length_past_the_plaintext_is_rejected() {
+        // Hand-seal a record whose inner length claims more than it carries
+        let mut pt = vec![FrameType::Stream as u8, 0x00, 0x01, 0x00];
+        pt.resize(64, 0);
+        let wire_len = (pt.len() + TAG_LEN) as u32;
+        let mut hdr = BytesMut::new();
+        put_u24(&mut hdr, wire_len);
+        let ct = crypto::aead::seal(&KEY.key, &[0; 12], &hdr, &pt);
+        let mut w = hdr.to_vec();
+        w.extend_from_slice(&ct);
+        assert_eq!(
+            RecordLayout::hidden(64).open(&w, KEY, [0; 12]),
+            Err(Error::InvalidLen)
+        );
+    }
+}
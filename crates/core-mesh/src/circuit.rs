@@ -110,6 +110,10 @@ pub struct CircuitRequest {
     /// Ephemeral X25519 public key for key agreement (32 bytes, hex-encoded)
     #[serde(default)]
     pub ephemeral_pubkey: Option<String>,
+    /// ML-KEM-768 encapsulation key offering hybrid key agreement
+    /// (1184 bytes, hex-encoded). Relays that do not know it ignore it.
+    #[serde(default)]
+    pub kem_pubkey: Option<String>,
 }
 
 impl CircuitRequest {
@@ -119,6 +123,7 @@ impl CircuitRequest {
             circuit_id,
             next_hop: next_hop.to_base58(),
             ephemeral_pubkey: None,
+            kem_pubkey: None,
         }
     }
 
@@ -128,6 +133,30 @@ impl CircuitRequest {
             circuit_id,
             next_hop: next_hop.to_base58(),
             ephemeral_pubkey: Some(hex::encode(pubkey)),
+            kem_pubkey: None,
+        }
+    }
+
+    /// Offer hybrid post-quantum key agreement alongside the X25519 key.
+    pub fn with_kem_pubkey(mut self, ek: &core_crypto::mlkem::EncapsulationKey) -> Self {
+        self.kem_pubkey = Some(hex::encode(ek.as_bytes()));
+        self
+    }
+
+    /// Get the ML-KEM-768 encapsulation key, if the initiator offered one.
+    pub fn get_kem_pubkey(
+        &self,
+    ) -> Result<Option<core_crypto::mlkem::EncapsulationKey>, CircuitError> {
+        match &self.kem_pubkey {
+            Some(hex_str) => {
+                let bytes = hex::decode(hex_str).map_err(|e| {
+                    CircuitError::DecodeFailed(format!("Invalid KEM key hex: {}", e))
+                })?;
+                core_crypto::mlkem::EncapsulationKey::from_bytes(&bytes)
+                    .map(Some)
+                    .map_err(|_| CircuitError::DecodeFailed("Invalid KEM key".to_string()))
+            }
+            None => Ok(None),
         }
     }
 
@@ -183,6 +212,10 @@ pub struct CircuitReady {
     /// Relay's ephemeral X25519 public key (32 bytes, hex-encoded)
     #[serde(default)]
     pub ephemeral_pubkey: Option<String>,
+    /// ML-KEM-768 ciphertext answering the request's KEM key (1088 bytes,
+    /// hex-encoded). Absent when the relay agreed on X25519 only.
+    #[serde(default)]
+    pub kem_ciphertext: Option<String>,
 }
 
 impl CircuitReady {
@@ -191,6 +224,7 @@ impl CircuitReady {
         Self {
             circuit_id,
             ephemeral_pubkey: None,
+            kem_ciphertext: None,
         }
     }
 
@@ -199,6 +233,33 @@ impl CircuitReady {
         Self {
             circuit_id,
             ephemeral_pubkey: Some(hex::encode(pubkey)),
+            kem_ciphertext: None,
+        }
+    }
+
+    /// Answer a hybrid request with the KEM ciphertext.
+    pub fn with_kem_ciphertext(mut self, ciphertext: &[u8]) -> Self {
+        self.kem_ciphertext = Some(hex::encode(ciphertext));
+        self
+    }
+
+    /// Get the ML-KEM-768 ciphertext, if the relay agreed to hybrid keys.
+    pub fn get_kem_ciphertext(&self) -> Result<Option<Vec<u8>>, CircuitError> {
+        match &self.kem_ciphertext {
+            Some(hex_str) => {
+                let bytes = hex::decode(hex_str).map_err(|e| {
+                    CircuitError::DecodeFailed(format!("Invalid KEM ciphertext hex: {}", e))
+                })?;
+                if bytes.len() != core_crypto::mlkem::CIPHERTEXT_LEN {
+                    return Err(CircuitError::DecodeFailed(format!(
+                        "Invalid KEM ciphertext length: expected {}, got {}",
+                        core_crypto::mlkem::CIPHERTEXT_LEN,
+                        bytes.len()
+                    )));
+                }
+                Ok(Some(bytes))
+            }
+            None => Ok(None),
         }
     }
 
@@ -895,6 +956,90 @@ pub fn establish_hop_keys(
     Ok(derive_relay_keys(&shared_secret))
 }
 
+/// Hybrid X25519 + ML-KEM-768 key agreement for a hop.
+///
+/// The initiator sends its KEM encapsulation key in [`CircuitRequest::kem_pubkey`];
+/// a relay that supports it encapsulates to that key and returns the ciphertext in
+/// [`CircuitReady::kem_ciphertext`]. Both ends then call this with the KEM shared
+/// secret, the encapsulation key and the ciphertext. Binding the last two, as
+/// X-Wing does, means a relay on the path cannot swap either without the keys
+/// differing. The initiator side is [`establish_initiator_hop_keys`].
+///
+/// # Arguments
+///
+/// * `my_private` - Our ephemeral private key
+/// * `peer_public` - The relay's public key
+/// * `kem_shared` - ML-KEM-768 shared secret (from encapsulation or decapsulation)
+/// * `kem_pubkey` - The initiator's encapsulation key
+/// * `kem_ciphertext` - The relay's ciphertext
+///
+/// # Returns
+///
+/// Relay keys that stay secret as long as either X25519 or ML-KEM-768 holds.
+pub fn establish_hop_keys_hybrid(
+    my_private: core_crypto::x25519::KeyPair,
+    peer_public: &[u8; 32],
+    kem_shared: &[u8; 32],
+    kem_pubkey: &core_crypto::mlkem::EncapsulationKey,
+    kem_ciphertext: &[u8],
+) -> Result<RelayKeys, CircuitError> {
+    use core_crypto::hkdf;
+
+    let dh_secret = core_crypto::x25519::dh(my_private.priv_key, peer_public)
+        .map_err(|_| CircuitError::HandshakeFailed("X25519 key agreement failed".to_string()))?;
+    let mut ikm = Vec::with_capacity(64 + kem_pubkey.as_bytes().len() + kem_ciphertext.len());
+    ikm.extend_from_slice(&dh_secret);
+    ikm.extend_from_slice(kem_shared);
+    ikm.extend_from_slice(kem_pubkey.as_bytes());
+    ikm.extend_from_slice(kem_ciphertext);
+    let prk = hkdf::extract(b"qnet-onion-hybrid-v2", &ikm);
+    let shared_secret: [u8; 32] = hkdf::expand(&prk, b"hop-secret");
+    Ok(derive_relay_keys(&shared_secret))
+}
+
+/// Initiator keys for a hop from the relay's [`CircuitReady`].
+///
+/// `kem` is the decapsulation key whose encapsulation key went out in
+/// [`CircuitRequest::kem_pubkey`], or `None` when no KEM key was offered. Once one
+/// was offered, a reply without a ciphertext is refused: a relay on the path could
+/// have stripped it to drop the hop to classical keys.
+///
+/// # Arguments
+///
+/// * `my_private` - Our ephemeral private key
+/// * `ready` - The relay's answer
+/// * `kem` - Our ML-KEM-768 decapsulation key, if we offered one
+///
+/// # Returns
+///
+/// Relay keys for this hop, hybrid when a KEM key was offered.
+pub fn establish_initiator_hop_keys(
+    my_private: core_crypto::x25519::KeyPair,
+    ready: &CircuitReady,
+    kem: Option<&core_crypto::mlkem::DecapsulationKey>,
+) -> Result<RelayKeys, CircuitError> {
+    let peer_public = ready.get_ephemeral_pubkey()?.ok_or_else(|| {
+        CircuitError::HandshakeFailed("CircuitReady has no ephemeral key".to_string())
+    })?;
+    let Some(dk) = kem else {
+        return establish_hop_keys(my_private, &peer_public);
+    };
+    let ct = ready.get_kem_ciphertext()?.ok_or_else(|| {
+        CircuitError::HandshakeFailed(
+            "KEM key offered but CircuitReady has no ciphertext".to_string(),
+        )
+    })?;
+    let kem_shared = core_crypto::mlkem::decapsulate(dk, &ct)
+        .map_err(|_| CircuitError::HandshakeFailed("ML-KEM decapsulation failed".to_string()))?;
+    establish_hop_keys_hybrid(
+        my_private,
+        &peer_public,
+        &kem_shared,
+        &dk.encapsulation_key(),
+        &ct,
+    )
+}
+
 /// Builder for constructing circuits through the mesh network.
 ///
 /// The `CircuitBuilder` uses the discovery system to select intermediate peers
@@ -1244,5 +1389,65 @@ mod tests {
         assert_eq!(decoded.circuit_id, 789);
         assert!(decoded.ephemeral_pubkey.is_none());
         assert_eq!(decoded.get_ephemeral_pubkey().unwrap(), None);
+        assert_eq!(decoded.get_kem_pubkey().unwrap(), None);
+    }
+
+    #[test]
+    fn test_hybrid_hop_keys() {
+        use core_crypto::{mlkem, x25519};
+
+        let peer = PeerId::random();
+        let (initiator, relay) = (x25519::generate_keypair(), x25519::generate_keypair());
+        let (init_pub, relay_pub) = (initiator.pubkey, relay.pubkey);
+        let (dk, ek) = mlkem::generate_keypair();
+
+        // Initiator offers a KEM key; relay encapsulates and answers
+        let req = CircuitRequest::with_ephemeral_key(7, peer, &init_pub).with_kem_pubkey(&ek);
+        let req = CircuitRequest::decode(&req.encode().unwrap()).unwrap();
+        let (ct, relay_shared) = mlkem::encapsulate(&req.get_kem_pubkey().unwrap().unwrap());
+        let ready = CircuitReady::with_ephemeral_key(7, &relay_pub).with_kem_ciphertext(&ct);
+        let ready = CircuitReady::decode(&ready.encode().unwrap()).unwrap();
+
+        let init_keys = establish_initiator_hop_keys(initiator, &ready, Some(&dk)).unwrap();
+        let relay_keys =
+            establish_hop_keys_hybrid(relay, &init_pub, &relay_shared, &ek, &ct).unwrap();
+        assert_eq!(init_keys.kf, relay_keys.kf);
+        assert_eq!(init_keys.kb, relay_keys.kb);
+
+        // The keys depend on the encapsulation key, not just the two secrets
+        let (initiator, relay) = (x25519::generate_keypair(), x25519::generate_keypair());
+        let (init_pub, relay_pub) = (initiator.pubkey, relay.pubkey);
+        let (ct, relay_shared) = mlkem::encapsulate(&ek);
+        let ready = CircuitReady::with_ephemeral_key(7, &relay_pub).with_kem_ciphertext(&ct);
+        let (_, other_ek) = mlkem::generate_keypair();
+        let init_keys = establish_initiator_hop_keys(initiator, &ready, Some(&dk)).unwrap();
+        let relay_keys =
+            establish_hop_keys_hybrid(relay, &init_pub, &relay_shared, &other_ek, &ct).unwrap();
+        assert_ne!(init_keys.kf, relay_keys.kf);
+
+        // A classical relay leaves the ciphertext out
+        let classical = CircuitReady::with_ephemeral_key(7, &relay_pub);
+        assert_eq!(classical.get_kem_ciphertext().unwrap(), None);
+        let initiator = x25519::generate_keypair();
+        assert!(establish_initiator_hop_keys(initiator, &classical, None).is_ok());
+    }
+
+    #[test]
+    fn test_stripped_kem_ciphertext_is_refused() {
+        use core_crypto::{mlkem, x25519};
+
+        let (initiator, relay) = (x25519::generate_keypair(), x25519::generate_keypair());
+        let (dk, ek) = mlkem::generate_keypair();
+        let (ct, _) = mlkem::encapsulate(&ek);
+        let ready = CircuitReady::with_ephemeral_key(9, &relay.pubkey).with_kem_ciphertext(&ct);
+
+        // A relay on the path drops the ciphertext from the JSON
+        let mut stripped = ready.clone();
+        stripped.kem_ciphertext = None;
+        let stripped = CircuitReady::decode(&stripped.encode().unwrap()).unwrap();
+        assert!(matches!(
+            establish_initiator_hop_keys(initiator, &stripped, Some(&dk)),
+            Err(CircuitError::HandshakeFailed(_))
+        ));
     }
 }
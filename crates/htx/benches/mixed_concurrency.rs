@@ -1,5 +1,5 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use htx::api::dial_inproc_secure;
+use htx::api::{dial_inproc_secure_with, MuxConfig};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
@@ -13,11 +13,8 @@ fn bench_mixed(c: &mut Criterion) {
     g.measurement_time(Duration::from_millis(1200));
 
     g.bench_function("small_vs_large_concurrent", |b| {
-        // RR scheduler can be forced via env for this bench
-        std::env::set_var("HTX_SCHEDULER_RR", "1");
-        std::env::set_var("HTX_SCHEDULER_PROFILE", "http");
-
-        let (client, server) = dial_inproc_secure();
+        // RR scheduler with the HTTP window/chunk profile
+        let (client, server) = dial_inproc_secure_with(MuxConfig::http());
         let running = Arc::new(AtomicBool::new(true));
         let rflag = running.clone();
         let echo = std::thread::spawn(move || {
@@ -0,0 +1,123 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use htx::api::{dial_inproc_secure_with, Conn, MuxConfig, Priority, SecureStream};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+const SMALL: usize = 16 * 1024;
+const BULK: usize = 4 * 1024 * 1024;
+
+// Server side: each stream asks for N bytes (u64 BE) and gets them followed by FIN.
+// Responses are scheduled with whatever priority the client signalled.
+fn serve(server: Conn, running: Arc<AtomicBool>) {
+    while running.load(Ordering::Relaxed) {
+        if let Some(s) = server.accept_stream(100) {
+            std::thread::spawn(move || {
+                let Some(req) = s.read() else { return };
+                let mut n = u64::from_be_bytes(req[..8].try_into().unwrap()) as usize;
+                let chunk = vec![5u8; 64 * 1024];
+                while n > 0 {
+                    let take = n.min(chunk.len());
+                    s.write(&chunk[..take]);
+                    n -= take;
+                }
+                s.shutdown_write();
+            });
+        }
+    }
+}
+
+fn fetch(s: &SecureStream, n: usize) -> usize {
+    s.write(&(n as u64).to_be_bytes());
+    let mut got = 0;
+    while got < n {
+        match s.read() {
+            Some(buf) => got += buf.len(),
+            None => break,
+        }
+    }
+    got
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let i = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+    sorted[i]
+}
+
+// Small fetches racing two endless bulk downloads on the same connection; reports the
+// tail of the small-fetch latency, which is what a page load feels.
+fn bench_priority(c: &mut Criterion) {
+    let mut g = c.benchmark_group("htx_priority_tail_latency");
+    g.sample_size(10);
+    g.warm_up_time(Duration::from_millis(300));
+    g.measurement_time(Duration::from_secs(2));
+
+    for (name, small_prio, bulk_prio) in [
+        ("equal_priority", Priority::default(), Priority::default()),
+        (
+            "interactive_over_bulk",
+            Priority::INTERACTIVE,
+            Priority::BULK,
+        ),
+    ] {
+        let (client, server) = dial_inproc_secure_with(MuxConfig::http());
+        let running = Arc::new(AtomicBool::new(true));
+        let srv = {
+            let running = running.clone();
+            std::thread::spawn(move || serve(server, running))
+        };
+        let bulk: Vec<_> = (0..2)
+            .map(|_| {
+                let client = client.clone();
+                let running = running.clone();
+                std::thread::spawn(move || {
+                    while running.load(Ordering::Relaxed) {
+                        let s = client.open_stream_with_priority(bulk_prio);
+                        fetch(&s, BULK);
+                    }
+                })
+            })
+            .collect();
+        // Let the bulk transfers fill the pipe first
+        std::thread::sleep(Duration::from_millis(100));
+
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        g.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    let s = client.open_stream_with_priority(small_prio);
+                    assert_eq!(fetch(&s, SMALL), SMALL);
+                    let took = start.elapsed();
+                    samples.lock().unwrap().push(took);
+                    total += took;
+                }
+                total
+            })
+        });
+        let mut sorted = samples.lock().unwrap().clone();
+        sorted.sort();
+        if !sorted.is_empty() {
+            println!(
+                "{name}: small fetch p50 {:?} p99 {:?} max {:?} ({} samples)",
+                percentile(&sorted, 0.50),
+                percentile(&sorted, 0.99),
+                sorted[sorted.len() - 1],
+                sorted.len()
+            );
+        }
+
+        running.store(false, Ordering::Relaxed);
+        for h in bulk {
+            let _ = h.join();
+        }
+        let _ = srv.join();
+    }
+    g.finish();
+}
+
+criterion_group!(benches, bench_priority);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+//! Print Noise handshake test vectors for every pattern, with and without psk2
+//! and hfs.
+//!
+//! cargo run -p htx --example noise_vectors > crates/htx/vectors/noise.json
+
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use htx::noise::FixedRng;
+use htx::{x25519_scalar, Handshake, HandshakeParams, Pattern};
+use serde_json::json;
+
+fn main() {
+    let (si, sr) = ([0x01u8; 32], [0x02u8; 32]);
+    let (ei, er) = ([0x11u8; 32], [0x22u8; 32]);
+    let psk = [0x42u8; 32];
+    let sr_scalar = x25519_scalar(sr);
+    let rs = (sr_scalar * X25519_BASEPOINT).to_bytes();
+
+    let mut out = Vec::new();
+    for pattern in Pattern::ALL {
+        for (psk, hybrid) in [
+            (None, false),
+            (Some(psk), false),
+            (None, true),
+            (Some(psk), true),
+        ] {
+            let params = HandshakeParams {
+                pattern,
+                psk,
+                hybrid,
+            };
+            let mut init = Handshake::initiator_with_rng(
+                params,
+                x25519_scalar(si),
+                Some(rs),
+                &mut FixedRng(ei),
+            )
+            .expect("initiator");
+            let mut resp = Handshake::responder_with_rng(params, sr_scalar, &mut FixedRng(er));
+            let mut messages = Vec::new();
+            let mut msg = init.next(None).expect("m1");
+            let mut turn = [&mut resp, &mut init];
+            while let Some(m) = msg {
+                msg = turn[0].next(Some(&m)).expect("handshake");
+                messages.push(hex::encode(m));
+                turn.swap(0, 1);
+            }
+            let (tx, rx) = init.transport_keys().expect("done");
+            out.push(json!({
+                "protocol": params.protocol_name(),
+                "psk": psk.map(hex::encode),
+                "messages": messages,
+                "initiator_tx": hex::encode(tx),
+                "initiator_rx": hex::encode(rx),
+                "exporter": hex::encode(init.exporter(b"channel-binding").expect("done")),
+            }));
+        }
+    }
+    let doc = json!({
+        "initiator_static": hex::encode(si),
+        "responder_static": hex::encode(sr),
+        "initiator_ephemeral": hex::encode(ei),
+        "responder_ephemeral": hex::encode(er),
+        "private_keys": "X25519 private keys (RFC 7748)",
+        "exporter_label": "channel-binding",
+        "vectors": out,
+    });
+    println!("{}", serde_json::to_string_pretty(&doc).expect("json"));
+}
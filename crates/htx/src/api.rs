@@ -1,25 +1,48 @@
+#[cfg(feature = "rustls-config")]
+use crate::inner::open_inner_ekm_only;
 use crate::inner::{
-    open_inner, open_inner_ekm_only, open_inner_with_compat, Caps, Exporter, TlsStream,
+    open_inner, open_inner_resumed, open_inner_with_compat, Caps, Exporter, TlsStream,
 };
 use crate::mux::{self, Mux, StreamHandle};
+pub use crate::mux::{
+    CoverStats, CoverTraffic, Datagram, DatagramStats, FlowId, FrameEvent, JitterProfile,
+    Keepalive, KeyMetrics, MuxConfig, Priority, RecordLayout, ResetCode, Role, RttStats,
+    SchedulerKind, SizingProfile, StealthProfile, StreamEnd, TrafficProfile, MAX_DATAGRAM_LEN,
+};
+#[cfg(feature = "rustls-config")]
+pub use crate::probe::ProbeGuard;
+use crate::resume::{unix_now, ResumeClient};
+pub use crate::resume::{ResumeError, Ticket, TicketCache, TicketIssuer};
+#[cfg(feature = "rustls-config")]
+pub use crate::server::{HtxServer, HtxServerConfig, ServerStats};
+pub use crate::tl::KeyUpdatePolicy;
 use crate::tls_mirror::Template;
+use crate::transport::TcpTransport;
+#[cfg(feature = "rustls-config")]
+use crate::transport::TlsTransport;
 use crate::Handshake;
-use bytes::Bytes;
+pub use crate::{HandshakeError, HandshakeParams, Pattern};
 use core_crypto as crypto;
 use curve25519_dalek::constants::X25519_BASEPOINT;
 use curve25519_dalek::scalar::Scalar;
-use rand::{RngCore, SeedableRng};
-use std::io::{Read, Write};
+use rand::{rngs::OsRng, RngCore};
+use std::io;
 use std::net::{TcpListener, TcpStream, ToSocketAddrs};
-use std::sync::mpsc;
+use std::pin::Pin;
+use std::sync::{mpsc, Mutex};
+use std::task::{ready, Context, Poll};
 use std::thread;
 use std::time::Duration;
+#[cfg(feature = "rustls-config")]
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 #[derive(Clone)]
 pub struct Conn {
     mux: Mux,
     tx_key: [u8; 32],
     rx_key: [u8; 32],
+    resumption: [u8; 32],
 }
 
 pub struct SecureStream {
@@ -28,76 +51,309 @@ pub struct SecureStream {
     rx_key: [u8; 32],
     send_ctr: std::sync::Arc<std::sync::atomic::AtomicU64>,
     recv_ctr: std::sync::Arc<std::sync::atomic::AtomicU64>,
+    // Mux chunks received but not yet forming a whole record
+    recv_buf: Mutex<Vec<u8>>,
+    // AsyncRead: opened plaintext not yet handed to the caller
+    plain: Vec<u8>,
+    plain_pos: usize,
+    // AsyncWrite: sealed record not yet accepted by the mux
+    sealed: Vec<u8>,
+    sealed_pos: usize,
 }
 
+// Each L3 record is [Len(u32 BE) | ciphertext||tag]. The mux may split or
+// coalesce chunks, so records are reassembled before opening.
+const RECORD_HDR: usize = 4;
+// Largest plaintext sealed per record on the AsyncWrite path.
+const MAX_ASYNC_RECORD: usize = 16 * 1024;
+
 impl Conn {
     pub fn open_stream(&self) -> SecureStream {
         let sh = self.mux.open_stream();
-        SecureStream {
-            inner: sh,
-            tx_key: self.tx_key,
-            rx_key: self.rx_key,
-            send_ctr: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
-            recv_ctr: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
-        }
+        SecureStream::new(sh, self.tx_key, self.rx_key)
+    }
+
+    /// Open a stream scheduled with `prio`; see [`Mux::open_stream_with_priority`].
+    pub fn open_stream_with_priority(&self, prio: Priority) -> SecureStream {
+        let sh = self.mux.open_stream_with_priority(prio);
+        SecureStream::new(sh, self.tx_key, self.rx_key)
     }
 
     pub fn accept_stream(&self, timeout_ms: u64) -> Option<SecureStream> {
         self.mux
             .accept_stream(std::time::Duration::from_millis(timeout_ms))
-            .map(|sh| SecureStream {
-                inner: sh,
-                tx_key: self.tx_key,
-                rx_key: self.rx_key,
-                send_ctr: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
-                recv_ctr: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
-            })
+            .map(|sh| SecureStream::new(sh, self.tx_key, self.rx_key))
+    }
+
+    /// Wait for the peer to open a stream; `None` once the connection is gone.
+    pub async fn accept(&self) -> Option<SecureStream> {
+        self.mux
+            .accept()
+            .await
+            .map(|sh| SecureStream::new(sh, self.tx_key, self.rx_key))
+    }
+
+    /// Send an unreliable datagram on `flow`; see [`Mux::send_datagram`].
+    pub fn send_datagram(&self, flow: FlowId, data: &[u8]) -> io::Result<()> {
+        self.mux.send_datagram(flow, data)
+    }
+
+    pub fn recv_datagram(&self, timeout_ms: u64) -> Option<Datagram> {
+        self.mux.recv_datagram(Duration::from_millis(timeout_ms))
+    }
+
+    /// Wait for the next datagram; `None` once the connection is gone.
+    pub async fn next_datagram(&self) -> Option<Datagram> {
+        self.mux.next_datagram().await
+    }
+
+    pub fn datagram_stats(&self) -> DatagramStats {
+        self.mux.datagram_stats()
+    }
+
+    pub fn cover_stats(&self) -> CoverStats {
+        self.mux.cover_stats()
     }
 
     pub fn key_update(&self) {
         self.mux.key_update();
     }
 
+    /// Drain the connection: GOAWAY, let in-flight streams finish for up to
+    /// `grace`, then close. See [`Mux::shutdown`].
+    pub fn shutdown(&self, grace: Duration) -> bool {
+        self.mux.shutdown(grace)
+    }
+
+    /// True once either side has sent GOAWAY.
+    pub fn is_draining(&self) -> bool {
+        self.mux.is_draining()
+    }
+
+    /// True once the underlying connection has gone away.
+    pub fn is_closed(&self) -> bool {
+        self.mux.is_closed()
+    }
+
     pub fn encryption_epoch(&self) -> u64 {
         self.mux.encryption_epoch()
     }
+
+    pub fn key_metrics(&self) -> KeyMetrics {
+        self.mux.key_metrics()
+    }
+
+    /// Round-trip estimate from keepalive PINGs (see [`MuxConfig::keepalive`]).
+    pub fn rtt(&self) -> Option<RttStats> {
+        self.mux.rtt()
+    }
+
+    /// Edge: send the client a resumption ticket for this session on the control stream.
+    pub fn send_ticket(&self, issuer: &TicketIssuer) {
+        self.mux.send_ticket(&issuer.issue(&self.resumption));
+    }
+
+    /// Client: next ticket from the edge, ready to keep in a [`TicketCache`].
+    pub fn recv_ticket(&self, timeout_ms: u64) -> Option<Ticket> {
+        let nt = self.mux.recv_ticket(Duration::from_millis(timeout_ms))?;
+        Some(Ticket::from_new_ticket(&nt, &self.resumption, unix_now()))
+    }
 }
 
 impl SecureStream {
+    fn new(inner: StreamHandle, tx_key: [u8; 32], rx_key: [u8; 32]) -> Self {
+        Self {
+            inner,
+            tx_key,
+            rx_key,
+            send_ctr: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            recv_ctr: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            recv_buf: Mutex::new(Vec::new()),
+            plain: Vec::new(),
+            plain_pos: 0,
+            sealed: Vec::new(),
+            sealed_pos: 0,
+        }
+    }
+
     fn next_nonce(counter: u64) -> [u8; 12] {
         let mut n = [0u8; 12];
         n[4..12].copy_from_slice(&counter.to_le_bytes());
         n
     }
 
-    pub fn write(&self, pt: &[u8]) {
+    fn seal_record(&self, pt: &[u8]) -> Vec<u8> {
         let ctr = self
             .send_ctr
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         let nonce = Self::next_nonce(ctr);
         let ct = crypto::aead::seal(&self.tx_key, &nonce, b"", pt);
-        self.inner.write(&ct);
+        let mut rec = Vec::with_capacity(RECORD_HDR + ct.len());
+        rec.extend_from_slice(&(ct.len() as u32).to_be_bytes());
+        rec.extend_from_slice(&ct);
+        rec
     }
 
-    pub fn read(&self) -> Option<Vec<u8>> {
-        let ct = self.inner.read()?;
+    fn open_record(&self, ct: &[u8]) -> Result<Vec<u8>, crypto::Error> {
         let ctr = self
             .recv_ctr
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         let nonce = Self::next_nonce(ctr);
-        crypto::aead::open(&self.rx_key, &nonce, b"", &ct).ok()
+        crypto::aead::open(&self.rx_key, &nonce, b"", ct)
+    }
+
+    pub fn write(&self, pt: &[u8]) {
+        let rec = self.seal_record(pt);
+        self.inner.write(&rec);
+    }
+
+    pub fn read(&self) -> Option<Vec<u8>> {
+        let mut buf = self.recv_buf.lock().unwrap();
+        loop {
+            if let Some(ct) = take_record(&mut buf) {
+                return self.open_record(&ct).ok();
+            }
+            let chunk = self.inner.read()?;
+            buf.extend_from_slice(&chunk);
+        }
     }
 
     pub fn try_read(&self) -> Option<Vec<u8>> {
-        let ct = self.inner.try_read()?;
-        let ctr = self
-            .recv_ctr
-            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        let nonce = Self::next_nonce(ctr);
-        crypto::aead::open(&self.rx_key, &nonce, b"", &ct).ok()
+        let mut buf = self.recv_buf.lock().unwrap();
+        loop {
+            if let Some(ct) = take_record(&mut buf) {
+                return self.open_record(&ct).ok();
+            }
+            let chunk = self.inner.try_read()?;
+            buf.extend_from_slice(&chunk);
+        }
+    }
+
+    /// Half-close: FIN after the records already written; reads continue.
+    pub fn shutdown_write(&self) {
+        self.inner.shutdown_write();
+    }
+
+    /// Abort the stream in both directions with a reason code for the peer.
+    pub fn reset(&self, code: ResetCode) {
+        self.inner.reset(code);
+    }
+
+    /// Gracefully close the stream; same as dropping it.
+    pub fn close(self) {}
+
+    /// How the peer's side ended, or `None` while it is still open.
+    pub fn end_state(&self) -> Option<StreamEnd> {
+        self.inner.end_state()
+    }
+
+    /// Reprioritize the stream on both ends.
+    pub fn set_priority(&self, prio: Priority) {
+        self.inner.set_priority(prio);
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.inner.priority()
+    }
+
+    // Drive the pending sealed record into the mux; Ready once it has all been accepted.
+    fn poll_drain_sealed(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.sealed_pos < self.sealed.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.sealed[self.sealed_pos..]))?;
+            self.sealed_pos += n;
+        }
+        self.sealed.clear();
+        self.sealed_pos = 0;
+        Poll::Ready(Ok(()))
     }
 }
 
+impl AsyncRead for SecureStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.plain_pos < this.plain.len() {
+                let n = (this.plain.len() - this.plain_pos).min(out.remaining());
+                out.put_slice(&this.plain[this.plain_pos..this.plain_pos + n]);
+                this.plain_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            let opened =
+                take_record(this.recv_buf.get_mut().unwrap()).map(|ct| this.open_record(&ct));
+            match opened {
+                Some(Ok(pt)) => {
+                    this.plain = pt;
+                    this.plain_pos = 0;
+                }
+                Some(Err(_)) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "htx record failed to authenticate",
+                    )))
+                }
+                None => match ready!(this.inner.poll_chunk(cx))? {
+                    Some(chunk) => this.recv_buf.get_mut().unwrap().extend_from_slice(&chunk),
+                    None => {
+                        if this.recv_buf.get_mut().unwrap().is_empty() {
+                            return Poll::Ready(Ok(()));
+                        }
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl AsyncWrite for SecureStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain_sealed(cx))?;
+        if data.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = data.len().min(MAX_ASYNC_RECORD);
+        this.sealed = this.seal_record(&data[..n]);
+        this.sealed_pos = 0;
+        // The record is ours now; push what we can and let flush finish the rest
+        if let Poll::Ready(Err(e)) = this.poll_drain_sealed(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_drain_sealed(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain_sealed(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+// Split the first complete [Len|ciphertext] record off the front of `buf`.
+fn take_record(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    if buf.len() < RECORD_HDR {
+        return None;
+    }
+    let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    if buf.len() < RECORD_HDR + len {
+        return None;
+    }
+    Some(buf.drain(..RECORD_HDR + len).skip(RECORD_HDR).collect())
+}
+
 // Dummy TLS exporter for in-proc demo; both sides share the same master secret
 struct DummyTls {
     master: [u8; 32],
@@ -118,20 +374,42 @@ impl Exporter for DummyTls {
     }
 }
 
-fn noise_xk_pair() -> (Handshake, Handshake) {
-    let si = Scalar::from_bytes_mod_order([1u8; 32]);
-    let sr = Scalar::from_bytes_mod_order([2u8; 32]);
+// Fresh statics per pair; ephemerals come from the OS CSPRNG inside Handshake
+fn noise_pair(params: HandshakeParams) -> (Handshake, Handshake) {
+    let si = Scalar::from_bytes_mod_order(random_key());
+    let sr = Scalar::from_bytes_mod_order(random_key());
     let rs = (sr * X25519_BASEPOINT).to_bytes();
-    let mut init = Handshake::init_initiator(si, rs);
-    let mut resp = Handshake::init_responder(sr);
-    let m1 = init.next(None).unwrap().unwrap();
-    let m2 = resp.next(Some(&m1)).unwrap().unwrap();
-    let m3 = init.next(Some(&m2)).unwrap().unwrap();
-    let _ = resp.next(Some(&m3)).unwrap();
+    let mut init = Handshake::initiator(params, si, Some(rs)).unwrap();
+    let mut resp = Handshake::responder(params, sr);
+    // Messages alternate until both sides have split
+    let mut msg = init.next(None).unwrap();
+    let mut turn = [&mut resp, &mut init];
+    while let Some(m) = msg {
+        msg = turn[0].next(Some(&m)).unwrap();
+        turn.swap(0, 1);
+    }
+    assert!(init.is_done() && resp.is_done());
     (init, resp)
 }
 
+fn random_key() -> [u8; 32] {
+    let mut k = [0u8; 32];
+    OsRng.fill_bytes(&mut k);
+    k
+}
+
 pub fn dial_inproc_secure() -> (Conn, Conn) {
+    dial_inproc_secure_with(MuxConfig::default())
+}
+
+/// In-process secure pair whose muxes both use `cfg`.
+pub fn dial_inproc_secure_with(cfg: MuxConfig) -> (Conn, Conn) {
+    dial_inproc_secure_noise(cfg, HandshakeParams::default())
+}
+
+/// In-process secure pair keyed by a `params` handshake. The pattern rides the
+/// inner caps, so it is bound into the keys like the record layout.
+pub fn dial_inproc_secure_noise(cfg: MuxConfig, params: HandshakeParams) -> (Conn, Conn) {
     // Calibrate template (static for demo)
     let tpl = Template {
         alpn: vec!["h2".into(), "http/1.1".into()],
@@ -139,32 +417,72 @@ pub fn dial_inproc_secure() -> (Conn, Conn) {
         groups: vec!["x25519".into()],
         extensions: vec![0, 11, 10, 35, 16, 23, 43, 51],
     };
-    let caps = Caps::default();
-    let (init_hs, resp_hs) = noise_xk_pair();
-    let master = {
-        let mut r = rand::rngs::StdRng::seed_from_u64(99);
-        let mut k = [0u8; 32];
-        r.fill_bytes(&mut k);
-        k
-    };
+    let caps = Caps::default()
+        .with_record_layout(cfg.layout())
+        .with_handshake(&params);
+    let (init_hs, resp_hs) = noise_pair(params);
+    let master = random_key();
     let tls_c = TlsStream::new(DummyTls { master });
     let tls_s = TlsStream::new(DummyTls { master });
     let ic = open_inner(&tls_c, &caps, &tpl, &init_hs).unwrap();
     let rc = open_inner(&tls_s, &caps, &tpl, &resp_hs).unwrap();
-    let (mux_c, mux_s) = mux::pair_encrypted(ic.tx_key, ic.rx_key, rc.tx_key, rc.rx_key);
+    let (mux_c, mux_s) = mux::pair_encrypted(ic.tx_key, ic.rx_key, rc.tx_key, rc.rx_key, cfg);
     let c = Conn {
         mux: mux_c,
         tx_key: ic.tx_key,
         rx_key: ic.rx_key,
+        resumption: ic.resumption,
     };
     let s = Conn {
         mux: mux_s,
         tx_key: rc.tx_key,
         rx_key: rc.rx_key,
+        resumption: rc.resumption,
     };
     (c, s)
 }
 
+/// In-process pair resumed from `ticket` instead of a full handshake. `issuer` is
+/// the edge that handed the ticket out; it refuses tickets that are expired or
+/// already used. The new session issues tickets of its own.
+pub fn resume_inproc_secure(
+    cfg: MuxConfig,
+    ticket: &Ticket,
+    issuer: &TicketIssuer,
+) -> Result<(Conn, Conn), ResumeError> {
+    let tpl = Template {
+        alpn: vec!["h2".into(), "http/1.1".into()],
+        sig_algs: vec!["rsa_pss_rsae_sha256".into()],
+        groups: vec!["x25519".into()],
+        extensions: vec![0, 11, 10, 35, 16, 23, 43, 51],
+    };
+    let caps = Caps::default().with_record_layout(cfg.layout());
+    let client = ResumeClient::new(ticket);
+    let (accept, server_keys) = issuer.accept(client.hello(), unix_now())?;
+    let client_keys = client.finish(&accept)?;
+    let master = random_key();
+    let tls_c = TlsStream::new(DummyTls { master });
+    let tls_s = TlsStream::new(DummyTls { master });
+    let ic = open_inner_resumed(&tls_c, &caps, &tpl, &client_keys)
+        .map_err(|_| ResumeError::Handshake)?;
+    let rc = open_inner_resumed(&tls_s, &caps, &tpl, &server_keys)
+        .map_err(|_| ResumeError::Handshake)?;
+    let (mux_c, mux_s) = mux::pair_encrypted(ic.tx_key, ic.rx_key, rc.tx_key, rc.rx_key, cfg);
+    let c = Conn {
+        mux: mux_c,
+        tx_key: ic.tx_key,
+        rx_key: ic.rx_key,
+        resumption: ic.resumption,
+    };
+    let s = Conn {
+        mux: mux_s,
+        tx_key: rc.tx_key,
+        rx_key: rc.rx_key,
+        resumption: rc.resumption,
+    };
+    Ok((c, s))
+}
+
 pub fn dial_inproc_secure_compat() -> (Conn, Conn) {
     // Same as above but binds keys with compat flag for translation interop
     let tpl = Template {
@@ -174,27 +492,30 @@ pub fn dial_inproc_secure_compat() -> (Conn, Conn) {
         extensions: vec![0, 11, 10, 35, 16, 23, 43, 51],
     };
     let caps = Caps::default();
-    let (init_hs, resp_hs) = noise_xk_pair();
-    let master = {
-        let mut r = rand::rngs::StdRng::seed_from_u64(101);
-        let mut k = [0u8; 32];
-        r.fill_bytes(&mut k);
-        k
-    };
+    let (init_hs, resp_hs) = noise_pair(HandshakeParams::default());
+    let master = random_key();
     let tls_c = TlsStream::new(DummyTls { master });
     let tls_s = TlsStream::new(DummyTls { master });
     let ic = open_inner_with_compat(&tls_c, &caps, &tpl, &init_hs, Some("compat=1.1")).unwrap();
     let rc = open_inner_with_compat(&tls_s, &caps, &tpl, &resp_hs, Some("compat=1.1")).unwrap();
-    let (mux_c, mux_s) = mux::pair_encrypted(ic.tx_key, ic.rx_key, rc.tx_key, rc.rx_key);
+    let (mux_c, mux_s) = mux::pair_encrypted(
+        ic.tx_key,
+        ic.rx_key,
+        rc.tx_key,
+        rc.rx_key,
+        MuxConfig::default(),
+    );
     let c = Conn {
         mux: mux_c,
         tx_key: ic.tx_key,
         rx_key: ic.rx_key,
+        resumption: ic.resumption,
     };
     let s = Conn {
         mux: mux_s,
         tx_key: rc.tx_key,
         rx_key: rc.rx_key,
+        resumption: rc.resumption,
     };
     (c, s)
 }
@@ -206,66 +527,54 @@ pub enum ApiError {
     Io(std::io::Error),
     Tls,
     NotImplemented,
+    /// The peer's caps ask for another inner record layout than ours.
+    LayoutMismatch {
+        ours: RecordLayout,
+        theirs: RecordLayout,
+    },
+    /// The inner Noise handshake failed, or the peer's caps name another pattern.
+    Handshake(HandshakeError),
+    /// The edge took the ticket but did not prove it could open it.
+    Resume(ResumeError),
+}
+
+pub fn dial(origin: &str) -> Result<Conn, ApiError> {
+    dial_with(origin, MuxConfig::default())
 }
 
+/// Like [`dial`], with explicit mux settings.
 #[cfg(feature = "rustls-config")]
-fn spawn_tls_pump<S: Read + Write + Send + 'static>(
-    mut tls: S,
-    to_net_rx: mpsc::Receiver<Bytes>,
-    from_net_tx: mpsc::Sender<Bytes>,
-) {
-    std::thread::spawn(move || {
-        let mut buf = Vec::<u8>::with_capacity(16 * 1024);
-        let mut tmp = [0u8; 4096];
-        loop {
-            // Flush pending writes
-            loop {
-                match to_net_rx.try_recv() {
-                    Ok(bytes) => {
-                        if tls.write_all(&bytes).is_err() {
-                            return;
-                        }
-                        let _ = tls.flush();
-                    }
-                    Err(mpsc::TryRecvError::Empty) => break,
-                    Err(mpsc::TryRecvError::Disconnected) => return,
-                }
-            }
-            // Read
-            match tls.read(&mut tmp) {
-                Ok(0) => return,
-                Ok(n) => {
-                    buf.extend_from_slice(&tmp[..n]);
-                    while buf.len() >= 4 {
-                        let len = ((buf[0] as usize) << 16)
-                            | ((buf[1] as usize) << 8)
-                            | (buf[2] as usize);
-                        // Wire format is [Len(u24) | Type(u8) | payload...];
-                        // total bytes in this frame on the wire = 3 + len.
-                        let total = 3 + len;
-                        if buf.len() < total {
-                            break;
-                        }
-                        let frame = Bytes::copy_from_slice(&buf[..total]);
-                        if from_net_tx.send(frame).is_err() {
-                            return;
-                        }
-                        buf.drain(..total);
-                    }
-                }
-                Err(_) => {
-                    // If no writes pending, back off a bit
-                    std::thread::sleep(std::time::Duration::from_millis(5));
-                }
-            }
-        }
-    });
+pub fn dial_with(origin: &str, mux_cfg: MuxConfig) -> Result<Conn, ApiError> {
+    dial_ticket(origin, mux_cfg, None)
 }
 
+/// Like [`dial`], resuming from the newest ticket `cache` holds for `origin` when
+/// there is one. An edge without tickets, or one that refuses this ticket (expired,
+/// already used, another ticket key), gets a full handshake instead. Tickets the new
+/// session hands out arrive through [`Conn::recv_ticket`]; keep them in `cache`.
 #[cfg(feature = "rustls-config")]
-pub fn dial(origin: &str) -> Result<Conn, ApiError> {
+pub fn dial_resumed(origin: &str, cache: &TicketCache) -> Result<Conn, ApiError> {
+    dial_resumed_with(origin, cache, MuxConfig::default())
+}
+
+/// Like [`dial_resumed`], with explicit mux settings.
+#[cfg(feature = "rustls-config")]
+pub fn dial_resumed_with(
+    origin: &str,
+    cache: &TicketCache,
+    mux_cfg: MuxConfig,
+) -> Result<Conn, ApiError> {
+    let ticket = cache.take(origin, unix_now());
+    dial_ticket(origin, mux_cfg, ticket.as_ref())
+}
+
+#[cfg(feature = "rustls-config")]
+fn dial_ticket(
+    origin: &str,
+    mux_cfg: MuxConfig,
+    ticket: Option<&Ticket>,
+) -> Result<Conn, ApiError> {
     use crate::bootstrap;
-    use crate::inner::Caps;
     use crate::tls_mirror::{build_client_hello, choose_template_rotating, Config as TlsCfg};
     use std::time::Duration;
     use url::Url;
@@ -306,12 +615,22 @@ pub fn dial(origin: &str) -> Result<Conn, ApiError> {
     let (_tid, tpl) = choose_template_rotating(&format!("https://{}:{}", host, port), Some(&tcfg))
         .map_err(|_| ApiError::Tls)?;
     let client = build_client_hello(&tpl);
+    let (conn, tcp) = tls_connect(&client, &host, (host.as_str(), port))?;
+    let knock_key = crate::probe::knock_key_from_env()?;
+    client_conn(conn, tcp, &tpl, mux_cfg, knock_key.as_ref(), ticket)
+}
 
-    // Build rustls client
+/// The dialer's outer TLS handshake with `host` at `addr`.
+#[cfg(feature = "rustls-config")]
+pub(crate) fn tls_connect(
+    client: &crate::tls_mirror::ClientConfig,
+    host: &str,
+    addr: impl std::net::ToSocketAddrs,
+) -> Result<(rustls::ClientConnection, TcpStream), ApiError> {
     let cfg = client.rustls.clone();
-    let server_name = rustls::ServerName::try_from(host.as_str()).map_err(|_| ApiError::Url)?;
+    let server_name = rustls::ServerName::try_from(host).map_err(|_| ApiError::Url)?;
     let mut conn = rustls::ClientConnection::new(cfg, server_name).map_err(|_| ApiError::Tls)?;
-    let mut tcp = TcpStream::connect((host.as_str(), port)).map_err(ApiError::Io)?;
+    let mut tcp = TcpStream::connect(addr).map_err(ApiError::Io)?;
     tcp.set_nodelay(true).ok();
     // Drive handshake
     while conn.is_handshaking() {
@@ -320,10 +639,30 @@ pub fn dial(origin: &str) -> Result<Conn, ApiError> {
             Err(e) => return Err(ApiError::Io(e)),
         }
     }
-    // Exporter context
-    let caps = Caps::default();
+    Ok((conn, tcp))
+}
+
+/// Client side after the outer TLS handshake: knock if the edge wants one, exchange
+/// caps, resume from `ticket` or run the configured handshake, bind the inner keys
+/// to the exporter and `tpl`, and start the mux.
+#[cfg(feature = "rustls-config")]
+pub(crate) fn client_conn(
+    conn: rustls::ClientConnection,
+    tcp: TcpStream,
+    tpl: &Template,
+    mux_cfg: MuxConfig,
+    knock_key: Option<&[u8; 32]>,
+    ticket: Option<&Ticket>,
+) -> Result<Conn, ApiError> {
+    let knock = knock_key
+        .map(|key| crate::probe::client_knock(&conn, key))
+        .transpose()?;
+    let mux_cfg = mux_cfg.role(Role::Initiator);
+    // Exporter context; the record layout and Noise pattern are part of it, so both
+    // ends must pick the same ones
+    let caps = inner_caps(&mux_cfg);
     // Build exporter context same as inner::open_inner uses
-    let tid = crate::tls_mirror::compute_template_id(&tpl);
+    let tid = crate::tls_mirror::compute_template_id(tpl);
     #[derive(serde::Serialize)]
     struct Bind<'a> {
         #[serde(with = "serde_bytes")]
@@ -362,48 +701,225 @@ pub fn dial(origin: &str) -> Result<Conn, ApiError> {
         }
     }
     let tls = TlsStream::new(RustlsExporter { ekm });
-    // Derive inner keys using EKM-only mode (no extra handshake on the wire)
-    let inner = open_inner_ekm_only(&tls, &caps, &tpl, true).map_err(|_| ApiError::Tls)?;
+    // EKM-only keys unless the caps exchange below leads to a Noise handshake
+    let inner = open_inner_ekm_only(&tls, &caps, tpl, true).map_err(|_| ApiError::Tls)?;
+    let mut stream = rustls::StreamOwned::new(conn, tcp);
+    if let Some(knock) = knock {
+        use std::io::Write;
+        stream.write_all(&knock).map_err(ApiError::Io)?;
+        stream.flush().map_err(ApiError::Io)?;
+    }
+    // Offer our caps; the edge answers with its own before either side starts the mux
+    let deadline = Instant::now() + crate::driver::DEFAULT_HANDSHAKE_TIMEOUT;
+    io_deadline(&stream.sock, Some(deadline))?;
+    let offer = match ticket {
+        Some(_) => caps.clone().with_resume(),
+        None => caps.clone(),
+    };
+    offer.write_to(&mut stream).map_err(caps_io)?;
+    let theirs = Caps::read_from(&mut stream).map_err(caps_io)?;
+    check_caps(&caps, &theirs)?;
+    // The edge answers a resume hello with an empty message when it refuses the ticket
+    let resumed = match ticket.filter(|_| theirs.resumes()) {
+        Some(ticket) => {
+            use crate::driver::HandshakeIo;
+            let client = ResumeClient::new(ticket);
+            let mut io = TlsHandshakeIo(&mut stream);
+            io.send(client.hello(), deadline).map_err(caps_io)?;
+            let accept = io.recv(deadline).map_err(caps_io)?;
+            match accept.is_empty() {
+                true => None,
+                false => Some(client.finish(&accept).map_err(ApiError::Resume)?),
+            }
+        }
+        None => None,
+    };
+    let inner = match (resumed, mux_cfg.handshake()) {
+        (Some(keys), _) => {
+            open_inner_resumed(&tls, &caps, tpl, &keys).map_err(|_| ApiError::Tls)?
+        }
+        (None, Some(noise)) => {
+            let mut hs = Handshake::initiator(noise.params, noise.local, noise.remote)
+                .map_err(ApiError::Handshake)?;
+            crate::driver::drive(&mut hs, &mut TlsHandshakeIo(&mut stream), deadline)
+                .map_err(ApiError::Handshake)?;
+            open_inner(&tls, &caps, tpl, &hs).map_err(|_| ApiError::Tls)?
+        }
+        (None, None) => inner,
+    };
+    io_deadline(&stream.sock, None)?;
     // Start mux over TLS stream
-    let (to_net_tx, to_net_rx) = mpsc::channel::<Bytes>();
-    let (from_net_tx, from_net_rx) = mpsc::channel::<Bytes>();
-    // Wrap conn + tcp into a StreamOwned for IO
-    // Avoid starving outgoing writes: set a small read timeout so the pump loop
-    // doesn't block indefinitely on reads and can interleave pending writes.
-    let _ = tcp.set_read_timeout(Some(Duration::from_millis(5)));
-    let tls_stream = rustls::StreamOwned::new(conn, tcp);
-    std::thread::spawn(move || spawn_tls_pump(tls_stream, to_net_rx, from_net_tx));
+    let transport = TlsTransport::new(stream).map_err(ApiError::Io)?;
     // Dev-only: allow plaintext mux (L2) while keeping per-stream AEAD (L3) intact
     let plaintext = std::env::var("HTX_INNER_PLAINTEXT").ok().as_deref() == Some("1");
     if plaintext {
         eprintln!("htx::api::dial: HTX_INNER_PLAINTEXT=1 — using PLAINTEXT mux (dev)");
     }
     let mux = if plaintext {
-        Mux::new(to_net_tx, from_net_rx)
+        Mux::new(transport, mux_cfg)
     } else {
-        Mux::new_encrypted(to_net_tx, from_net_rx, inner.tx_key, inner.rx_key)
+        Mux::new_encrypted(transport, inner.tx_key, inner.rx_key, mux_cfg)
     };
     Ok(Conn {
         mux,
         tx_key: inner.tx_key,
         rx_key: inner.rx_key,
+        resumption: inner.resumption,
     })
 }
 
 #[cfg(not(feature = "rustls-config"))]
-pub fn dial(_origin: &str) -> Result<Conn, ApiError> {
+pub fn dial_with(_origin: &str, _cfg: MuxConfig) -> Result<Conn, ApiError> {
     Err(ApiError::FeatureDisabled)
 }
 
 #[cfg(feature = "rustls-config")]
 pub fn accept(bind: &str) -> Result<Conn, ApiError> {
-    use crate::tls_mirror::Template;
+    accept_with(bind, MuxConfig::default())
+}
+
+/// Like [`accept`], with explicit mux settings. For a listener that serves many
+/// clients at once see [`HtxServer`].
+#[cfg(feature = "rustls-config")]
+pub fn accept_with(bind: &str, mux_cfg: MuxConfig) -> Result<Conn, ApiError> {
     // Build a vanilla rustls ServerConfig using a self-signed cert loaded from env paths for PoC.
     // PROD: Use a real certificate for decoy hostnames via ACME/issued certs.
+    let (cert_path, key_path) = tls_paths_from_env()?;
+    let mut scfg = load_tls_files(&cert_path, &key_path)?;
+    // With HTX_KNOCK_KEY and HTX_COVER_ORIGIN set, probers are spliced to the cover
+    let guard = crate::probe::ProbeGuard::from_env()?;
+    if let Some(guard) = &guard {
+        guard.restrict_alpn(&mut scfg);
+    }
+    let scfg = std::sync::Arc::new(scfg);
+
+    // Bind TCP and accept until one HTX client arrives (callers can loop)
+    let listener = TcpListener::bind(bind).map_err(ApiError::Io)?;
+    loop {
+        let tcp = match listener.accept() {
+            Ok((tcp, _peer)) => tcp,
+            Err(e) => {
+                // When in blocking mode, accept should block; but to be safe, back off on transient errors
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::Interrupted
+                {
+                    std::thread::sleep(Duration::from_millis(5));
+                    continue;
+                }
+                return Err(ApiError::Io(e));
+            }
+        };
+        // Ensure the accepted socket is in blocking mode for rustls handshake progress
+        let _ = tcp.set_nonblocking(false);
+        let deadline = std::time::Instant::now() + crate::driver::DEFAULT_HANDSHAKE_TIMEOUT;
+        match crate::server::handshake(
+            scfg.clone(),
+            tcp,
+            deadline,
+            mux_cfg.clone(),
+            guard.as_ref(),
+            None,
+        )? {
+            crate::server::Admitted::Htx(conn) => return Ok(conn),
+            crate::server::Admitted::Cover(splice) => {
+                thread::spawn(move || splice.run());
+            }
+        }
+    }
+}
+
+// Bound the caps exchange by `deadline`; `None` lifts the socket timeouts again
+#[cfg(feature = "rustls-config")]
+fn io_deadline(tcp: &TcpStream, deadline: Option<Instant>) -> Result<(), ApiError> {
+    let left = deadline.map(|d| {
+        d.saturating_duration_since(Instant::now())
+            .max(Duration::from_millis(1))
+    });
+    tcp.set_read_timeout(left).map_err(ApiError::Io)?;
+    tcp.set_write_timeout(left).map_err(ApiError::Io)
+}
+
+// Timed-out socket reads come back as WouldBlock on Unix
+#[cfg(feature = "rustls-config")]
+fn caps_io(e: io::Error) -> ApiError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock => ApiError::Io(io::ErrorKind::TimedOut.into()),
+        _ => ApiError::Io(e),
+    }
+}
+
+// Caps the secure dial/accept paths offer for `cfg`
+#[cfg(feature = "rustls-config")]
+fn inner_caps(cfg: &MuxConfig) -> Caps {
+    let caps = Caps::default().with_record_layout(cfg.layout());
+    match cfg.handshake() {
+        Some(noise) => caps.with_inner_handshake(&noise.params),
+        None => caps,
+    }
+}
+
+#[cfg(feature = "rustls-config")]
+fn check_caps(ours: &Caps, theirs: &Caps) -> Result<(), ApiError> {
+    let (layout, their_layout) = (ours.record_layout(), theirs.record_layout());
+    if layout != their_layout {
+        return Err(ApiError::LayoutMismatch {
+            ours: layout,
+            theirs: their_layout,
+        });
+    }
+    if ours.handshake_name() != theirs.handshake_name() {
+        return Err(ApiError::Handshake(HandshakeError::PatternMismatch));
+    }
+    Ok(())
+}
+
+// Noise messages inside the outer TLS, framed as the driver frames them on TCP. The
+// socket timeouts set for the caps exchange bound every read and write.
+#[cfg(feature = "rustls-config")]
+struct TlsHandshakeIo<'a, S>(&'a mut S);
+
+#[cfg(feature = "rustls-config")]
+impl<S: io::Read + io::Write> crate::driver::HandshakeIo for TlsHandshakeIo<'_, S> {
+    fn send(&mut self, msg: &[u8], _deadline: Instant) -> io::Result<()> {
+        let len = u16::try_from(msg.len()).map_err(|_| io::ErrorKind::InvalidInput)?;
+        self.0.write_all(&len.to_be_bytes())?;
+        self.0.write_all(msg)?;
+        self.0.flush()
+    }
+
+    fn recv(&mut self, _deadline: Instant) -> io::Result<Vec<u8>> {
+        let mut len = [0u8; 2];
+        self.0.read_exact(&mut len)?;
+        let mut msg = vec![0u8; u16::from_be_bytes(len) as usize];
+        self.0.read_exact(&mut msg)?;
+        Ok(msg)
+    }
+}
+
+/// Certificate and key paths from `HTX_TLS_CERT` / `HTX_TLS_KEY`.
+#[cfg(feature = "rustls-config")]
+pub(crate) fn tls_paths_from_env() -> Result<(String, String), ApiError> {
     let cert_path = std::env::var("HTX_TLS_CERT").map_err(|_| ApiError::Tls)?;
     let key_path = std::env::var("HTX_TLS_KEY").map_err(|_| ApiError::Tls)?;
-    let cert_pem = std::fs::read(&cert_path).map_err(ApiError::Io)?;
-    let key_pem = std::fs::read(&key_path).map_err(ApiError::Io)?;
+    Ok((cert_path, key_path))
+}
+
+#[cfg(feature = "rustls-config")]
+pub(crate) fn load_tls_files(
+    cert_path: impl AsRef<std::path::Path>,
+    key_path: impl AsRef<std::path::Path>,
+) -> Result<rustls::ServerConfig, ApiError> {
+    let cert_pem = std::fs::read(cert_path).map_err(ApiError::Io)?;
+    let key_pem = std::fs::read(key_path).map_err(ApiError::Io)?;
+    load_tls_config(&cert_pem, &key_pem)
+}
+
+/// Edge TLS config from a PEM chain and a PKCS#8 or RSA key.
+#[cfg(feature = "rustls-config")]
+pub(crate) fn load_tls_config(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+) -> Result<rustls::ServerConfig, ApiError> {
     let certs = rustls_pemfile::certs(&mut &cert_pem[..])
         .map_err(|_| ApiError::Tls)?
         .into_iter()
@@ -427,48 +943,31 @@ pub fn accept(bind: &str) -> Result<Conn, ApiError> {
         .map_err(|_| ApiError::Tls)?;
     // ALPN: allow common protocols
     scfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(scfg)
+}
 
-    // Bind TCP and accept one connection (callers can loop)
-    let listener = TcpListener::bind(bind).map_err(ApiError::Io)?;
-    // Accept a single client for simplicity
-    let (mut tcp, _peer) = loop {
-        match listener.accept() {
-            Ok(c) => break c,
-            Err(e) => {
-                // When in blocking mode, accept should block; but to be safe, back off on transient errors
-                if e.kind() == std::io::ErrorKind::WouldBlock
-                    || e.kind() == std::io::ErrorKind::Interrupted
-                {
-                    std::thread::sleep(Duration::from_millis(5));
-                    continue;
-                }
-                return Err(ApiError::Io(e));
-            }
-        }
-    };
-    // Ensure the accepted socket is in blocking mode for rustls handshake progress
-    let _ = tcp.set_nonblocking(false);
-    let _ = tcp.set_nodelay(true);
-
-    // Drive rustls handshake
-    let mut conn =
-        rustls::ServerConnection::new(std::sync::Arc::new(scfg)).map_err(|_| ApiError::Tls)?;
-    while conn.is_handshaking() {
-        match conn.complete_io(&mut tcp) {
-            Ok(_) => {}
-            Err(e) => {
-                // Map WouldBlock/Interrupted to a short backoff; otherwise, propagate
-                if e.kind() == std::io::ErrorKind::WouldBlock
-                    || e.kind() == std::io::ErrorKind::Interrupted
-                {
-                    std::thread::sleep(Duration::from_millis(2));
-                    continue;
-                }
-                return Err(ApiError::Io(e));
-            }
-        }
+// Use a conservative default template; ALPN negotiated is already reflected by outer TLS
+#[cfg(feature = "rustls-config")]
+pub(crate) fn edge_template() -> Template {
+    Template {
+        alpn: vec!["h2".into(), "http/1.1".into()],
+        sig_algs: vec!["rsa_pss_rsae_sha256".into()],
+        groups: vec!["x25519".into()],
+        extensions: vec![0, 11, 10, 35, 16, 23, 43, 51],
     }
+}
 
+/// Edge side after the outer TLS handshake: answer the client's caps offer by
+/// `deadline`, redeem its ticket when `tickets` is set and it offers one (or run
+/// the configured handshake), bind the inner keys to the exporter and start the mux.
+#[cfg(feature = "rustls-config")]
+pub(crate) fn server_conn(
+    conn: rustls::ServerConnection,
+    tcp: TcpStream,
+    deadline: Instant,
+    mux_cfg: MuxConfig,
+    tickets: Option<&TicketIssuer>,
+) -> Result<Conn, ApiError> {
     // Exporter wrapper over rustls server
     struct RustlsExporterS {
         ekm: [u8; 32],
@@ -483,14 +982,45 @@ pub fn accept(bind: &str) -> Result<Conn, ApiError> {
             Ok(self.ekm[..len.min(32)].to_vec())
         }
     }
-    // Use a conservative default template; ALPN negotiated is already reflected by outer TLS
-    let tpl = Template {
-        alpn: vec!["h2".into(), "http/1.1".into()],
-        sig_algs: vec!["rsa_pss_rsae_sha256".into()],
-        groups: vec!["x25519".into()],
-        extensions: vec![0, 11, 10, 35, 16, 23, 43, 51],
+    let tpl = edge_template();
+    let mux_cfg = mux_cfg.role(Role::Responder);
+    let caps = inner_caps(&mux_cfg);
+    // Always answer, even on a mismatch, so the client can report it too
+    let mut stream = rustls::StreamOwned::new(conn, tcp);
+    io_deadline(&stream.sock, Some(deadline))?;
+    let theirs = Caps::read_from(&mut stream).map_err(caps_io)?;
+    let tickets = tickets.filter(|_| theirs.resumes());
+    let answer = match tickets {
+        Some(_) => caps.clone().with_resume(),
+        None => caps.clone(),
     };
-    let caps = Caps::default();
+    answer.write_to(&mut stream).map_err(caps_io)?;
+    check_caps(&caps, &theirs)?;
+    let resumed = match tickets {
+        Some(issuer) => {
+            use crate::driver::HandshakeIo;
+            let mut io = TlsHandshakeIo(&mut stream);
+            let hello = io.recv(deadline).map_err(caps_io)?;
+            let (accept, keys) = match issuer.accept(&hello, unix_now()) {
+                Ok((accept, keys)) => (accept, Some(keys)),
+                // Refused: the client falls back to a full handshake
+                Err(_) => (Vec::new(), None),
+            };
+            io.send(&accept, deadline).map_err(caps_io)?;
+            keys
+        }
+        None => None,
+    };
+    let hs = match (&resumed, mux_cfg.handshake()) {
+        (None, Some(noise)) => {
+            let mut hs = Handshake::responder(noise.params, noise.local);
+            crate::driver::drive(&mut hs, &mut TlsHandshakeIo(&mut stream), deadline)
+                .map_err(ApiError::Handshake)?;
+            Some(hs)
+        }
+        _ => None,
+    };
+    io_deadline(&stream.sock, None)?;
     let tid = crate::tls_mirror::compute_template_id(&tpl);
     #[derive(serde::Serialize)]
     struct Bind<'a> {
@@ -506,43 +1036,51 @@ pub fn accept(bind: &str) -> Result<Conn, ApiError> {
     let mut ekm = [0u8; 32];
     let no_ctx = std::env::var("HTX_EKM_EXPORTER_NO_CTX").ok().as_deref() == Some("1");
     if no_ctx {
-        conn.export_keying_material(&mut ekm, b"qnet inner", None)
+        stream
+            .conn
+            .export_keying_material(&mut ekm, b"qnet inner", None)
             .map_err(|_| ApiError::Tls)?;
     } else {
-        conn.export_keying_material(&mut ekm, b"qnet inner", Some(&ctx))
+        stream
+            .conn
+            .export_keying_material(&mut ekm, b"qnet inner", Some(&ctx))
             .map_err(|_| ApiError::Tls)?;
     }
     let tls = TlsStream::new(RustlsExporterS { ekm });
     // Derive inner keys as server side
-    let inner = open_inner_ekm_only(&tls, &caps, &tpl, false).map_err(|_| ApiError::Tls)?;
+    let inner = match (&resumed, &hs) {
+        (Some(keys), _) => open_inner_resumed(&tls, &caps, &tpl, keys),
+        (None, Some(hs)) => open_inner(&tls, &caps, &tpl, hs),
+        (None, None) => open_inner_ekm_only(&tls, &caps, &tpl, false),
+    }
+    .map_err(|_| ApiError::Tls)?;
 
     // Start mux over TLS stream
-    let (to_net_tx, to_net_rx) = mpsc::channel::<Bytes>();
-    let (from_net_tx, from_net_rx) = mpsc::channel::<Bytes>();
-    // Avoid starving outgoing writes: set a small read timeout so the pump loop
-    // doesn't block indefinitely on reads and can interleave pending writes.
-    let _ = tcp.set_read_timeout(Some(Duration::from_millis(5)));
-    let tls_stream = rustls::StreamOwned::new(conn, tcp);
-    std::thread::spawn(move || spawn_tls_pump(tls_stream, to_net_rx, from_net_tx));
+    let transport = TlsTransport::new(stream).map_err(ApiError::Io)?;
     let plaintext = std::env::var("HTX_INNER_PLAINTEXT").ok().as_deref() == Some("1");
     if plaintext {
         eprintln!("htx::api::accept: HTX_INNER_PLAINTEXT=1 — using PLAINTEXT mux (dev)");
     }
     let mux = if plaintext {
-        Mux::new(to_net_tx, from_net_rx)
+        Mux::new(transport, mux_cfg)
     } else {
-        Mux::new_encrypted(to_net_tx, from_net_rx, inner.tx_key, inner.rx_key)
+        Mux::new_encrypted(transport, inner.tx_key, inner.rx_key, mux_cfg)
     };
     Ok(Conn {
         mux,
         tx_key: inner.tx_key,
         rx_key: inner.rx_key,
+        resumption: inner.resumption,
     })
 }
 #[cfg(not(feature = "rustls-config"))]
 pub fn accept(_bind: &str) -> Result<(), ApiError> {
     Err(ApiError::FeatureDisabled)
 }
+#[cfg(not(feature = "rustls-config"))]
+pub fn accept_with(_bind: &str, _cfg: MuxConfig) -> Result<(), ApiError> {
+    Err(ApiError::FeatureDisabled)
+}
 
 #[cfg(test)]
 mod tests {
@@ -567,6 +1105,59 @@ mod tests {
         t.join().unwrap();
     }
 
+    #[test]
+    fn api_echo_over_every_noise_pattern() {
+        for pattern in Pattern::ALL {
+            let params = HandshakeParams::new(pattern).psk2([3; 32]);
+            let (client, server) = dial_inproc_secure_noise(MuxConfig::default(), params);
+            let t = thread::spawn(move || {
+                let s = server.accept_stream(1000).expect("stream");
+                let buf = s.read().expect("req");
+                s.write(&buf);
+            });
+            let st = client.open_stream();
+            st.write(pattern.as_str().as_bytes());
+            assert_eq!(st.read().expect("resp"), pattern.as_str().as_bytes());
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn api_resumes_from_a_ticket_sent_on_the_control_stream() {
+        let issuer = TicketIssuer::generate();
+        let cache = TicketCache::new();
+        let origin = "https://edge.example";
+        let (client, server) = dial_inproc_secure();
+        server.send_ticket(&issuer);
+        server.send_ticket(&issuer);
+        for _ in 0..2 {
+            cache.insert(origin, client.recv_ticket(1000).expect("ticket"));
+        }
+
+        let ticket = cache.take(origin, unix_now()).expect("cached");
+        let (client, server) =
+            resume_inproc_secure(MuxConfig::default(), &ticket, &issuer).unwrap();
+        let t = thread::spawn(move || {
+            let s = server.accept_stream(1000).expect("stream");
+            s.write(&s.read().expect("req"));
+            server
+        });
+        let st = client.open_stream();
+        st.write(b"resumed");
+        assert_eq!(st.read().expect("resp"), b"resumed");
+        let server = t.join().unwrap();
+
+        // Tickets are single use; the resumed session hands out fresh ones
+        assert!(matches!(
+            resume_inproc_secure(MuxConfig::default(), &ticket, &issuer),
+            Err(ResumeError::Replayed)
+        ));
+        server.send_ticket(&issuer);
+        let next = client.recv_ticket(1000).expect("ticket");
+        assert!(resume_inproc_secure(MuxConfig::default(), &next, &issuer).is_ok());
+        assert_eq!(cache.len(origin), 1);
+    }
+
     #[test]
     fn api_echo_e2e_compat() {
         let (client, server) = dial_inproc_secure_compat();
@@ -584,64 +1175,68 @@ mod tests {
         assert_eq!(got, msg);
         t.join().unwrap();
     }
-}
 
-fn spawn_socket_pump(
-    sock: TcpStream,
-    to_net_rx: mpsc::Receiver<Bytes>,
-    from_net_tx: mpsc::Sender<Bytes>,
-) {
-    sock.set_nodelay(true).ok();
-    let mut sock_r = sock.try_clone().expect("clone");
-    let mut sock_w = sock;
+    #[test]
+    fn api_record_larger_than_mux_chunk_roundtrips() {
+        // A single sealed record spans several mux chunks and must be reassembled
+        let (client, server) = dial_inproc_secure();
+        let t = thread::spawn(move || {
+            let s = server.accept_stream(1000).expect("accept");
+            let buf = s.read().expect("record");
+            s.write(&buf);
+        });
+        let st = client.open_stream();
+        let msg: Vec<u8> = (0..40_000u32).map(|i| i as u8).collect();
+        st.write(&msg);
+        assert_eq!(st.read().expect("resp"), msg);
+        t.join().unwrap();
+    }
 
-    // Writer: frames -> socket
-    let to_net_rx_w = to_net_rx;
-    let writer = thread::spawn(move || {
-        while let Ok(bytes) = to_net_rx_w.recv() {
-            if sock_w.write_all(&bytes).is_err() {
-                break;
-            }
-        }
-    });
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn api_async_copy_bidirectional_over_secure_stream() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
 
-    // Reader: socket -> frames
-    let reader = thread::spawn(move || {
-        let mut buf = Vec::<u8>::with_capacity(16 * 1024);
-        let mut tmp = [0u8; 4096];
-        loop {
-            match sock_r.read(&mut tmp) {
-                Ok(0) => break,
-                Ok(n) => {
-                    buf.extend_from_slice(&tmp[..n]);
-                    // parse frames if full
+        let (client, server) = dial_inproc_secure();
+        // Server side: echo every inner stream back using only async I/O
+        tokio::spawn(async move {
+            while let Some(mut ss) = server.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 4096];
                     loop {
-                        if buf.len() < 4 {
-                            break;
-                        }
-                        let len = ((buf[0] as usize) << 16)
-                            | ((buf[1] as usize) << 8)
-                            | (buf[2] as usize);
-                        // [Len(u24) | Type | payload] => total = 3 + len
-                        let total = 3 + len;
-                        if buf.len() < total {
-                            break;
-                        }
-                        let frame = Bytes::copy_from_slice(&buf[..total]);
-                        if from_net_tx.send(frame).is_err() {
-                            break;
+                        match AsyncReadExt::read(&mut ss, &mut buf).await {
+                            Ok(0) | Err(_) => break,
+                            Ok(n) => {
+                                if ss.write_all(&buf[..n]).await.is_err() {
+                                    break;
+                                }
+                                let _ = ss.flush().await;
+                            }
                         }
-                        buf.drain(..total);
                     }
-                }
-                Err(_) => break,
+                });
             }
-        }
-    });
+        });
 
-    // detach threads; they exit on channel close or socket close
-    let _ = writer.join();
-    let _ = reader.join();
+        // Proxy a local TCP socket straight into an HTX stream
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut ss = client.open_stream();
+            let _ = tokio::io::copy_bidirectional(&mut tcp, &mut ss).await;
+        });
+
+        let mut sock = TcpStream::connect(addr).await.unwrap();
+        let msg: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        sock.write_all(&msg).await.unwrap();
+        let mut got = vec![0u8; msg.len()];
+        tokio::time::timeout(Duration::from_secs(10), sock.read_exact(&mut got))
+            .await
+            .expect("echo timed out")
+            .unwrap();
+        assert_eq!(got, msg);
+    }
 }
 
 #[derive(Clone)]
@@ -659,6 +1254,12 @@ impl HtxConn {
     pub fn encryption_epoch(&self) -> u64 {
         self.mux.encryption_epoch()
     }
+    pub fn key_metrics(&self) -> KeyMetrics {
+        self.mux.key_metrics()
+    }
+    pub fn shutdown(&self, grace: Duration) -> bool {
+        self.mux.shutdown(grace)
+    }
 }
 
 pub struct HtxListener {
@@ -671,15 +1272,11 @@ impl HtxListener {
         let (acc_tx, acc_rx) = mpsc::channel();
         thread::spawn(move || {
             for stream in listener.incoming().flatten() {
-                // Build channel pair connecting socket to Mux
-                let (to_net_tx, to_net_rx) = mpsc::channel::<Bytes>();
-                let (from_net_tx, from_net_rx) = mpsc::channel::<Bytes>();
-                // Start socket pump
-                thread::spawn(move || spawn_socket_pump(stream, to_net_rx, from_net_tx));
-                // Create Mux using the channels
-                let mux = Mux::new(to_net_tx, from_net_rx);
-                let conn = HtxConn { mux };
-                let _ = acc_tx.send(conn);
+                let Ok(transport) = TcpTransport::new(stream) else {
+                    continue;
+                };
+                let mux = Mux::new(transport, MuxConfig::default().role(Role::Responder));
+                let _ = acc_tx.send(HtxConn { mux });
             }
         });
         Ok(HtxListener { incoming: acc_rx })
@@ -691,10 +1288,7 @@ impl HtxListener {
 }
 
 pub fn dial_socket<A: ToSocketAddrs>(addr: A) -> std::io::Result<HtxConn> {
-    let stream = TcpStream::connect(addr)?;
-    let (to_net_tx, to_net_rx) = mpsc::channel::<Bytes>();
-    let (from_net_tx, from_net_rx) = mpsc::channel::<Bytes>();
-    thread::spawn(move || spawn_socket_pump(stream, to_net_rx, from_net_tx));
-    let mux = Mux::new(to_net_tx, from_net_rx);
+    let transport = TcpTransport::new(TcpStream::connect(addr)?)?;
+    let mux = Mux::new(transport, MuxConfig::default());
     Ok(HtxConn { mux })
 }
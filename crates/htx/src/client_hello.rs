@@ -0,0 +1,426 @@
+//! ClientHello templates from `utls-gen`.
+//!
+//! `utls-gen` writes a browser's ClientHello handshake message (type, length
+//! and body) to `template_N.bin`. [`HelloTemplate::parse`] keeps everything
+//! that fingerprints the browser: cipher suite order, extension order and
+//! contents, GREASE positions and whether the hello is padded.
+//! [`HelloTemplate::template`] gives the [`Template`] summary that template IDs
+//! and inner keys are bound to, and [`crate::fingerprint`] computes JA3 and JA4
+//! from a parsed hello.
+//!
+//! Sending a template's bytes is out of scope. rustls writes its own ClientHello
+//! and has no hook for a caller-built one, so `dial` puts rustls's hello on the
+//! wire; byte-exact mimicry needs a TLS client that writes the hello itself.
+
+use crate::tls_mirror::Template;
+use std::path::Path;
+
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_SUPPORTED_GROUPS: u16 = 10;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+const EXT_ALPN: u16 = 16;
+const EXT_PADDING: u16 = 21;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+const EXT_KEY_SHARE: u16 = 51;
+
+#[derive(Debug, thiserror::Error)]
+pub enum HelloError {
+    #[error("ClientHello truncated")]
+    Truncated,
+    #[error("not a ClientHello handshake message")]
+    NotClientHello,
+    #[error("not a ServerHello handshake message")]
+    NotServerHello,
+    #[error("record is not a handshake record")]
+    NotHandshake,
+    #[error("bytes after the ClientHello")]
+    TrailingBytes,
+    #[error("malformed {0} extension")]
+    BadExtension(u16),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// RFC 8701 GREASE value (`0x?a?a` with both bytes equal).
+pub fn is_grease(v: u16) -> bool {
+    v & 0x0f0f == 0x0a0a && v >> 8 == v & 0xff
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Extension {
+    /// A single host_name; the name is [`HelloTemplate::server_name`].
+    ServerName,
+    /// BoringSSL-style padding; its length is not part of the fingerprint.
+    Padding,
+    /// Groups in order; the keys are per connection and not kept.
+    KeyShare(Vec<u16>),
+    Other(u16, Vec<u8>),
+}
+
+/// A parsed ClientHello without its per-connection values; see the module docs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelloTemplate {
+    legacy_version: u16,
+    cipher_suites: Vec<u16>,
+    // `None` when the hello has no extensions block at all
+    extensions: Option<Vec<Extension>>,
+    server_name: Option<String>,
+}
+
+impl HelloTemplate {
+    /// Parse a ClientHello handshake message as written by `utls-gen`.
+    pub fn parse(msg: &[u8]) -> Result<Self, HelloError> {
+        let mut r = Reader(msg);
+        if r.u8()? != HANDSHAKE_CLIENT_HELLO {
+            return Err(HelloError::NotClientHello);
+        }
+        let len = r.u24()?;
+        let mut body = Reader(r.take(len)?);
+        if !r.0.is_empty() {
+            return Err(HelloError::TrailingBytes);
+        }
+        let legacy_version = body.u16()?;
+        body.take(32)?; // random
+        let sid_len = body.u8()? as usize;
+        body.take(sid_len)?;
+        let cs_len = body.u16()? as usize;
+        let mut cs = Reader(body.take(cs_len)?);
+        let mut cipher_suites = Vec::with_capacity(cs_len / 2);
+        while !cs.0.is_empty() {
+            cipher_suites.push(cs.u16()?);
+        }
+        let comp_len = body.u8()? as usize;
+        body.take(comp_len)?;
+        let mut server_name = None;
+        let extensions = match body.0.is_empty() {
+            true => None,
+            false => {
+                let ext_len = body.u16()? as usize;
+                let mut exts = Reader(body.take(ext_len)?);
+                let mut out = Vec::new();
+                while !exts.0.is_empty() {
+                    let typ = exts.u16()?;
+                    let len = exts.u16()? as usize;
+                    let data = exts.take(len)?;
+                    out.push(parse_extension(typ, data, &mut server_name)?);
+                }
+                Some(out)
+            }
+        };
+        if !body.0.is_empty() {
+            return Err(HelloError::TrailingBytes);
+        }
+        Ok(Self {
+            legacy_version,
+            cipher_suites,
+            extensions,
+            server_name,
+        })
+    }
+
+    /// Read and parse a `utls-gen` blob.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HelloError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    /// `template_0.bin`, `template_1.bin`, ... from a `utls-gen` output directory,
+    /// in order, stopping at the first missing index.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<Self>, HelloError> {
+        let mut out = Vec::new();
+        loop {
+            let path = dir.as_ref().join(format!("template_{}.bin", out.len()));
+            if !path.exists() {
+                return Ok(out);
+            }
+            out.push(Self::load(path)?);
+        }
+    }
+
+    /// Whether the hello offers anything to connect with: blobs written before
+    /// `utls-gen` built the handshake state have no cipher suites or extensions.
+    pub fn is_usable(&self) -> bool {
+        !self.cipher_suites.is_empty() && self.extensions.is_some()
+    }
+
+    pub fn legacy_version(&self) -> u16 {
+        self.legacy_version
+    }
+
+    pub fn cipher_suites(&self) -> &[u16] {
+        &self.cipher_suites
+    }
+
+    /// Host name from the server_name extension.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Body of an extension kept verbatim (anything but SNI, padding and key_share).
+    pub(crate) fn extension_data(&self, typ: u16) -> Option<&[u8]> {
+        self.extensions.iter().flatten().find_map(|e| match e {
+            Extension::Other(t, data) if *t == typ => Some(data.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Extension types in wire order, GREASE included.
+    pub fn extension_types(&self) -> Vec<u16> {
+        self.extensions
+            .iter()
+            .flatten()
+            .map(|e| match e {
+                Extension::ServerName => EXT_SERVER_NAME,
+                Extension::Padding => EXT_PADDING,
+                Extension::KeyShare(_) => EXT_KEY_SHARE,
+                Extension::Other(t, _) => *t,
+            })
+            .collect()
+    }
+
+    /// Groups of the key_share entries, in order.
+    pub fn key_share_groups(&self) -> &[u16] {
+        self.extensions
+            .iter()
+            .flatten()
+            .find_map(|e| match e {
+                Extension::KeyShare(groups) => Some(groups.as_slice()),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
+
+    /// Contents of the extensions that are plain `u16` lists.
+    pub(crate) fn u16_list(&self, typ: u16, data: &[u8]) -> Option<Vec<u16>> {
+        let prefix = match typ {
+            EXT_SUPPORTED_GROUPS | EXT_SIGNATURE_ALGORITHMS => 2,
+            EXT_SUPPORTED_VERSIONS => 1,
+            _ => return None,
+        };
+        let list = data.get(prefix..)?;
+        (list.len() % 2 == 0).then(|| {
+            list.chunks(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect()
+        })
+    }
+
+    /// Summary for template IDs and the exporter binding: ALPN, signature
+    /// algorithms, groups and extension types, GREASE left out.
+    pub fn template(&self) -> Template {
+        let mut tpl = Template {
+            alpn: Vec::new(),
+            sig_algs: Vec::new(),
+            groups: Vec::new(),
+            extensions: Vec::new(),
+        };
+        for e in self.extensions.iter().flatten() {
+            if let Extension::Other(t, data) = e {
+                match *t {
+                    EXT_ALPN => tpl.alpn = parse_alpn(data).unwrap_or_default(),
+                    EXT_SIGNATURE_ALGORITHMS => {
+                        tpl.sig_algs = self.named(*t, data, sig_alg_name);
+                    }
+                    EXT_SUPPORTED_GROUPS => tpl.groups = self.named(*t, data, group_name),
+                    _ => {}
+                }
+            }
+        }
+        tpl.extensions = self
+            .extension_types()
+            .into_iter()
+            .filter(|&t| !is_grease(t))
+            .collect();
+        tpl
+    }
+
+    fn named(&self, typ: u16, data: &[u8], name: fn(u16) -> Option<&'static str>) -> Vec<String> {
+        self.u16_list(typ, data)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&v| !is_grease(v))
+            .map(|v| name(v).map_or_else(|| format!("0x{v:04x}"), str::to_string))
+            .collect()
+    }
+}
+
+fn parse_extension(
+    typ: u16,
+    data: &[u8],
+    server_name: &mut Option<String>,
+) -> Result<Extension, HelloError> {
+    let ext = match typ {
+        EXT_SERVER_NAME if !data.is_empty() => match parse_server_name(data) {
+            Some(name) => {
+                *server_name = Some(name);
+                Some(Extension::ServerName)
+            }
+            // Anything but a single host_name is kept verbatim
+            None => Some(Extension::Other(typ, data.to_vec())),
+        },
+        EXT_PADDING => Some(Extension::Padding),
+        EXT_KEY_SHARE => parse_key_share(data).map(Extension::KeyShare),
+        _ => Some(Extension::Other(typ, data.to_vec())),
+    };
+    ext.ok_or(HelloError::BadExtension(typ))
+}
+
+fn parse_server_name(data: &[u8]) -> Option<String> {
+    let mut r = Reader(data);
+    let len = r.u16().ok()? as usize;
+    let mut list = Reader(r.take(len).ok()?);
+    let kind = list.u8().ok()?;
+    let len = list.u16().ok()? as usize;
+    let name = list.take(len).ok()?;
+    if kind != 0 || !list.0.is_empty() || !r.0.is_empty() {
+        return None;
+    }
+    String::from_utf8(name.to_vec()).ok()
+}
+
+// Groups of the key_share entries; the keys themselves are skipped
+fn parse_key_share(data: &[u8]) -> Option<Vec<u16>> {
+    let mut r = Reader(data);
+    let len = r.u16().ok()? as usize;
+    let mut list = Reader(r.take(len).ok()?);
+    if !r.0.is_empty() {
+        return None;
+    }
+    let mut groups = Vec::new();
+    while !list.0.is_empty() {
+        groups.push(list.u16().ok()?);
+        let len = list.u16().ok()? as usize;
+        list.take(len).ok()?;
+    }
+    Some(groups)
+}
+
+pub(crate) fn parse_alpn(data: &[u8]) -> Option<Vec<String>> {
+    let mut r = Reader(data);
+    let len = r.u16().ok()? as usize;
+    let mut list = Reader(r.take(len).ok()?);
+    let mut out = Vec::new();
+    while !list.0.is_empty() {
+        let len = list.u8().ok()? as usize;
+        out.push(String::from_utf8_lossy(list.take(len).ok()?).into_owned());
+    }
+    Some(out)
+}
+
+fn sig_alg_name(v: u16) -> Option<&'static str> {
+    Some(match v {
+        0x0201 => "rsa_pkcs1_sha1",
+        0x0203 => "ecdsa_sha1",
+        0x0401 => "rsa_pkcs1_sha256",
+        0x0501 => "rsa_pkcs1_sha384",
+        0x0601 => "rsa_pkcs1_sha512",
+        0x0403 => "ecdsa_secp256r1_sha256",
+        0x0503 => "ecdsa_secp384r1_sha384",
+        0x0603 => "ecdsa_secp521r1_sha512",
+        0x0804 => "rsa_pss_rsae_sha256",
+        0x0805 => "rsa_pss_rsae_sha384",
+        0x0806 => "rsa_pss_rsae_sha512",
+        0x0807 => "ed25519",
+        0x0808 => "ed448",
+        _ => return None,
+    })
+}
+
+fn group_name(v: u16) -> Option<&'static str> {
+    Some(match v {
+        0x0017 => "secp256r1",
+        0x0018 => "secp384r1",
+        0x0019 => "secp521r1",
+        0x001d => "x25519",
+        0x001e => "x448",
+        0x0100 => "ffdhe2048",
+        0x0101 => "ffdhe3072",
+        0x11ec => "x25519mlkem768",
+        0x6399 => "x25519kyber768draft00",
+        _ => return None,
+    })
+}
+
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], HelloError> {
+        if self.0.len() < n {
+            return Err(HelloError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, HelloError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, HelloError> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u24(&mut self) -> Result<usize, HelloError> {
+        let b = self.take(3)?;
+        Ok(usize::from(b[0]) << 16 | usize::from(b[1]) << 8 | usize::from(b[2]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hand-assembled in Chrome's pre-permutation layout: GREASE cipher and
+    // extensions, GREASE group shared by supported_groups and key_share, and
+    // BoringSSL padding up to 512 bytes
+    const CHROMELIKE: &[u8] = include_bytes!("../vectors/client_hello_chromelike.bin");
+
+    #[test]
+    fn parses_a_chromelike_hello() {
+        let tpl = HelloTemplate::parse(CHROMELIKE).unwrap();
+        assert!(tpl.is_usable());
+        assert_eq!(tpl.cipher_suites()[..2], [0x8a8a, 0x1301]);
+        assert_eq!(tpl.extension_types()[..3], [0x0a0a, 0, 23]);
+        assert_eq!(*tpl.extension_types().last().unwrap(), EXT_PADDING);
+        assert!(is_grease(tpl.key_share_groups()[0]));
+        let summary = tpl.template();
+        assert_eq!(summary.alpn, ["h2", "http/1.1"]);
+        assert_eq!(summary.groups, ["x25519", "secp256r1", "secp384r1"]);
+        assert!(!summary.extensions.iter().any(|&t| is_grease(t)));
+    }
+
+    #[test]
+    fn utls_gen_output_is_usable() {
+        // Whatever `utls-gen generate` left in its directory must be a built hello
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../utls-gen");
+        for (i, tpl) in HelloTemplate::load_dir(dir).unwrap().iter().enumerate() {
+            assert!(tpl.is_usable(), "template_{i}.bin has no cipher suites");
+            let types = tpl.extension_types();
+            assert!(types.contains(&EXT_SUPPORTED_VERSIONS) && types.contains(&EXT_KEY_SHARE));
+            assert!(!tpl.key_share_groups().is_empty());
+            assert!(!tpl.template().alpn.is_empty());
+        }
+    }
+
+    #[test]
+    fn malformed_hellos_are_rejected() {
+        assert!(matches!(
+            HelloTemplate::parse(&CHROMELIKE[..100]),
+            Err(HelloError::Truncated)
+        ));
+        let mut server_hello = CHROMELIKE.to_vec();
+        server_hello[0] = 2;
+        assert!(matches!(
+            HelloTemplate::parse(&server_hello),
+            Err(HelloError::NotClientHello)
+        ));
+        let mut trailing = CHROMELIKE.to_vec();
+        trailing.push(0);
+        assert!(matches!(
+            HelloTemplate::parse(&trailing),
+            Err(HelloError::TrailingBytes)
+        ));
+    }
+}
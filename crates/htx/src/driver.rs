@@ -0,0 +1,311 @@
+//! Run a [`Handshake`] over a transport with a deadline.
+//!
+//! Handshake messages travel as `[Len(u16 BE) | message]`, as in the Noise spec.
+//! [`drive`] works over anything implementing [`HandshakeIo`]: TCP streams and
+//! in-process channel pairs are provided here. [`Client`] and [`Server`] combine
+//! it with a TCP connect or accept.
+
+use crate::{Handshake, HandshakeError, HandshakeParams};
+use curve25519_dalek::scalar::Scalar;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Time allowed for connect plus handshake unless set otherwise.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A carrier of whole handshake messages.
+pub trait HandshakeIo {
+    fn send(&mut self, msg: &[u8], deadline: Instant) -> io::Result<()>;
+    /// Next whole message. Fails with `TimedOut` once `deadline` passes.
+    fn recv(&mut self, deadline: Instant) -> io::Result<Vec<u8>>;
+}
+
+/// Run `hs` to completion over `io`, failing with [`HandshakeError::Timeout`] if it
+/// is not done by `deadline`. The caller reads the keys from `hs` afterwards.
+pub fn drive<T: HandshakeIo + ?Sized>(
+    hs: &mut Handshake,
+    io: &mut T,
+    deadline: Instant,
+) -> Result<(), HandshakeError> {
+    if hs.is_my_turn() {
+        let m1 = hs.next(None)?.expect("initiator sends first");
+        io.send(&m1, deadline).map_err(io_error)?;
+    }
+    while !hs.is_done() {
+        let msg = io.recv(deadline).map_err(io_error)?;
+        if let Some(out) = hs.next(Some(&msg))? {
+            io.send(&out, deadline).map_err(io_error)?;
+        }
+    }
+    Ok(())
+}
+
+fn io_error(e: io::Error) -> HandshakeError {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => HandshakeError::Timeout,
+        kind => HandshakeError::Io(kind),
+    }
+}
+
+fn time_left(deadline: Instant) -> io::Result<Duration> {
+    match deadline.saturating_duration_since(Instant::now()) {
+        left if left.is_zero() => Err(io::ErrorKind::TimedOut.into()),
+        left => Ok(left),
+    }
+}
+
+impl HandshakeIo for TcpStream {
+    fn send(&mut self, msg: &[u8], deadline: Instant) -> io::Result<()> {
+        let len = u16::try_from(msg.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "handshake message too long")
+        })?;
+        let mut buf = Vec::with_capacity(2 + msg.len());
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.extend_from_slice(msg);
+        self.set_write_timeout(Some(time_left(deadline)?))?;
+        let res = self.write_all(&buf);
+        self.set_write_timeout(None)?;
+        res
+    }
+
+    fn recv(&mut self, deadline: Instant) -> io::Result<Vec<u8>> {
+        let mut len = [0u8; 2];
+        read_full(self, &mut len, deadline)?;
+        let mut msg = vec![0u8; u16::from_be_bytes(len) as usize];
+        read_full(self, &mut msg, deadline)?;
+        Ok(msg)
+    }
+}
+
+// read_exact that gives up at the deadline; the stream is left without a timeout
+fn read_full(s: &mut TcpStream, buf: &mut [u8], deadline: Instant) -> io::Result<()> {
+    let mut got = 0;
+    let res = loop {
+        if got == buf.len() {
+            break Ok(());
+        }
+        if let Err(e) = time_left(deadline).and_then(|left| s.set_read_timeout(Some(left))) {
+            break Err(e);
+        }
+        match s.read(&mut buf[got..]) {
+            Ok(0) => break Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => got += n,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    s.set_read_timeout(None)?;
+    res
+}
+
+/// One end of an in-process message pipe; see [`channel_pair`].
+pub struct ChannelIo {
+    tx: mpsc::Sender<Vec<u8>>,
+    rx: mpsc::Receiver<Vec<u8>>,
+}
+
+/// Two connected [`ChannelIo`] ends, for handshakes carried by something other than
+/// a socket (and for tests).
+pub fn channel_pair() -> (ChannelIo, ChannelIo) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    (
+        ChannelIo { tx: a_tx, rx: a_rx },
+        ChannelIo { tx: b_tx, rx: b_rx },
+    )
+}
+
+impl HandshakeIo for ChannelIo {
+    fn send(&mut self, msg: &[u8], _deadline: Instant) -> io::Result<()> {
+        self.tx
+            .send(msg.to_vec())
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn recv(&mut self, deadline: Instant) -> io::Result<Vec<u8>> {
+        self.rx
+            .recv_timeout(time_left(deadline)?)
+            .map_err(|e| match e {
+                mpsc::RecvTimeoutError::Timeout => io::ErrorKind::TimedOut.into(),
+                mpsc::RecvTimeoutError::Disconnected => io::ErrorKind::UnexpectedEof.into(),
+            })
+    }
+}
+
+/// Initiator end of an HTX handshake over TCP.
+pub struct Client {
+    params: HandshakeParams,
+    si: Scalar,
+    rs: Option<[u8; 32]>,
+    timeout: Duration,
+}
+
+impl Client {
+    /// `rs` is the responder's static key, required when the pattern pins it.
+    pub fn new(params: HandshakeParams, si: Scalar, rs: Option<[u8; 32]>) -> Self {
+        Self {
+            params,
+            si,
+            rs,
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+
+    /// Deadline for connect plus handshake (default [`DEFAULT_HANDSHAKE_TIMEOUT`]).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Connect to `addr` and complete the handshake before the timeout.
+    pub fn dial<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+    ) -> Result<(TcpStream, Handshake), HandshakeError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut hs = Handshake::initiator(self.params, self.si, self.rs)?;
+        let mut last = HandshakeError::Io(io::ErrorKind::AddrNotAvailable);
+        for addr in addr.to_socket_addrs().map_err(io_error)? {
+            let left = time_left(deadline).map_err(io_error)?;
+            match TcpStream::connect_timeout(&addr, left) {
+                Ok(mut stream) => {
+                    stream.set_nodelay(true).map_err(io_error)?;
+                    drive(&mut hs, &mut stream, deadline)?;
+                    return Ok((stream, hs));
+                }
+                Err(e) => last = io_error(e),
+            }
+        }
+        Err(last)
+    }
+}
+
+/// Responder end of an HTX handshake over TCP.
+pub struct Server {
+    params: HandshakeParams,
+    sr: Scalar,
+    timeout: Duration,
+}
+
+impl Server {
+    pub fn new(params: HandshakeParams, sr: Scalar) -> Self {
+        Self {
+            params,
+            sr,
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+
+    /// Time a connection gets to finish the handshake (default [`DEFAULT_HANDSHAKE_TIMEOUT`]).
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Accept one connection on `listener` and complete the handshake. The timeout
+    /// starts once the connection arrives.
+    pub fn accept(&self, listener: &TcpListener) -> Result<(TcpStream, Handshake), HandshakeError> {
+        let (stream, _peer) = listener.accept().map_err(io_error)?;
+        self.handshake(stream)
+    }
+
+    /// Run the responder side on an accepted stream.
+    pub fn handshake(
+        &self,
+        mut stream: TcpStream,
+    ) -> Result<(TcpStream, Handshake), HandshakeError> {
+        let deadline = Instant::now() + self.timeout;
+        stream.set_nodelay(true).map_err(io_error)?;
+        let mut hs = Handshake::responder(self.params, self.sr);
+        drive(&mut hs, &mut stream, deadline)?;
+        Ok((stream, hs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pattern;
+    use curve25519_dalek::constants::X25519_BASEPOINT;
+    use std::thread;
+
+    fn keys() -> (Scalar, Scalar, [u8; 32]) {
+        let si = Scalar::from_bytes_mod_order([1u8; 32]);
+        let sr = Scalar::from_bytes_mod_order([2u8; 32]);
+        (si, sr, (sr * X25519_BASEPOINT).to_bytes())
+    }
+
+    #[test]
+    fn client_and_server_agree_over_tcp() {
+        let (si, sr, rs) = keys();
+        for params in [
+            HandshakeParams::new(Pattern::Xk),
+            HandshakeParams::new(Pattern::Ik).hybrid(),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let t = thread::spawn(move || Server::new(params, sr).accept(&listener));
+            let (_c, init) = Client::new(params, si, Some(rs)).dial(addr).unwrap();
+            let (_s, resp) = t.join().unwrap().unwrap();
+            let (tx, rx) = init.transport_keys().unwrap();
+            assert_eq!(resp.transport_keys(), Some((rx, tx)));
+        }
+    }
+
+    #[test]
+    fn silent_peer_times_out() {
+        let (si, _, rs) = keys();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Accepts but never answers
+        let t = thread::spawn(move || listener.accept().map(|(s, _)| s));
+        let start = Instant::now();
+        let res = Client::new(HandshakeParams::default(), si, Some(rs))
+            .timeout(Duration::from_millis(200))
+            .dial(addr);
+        assert!(matches!(res, Err(HandshakeError::Timeout)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        drop(t.join().unwrap());
+
+        // Same over channels, and a vanished peer is reported as such
+        let (_si, sr, _) = keys();
+        let (mut a, b) = channel_pair();
+        let mut resp = Handshake::responder(HandshakeParams::default(), sr);
+        let soon = Instant::now() + Duration::from_millis(50);
+        assert_eq!(drive(&mut resp, &mut a, soon), Err(HandshakeError::Timeout));
+        drop(b);
+        let mut resp = Handshake::responder(HandshakeParams::default(), sr);
+        let later = Instant::now() + Duration::from_secs(1);
+        assert_eq!(
+            drive(&mut resp, &mut a, later),
+            Err(HandshakeError::Io(io::ErrorKind::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn pattern_mismatch_is_reported_by_the_driver() {
+        let (si, sr, rs) = keys();
+        let (mut a, mut b) = channel_pair();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let t = thread::spawn(move || {
+            let mut resp = Handshake::responder(HandshakeParams::new(Pattern::Xk), sr);
+            drive(&mut resp, &mut b, deadline)
+        });
+        let mut init =
+            Handshake::initiator(HandshakeParams::new(Pattern::Ik), si, Some(rs)).unwrap();
+        let _ = drive(
+            &mut init,
+            &mut a,
+            Instant::now() + Duration::from_millis(200),
+        );
+        assert_eq!(t.join().unwrap(), Err(HandshakeError::PatternMismatch));
+    }
+}
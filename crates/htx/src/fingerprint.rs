@@ -0,0 +1,399 @@
+//! JA3, JA3S and JA4 fingerprints of real hellos.
+//!
+//! Fingerprints are computed from the bytes a peer sent, either whole TLS
+//! records or a bare handshake message. GREASE values are left out, as every
+//! fingerprinting tool does. [`capture_client_hello`] records what a dialer
+//! puts on the wire by pointing it at a loopback listener, so what we claim
+//! to send can be checked against what we do send.
+
+use crate::client_hello::{is_grease, parse_alpn, HelloError, HelloTemplate, Reader};
+use ring::digest;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONTENT_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_SERVER_HELLO: u8 = 2;
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_SUPPORTED_GROUPS: u16 = 10;
+const EXT_POINT_FORMATS: u16 = 11;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+const EXT_ALPN: u16 = 16;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+// How long a capture waits for the dialer to send its hello
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCEPT_POLL: Duration = Duration::from_millis(10);
+
+/// Fingerprints of a ClientHello.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientFingerprint {
+    /// `version,ciphers,extensions,groups,point_formats`, the input to [`Self::ja3`].
+    pub ja3_string: String,
+    /// MD5 of [`Self::ja3_string`], in hex.
+    pub ja3: String,
+    pub ja4: String,
+}
+
+impl ClientFingerprint {
+    /// Fingerprint a ClientHello given as TLS records or as a handshake message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HelloError> {
+        Ok(Self::of(&HelloTemplate::parse(&handshake_message(bytes)?)?))
+    }
+
+    pub fn of(hello: &HelloTemplate) -> Self {
+        let ciphers = strip_grease(hello.cipher_suites());
+        let extensions = strip_grease(&hello.extension_types());
+        let groups = hello
+            .extension_data(EXT_SUPPORTED_GROUPS)
+            .and_then(|d| hello.u16_list(EXT_SUPPORTED_GROUPS, d))
+            .map(|g| strip_grease(&g))
+            .unwrap_or_default();
+        let point_formats = hello
+            .extension_data(EXT_POINT_FORMATS)
+            .and_then(|d| d.get(1..))
+            .unwrap_or_default();
+        let ja3_string = format!(
+            "{},{},{},{},{}",
+            hello.legacy_version(),
+            decimal_list(&ciphers),
+            decimal_list(&extensions),
+            decimal_list(&groups),
+            point_formats
+                .iter()
+                .map(u8::to_string)
+                .collect::<Vec<_>>()
+                .join("-"),
+        );
+        let ja3 = format!("{:x}", md5::compute(&ja3_string));
+        let ja4 = ja4(hello, &ciphers, &extensions);
+        Self {
+            ja3_string,
+            ja3,
+            ja4,
+        }
+    }
+}
+
+// t, TLS version, SNI, cipher and extension counts, ALPN; then the sorted
+// cipher hash; then the sorted extension hash with signature algorithms in
+// wire order. SNI and ALPN count as extensions but are not hashed.
+fn ja4(hello: &HelloTemplate, ciphers: &[u16], extensions: &[u16]) -> String {
+    let version = hello
+        .extension_data(EXT_SUPPORTED_VERSIONS)
+        .and_then(|d| hello.u16_list(EXT_SUPPORTED_VERSIONS, d))
+        .and_then(|v| strip_grease(&v).into_iter().max())
+        .unwrap_or(hello.legacy_version());
+    let version = match version {
+        0x0304 => "13",
+        0x0303 => "12",
+        0x0302 => "11",
+        0x0301 => "10",
+        0x0300 => "s3",
+        0x0002 => "s2",
+        _ => "00",
+    };
+    let sni = match extensions.contains(&EXT_SERVER_NAME) {
+        true => 'd',
+        false => 'i',
+    };
+    let alpn = hello
+        .extension_data(EXT_ALPN)
+        .and_then(parse_alpn)
+        .and_then(|a| a.into_iter().next())
+        .map_or_else(|| "00".to_string(), |a| alpn_chars(a.as_bytes()));
+
+    let mut sorted_ciphers = ciphers.to_vec();
+    sorted_ciphers.sort_unstable();
+    let mut hashed_exts: Vec<u16> = extensions
+        .iter()
+        .copied()
+        .filter(|&e| e != EXT_SERVER_NAME && e != EXT_ALPN)
+        .collect();
+    hashed_exts.sort_unstable();
+    let sig_algs = hello
+        .extension_data(EXT_SIGNATURE_ALGORITHMS)
+        .and_then(|d| hello.u16_list(EXT_SIGNATURE_ALGORITHMS, d))
+        .map(|s| strip_grease(&s))
+        .unwrap_or_default();
+    let mut ext_input = hex_list(&hashed_exts);
+    if !sig_algs.is_empty() {
+        ext_input = format!("{ext_input}_{}", hex_list(&sig_algs));
+    }
+    format!(
+        "t{version}{sni}{:02}{:02}{alpn}_{}_{}",
+        ciphers.len().min(99),
+        extensions.len().min(99),
+        truncated_sha256(&hex_list(&sorted_ciphers)),
+        truncated_sha256(&ext_input),
+    )
+}
+
+// First and last character of the first ALPN value, or of its hex form when
+// either is not alphanumeric
+fn alpn_chars(a: &[u8]) -> String {
+    match (a.first(), a.last()) {
+        (Some(&f), Some(&l)) if f.is_ascii_alphanumeric() && l.is_ascii_alphanumeric() => {
+            format!("{}{}", f as char, l as char)
+        }
+        (Some(_), Some(_)) => {
+            let h = hex::encode(a);
+            format!("{}{}", &h[..1], &h[h.len() - 1..])
+        }
+        _ => "00".to_string(),
+    }
+}
+
+/// Fingerprints of a ServerHello.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerFingerprint {
+    /// `version,cipher,extensions`, the input to [`Self::ja3s`].
+    pub ja3s_string: String,
+    /// MD5 of [`Self::ja3s_string`], in hex.
+    pub ja3s: String,
+}
+
+impl ServerFingerprint {
+    /// Fingerprint a ServerHello given as TLS records or as a handshake message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HelloError> {
+        let msg = handshake_message(bytes)?;
+        let mut r = Reader(&msg);
+        if r.u8()? != HANDSHAKE_SERVER_HELLO {
+            return Err(HelloError::NotServerHello);
+        }
+        let len = r.u24()?;
+        let mut body = Reader(r.take(len)?);
+        let version = body.u16()?;
+        body.take(32)?;
+        let sid_len = body.u8()? as usize;
+        body.take(sid_len)?;
+        let cipher = body.u16()?;
+        body.u8()?;
+        let mut extensions = Vec::new();
+        if !body.0.is_empty() {
+            let ext_len = body.u16()? as usize;
+            let mut exts = Reader(body.take(ext_len)?);
+            while !exts.0.is_empty() {
+                extensions.push(exts.u16()?);
+                let len = exts.u16()? as usize;
+                exts.take(len)?;
+            }
+        }
+        if !body.0.is_empty() {
+            return Err(HelloError::TrailingBytes);
+        }
+        let ja3s_string = format!(
+            "{version},{cipher},{}",
+            decimal_list(&strip_grease(&extensions))
+        );
+        let ja3s = format!("{:x}", md5::compute(&ja3s_string));
+        Ok(Self { ja3s_string, ja3s })
+    }
+}
+
+/// The first handshake message in `bytes`: reassembled from handshake records
+/// when `bytes` starts with one, else `bytes` itself.
+pub fn handshake_message(bytes: &[u8]) -> Result<Vec<u8>, HelloError> {
+    if bytes.first() != Some(&CONTENT_HANDSHAKE) {
+        return Ok(bytes.to_vec());
+    }
+    let mut r = Reader(bytes);
+    let mut msg = Vec::new();
+    loop {
+        if msg.len() >= 4 {
+            let len =
+                4 + (usize::from(msg[1]) << 16 | usize::from(msg[2]) << 8 | usize::from(msg[3]));
+            if msg.len() >= len {
+                msg.truncate(len);
+                return Ok(msg);
+            }
+        }
+        if r.u8()? != CONTENT_HANDSHAKE {
+            return Err(HelloError::NotHandshake);
+        }
+        r.u16()?;
+        let len = r.u16()? as usize;
+        msg.extend_from_slice(r.take(len)?);
+    }
+}
+
+/// Run `dial` against a loopback listener and return the TLS records it sends
+/// up to the end of its ClientHello. The connection is then dropped, so the
+/// dialer sees its handshake fail. Fails with `TimedOut` if no hello arrives
+/// within 5 s, and with `NotConnected` if `dial` returns without connecting.
+pub fn capture_client_hello<F>(dial: F) -> io::Result<Vec<u8>>
+where
+    F: FnOnce(SocketAddr) + Send + 'static,
+{
+    let deadline = Instant::now() + CAPTURE_TIMEOUT;
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    listener.set_nonblocking(true)?;
+    let addr = listener.local_addr()?;
+    let dialer = thread::spawn(move || dial(addr));
+    // A dialer stuck before connecting is left behind rather than joined
+    let mut tcp = loop {
+        match listener.accept() {
+            Ok((tcp, _)) => break tcp,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        if dialer.is_finished() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        if Instant::now() >= deadline {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        thread::sleep(ACCEPT_POLL);
+    };
+    tcp.set_nonblocking(false)?;
+    let left = deadline.saturating_duration_since(Instant::now());
+    tcp.set_read_timeout(Some(left.max(ACCEPT_POLL)))?;
+    let mut got = Vec::new();
+    let mut buf = [0u8; 4096];
+    let res = loop {
+        match handshake_message(&got) {
+            Ok(_) if !got.is_empty() => break Ok(got),
+            Err(HelloError::Truncated) | Ok(_) => {}
+            Err(e) => break Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+        match tcp.read(&mut buf) {
+            Ok(0) => break Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => got.extend_from_slice(&buf[..n]),
+            Err(e) => break Err(e),
+        }
+    };
+    drop(tcp);
+    let _ = dialer.join();
+    res
+}
+
+fn strip_grease(v: &[u16]) -> Vec<u16> {
+    v.iter().copied().filter(|&x| !is_grease(x)).collect()
+}
+
+fn decimal_list(v: &[u16]) -> String {
+    v.iter().map(u16::to_string).collect::<Vec<_>>().join("-")
+}
+
+fn hex_list(v: &[u16]) -> String {
+    v.iter()
+        .map(|x| format!("{x:04x}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// First 12 hex characters of SHA-256, or zeros for an empty list
+fn truncated_sha256(s: &str) -> String {
+    if s.is_empty() {
+        return "0".repeat(12);
+    }
+    hex::encode(digest::digest(&digest::SHA256, s.as_bytes()))[..12].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHROMELIKE: &[u8] = include_bytes!("../vectors/client_hello_chromelike.bin");
+
+    // `msg` split over handshake records of at most `chunk` bytes
+    fn records(msg: &[u8], chunk: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for c in msg.chunks(chunk) {
+            out.extend_from_slice(&[CONTENT_HANDSHAKE, 3, 1]);
+            out.extend_from_slice(&(c.len() as u16).to_be_bytes());
+            out.extend_from_slice(c);
+        }
+        out
+    }
+
+    #[test]
+    fn chromelike_hello_has_chromes_fingerprints() {
+        let fp = ClientFingerprint::from_bytes(CHROMELIKE).unwrap();
+        // Chrome's published JA3, and the JA4 of the Chrome example in the JA4 spec
+        assert_eq!(fp.ja3, "cd08e31494f9531f560d64c695473da9");
+        assert_eq!(fp.ja4, "t13d1516h2_8daaf6152771_e5627efa2ab1");
+        assert!(fp.ja3_string.ends_with(",29-23-24,0"));
+        // One record or several, the hello is the same
+        assert_eq!(
+            ClientFingerprint::from_bytes(&records(CHROMELIKE, 16384)).unwrap(),
+            fp
+        );
+        let split = records(CHROMELIKE, 100);
+        assert_eq!(ClientFingerprint::from_bytes(&split).unwrap(), fp);
+        assert!(matches!(
+            ClientFingerprint::from_bytes(&split[..300]),
+            Err(HelloError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn ja3s_of_a_server_hello() {
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&[7; 32]);
+        body.push(0);
+        body.extend_from_slice(&[0xc0, 0x2f, 0]);
+        let exts: &[u8] = &[
+            0xff, 0x01, 0, 1, 0, // renegotiation_info
+            0x00, 0x0b, 0, 2, 1, 0, // ec_point_formats
+            0x0a, 0x0a, 0, 0, // GREASE
+            0x00, 0x23, 0, 0, // session_ticket
+        ];
+        body.extend_from_slice(&(exts.len() as u16).to_be_bytes());
+        body.extend_from_slice(exts);
+        let mut msg = vec![HANDSHAKE_SERVER_HELLO, 0];
+        msg.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&body);
+
+        let fp = ServerFingerprint::from_bytes(&records(&msg, 16384)).unwrap();
+        assert_eq!(fp.ja3s_string, "771,49199,65281-11-35");
+        assert_eq!(fp.ja3s, "ccc514751b175866924439bdbb5bba34");
+        assert_eq!(ServerFingerprint::from_bytes(&msg).unwrap(), fp);
+        assert!(matches!(
+            ServerFingerprint::from_bytes(CHROMELIKE),
+            Err(HelloError::NotServerHello)
+        ));
+    }
+
+    #[test]
+    fn capture_gives_up_on_a_dialer_that_never_connects() {
+        let start = Instant::now();
+        let err = capture_client_hello(|_| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        let err = capture_client_hello(|_| thread::sleep(Duration::from_secs(30))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < CAPTURE_TIMEOUT + Duration::from_secs(2));
+    }
+
+    #[cfg(feature = "rustls-config")]
+    #[test]
+    fn dialer_sends_the_template_fingerprint() {
+        use crate::tls_mirror::{build_client_hello, dialer_fingerprint, Template};
+
+        let tpl = Template {
+            alpn: vec!["http/1.1".into()],
+            sig_algs: vec!["ecdsa_secp256r1_sha256".into()],
+            groups: vec!["secp384r1".into(), "x25519".into()],
+            extensions: vec![0, 10, 11, 16, 43, 51],
+        };
+        let client = build_client_hello(&tpl);
+        let claimed = (client.ja3.clone(), client.ja4.clone());
+        let wire = capture_client_hello(move |addr| {
+            let _ = crate::api::tls_connect(&client, "edge.example.com", addr);
+        })
+        .unwrap();
+
+        let fp = ClientFingerprint::from_bytes(&wire).unwrap();
+        assert_eq!((fp.ja3.clone(), fp.ja4.clone()), claimed);
+        assert_eq!(fp, dialer_fingerprint(&tpl));
+        assert_eq!(&fp.ja4[..4], "t13d");
+        assert_eq!(&fp.ja4[8..10], "h1");
+        // The groups and ALPN the template asks for, in its order
+        let hello = HelloTemplate::parse(&handshake_message(&wire).unwrap()).unwrap();
+        assert_eq!(hello.server_name(), Some("edge.example.com"));
+        let seen = hello.template();
+        assert_eq!(seen.alpn, tpl.alpn);
+        assert_eq!(seen.groups, tpl.groups);
+        assert_eq!(hello.key_share_groups(), [0x0018]);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::mux::RecordLayout;
+use crate::resume::ResumedKeys;
 use crate::tls_mirror::Template;
-use crate::Handshake;
+use crate::{Handshake, HandshakeParams, Pattern};
 use core_cbor as cbor;
 use core_crypto as crypto;
 use serde::{Deserialize, Serialize};
@@ -8,6 +10,8 @@ use serde::{Deserialize, Serialize};
 pub enum Error {
     Exporter,
     NotReady,
+    /// The handshake ran a different Noise pattern than the caps select.
+    Pattern,
 }
 
 // Minimal capabilities for binding; extend as needed.
@@ -16,6 +20,124 @@ pub struct Caps {
     pub features: Vec<String>,
 }
 
+// Feature carrying the hidden record size, e.g. "hidden-records=1024"
+const HIDDEN_RECORDS: &str = "hidden-records=";
+// Feature naming the Noise pattern, e.g. "noise=IKpsk2"
+const NOISE: &str = "noise=";
+// Offer flag: a client holding a ticket asks to resume instead of a full handshake
+const RESUME: &str = "resume";
+// Largest caps message a peer may send in the exchange
+const MAX_CAPS_LEN: usize = 4096;
+
+impl Caps {
+    /// Advertise `layout` for the inner mux. The secure dial/accept paths exchange
+    /// caps before the mux starts and fail with [`crate::ApiError::LayoutMismatch`]
+    /// when the ends picked different layouts. Caps are also part of the key
+    /// binding, so a peer that skips that check still never gets a frame misparsed.
+    /// The clear layout adds nothing, keeping default caps (and keys) unchanged.
+    pub fn with_record_layout(mut self, layout: RecordLayout) -> Self {
+        self.features.retain(|f| !f.starts_with(HIDDEN_RECORDS));
+        if let RecordLayout::Hidden { record_size } = layout {
+            self.features.push(format!("{HIDDEN_RECORDS}{record_size}"));
+        }
+        self
+    }
+
+    /// The record layout these caps select (clear unless a hidden size is present).
+    pub fn record_layout(&self) -> RecordLayout {
+        self.features
+            .iter()
+            .find_map(|f| f.strip_prefix(HIDDEN_RECORDS)?.parse().ok())
+            .map(RecordLayout::hidden)
+            .unwrap_or_default()
+    }
+
+    /// Select the Noise pattern and modifiers (for `psk2` only that one is used; the
+    /// key itself never leaves the peers). Plain XK adds nothing, like the clear
+    /// record layout.
+    pub fn with_handshake(mut self, params: &HandshakeParams) -> Self {
+        self.features.retain(|f| !f.starts_with(NOISE));
+        if params.name() != Pattern::Xk.as_str() {
+            self.features.push(format!("{NOISE}{}", params.name()));
+        }
+        self
+    }
+
+    /// Ask for a Noise handshake inside the outer TLS. Unlike [`Caps::with_handshake`]
+    /// this names plain XK too: caps without a pattern tell the secure dial/accept
+    /// paths to key from the TLS exporter alone.
+    pub fn with_inner_handshake(self, params: &HandshakeParams) -> Self {
+        let mut caps = self.with_handshake(params);
+        if caps.handshake_name().is_none() {
+            caps.features.push(format!("{NOISE}{}", params.name()));
+        }
+        caps
+    }
+
+    /// The Noise pattern these caps name, e.g. `IKpsk2`; `None` for none at all.
+    pub fn handshake_name(&self) -> Option<&str> {
+        self.features.iter().find_map(|f| f.strip_prefix(NOISE))
+    }
+
+    /// Handshake parameters these caps select, completed with `psk`. `None` when the
+    /// caps name an unknown pattern or ask for `psk2` and no key was given.
+    pub fn handshake_params(&self, psk: Option<[u8; 32]>) -> Option<HandshakeParams> {
+        let (params, wants_psk) = match self.handshake_name() {
+            Some(name) => crate::noise::parse_name(name)?,
+            None => (HandshakeParams::new(Pattern::Xk), false),
+        };
+        match (wants_psk, psk) {
+            (true, Some(psk)) => Some(params.psk2(psk)),
+            (true, None) => None,
+            (false, _) => Some(params),
+        }
+    }
+
+    /// Offer (client) or agree (edge) to resume from a ticket in the caps exchange.
+    /// Only the exchanged caps carry it; keys are bound to the caps without it.
+    pub fn with_resume(mut self) -> Self {
+        if !self.resumes() {
+            self.features.push(RESUME.into());
+        }
+        self
+    }
+
+    pub fn resumes(&self) -> bool {
+        self.features.iter().any(|f| f == RESUME)
+    }
+
+    /// Send these caps as a u16 big-endian length followed by det-CBOR.
+    pub fn write_to(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        let body = cbor::to_det_cbor(self)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        if body.len() > MAX_CAPS_LEN {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+        w.write_all(&(body.len() as u16).to_be_bytes())?;
+        w.write_all(&body)?;
+        w.flush()
+    }
+
+    /// Read caps written by [`Caps::write_to`]; oversized or malformed ones are `InvalidData`.
+    pub fn read_from(r: &mut impl std::io::Read) -> std::io::Result<Self> {
+        let mut len = [0u8; 2];
+        r.read_exact(&mut len)?;
+        let len = u16::from_be_bytes(len) as usize;
+        if len > MAX_CAPS_LEN {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        let mut body = vec![0u8; len];
+        r.read_exact(&mut body)?;
+        cbor::from_det_cbor(&body)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    // Whether `hs` ran the pattern these caps select
+    fn matches(&self, hs: &Handshake) -> bool {
+        self.handshake_params(hs.params().psk).as_ref() == Some(hs.params())
+    }
+}
+
 // TLS exporter abstraction so we can bind secrets to the outer TLS.
 pub trait Exporter {
     fn export(&self, label: &[u8], context: &[u8], len: usize) -> Result<Vec<u8>, Error>;
@@ -41,6 +163,8 @@ impl TlsStream {
 pub struct InnerConn {
     pub tx_key: [u8; 32],
     pub rx_key: [u8; 32],
+    /// Same on both ends; keys resumption tickets for this session (see [`crate::resume`]).
+    pub resumption: [u8; 32],
 }
 
 #[derive(Serialize)]
@@ -90,11 +214,19 @@ pub fn open_inner(
     hs: &Handshake,
 ) -> Result<InnerConn, Error> {
     let (base_tx, base_rx) = hs.transport_keys().ok_or(Error::NotReady)?;
+    if !caps.matches(hs) {
+        return Err(Error::Pattern);
+    }
     let ctx = exporter_context(template, caps);
     let ekm = tls.export(b"qnet inner", &ctx, 32)?; // 32B exporter secret
     let tx_key = bind_key(&base_tx, &ekm, &ctx);
     let rx_key = bind_key(&base_rx, &ekm, &ctx);
-    Ok(InnerConn { tx_key, rx_key })
+    let resumption = bind_key(&handshake_resumption(hs)?, &ekm, &ctx);
+    Ok(InnerConn {
+        tx_key,
+        rx_key,
+        resumption,
+    })
 }
 
 /// Same as `open_inner` but allows passing an optional compatibility flag that
@@ -107,11 +239,19 @@ pub fn open_inner_with_compat(
     compat: Option<&str>,
 ) -> Result<InnerConn, Error> {
     let (base_tx, base_rx) = hs.transport_keys().ok_or(Error::NotReady)?;
+    if !caps.matches(hs) {
+        return Err(Error::Pattern);
+    }
     let ctx = exporter_context_with_compat(template, caps, compat);
     let ekm = tls.export(b"qnet inner", &ctx, 32)?;
     let tx_key = bind_key(&base_tx, &ekm, &ctx);
     let rx_key = bind_key(&base_rx, &ekm, &ctx);
-    Ok(InnerConn { tx_key, rx_key })
+    let resumption = bind_key(&handshake_resumption(hs)?, &ekm, &ctx);
+    Ok(InnerConn {
+        tx_key,
+        rx_key,
+        resumption,
+    })
 }
 
 /// Derive inner channel keys using only the TLS exporter (EKM) and binding context.
@@ -134,7 +274,33 @@ pub fn open_inner_ekm_only(
     let c2s: [u8; 32] = crypto::hkdf::expand(&prk, b"c2s|key");
     let s2c: [u8; 32] = crypto::hkdf::expand(&prk, b"s2c|key");
     let (tx_key, rx_key) = if is_client { (c2s, s2c) } else { (s2c, c2s) };
-    Ok(InnerConn { tx_key, rx_key })
+    let resumption: [u8; 32] = crypto::hkdf::expand(&prk, b"resumption");
+    Ok(InnerConn {
+        tx_key,
+        rx_key,
+        resumption,
+    })
+}
+
+/// Bind the keys of a resumed session (see [`crate::resume`]) to the TLS exporter
+/// and (TemplateID, Caps), as [`open_inner`] does for a full handshake.
+pub fn open_inner_resumed(
+    tls: &TlsStream,
+    caps: &Caps,
+    template: &Template,
+    keys: &ResumedKeys,
+) -> Result<InnerConn, Error> {
+    let ctx = exporter_context(template, caps);
+    let ekm = tls.export(b"qnet inner", &ctx, 32)?;
+    Ok(InnerConn {
+        tx_key: bind_key(&keys.tx_key, &ekm, &ctx),
+        rx_key: bind_key(&keys.rx_key, &ekm, &ctx),
+        resumption: bind_key(&keys.resumption, &ekm, &ctx),
+    })
+}
+
+fn handshake_resumption(hs: &Handshake) -> Result<[u8; 32], Error> {
+    hs.exporter(b"resumption").ok_or(Error::NotReady)
 }
 
 #[cfg(test)]
@@ -236,4 +402,70 @@ mod tests {
         let ct = crypto::aead::seal(&ic.tx_key, &n, aad, pt);
         assert!(crypto::aead::open(&rc.rx_key, &n, aad, &ct).is_err());
     }
+
+    #[test]
+    fn caps_survive_the_exchange_encoding() {
+        let caps = Caps::default().with_record_layout(RecordLayout::hidden(1024));
+        let mut wire = Vec::new();
+        caps.write_to(&mut wire).unwrap();
+        assert_eq!(Caps::read_from(&mut &wire[..]).unwrap(), caps);
+        // Truncated or oversized messages are refused
+        assert!(Caps::read_from(&mut &wire[..wire.len() - 1]).is_err());
+        let big = [0xffu8, 0xff];
+        let err = Caps::read_from(&mut &big[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn record_layout_rides_the_caps_binding() {
+        let hidden = RecordLayout::hidden(1024);
+        let caps = Caps::default().with_record_layout(hidden);
+        assert_eq!(caps.record_layout(), hidden);
+        assert_eq!(Caps::default().record_layout(), RecordLayout::Clear);
+        assert_eq!(
+            caps.clone().with_record_layout(RecordLayout::Clear),
+            Caps::default()
+        );
+
+        // Peers that picked different layouts end up with different keys
+        let (init, resp) = do_noise_xk();
+        let tls = TlsStream::new(DummyTls { master: [7u8; 32] });
+        let tpl = mk_tpl();
+        let ic = open_inner(&tls, &caps, &tpl, &init).unwrap();
+        let rc = open_inner(&tls, &Caps::default(), &tpl, &resp).unwrap();
+        assert_ne!(ic.tx_key, rc.rx_key);
+        let rc = open_inner(&tls, &caps, &tpl, &resp).unwrap();
+        assert_eq!(ic.tx_key, rc.rx_key);
+    }
+
+    #[test]
+    fn handshake_pattern_is_selected_by_caps() {
+        let ikpsk = HandshakeParams::new(Pattern::Ik).psk2([9; 32]);
+        let caps = Caps::default().with_handshake(&ikpsk);
+        assert_eq!(caps.features, vec!["noise=IKpsk2".to_string()]);
+        assert_eq!(caps.handshake_params(Some([9; 32])), Some(ikpsk));
+        // The key never rides the caps, so a psk2 selection needs one locally
+        assert_eq!(caps.handshake_params(None), None);
+        assert_eq!(
+            Caps::default().handshake_params(Some([9; 32])),
+            Some(HandshakeParams::default())
+        );
+        assert_eq!(
+            Caps::default().with_handshake(&HandshakeParams::default()),
+            Caps::default()
+        );
+        let hybrid = HandshakeParams::new(Pattern::Xk).hybrid().psk2([9; 32]);
+        let caps_hfs = Caps::default().with_handshake(&hybrid);
+        assert_eq!(caps_hfs.features, vec!["noise=XKhfs+psk2".to_string()]);
+        assert_eq!(caps_hfs.handshake_params(Some([9; 32])), Some(hybrid));
+
+        // Keys from an XK handshake are refused under caps that select IK
+        let (init, _) = do_noise_xk();
+        let tls = TlsStream::new(DummyTls { master: [7u8; 32] });
+        assert!(matches!(
+            open_inner(&tls, &caps, &mk_tpl(), &init),
+            Err(Error::Pattern)
+        ));
+        assert!(open_inner(&tls, &Caps::default(), &mk_tpl(), &init).is_ok());
+    }
 }
@@ -1,15 +1,29 @@
-//! HTX Noise XK handshake primitives and placeholders for future APIs.
+//! HTX Noise handshake (XK, IK, XX, optionally psk2 and hybrid hfs) and its TCP driver.
 
 pub mod bootstrap;
+pub mod client_hello;
 pub mod decoy;
+pub mod driver;
+pub mod fingerprint;
+pub mod noise;
+#[cfg(feature = "rustls-config")]
+pub mod probe;
+pub mod resume;
+#[cfg(feature = "rustls-config")]
+pub mod server;
 
 use core_crypto as crypto;
+use core_crypto::mlkem;
 use curve25519_dalek::constants::X25519_BASEPOINT;
 use curve25519_dalek::montgomery::MontgomeryPoint;
 use curve25519_dalek::scalar::Scalar;
-use rand::{RngCore, SeedableRng};
+use rand::{rngs::OsRng, CryptoRng, RngCore};
 use ring::digest::{Context as Sha256, SHA256};
 
+pub use driver::{Client, Server};
+use noise::Token;
+pub use noise::{HandshakeParams, Pattern};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Role {
     Initiator,
@@ -22,93 +36,251 @@ pub enum State {
     Done,
 }
 
+/// Why a handshake step failed. The handshake is dead afterwards: every later
+/// [`Handshake::next`] returns the same error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum HandshakeError {
+    /// The message is not the length this step of the pattern produces.
+    #[error("bad message length")]
+    BadLength,
+    /// An encrypted field or the payload did not authenticate.
+    #[error("decryption failed")]
+    Decrypt,
+    /// A message arrived while this side was due to send, or after the handshake finished.
+    #[error("unexpected message")]
+    UnexpectedMessage,
+    /// The peer proved a different static key than the pinned one.
+    #[error("remote static mismatch")]
+    StaticMismatch,
+    /// The peer's ML-KEM key or ciphertext is malformed.
+    #[error("bad kem key or ciphertext")]
+    BadKem,
+    /// The pattern needs a key the caller did not supply (the responder's static).
+    #[error("missing key")]
+    MissingKey,
+    /// The message fits another pattern or modifier set than the one configured.
+    #[error("handshake pattern mismatch")]
+    PatternMismatch,
+    /// The deadline passed before the handshake finished.
+    #[error("handshake timed out")]
+    Timeout,
+    /// The transport failed or closed mid-handshake.
+    #[error("transport error: {0:?}")]
+    Io(std::io::ErrorKind),
+}
+
 #[derive(Debug, Clone)]
 pub struct Handshake {
     role: Role,
+    params: HandshakeParams,
     // Noise state
     h: [u8; 32],
     ck: [u8; 32],
+    // current cipher key and its nonce
+    k: Option<[u8; 32]>,
+    n: u64,
     // DH keys
-    s: Option<(Scalar, [u8; 32])>, // local static (sk, pk)
-    rs: [u8; 32],                  // remote static public
-    e: Option<(Scalar, [u8; 32])>, // local ephemeral (sk, pk)
-    re: Option<[u8; 32]>,          // remote ephemeral
-    // cipherstate nonces
-    _n_send: u64,
-    _n_recv: u64,
+    s: (Scalar, [u8; 32]), // local static (sk, pk)
+    rs: Option<[u8; 32]>,  // remote static public
+    e: (Scalar, [u8; 32]), // local ephemeral (sk, pk), drawn at construction
+    re: Option<[u8; 32]>,  // remote ephemeral
+    // hfs: our KEM key (initiator), the peer's, encapsulation randomness (responder)
+    kem: Option<mlkem::DecapsulationKey>,
+    rkem: Option<mlkem::EncapsulationKey>,
+    kem_seed: [u8; 32],
     // transport keys (after split)
     tx_key: Option<[u8; 32]>,
     rx_key: Option<[u8; 32]>,
-    stage: u8,
-    cur_k: Option<[u8; 32]>,
+    // index of the next message in the pattern
+    msg: usize,
+    // first error; the handshake is aborted once set
+    failed: Option<HandshakeError>,
 }
 
 impl Handshake {
+    /// XK initiator whose ephemeral comes from the OS CSPRNG.
     pub fn init_initiator(si: Scalar, rs: [u8; 32]) -> Self {
-        let spk = (si * X25519_BASEPOINT).to_bytes();
-        Self::new(Role::Initiator, Some((si, spk)), rs)
+        Self::init_initiator_with_rng(si, rs, &mut OsRng)
     }
 
+    /// XK responder whose ephemeral comes from the OS CSPRNG.
     pub fn init_responder(sr: Scalar) -> Self {
-        let spk = (sr * X25519_BASEPOINT).to_bytes();
-        // rs for responder is its own static public (mixed as pre-message)
-        Self::new(Role::Responder, Some((sr, spk)), spk)
-    }
-
-    fn new(role: Role, s: Option<(Scalar, [u8; 32])>, rs: [u8; 32]) -> Self {
-        let proto = b"Noise_XK_25519_ChaChaPoly_SHA256";
-        let (mut h, mut ck) = (sha256_init(proto), [0u8; 32]);
-        ck.copy_from_slice(&h);
-        // pre-messages: <- s (responder static)
-        h = mix_hash(&h, &rs);
-        Self {
+        Self::init_responder_with_rng(sr, &mut OsRng)
+    }
+
+    /// XK initiator drawing its ephemeral from `rng`. Only pass a seeded RNG for
+    /// reproducible test vectors; reusing an ephemeral breaks forward secrecy.
+    pub fn init_initiator_with_rng<R: RngCore + CryptoRng>(
+        si: Scalar,
+        rs: [u8; 32],
+        rng: &mut R,
+    ) -> Self {
+        Self::new(
+            Role::Initiator,
+            HandshakeParams::default(),
+            si,
+            Some(rs),
+            rng,
+        )
+    }
+
+    /// XK responder drawing its ephemeral from `rng` (see [`Handshake::init_initiator_with_rng`]).
+    pub fn init_responder_with_rng<R: RngCore + CryptoRng>(sr: Scalar, rng: &mut R) -> Self {
+        Self::new(Role::Responder, HandshakeParams::default(), sr, None, rng)
+    }
+
+    /// Initiator for any pattern. `rs` is the responder's static key, required
+    /// when [`Pattern::needs_remote_static`] and ignored otherwise.
+    pub fn initiator(
+        params: HandshakeParams,
+        si: Scalar,
+        rs: Option<[u8; 32]>,
+    ) -> Result<Self, HandshakeError> {
+        Self::initiator_with_rng(params, si, rs, &mut OsRng)
+    }
+
+    pub fn initiator_with_rng<R: RngCore + CryptoRng>(
+        params: HandshakeParams,
+        si: Scalar,
+        rs: Option<[u8; 32]>,
+        rng: &mut R,
+    ) -> Result<Self, HandshakeError> {
+        let rs = match (params.pattern.needs_remote_static(), rs) {
+            (true, None) => return Err(HandshakeError::MissingKey),
+            (true, rs) => rs,
+            (false, _) => None,
+        };
+        Ok(Self::new(Role::Initiator, params, si, rs, rng))
+    }
+
+    pub fn responder(params: HandshakeParams, sr: Scalar) -> Self {
+        Self::responder_with_rng(params, sr, &mut OsRng)
+    }
+
+    pub fn responder_with_rng<R: RngCore + CryptoRng>(
+        params: HandshakeParams,
+        sr: Scalar,
+        rng: &mut R,
+    ) -> Self {
+        Self::new(Role::Responder, params, sr, None, rng)
+    }
+
+    fn new<R: RngCore + CryptoRng>(
+        role: Role,
+        params: HandshakeParams,
+        sk: Scalar,
+        rs: Option<[u8; 32]>,
+        rng: &mut R,
+    ) -> Self {
+        let s = (sk, (sk * X25519_BASEPOINT).to_bytes());
+        let e = gen_keypair(rng);
+        let (mut kem, mut kem_seed) = (None, [0u8; 32]);
+        if params.hybrid {
+            match role {
+                Role::Initiator => {
+                    let (mut d, mut z) = ([0u8; 32], [0u8; 32]);
+                    rng.fill_bytes(&mut d);
+                    rng.fill_bytes(&mut z);
+                    kem = Some(mlkem::keypair_from_seed(&d, &z).0);
+                }
+                Role::Responder => rng.fill_bytes(&mut kem_seed),
+            }
+        }
+        let mut hs = Self {
             role,
-            h,
-            ck,
+            params,
+            h: [0; 32],
+            ck: [0; 32],
+            k: None,
+            n: 0,
             s,
             rs,
-            e: None,
+            e,
             re: None,
-            _n_send: 0,
-            _n_recv: 0,
+            kem,
+            rkem: None,
+            kem_seed,
             tx_key: None,
             rx_key: None,
-            stage: 0,
-            cur_k: None,
+            msg: 0,
+            failed: None,
+        };
+        hs.init_symmetric();
+        hs
+    }
+
+    // Protocol name, the (empty) prologue, then pre-messages
+    fn init_symmetric(&mut self) {
+        self.h = sha256_init(self.params.protocol_name().as_bytes());
+        self.ck = self.h;
+        self.h = mix_hash(&self.h, &[]);
+        if self.params.pattern.needs_remote_static() {
+            // <- s (responder static)
+            let pre = match self.role {
+                Role::Initiator => self.rs.expect("checked by caller"),
+                Role::Responder => self.s.1,
+            };
+            self.h = mix_hash(&self.h, &pre);
         }
     }
 
-    // Next step in handshake. Returns Some(message_out) when sending; None when a send isn't needed.
-    // Provide msg_in when receiving a message.
-    pub fn next(&mut self, msg_in: Option<&[u8]>) -> Result<Option<Vec<u8>>, &'static str> {
-        match self.role {
-            Role::Initiator => match (self.stage, msg_in) {
-                (0, None) => {
-                    let r = self.initiator_msg1()?;
-                    self.stage = 1;
-                    Ok(r)
-                }
-                (1, Some(m2)) => {
-                    let r = self.initiator_msg3(m2)?;
-                    self.stage = 2;
-                    Ok(r)
-                }
-                _ => Ok(None),
-            },
-            Role::Responder => match (self.stage, msg_in) {
-                (0, Some(m1)) => {
-                    let r = self.responder_msg2(m1)?;
-                    self.stage = 1;
-                    Ok(r)
-                }
-                (1, Some(m3)) => {
-                    self.responder_finalize_msg3(m3)?;
-                    self.stage = 2;
-                    Ok(None)
-                }
-                _ => Ok(None),
-            },
+    pub fn params(&self) -> &HandshakeParams {
+        &self.params
+    }
+
+    /// The peer's static key, once the pattern has revealed it.
+    pub fn remote_static(&self) -> Option<[u8; 32]> {
+        self.rs
+    }
+
+    /// Next step in handshake. Returns Some(message_out) when sending; None when a send
+    /// isn't needed. Provide msg_in when receiving a message; one that arrives while this
+    /// side is due to send, or once the handshake is done, is rejected.
+    pub fn next(&mut self, msg_in: Option<&[u8]>) -> Result<Option<Vec<u8>>, HandshakeError> {
+        if let Some(e) = self.failed {
+            return Err(e);
+        }
+        let out = self.step(msg_in);
+        if let Err(e) = out {
+            self.failed = Some(e);
+        }
+        out
+    }
+
+    /// Whether this side sends the next message (false once done).
+    pub fn is_my_turn(&self) -> bool {
+        self.msg < self.params.message_count() && self.writes_next()
+    }
+
+    fn step(&mut self, msg_in: Option<&[u8]>) -> Result<Option<Vec<u8>>, HandshakeError> {
+        let done = self.msg == self.params.message_count();
+        match msg_in {
+            Some(m) if !done && !self.writes_next() => {
+                self.read_first_or_next(m)?;
+                self.msg += 1;
+            }
+            Some(_) => return Err(HandshakeError::UnexpectedMessage),
+            None if !done && self.writes_next() => {}
+            None => return Ok(None),
         }
+        let out = if self.msg < self.params.message_count() && self.writes_next() {
+            let out = self.write_message()?;
+            self.msg += 1;
+            Some(out)
+        } else {
+            None
+        };
+        if self.msg == self.params.message_count() {
+            // Split keys; responder.tx == initiator.rx
+            let (k1, k2) = split(self.ck);
+            let (tx, rx) = match self.role {
+                Role::Initiator => (k1, k2),
+                Role::Responder => (k2, k1),
+            };
+            self.tx_key = Some(tx);
+            self.rx_key = Some(rx);
+        }
+        Ok(out)
     }
 
     pub fn is_done(&self) -> bool {
@@ -135,133 +307,226 @@ impl Handshake {
         Some(crypto::hkdf::expand::<32>(&prk, &info))
     }
 
-    // Initiator: -> e
-    fn initiator_msg1(&mut self) -> Result<Option<Vec<u8>>, &'static str> {
-        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
-        let mut ei_bytes = [0u8; 32];
-        rng.fill_bytes(&mut ei_bytes);
-        let ei = Scalar::from_bytes_mod_order(ei_bytes); // deterministic for tests
-        let eipk = (ei * X25519_BASEPOINT).to_bytes();
-        self.h = mix_hash(&self.h, &eipk);
-        self.e = Some((ei, eipk));
-        Ok(Some(eipk.to_vec()))
+    // Initiator sends even-numbered messages, responder odd ones
+    fn writes_next(&self) -> bool {
+        self.msg.is_multiple_of(2) == (self.role == Role::Initiator)
+    }
+
+    // A hybrid responder also takes a classical first message and drops to the
+    // plain pattern for the rest of the handshake.
+    fn read_first_or_next(&mut self, m: &[u8]) -> Result<(), HandshakeError> {
+        if !(self.role == Role::Responder && self.msg == 0 && self.params.hybrid) {
+            return self.read_message(m);
+        }
+        let mut classical = self.clone();
+        let err = match self.read_message(m) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        classical.params.hybrid = false;
+        classical.init_symmetric();
+        classical.read_message(m).map_err(|_| err)?;
+        *self = classical;
+        Ok(())
+    }
+
+    fn write_message(&mut self) -> Result<Vec<u8>, HandshakeError> {
+        let mut out = Vec::new();
+        for t in self.params.message(self.msg) {
+            match t {
+                Token::E => {
+                    let epk = self.e.1;
+                    self.mix_e(&epk);
+                    out.extend_from_slice(&epk);
+                }
+                Token::S => {
+                    let spk = self.s.1;
+                    out.extend_from_slice(&self.encrypt_and_hash(&spk));
+                }
+                Token::E1 => {
+                    let ek = self.kem.as_ref().ok_or(HandshakeError::BadKem)?;
+                    let ek = ek.encapsulation_key();
+                    out.extend_from_slice(&self.encrypt_and_hash(ek.as_bytes()));
+                }
+                Token::Ekem1 => {
+                    let ek = self.rkem.as_ref().ok_or(HandshakeError::BadKem)?;
+                    let (ct, shared) = mlkem::encapsulate_with(ek, &self.kem_seed);
+                    out.extend_from_slice(&self.encrypt_and_hash(&ct));
+                    self.mix_key(&shared);
+                }
+                Token::Psk => self.mix_psk()?,
+                dh => {
+                    let shared = self.dh(dh)?;
+                    self.mix_key(&shared);
+                }
+            }
+        }
+        // Empty payload: just its tag once a key exists, but always hashed
+        out.extend_from_slice(&self.encrypt_and_hash(&[]));
+        Ok(out)
+    }
+
+    fn read_message(&mut self, mut m: &[u8]) -> Result<(), HandshakeError> {
+        if m.len() != self.params.message_len(self.msg) {
+            return Err(self.length_error(m.len()));
+        }
+        for t in self.params.message(self.msg) {
+            match t {
+                Token::E => {
+                    let re: [u8; 32] = take(&mut m, 32)?.try_into().expect("32 bytes");
+                    self.mix_e(&re);
+                    self.re = Some(re);
+                }
+                Token::S => {
+                    let ct = take(&mut m, 32 + self.tag_len())?;
+                    let pk = self.decrypt_and_hash(ct)?;
+                    let pk: [u8; 32] = pk.as_slice().try_into().expect("32 bytes");
+                    // A pinned static must be the one the peer proves
+                    if self.rs.is_some_and(|rs| rs != pk) {
+                        return Err(HandshakeError::StaticMismatch);
+                    }
+                    self.rs = Some(pk);
+                }
+                Token::E1 => {
+                    let ct = take(&mut m, mlkem::ENCAPS_KEY_LEN + self.tag_len())?;
+                    let ek = self.decrypt_and_hash(ct)?;
+                    let ek = mlkem::EncapsulationKey::from_bytes(&ek)
+                        .map_err(|_| HandshakeError::BadKem)?;
+                    self.rkem = Some(ek);
+                }
+                Token::Ekem1 => {
+                    let ct = take(&mut m, mlkem::CIPHERTEXT_LEN + self.tag_len())?;
+                    let ct = self.decrypt_and_hash(ct)?;
+                    let dk = self.kem.as_ref().ok_or(HandshakeError::BadKem)?;
+                    let shared = mlkem::decapsulate(dk, &ct).map_err(|_| HandshakeError::BadKem)?;
+                    self.mix_key(&shared);
+                }
+                Token::Psk => self.mix_psk()?,
+                dh => {
+                    let shared = self.dh(dh)?;
+                    self.mix_key(&shared);
+                }
+            }
+        }
+        // Empty payload; the length check above leaves only its tag
+        self.decrypt_and_hash(m)?;
+        Ok(())
     }
 
-    // Responder: <- e, -> e, ee, s, es
-    fn responder_msg2(&mut self, m1: &[u8]) -> Result<Option<Vec<u8>>, &'static str> {
-        if m1.len() != 32 {
-            return Err("bad m1 len");
+    // A message of the wrong length may still fit another pattern or modifier
+    // set, which says more about what went wrong than the length alone
+    fn length_error(&self, len: usize) -> HandshakeError {
+        let other = Pattern::ALL.into_iter().any(|pattern| {
+            [(false, false), (true, false), (false, true), (true, true)]
+                .into_iter()
+                .any(|(psk, hybrid)| {
+                    let p = HandshakeParams {
+                        pattern,
+                        psk: psk.then_some([0; 32]),
+                        hybrid,
+                    };
+                    self.msg < p.message_count() && p.message_len(self.msg) == len
+                })
+        });
+        if other {
+            HandshakeError::PatternMismatch
+        } else {
+            HandshakeError::BadLength
         }
-        let ei = <[u8; 32]>::try_from(m1).map_err(|_| "m1")?;
-        self.h = mix_hash(&self.h, &ei);
-        self.re = Some(ei);
-
-        // Generate responder ephemeral
-        let mut rng = rand::rngs::StdRng::seed_from_u64(13);
-        let mut er_bytes = [0u8; 32];
-        rng.fill_bytes(&mut er_bytes);
-        let er = Scalar::from_bytes_mod_order(er_bytes);
-        let erpk = (er * X25519_BASEPOINT).to_bytes();
-        self.h = mix_hash(&self.h, &erpk);
-        self.e = Some((er, erpk));
-
-        // ck, k = MixKey(DH(e_r, e_i))
-        let dh_ee = x25519(&er, &ei);
-        let (ck1, k1) = mix_key(self.ck, &dh_ee);
-        self.ck = ck1;
-        // Encrypt s_r with key k1
-        let (sr, srpk) = self.s.as_ref().ok_or("no sr")?;
-        let aad = self.h; // AAD is h
-        let mut nonce = [0u8; 12]; // n=0
-        let ct_s = aead_seal(&k1, &mut nonce, &aad, srpk.as_slice());
-        self.h = mix_hash(&self.h, &ct_s);
-
-        // MixKey with es = DH(e_i, s_r)
-        let dh_es = x25519(sr, &ei);
-        let (ck2, k2) = mix_key(self.ck, &dh_es);
-        self.ck = ck2;
-        self.cur_k = Some(k2); // save k2 for decrypting s_i in m3
-
-        // Build message2: er || enc(s_r)
-        let mut out = Vec::with_capacity(32 + ct_s.len());
-        out.extend_from_slice(&erpk);
-        out.extend_from_slice(&ct_s);
-        Ok(Some(out))
-    }
-
-    // Initiator: receive m2, -> s, se
-    fn initiator_msg3(&mut self, m2: &[u8]) -> Result<Option<Vec<u8>>, &'static str> {
-        if m2.len() < 32 + 16 {
-            return Err("bad m2 len");
+    }
+
+    // Encrypted fields carry a tag once a key exists
+    fn tag_len(&self) -> usize {
+        if self.k.is_some() {
+            16
+        } else {
+            0
         }
-        let er = <[u8; 32]>::try_from(&m2[..32]).map_err(|_| "m2 er")?;
-        self.h = mix_hash(&self.h, &er);
-        self.re = Some(er);
-        let ct_s = &m2[32..];
-
-        // MixKey with ee
-        let (ei, _) = self.e.as_ref().ok_or("no ei")?;
-        let dh_ee = x25519(ei, &er);
-        let (ck1, k1) = mix_key(self.ck, &dh_ee);
-        self.ck = ck1;
-
-        // Decrypt s_r
-        let aad = self.h;
-        let mut nonce = [0u8; 12];
-        let srpk = aead_open(&k1, &mut nonce, &aad, ct_s).map_err(|_| "decrypt s_r")?;
-        self.h = mix_hash(&self.h, ct_s);
-        self.rs = srpk.as_slice().try_into().map_err(|_| "srpk")?;
-
-        // MixKey with es = DH(e_i, s_r)
-        let dh_es = x25519(ei, &self.rs);
-        let (ck2, k2) = mix_key(self.ck, &dh_es);
-        self.ck = ck2;
-        self.cur_k = Some(k2); // save k2 for encrypting s_i
-
-        // Now send s_i and MixKey with se
-        let (si, sipk) = self.s.as_ref().ok_or("no si")?;
-        // Encrypt s_i with current k2
-        let k2 = self.cur_k.ok_or("no k2")?;
-        let aad2 = self.h;
-        let mut nonce2 = [0u8; 12];
-        let ct_si = aead_seal(&k2, &mut nonce2, &aad2, sipk.as_slice());
-        self.h = mix_hash(&self.h, &ct_si);
-
-        // Then MixKey with se = DH(s_i, e_r)
-        let dh_se = x25519(si, &er);
-        let (ck3, _k3) = mix_key(self.ck, &dh_se);
-        self.ck = ck3;
-
-        // Split traffic keys
-        let (k_tx, k_rx) = split(self.ck);
-        self.tx_key = Some(k_tx);
-        self.rx_key = Some(k_rx);
-        Ok(Some(ct_si))
-    }
-
-    // Responder: receive m3 (enc s_i), finalize and split
-    fn responder_finalize_msg3(&mut self, m3: &[u8]) -> Result<(), &'static str> {
-        if m3.len() < 16 {
-            return Err("bad m3 len");
+    }
+
+    fn dh(&self, t: Token) -> Result<[u8; 32], HandshakeError> {
+        let re = self.re.ok_or(HandshakeError::MissingKey);
+        let rs = self.rs.ok_or(HandshakeError::MissingKey);
+        let (sk, pk) = match (t, self.role) {
+            (Token::Ee, _) => (&self.e.0, re?),
+            (Token::Es, Role::Initiator) | (Token::Se, Role::Responder) => (&self.e.0, rs?),
+            (Token::Es, Role::Responder) | (Token::Se, Role::Initiator) => (&self.s.0, re?),
+            (Token::Ss, _) => (&self.s.0, rs?),
+            _ => unreachable!("not a dh token"),
+        };
+        Ok(x25519(sk, &pk))
+    }
+
+    fn mix_e(&mut self, epk: &[u8; 32]) {
+        self.h = mix_hash(&self.h, epk);
+        // PSK handshakes also mix ephemerals into the key
+        if self.params.psk.is_some() {
+            self.mix_key(epk);
         }
-        // Decrypt s_i with current k2
-        let k2 = self.cur_k.ok_or("no k2")?;
-        let aad = self.h;
-        let mut nonce = [0u8; 12];
-        let sipk = aead_open(&k2, &mut nonce, &aad, m3).map_err(|_| "decrypt s_i")?;
-        self.h = mix_hash(&self.h, m3);
-        self.rs = sipk.as_slice().try_into().map_err(|_| "sipk")?; // store initiator static
-                                                                   // Then MixKey with se = DH(e_r, s_i)
-        let (er, _erpk) = self.e.as_ref().ok_or("no er")?;
-        let dh_se = x25519(er, &self.rs);
-        let (ck3, _k3) = mix_key(self.ck, &dh_se);
-        self.ck = ck3;
-        // Split keys; responder.tx == initiator.rx
-        let (k1, k2) = split(self.ck);
-        self.tx_key = Some(k2);
-        self.rx_key = Some(k1);
+    }
+
+    fn mix_key(&mut self, ikm: &[u8]) {
+        let (ck, k) = mix_key(self.ck, ikm);
+        self.ck = ck;
+        self.k = Some(k);
+        self.n = 0;
+    }
+
+    fn mix_psk(&mut self) -> Result<(), HandshakeError> {
+        let psk = self.params.psk.ok_or(HandshakeError::MissingKey)?;
+        let prk = crypto::hkdf::extract(&self.ck, &psk);
+        let out: [u8; 96] = crypto::hkdf::expand(&prk, b"");
+        self.ck.copy_from_slice(&out[..32]);
+        self.h = mix_hash(&self.h, &out[32..64]);
+        self.k = Some(out[64..].try_into().expect("32 bytes"));
+        self.n = 0;
         Ok(())
     }
+
+    // AAD is h; without a key yet the data goes out in the clear
+    fn encrypt_and_hash(&mut self, pt: &[u8]) -> Vec<u8> {
+        let ct = match self.k {
+            Some(k) => {
+                let mut nonce = nonce(self.n);
+                self.n += 1;
+                aead_seal(&k, &mut nonce, &self.h, pt)
+            }
+            None => pt.to_vec(),
+        };
+        self.h = mix_hash(&self.h, &ct);
+        ct
+    }
+
+    fn decrypt_and_hash(&mut self, ct: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        let pt = match self.k {
+            Some(k) => {
+                let mut nonce = nonce(self.n);
+                let pt =
+                    aead_open(&k, &mut nonce, &self.h, ct).map_err(|_| HandshakeError::Decrypt)?;
+                self.n += 1;
+                pt
+            }
+            None => ct.to_vec(),
+        };
+        self.h = mix_hash(&self.h, ct);
+        Ok(pt)
+    }
+}
+
+fn take<'a>(m: &mut &'a [u8], n: usize) -> Result<&'a [u8], HandshakeError> {
+    if m.len() < n {
+        return Err(HandshakeError::BadLength);
+    }
+    let (head, rest) = m.split_at(n);
+    *m = rest;
+    Ok(head)
+}
+
+// ChaChaPoly nonce: four zero bytes then the counter, little-endian
+fn nonce(n: u64) -> [u8; 12] {
+    let mut out = [0u8; 12];
+    out[4..].copy_from_slice(&n.to_le_bytes());
+    out
 }
 
 fn aead_seal(key: &[u8; 32], nonce12: &mut [u8; 12], aad: &[u8], pt: &[u8]) -> Vec<u8> {
@@ -277,6 +542,23 @@ fn x25519(sk: &Scalar, peer_pk: &[u8; 32]) -> [u8; 32] {
     shared.to_bytes()
 }
 
+fn gen_keypair<R: RngCore + CryptoRng>(rng: &mut R) -> (Scalar, [u8; 32]) {
+    let mut sk = [0u8; 32];
+    rng.fill_bytes(&mut sk);
+    let sk = x25519_scalar(sk);
+    (sk, (sk * X25519_BASEPOINT).to_bytes())
+}
+
+/// The scalar an X25519 private key (RFC 7748) stands for: its bytes clamped,
+/// then reduced mod the group order. Static keys built this way give the same
+/// public key and shared secrets as any other Noise implementation.
+pub fn x25519_scalar(mut sk: [u8; 32]) -> Scalar {
+    sk[0] &= 248;
+    sk[31] &= 127;
+    sk[31] |= 64;
+    Scalar::from_bytes_mod_order(sk)
+}
+
 fn sha256_init(proto: &[u8]) -> [u8; 32] {
     if proto.len() <= 32 {
         let mut out = [0u8; 32];
@@ -309,7 +591,7 @@ fn mix_key(mut ck: [u8; 32], ikm: &[u8]) -> ([u8; 32], [u8; 32]) {
 
 fn split(ck: [u8; 32]) -> ([u8; 32], [u8; 32]) {
     let prk = crypto::hkdf::extract(&ck, &[]);
-    let out: [u8; 64] = crypto::hkdf::expand(&prk, b"split");
+    let out: [u8; 64] = crypto::hkdf::expand(&prk, b"");
     let mut k1 = [0u8; 32];
     let mut k2 = [0u8; 32];
     k1.copy_from_slice(&out[..32]);
@@ -317,31 +599,20 @@ fn split(ck: [u8; 32]) -> ([u8; 32], [u8; 32]) {
     (k1, k2)
 }
 
-// Placeholder structs for future public API
-pub struct Client;
-pub struct Server;
-
-impl Client {
-    pub fn dial(_addr: &str) -> Result<(), &'static str> {
-        Ok(())
-    }
-}
-impl Server {
-    pub fn accept(_bind: &str) -> Result<(), &'static str> {
-        Ok(())
-    }
-}
-
 pub mod api;
 pub mod inner;
 pub mod mux;
+mod sched;
 pub mod tl;
 pub mod tls_mirror;
 pub mod transition;
+pub mod transport;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::noise::FixedRng;
+    use rand::{rngs::StdRng, SeedableRng};
 
     // Deterministic static keys for test
     fn static_keys() -> (Scalar, Scalar) {
@@ -394,7 +665,7 @@ mod tests {
 
     #[test]
     fn noise_xk_m1_tamper_detected() {
-        // Tampering with m1 should cause responder to fail on m2 or produce different keys
+        // m1 carries es and a payload tag, so the responder catches the tampering
         let (si, sr) = static_keys();
         let rs = (sr * X25519_BASEPOINT).to_bytes();
 
@@ -405,14 +676,10 @@ mod tests {
         // Tamper with ephemeral point in m1 (first 32 bytes)
         m1[0] ^= 1;
 
-        // Responder should still process (X25519 is lenient) but keys will differ
-        let m2 = resp.next(Some(&m1)).unwrap().unwrap();
-
-        // Initiator will fail to decrypt m2 because transcript differs
-        let result = init.next(Some(&m2));
+        let result = resp.next(Some(&m1));
         assert!(
             result.is_err(),
-            "Tampered m1 should cause m2 decryption failure"
+            "Tampered m1 should fail the responder's decryption"
         );
     }
 
@@ -474,54 +741,390 @@ mod tests {
         let mut resp = Handshake::init_responder(sr);
 
         let m1 = init.next(None).unwrap().unwrap();
-        let m2 = resp.next(Some(&m1)).unwrap().unwrap();
 
-        // Initiator should fail to decrypt m2 because ee/es will be wrong
-        let result = init.next(Some(&m2));
+        // es differs, so the responder cannot open m1's payload
+        let result = resp.next(Some(&m1));
         assert!(
             result.is_err(),
             "Wrong responder static key should cause decryption failure"
         );
     }
 
-    #[test]
-    fn noise_xk_exporter_deterministic() {
-        // Same handshake should produce same exporter output
+    // One full handshake; `seeds` pins the initiator and responder ephemerals.
+    fn run_handshake(seeds: Option<(u64, u64)>) -> (Handshake, Handshake, Vec<Vec<u8>>) {
         let (si, sr) = static_keys();
         let rs = (sr * X25519_BASEPOINT).to_bytes();
+        let (mut init, mut resp) = match seeds {
+            Some((a, b)) => (
+                Handshake::init_initiator_with_rng(si, rs, &mut StdRng::seed_from_u64(a)),
+                Handshake::init_responder_with_rng(sr, &mut StdRng::seed_from_u64(b)),
+            ),
+            None => (
+                Handshake::init_initiator(si, rs),
+                Handshake::init_responder(sr),
+            ),
+        };
+        let m1 = init.next(None).unwrap().unwrap();
+        let m2 = resp.next(Some(&m1)).unwrap().unwrap();
+        let m3 = init.next(Some(&m2)).unwrap().unwrap();
+        let _ = resp.next(Some(&m3)).unwrap();
+        (init, resp, vec![m1, m2, m3])
+    }
+
+    fn hex(b: &[u8]) -> String {
+        b.iter().map(|x| format!("{x:02x}")).collect()
+    }
 
-        // First handshake
-        let mut init1 = Handshake::init_initiator(si, rs);
-        let mut resp1 = Handshake::init_responder(sr);
-        let m1 = init1.next(None).unwrap().unwrap();
-        let m2 = resp1.next(Some(&m1)).unwrap().unwrap();
-        let m3 = init1.next(Some(&m2)).unwrap().unwrap();
-        let _ = resp1.next(Some(&m3)).unwrap();
+    #[test]
+    fn noise_xk_exporter_deterministic() {
+        // Same statics and same injected ephemerals produce the same exporter
+        let (init1, resp1, _) = run_handshake(Some((7, 13)));
         let exp1_init = init1.exporter(b"channel-binding").unwrap();
         let exp1_resp = resp1.exporter(b"channel-binding").unwrap();
-
-        // Second handshake with same keys
-        let mut init2 = Handshake::init_initiator(si, rs);
-        let mut resp2 = Handshake::init_responder(sr);
-        let m1_2 = init2.next(None).unwrap().unwrap();
-        let m2_2 = resp2.next(Some(&m1_2)).unwrap().unwrap();
-        let m3_2 = init2.next(Some(&m2_2)).unwrap().unwrap();
-        let _ = resp2.next(Some(&m3_2)).unwrap();
+        let (init2, _, _) = run_handshake(Some((7, 13)));
         let exp2_init = init2.exporter(b"channel-binding").unwrap();
-        let exp2_resp = resp2.exporter(b"channel-binding").unwrap();
 
         // Both parties should have matching exporters
         assert_eq!(
             exp1_init, exp1_resp,
             "Initiator and responder exporters must match"
         );
-        // Same static keys produce same exporter (deterministic)
         assert_eq!(
             exp1_init, exp2_init,
             "Same keys should produce same exporter"
         );
     }
 
+    #[test]
+    fn noise_xk_seeded_test_vector() {
+        let (init, _, msgs) = run_handshake(Some((7, 13)));
+        assert_eq!(
+            hex(&msgs[0]),
+            "414862f45ddcdf54660ce2951e78dd540fb5060ffe3668872863251bb0cf7758\
+             daf3883833bd672ff0aba5d32e6bc0eb"
+        );
+        assert_eq!(
+            hex(&msgs[1]),
+            "bb381183726668a1651063469689c7231d1aecfefcedc9e431d0bf5e41ef8f5f\
+             5ed1b8ab3fe0ff7c442e9b5ef1e45ec3"
+        );
+        assert_eq!(
+            hex(&msgs[2]),
+            "79ca37ad97262e3f5ba77e61d2be3520db3c80d6e3b0863fdd6390a65cbd7f06\
+             1fa7c6845da31f5b1be311a65dea8a57c9951284be970d5719bde9618831a682"
+        );
+        assert_eq!(
+            hex(&init.transport_keys().unwrap().0),
+            "7bd416b80505e80017d8ea05a650be4c9e0599fc6e1dfae1294cf2cdbd0cc8dd"
+        );
+        assert_eq!(
+            hex(&init.exporter(b"channel-binding").unwrap()),
+            "052ccbaff0c3be51661072ace186da26268077635660b8d8a89bf6178769a536"
+        );
+    }
+
+    #[test]
+    fn noise_xk_default_handshakes_never_reuse_ephemerals() {
+        let mut ephemerals = std::collections::HashSet::new();
+        let mut keys = std::collections::HashSet::new();
+        for _ in 0..32 {
+            let (init, resp, msgs) = run_handshake(None);
+            assert!(init.is_done() && resp.is_done());
+            // m1 is the initiator ephemeral, m2 starts with the responder's
+            assert!(
+                ephemerals.insert(msgs[0].clone()),
+                "initiator ephemeral reused"
+            );
+            assert!(
+                ephemerals.insert(msgs[1][..32].to_vec()),
+                "responder ephemeral reused"
+            );
+            assert!(keys.insert(init.transport_keys().unwrap()));
+        }
+    }
+
+    // Run `init` and `resp` to completion, returning the messages exchanged
+    fn exchange(
+        init: &mut Handshake,
+        resp: &mut Handshake,
+    ) -> Result<Vec<Vec<u8>>, HandshakeError> {
+        let mut msgs = Vec::new();
+        let mut msg = init.next(None)?;
+        let mut turn = [resp, init];
+        while let Some(m) = msg {
+            msg = turn[0].next(Some(&m))?;
+            msgs.push(m);
+            turn.swap(0, 1);
+        }
+        Ok(msgs)
+    }
+
+    fn all_params() -> Vec<HandshakeParams> {
+        Pattern::ALL
+            .into_iter()
+            .flat_map(|p| {
+                [
+                    HandshakeParams::new(p),
+                    HandshakeParams::new(p).psk2([0x42; 32]),
+                    HandshakeParams::new(p).hybrid(),
+                    HandshakeParams::new(p).hybrid().psk2([0x42; 32]),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn noise_published_test_vectors() {
+        let doc: serde_json::Value =
+            serde_json::from_str(include_str!("../vectors/noise.json")).unwrap();
+        let key = |k: &str| -> [u8; 32] {
+            hex::decode(doc[k].as_str().unwrap())
+                .unwrap()
+                .try_into()
+                .unwrap()
+        };
+        let si = x25519_scalar(key("initiator_static"));
+        let sr = x25519_scalar(key("responder_static"));
+        let rs = (sr * X25519_BASEPOINT).to_bytes();
+        let vectors = doc["vectors"].as_array().unwrap();
+        assert_eq!(vectors.len(), all_params().len());
+        for (v, params) in vectors.iter().zip(all_params()) {
+            assert_eq!(v["protocol"], params.protocol_name());
+            let psk = v["psk"]
+                .as_str()
+                .map(|p| hex::decode(p).unwrap().try_into().unwrap());
+            assert_eq!(psk, params.psk);
+            let mut init = Handshake::initiator_with_rng(
+                params,
+                si,
+                Some(rs),
+                &mut FixedRng(key("initiator_ephemeral")),
+            )
+            .unwrap();
+            let mut resp = Handshake::responder_with_rng(
+                params,
+                sr,
+                &mut FixedRng(key("responder_ephemeral")),
+            );
+            let msgs: Vec<String> = exchange(&mut init, &mut resp)
+                .unwrap()
+                .iter()
+                .map(hex::encode)
+                .collect();
+            assert_eq!(serde_json::json!(msgs), v["messages"], "{}", params.name());
+            let (tx, rx) = init.transport_keys().unwrap();
+            assert_eq!(v["initiator_tx"], hex::encode(tx));
+            assert_eq!(v["initiator_rx"], hex::encode(rx));
+            assert_eq!(
+                v["exporter"],
+                hex::encode(init.exporter(b"channel-binding").unwrap())
+            );
+        }
+    }
+
+    // The classical vectors are exactly what snow, an independent Noise
+    // implementation, sends from the same keys. snow has no ML-KEM-768, so the
+    // hfs vectors rest on the same symmetric state but are not checked here.
+    #[test]
+    fn noise_vectors_match_snow() {
+        let doc: serde_json::Value =
+            serde_json::from_str(include_str!("../vectors/noise.json")).unwrap();
+        let key = |k: &str| hex::decode(doc[k].as_str().unwrap()).unwrap();
+        let (si, sr) = (key("initiator_static"), key("responder_static"));
+        let (ei, er) = (key("initiator_ephemeral"), key("responder_ephemeral"));
+        let rs = (x25519_scalar(sr.clone().try_into().unwrap()) * X25519_BASEPOINT).to_bytes();
+        let mut checked = 0;
+        for v in doc["vectors"].as_array().unwrap() {
+            let protocol = v["protocol"].as_str().unwrap();
+            if protocol.contains("hfs") {
+                continue;
+            }
+            let psk = v["psk"].as_str().map(|p| hex::decode(p).unwrap());
+            let mut init = snow::Builder::new(protocol.parse().unwrap())
+                .local_private_key(&si)
+                .fixed_ephemeral_key_for_testing_only(&ei);
+            let mut resp = snow::Builder::new(protocol.parse().unwrap())
+                .local_private_key(&sr)
+                .fixed_ephemeral_key_for_testing_only(&er);
+            if !protocol.starts_with("Noise_XX") {
+                init = init.remote_public_key(&rs);
+            }
+            if let Some(psk) = &psk {
+                init = init.psk(2, psk);
+                resp = resp.psk(2, psk);
+            }
+            let mut init = init.build_initiator().unwrap();
+            let mut resp = resp.build_responder().unwrap();
+            let (mut msg, mut payload) = ([0u8; 1024], [0u8; 1024]);
+            let mut sent = Vec::new();
+            let mut turn = [&mut init, &mut resp];
+            while !turn[0].is_handshake_finished() {
+                let n = turn[0].write_message(&[], &mut msg).unwrap();
+                turn[1].read_message(&msg[..n], &mut payload).unwrap();
+                sent.push(hex::encode(&msg[..n]));
+                turn.swap(0, 1);
+            }
+            assert_eq!(serde_json::json!(sent), v["messages"], "{protocol}");
+            let (tx, rx) = init.dangerously_get_raw_split();
+            assert_eq!(v["initiator_tx"], hex::encode(tx), "{protocol}");
+            assert_eq!(v["initiator_rx"], hex::encode(rx), "{protocol}");
+            checked += 1;
+        }
+        assert_eq!(checked, 2 * Pattern::ALL.len());
+    }
+
+    #[test]
+    fn noise_every_pattern_agrees_and_learns_statics() {
+        let (si, sr) = static_keys();
+        let ipk = (si * X25519_BASEPOINT).to_bytes();
+        let rpk = (sr * X25519_BASEPOINT).to_bytes();
+        for params in all_params() {
+            let mut init = Handshake::initiator(params, si, Some(rpk)).unwrap();
+            let mut resp = Handshake::responder(params, sr);
+            let msgs = exchange(&mut init, &mut resp).unwrap();
+            assert_eq!(msgs.len(), params.pattern.messages().len());
+            let (tx, rx) = init.transport_keys().unwrap();
+            assert_eq!(resp.transport_keys(), Some((rx, tx)), "{}", params.name());
+            assert_eq!(init.remote_static(), Some(rpk));
+            assert_eq!(resp.remote_static(), Some(ipk));
+            // Nothing more to send once split
+            assert_eq!(init.next(None), Ok(None));
+            assert_eq!(resp.next(None), Ok(None));
+        }
+        // XX needs no pinned key; XK and IK refuse to start without one
+        assert!(Handshake::initiator(HandshakeParams::new(Pattern::Xx), si, None).is_ok());
+        assert!(Handshake::initiator(HandshakeParams::new(Pattern::Ik), si, None).is_err());
+    }
+
+    #[test]
+    fn noise_mismatched_pattern_or_psk_fails() {
+        let (si, sr) = static_keys();
+        let rs = (sr * X25519_BASEPOINT).to_bytes();
+        let ik = HandshakeParams::new(Pattern::Ik);
+        let run = |a: HandshakeParams, b: HandshakeParams| {
+            let mut init = Handshake::initiator(a, si, Some(rs)).unwrap();
+            let mut resp = Handshake::responder(b, sr);
+            exchange(&mut init, &mut resp)
+        };
+        assert!(run(ik, HandshakeParams::new(Pattern::Xk)).is_err());
+        assert!(run(ik.psk2([1; 32]), ik.psk2([2; 32])).is_err());
+        assert!(run(ik.psk2([1; 32]), ik).is_err());
+        let xx = HandshakeParams::new(Pattern::Xx);
+        assert!(run(xx.psk2([1; 32]), xx.psk2([2; 32])).is_err());
+
+        // IK towards the wrong key fails on the responder's first read
+        let mut init =
+            Handshake::initiator(ik, si, Some((si * X25519_BASEPOINT).to_bytes())).unwrap();
+        let mut resp = Handshake::responder(ik, sr);
+        let m1 = init.next(None).unwrap().unwrap();
+        assert!(resp.next(Some(&m1)).is_err());
+    }
+
+    #[test]
+    fn noise_rejects_messages_in_the_wrong_stage() {
+        let (si, sr) = static_keys();
+        let rs = (sr * X25519_BASEPOINT).to_bytes();
+        let mut init = Handshake::init_initiator(si, rs);
+        let mut resp = Handshake::init_responder(sr);
+        // Nothing to send while waiting for a message
+        assert_eq!(resp.next(None), Ok(None));
+        // The initiator speaks first, so a message before m1 is out of turn
+        let m1 = Handshake::init_initiator(si, rs)
+            .next(None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(init.next(Some(&m1)), Err(HandshakeError::UnexpectedMessage));
+        // The error sticks
+        assert_eq!(init.next(None), Err(HandshakeError::UnexpectedMessage));
+
+        let mut init = Handshake::init_initiator(si, rs);
+        let mut resp = Handshake::init_responder(sr);
+        exchange(&mut init, &mut resp).unwrap();
+        assert_eq!(
+            init.next(Some(&[0u8; 48])),
+            Err(HandshakeError::UnexpectedMessage)
+        );
+    }
+
+    #[test]
+    fn noise_errors_are_typed() {
+        let (si, sr) = static_keys();
+        let rs = (sr * X25519_BASEPOINT).to_bytes();
+        let xk = HandshakeParams::new(Pattern::Xk);
+        let ik = HandshakeParams::new(Pattern::Ik);
+
+        // m1 from IK handed to an XK responder
+        let mut init = Handshake::initiator(ik, si, Some(rs)).unwrap();
+        let m1 = init.next(None).unwrap().unwrap();
+        let mut resp = Handshake::responder(xk, sr);
+        assert_eq!(resp.next(Some(&m1)), Err(HandshakeError::PatternMismatch));
+        // Truncated, and tampered
+        let mut resp = Handshake::responder(ik, sr);
+        assert_eq!(
+            resp.next(Some(&m1[..m1.len() - 1])),
+            Err(HandshakeError::BadLength)
+        );
+        let mut bad = m1.clone();
+        bad[40] ^= 1;
+        let mut resp = Handshake::responder(ik, sr);
+        assert_eq!(resp.next(Some(&bad)), Err(HandshakeError::Decrypt));
+        assert_eq!(
+            Handshake::initiator(ik, si, None).err(),
+            Some(HandshakeError::MissingKey)
+        );
+        // A pinned key is part of the transcript, so the wrong edge fails to decrypt
+        let mut init = Handshake::init_initiator(si, rs);
+        let mut resp = Handshake::init_responder(si);
+        let m1 = init.next(None).unwrap().unwrap();
+        assert_eq!(resp.next(Some(&m1)), Err(HandshakeError::Decrypt));
+    }
+
+    #[test]
+    fn noise_hybrid_negotiates_down_to_classical_peers() {
+        let (si, sr) = static_keys();
+        let rs = (sr * X25519_BASEPOINT).to_bytes();
+        for params in all_params().into_iter().filter(|p| !p.hybrid) {
+            // Hybrid responder, classical initiator: falls back and agrees
+            let mut init = Handshake::initiator(params, si, Some(rs)).unwrap();
+            let mut resp = Handshake::responder(params.hybrid(), sr);
+            exchange(&mut init, &mut resp).unwrap();
+            assert_eq!(resp.params(), &params, "{}", params.name());
+            let (tx, rx) = init.transport_keys().unwrap();
+            assert_eq!(resp.transport_keys(), Some((rx, tx)));
+
+            // Hybrid initiator, classical responder: refused
+            let mut init = Handshake::initiator(params.hybrid(), si, Some(rs)).unwrap();
+            let mut resp = Handshake::responder(params, sr);
+            assert!(exchange(&mut init, &mut resp).is_err());
+        }
+    }
+
+    #[test]
+    fn noise_hybrid_rejects_tampered_kem_fields() {
+        let (si, sr) = static_keys();
+        let rs = (sr * X25519_BASEPOINT).to_bytes();
+        let xk = HandshakeParams::new(Pattern::Xk).hybrid();
+
+        // e1 follows the 32-byte ephemeral in message 1. It goes out before any
+        // key exists, so the swap shows up when the initiator reads message 2.
+        let mut init = Handshake::initiator(xk, si, Some(rs)).unwrap();
+        let mut resp = Handshake::responder(xk, sr);
+        let mut m1 = init.next(None).unwrap().unwrap();
+        m1[32 + 7] ^= 1;
+        if let Ok(Some(m2)) = resp.next(Some(&m1)) {
+            assert!(init.next(Some(&m2)).is_err());
+        }
+
+        // ekem1 follows the responder's ephemeral in message 2
+        let mut init = Handshake::initiator(xk, si, Some(rs)).unwrap();
+        let mut resp = Handshake::responder(xk, sr);
+        let m1 = init.next(None).unwrap().unwrap();
+        let mut m2 = resp.next(Some(&m1)).unwrap().unwrap();
+        m2[32 + 7] ^= 1;
+        assert!(init.next(Some(&m2)).is_err());
+        assert!(resp.params().hybrid);
+    }
+
     #[test]
     fn noise_xk_exporter_different_labels_differ() {
         let (si, sr) = static_keys();
@@ -1,20 +1,640 @@
 use bytes::{BufMut, Bytes, BytesMut};
 use std::collections::HashMap;
 use std::collections::VecDeque;
-use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 #[cfg(feature = "stealth-mode")]
 use core_framing::{jitter as jitter_mod, sizing as sizing_mod};
 
+use crate::resume::NewTicket;
+use crate::sched::{Next, Outgoing, Scheduler};
+use crate::tl::{self, KeyUpdatePolicy, PolicyTracker};
 use crate::transition::SignedControl;
+use crate::transport::{memory_pair, FrameTransport};
+use crate::HandshakeParams;
 use core_crypto as crypto;
 use core_framing as framing;
+use curve25519_dalek::scalar::Scalar;
 use serde_cbor as cbor;
 
+pub use crate::sched::Priority;
+pub use core_framing::profile::TrafficProfile;
+pub use core_framing::RecordLayout;
+
 type StreamId = u32;
 
+/// Identifies a datagram flow (e.g. one UDP association) within a connection.
+/// Flow ids are chosen by the application; the mux only carries them.
+pub type FlowId = u32;
+
+/// Tickets from the peer held until the app takes them; older ones are dropped.
+const MAX_QUEUED_TICKETS: usize = 8;
+
+/// Largest datagram payload accepted by [`Mux::send_datagram`]; any UDP payload fits.
+pub const MAX_DATAGRAM_LEN: usize = 65_535;
+
+// Bytes waiting for the carrier beyond which new datagrams are dropped instead of sent:
+// an unreliable message is worthless once it queues behind this much.
+const DATAGRAM_SEND_BACKLOG: usize = 256 * 1024;
+
+// How many recently closed stream ids we remember so late frames for them are
+// dropped instead of being mistaken for a newly opened stream.
+const RETIRED_IDS: usize = 1024;
+
+// Rotate keys once a direction has used this many nonces, whatever the policy says,
+// so the 64-bit counter in `ctr_to_nonce` can never wrap under one key.
+const NONCE_CEILING: u64 = 1 << 48;
+
+// Smallest window any config grants. Senders assume it until the peer's SETTINGS
+// arrive, so nothing sent early can overrun a peer configured with less.
+const MIN_WINDOW: usize = 1024;
+
+/// Reason code carried in an RST_STREAM frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetCode(pub u32);
+
+impl ResetCode {
+    pub const NO_ERROR: ResetCode = ResetCode(0x0);
+    pub const CANCEL: ResetCode = ResetCode(0x1);
+    pub const REFUSED: ResetCode = ResetCode(0x2);
+    pub const INTERNAL: ResetCode = ResetCode(0x3);
+    /// The proxied upstream connection failed or was reset.
+    pub const CONNECT_ERROR: ResetCode = ResetCode(0x4);
+    /// The peer sent more data than the window it was granted.
+    pub const FLOW_CONTROL: ResetCode = ResetCode(0x5);
+    /// The peer broke the mux protocol, e.g. both ends claimed the same [`Role`].
+    pub const PROTOCOL: ResetCode = ResetCode(0x6);
+}
+
+/// How the receive side of a stream ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamEnd {
+    /// The peer sent FIN after its last byte: a clean end of stream.
+    Fin,
+    /// The stream was aborted by either side; buffered data is discarded.
+    Reset(ResetCode),
+    /// The connection went away before the stream finished.
+    ConnectionLost,
+}
+
+/// How outgoing STREAM data is scheduled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulerKind {
+    /// Writers send inline as soon as they hold credit.
+    Inline,
+    /// A writer thread interleaves queued chunks across streams by deficit round-robin,
+    /// most urgent [`Priority`] first and weighted within an urgency level. The only
+    /// mode in which stream priorities change what goes out first.
+    RoundRobin,
+}
+
+/// Which end of the connection a mux is. The two ends open streams from disjoint id
+/// ranges, so streams opened at the same moment by both sides never collide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Role {
+    /// The dialing side; opens odd stream ids (1, 3, 5, ...).
+    #[default]
+    Initiator,
+    /// The accepting side; opens even stream ids (2, 4, 6, ...).
+    Responder,
+}
+
+impl Role {
+    fn first_id(self) -> StreamId {
+        match self {
+            Role::Initiator => 1,
+            Role::Responder => 2,
+        }
+    }
+
+    // True for ids this end allocates; the peer never opens one of them.
+    fn owns(self, id: StreamId) -> bool {
+        id != 0 && id % 2 == self.first_id() % 2
+    }
+}
+
+/// Record size distribution for stealth shaping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizingProfile {
+    Small,
+    #[default]
+    Webby,
+    Bursty,
+}
+
+/// Inter-record delay distribution for stealth shaping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JitterProfile {
+    Small,
+    #[default]
+    Webby,
+}
+
+/// Record sizing and timing used by `stealth-mode` builds; ignored otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StealthProfile {
+    pub sizing: SizingProfile,
+    pub jitter: JitterProfile,
+    /// Fixed seeds make sizes and delays reproducible (tests, captures).
+    pub sizer_seed: Option<u64>,
+    pub jitter_seed: Option<u64>,
+}
+
+/// Periodic PING settings; see [`MuxConfig::keepalive`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    pub interval: Duration,
+    /// Unanswered PINGs in a row after which the peer is declared dead and the connection closed.
+    pub max_missed: u32,
+    /// Pad PINGs (and so their PONGs) to sizes drawn like ordinary data records.
+    pub stealth_sized: bool,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            max_missed: 3,
+            stealth_sized: false,
+        }
+    }
+}
+
+/// Cover traffic settings; see [`MuxConfig::cover_traffic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoverTraffic {
+    /// Time between send slots.
+    pub interval: Duration,
+    /// Payload bytes every slot carries: STREAM data padded up to it, or one PADDING frame.
+    pub record_size: usize,
+    /// Take slot gaps and sizes from the connection's [`TrafficProfile`] instead of
+    /// `interval`/`record_size` (which still apply when none is configured).
+    pub follow_profile: bool,
+    /// Most bytes per second spent on PADDING-only slots; idle slots stay silent once it
+    /// is used up. `None` means no limit.
+    pub budget: Option<usize>,
+}
+
+impl CoverTraffic {
+    pub fn constant_rate(interval: Duration, record_size: usize) -> Self {
+        Self {
+            interval,
+            record_size,
+            follow_profile: false,
+            budget: None,
+        }
+    }
+}
+
+impl Default for CoverTraffic {
+    fn default() -> Self {
+        Self::constant_rate(Duration::from_millis(50), 1024)
+    }
+}
+
+/// Cover traffic counters for one connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoverStats {
+    pub slots: u64,
+    /// Slots that carried stream data.
+    pub data_slots: u64,
+    /// Slots that carried a datagram.
+    pub datagram_slots: u64,
+    /// Slots filled with a PADDING frame.
+    pub padding_slots: u64,
+    /// Idle slots left silent because the budget was used up.
+    pub skipped_slots: u64,
+    /// Stream data and datagram bytes sent in slots.
+    pub data_bytes: u64,
+    /// Filler sent: whole PADDING frames plus the padding after short data.
+    pub padding_bytes: u64,
+}
+
+impl CoverStats {
+    /// Share of slot bytes that were filler rather than stream data (0.0 to 1.0).
+    pub fn overhead(&self) -> f64 {
+        let total = self.data_bytes + self.padding_bytes;
+        if total == 0 {
+            0.0
+        } else {
+            self.padding_bytes as f64 / total as f64
+        }
+    }
+}
+
+/// Round-trip time estimate from PING/PONG exchanges (RFC 6298 smoothing).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttStats {
+    pub smoothed: Duration,
+    pub variance: Duration,
+    pub min: Duration,
+    pub latest: Duration,
+    pub samples: u64,
+}
+
+impl RttStats {
+    fn first(rtt: Duration) -> Self {
+        Self {
+            smoothed: rtt,
+            variance: rtt / 2,
+            min: rtt,
+            latest: rtt,
+            samples: 1,
+        }
+    }
+
+    fn update(&mut self, rtt: Duration) {
+        let err = self.smoothed.abs_diff(rtt);
+        self.variance = (self.variance * 3 + err) / 4;
+        self.smoothed = (self.smoothed * 7 + rtt) / 8;
+        self.min = self.min.min(rtt);
+        self.latest = rtt;
+        self.samples += 1;
+    }
+}
+
+/// Frame-level event passed to a [`MuxConfig::debug_hook`].
+#[derive(Debug, Clone, Copy)]
+pub enum FrameEvent {
+    /// Raw bytes arrived from the wire, before decryption.
+    Wire { len: usize },
+    Received {
+        ty: framing::FrameType,
+        stream: StreamId,
+        payload_len: usize,
+    },
+    Sent {
+        ty: framing::FrameType,
+        stream: StreamId,
+        wire_len: usize,
+        encrypted: bool,
+    },
+    /// A wire frame failed to decrypt or parse and was dropped.
+    Rejected {
+        error: framing::Error,
+        wire_len: usize,
+    },
+}
+
+pub type DebugHook = Arc<dyn Fn(&FrameEvent) + Send + Sync>;
+
+/// Per-connection mux settings.
+///
+/// Start from `MuxConfig::default()` (64 KiB stream window, 1 MiB connection window,
+/// 4 KiB chunks, inline writes) or [`MuxConfig::http`] and chain setters. Only
+/// [`MuxConfig::from_env`] reads the process environment; connections never do.
+#[derive(Clone)]
+pub struct MuxConfig {
+    initial_window: usize,
+    connection_window: usize,
+    memory_cap: usize,
+    chunk_size: usize,
+    role: Role,
+    scheduler: SchedulerKind,
+    stealth: StealthProfile,
+    traffic_profile: Option<Arc<TrafficProfile>>,
+    old_key_overlap: usize,
+    key_update: KeyUpdatePolicy,
+    keepalive: Option<Keepalive>,
+    cover: Option<CoverTraffic>,
+    datagram_queue: usize,
+    record_layout: RecordLayout,
+    inner_handshake: Option<InnerHandshake>,
+    debug_hook: Option<DebugHook>,
+}
+
+/// Noise handshake the secure dial/accept paths run inside the outer TLS; see
+/// [`MuxConfig::inner_handshake`].
+#[derive(Clone)]
+#[cfg_attr(not(feature = "rustls-config"), allow(dead_code))]
+pub(crate) struct InnerHandshake {
+    pub(crate) params: HandshakeParams,
+    pub(crate) local: Scalar,
+    pub(crate) remote: Option<[u8; 32]>,
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        Self {
+            initial_window: 64 * 1024,
+            connection_window: 1024 * 1024,
+            memory_cap: 8 * 1024 * 1024,
+            chunk_size: 4096,
+            role: Role::Initiator,
+            scheduler: SchedulerKind::Inline,
+            stealth: StealthProfile::default(),
+            traffic_profile: None,
+            old_key_overlap: 3,
+            key_update: KeyUpdatePolicy::default(),
+            keepalive: None,
+            cover: None,
+            datagram_queue: 256,
+            record_layout: RecordLayout::Clear,
+            inner_handshake: None,
+            debug_hook: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for MuxConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MuxConfig")
+            .field("initial_window", &self.initial_window)
+            .field("connection_window", &self.connection_window)
+            .field("memory_cap", &self.memory_cap)
+            .field("chunk_size", &self.chunk_size)
+            .field("role", &self.role)
+            .field("scheduler", &self.scheduler)
+            .field("stealth", &self.stealth)
+            .field(
+                "traffic_profile",
+                &self.traffic_profile.as_ref().map(|p| &p.name),
+            )
+            .field("old_key_overlap", &self.old_key_overlap)
+            .field("key_update", &self.key_update)
+            .field("keepalive", &self.keepalive)
+            .field("cover", &self.cover)
+            .field("datagram_queue", &self.datagram_queue)
+            .field("record_layout", &self.record_layout)
+            .field(
+                "inner_handshake",
+                &self.inner_handshake.as_ref().map(|h| h.params.name()),
+            )
+            .field("debug_hook", &self.debug_hook.is_some())
+            .finish()
+    }
+}
+
+impl MuxConfig {
+    /// HTTP-friendly profile: 256 KiB stream window, 4 MiB connection window, 16 KiB chunks,
+    /// round-robin scheduling.
+    pub fn http() -> Self {
+        Self::default()
+            .initial_window(256 * 1024)
+            .connection_window(4 * 1024 * 1024)
+            .chunk_size(16 * 1024)
+            .scheduler(SchedulerKind::RoundRobin)
+    }
+
+    /// Settings from the `HTX_*` / `STEALTH_*` variables the apps document:
+    ///
+    /// - `HTX_SCHEDULER_PROFILE=http`, or a truthy `PREFER_QUIC` without it, starts
+    ///   from [`MuxConfig::http`]; `HTX_SCHEDULER_RR` then forces round-robin on or off.
+    /// - `HTX_INITIAL_WINDOW`, `HTX_CHUNK`, `HTX_CONN_WINDOW`, `HTX_MEMORY_CAP` override sizes.
+    /// - `STEALTH_SIZING_PROFILE` / `STEALTH_JITTER_PROFILE` (any case), `STEALTH_SIZER_SEED`,
+    ///   `STEALTH_JITTER_SEED` and `STEALTH_TRAFFIC_PROFILE` (a learned CBOR profile) shape records.
+    /// - `HTX_KEY_UPDATE_FRAMES` / `HTX_KEY_UPDATE_SECS` set the rekey policy (0 disables a trigger).
+    /// - `HTX_KEEPALIVE_SECS` turns on PINGs (`HTX_KEEPALIVE_MISSED`, `HTX_KEEPALIVE_STEALTH`).
+    /// - `HTX_COVER_INTERVAL_MS` turns on cover traffic (`HTX_COVER_RECORD` bytes per slot,
+    ///   `HTX_COVER_BUDGET` padding bytes/s, `HTX_COVER_PROFILE` follows the traffic profile).
+    /// - `HTX_HIDDEN_RECORDS=<bytes>` selects [`RecordLayout::Hidden`] with that record size.
+    /// - `HTX_DEBUG_MUX` logs every frame, `HTX_DEBUG_FRAMES` only the rejected ones.
+    ///
+    /// Flags accept `1`, `true`, `yes` or `on` in any case; unparsable numbers are ignored.
+    pub fn from_env() -> Self {
+        Self::from_vars(|k| std::env::var(k).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let flag = |k: &str| {
+            var(k).map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        };
+        let on = |k: &str| flag(k).unwrap_or(false);
+        let http = match var("HTX_SCHEDULER_PROFILE") {
+            Some(p) => p.eq_ignore_ascii_case("http"),
+            None => on("PREFER_QUIC"),
+        };
+        let mut cfg = if http { Self::http() } else { Self::default() };
+        if let Some(n) = var("HTX_INITIAL_WINDOW").and_then(|v| v.parse().ok()) {
+            cfg = cfg.initial_window(n);
+        }
+        if let Some(n) = var("HTX_CHUNK").and_then(|v| v.parse().ok()) {
+            cfg = cfg.chunk_size(n);
+        }
+        if let Some(n) = var("HTX_CONN_WINDOW").and_then(|v| v.parse().ok()) {
+            cfg = cfg.connection_window(n);
+        }
+        if let Some(n) = var("HTX_MEMORY_CAP").and_then(|v| v.parse().ok()) {
+            cfg = cfg.memory_cap(n);
+        }
+        match flag("HTX_SCHEDULER_RR") {
+            Some(true) => cfg = cfg.scheduler(SchedulerKind::RoundRobin),
+            Some(false) => cfg = cfg.scheduler(SchedulerKind::Inline),
+            None => {}
+        }
+        let lower = |k: &str| var(k).map(|v| v.to_ascii_lowercase());
+        let sizing = match lower("STEALTH_SIZING_PROFILE").as_deref() {
+            Some("small") => SizingProfile::Small,
+            Some("bursty") => SizingProfile::Bursty,
+            _ => SizingProfile::Webby,
+        };
+        let jitter = match lower("STEALTH_JITTER_PROFILE").as_deref() {
+            Some("small") => JitterProfile::Small,
+            _ => JitterProfile::Webby,
+        };
+        cfg = cfg.stealth_profile(StealthProfile {
+            sizing,
+            jitter,
+            sizer_seed: var("STEALTH_SIZER_SEED").and_then(|v| v.parse().ok()),
+            jitter_seed: var("STEALTH_JITTER_SEED").and_then(|v| v.parse().ok()),
+        });
+        if let Some(path) = var("STEALTH_TRAFFIC_PROFILE") {
+            match TrafficProfile::load(&path) {
+                Ok(p) => cfg = cfg.traffic_profile(p),
+                Err(e) => eprintln!(
+                    "htx::mux: STEALTH_TRAFFIC_PROFILE={path}: {e:?}; using built-in shaping"
+                ),
+            }
+        }
+        let mut policy = KeyUpdatePolicy::default();
+        if let Some(n) = var("HTX_KEY_UPDATE_FRAMES").and_then(|v| v.parse().ok()) {
+            policy.max_frames = n;
+        }
+        if let Some(n) = var("HTX_KEY_UPDATE_SECS").and_then(|v| v.parse().ok()) {
+            policy.max_seconds = n;
+        }
+        cfg = cfg.key_update_policy(policy);
+        if let Some(secs) = var("HTX_KEEPALIVE_SECS").and_then(|v| v.parse::<u64>().ok()) {
+            let mut ka = Keepalive {
+                interval: Duration::from_secs(secs.max(1)),
+                stealth_sized: on("HTX_KEEPALIVE_STEALTH"),
+                ..Keepalive::default()
+            };
+            if let Some(n) = var("HTX_KEEPALIVE_MISSED").and_then(|v| v.parse().ok()) {
+                ka.max_missed = n;
+            }
+            cfg = cfg.keepalive(ka);
+        }
+        if let Some(ms) = var("HTX_COVER_INTERVAL_MS").and_then(|v| v.parse().ok()) {
+            let mut cover = CoverTraffic {
+                interval: Duration::from_millis(ms),
+                follow_profile: on("HTX_COVER_PROFILE"),
+                budget: var("HTX_COVER_BUDGET").and_then(|v| v.parse().ok()),
+                ..CoverTraffic::default()
+            };
+            if let Some(n) = var("HTX_COVER_RECORD").and_then(|v| v.parse().ok()) {
+                cover.record_size = n;
+            }
+            cfg = cfg.cover_traffic(cover);
+        }
+        if let Some(n) = var("HTX_HIDDEN_RECORDS").and_then(|v| v.parse().ok()) {
+            cfg = cfg.record_layout(RecordLayout::hidden(n));
+        }
+        let all_frames = on("HTX_DEBUG_MUX");
+        if all_frames || on("HTX_DEBUG_FRAMES") {
+            cfg = cfg.debug_hook(move |ev| {
+                if all_frames || matches!(ev, FrameEvent::Rejected { .. }) {
+                    eprintln!("htx::mux: {ev:?}");
+                }
+            });
+        }
+        cfg
+    }
+
+    /// Per-stream credit granted to the peer before it must wait for WINDOW_UPDATE (floor 1 KiB).
+    /// Announced in the SETTINGS frame, so the two ends need not agree.
+    pub fn initial_window(mut self, bytes: usize) -> Self {
+        self.initial_window = bytes.max(MIN_WINDOW);
+        self
+    }
+
+    /// Credit shared by all streams of the connection (floor 1 KiB). A stream can only
+    /// send when both its own window and this one have room.
+    pub fn connection_window(mut self, bytes: usize) -> Self {
+        self.connection_window = bytes.max(MIN_WINDOW);
+        self
+    }
+
+    /// Most unread data a connection may buffer (floor 1 KiB). The connection window
+    /// advertised to the peer never exceeds it; a peer that overruns it gets GOAWAY.
+    pub fn memory_cap(mut self, bytes: usize) -> Self {
+        self.memory_cap = bytes.max(MIN_WINDOW);
+        self
+    }
+
+    /// Data bytes per STREAM frame when no stealth profile drives sizing (512 B to 64 KiB).
+    pub fn chunk_size(mut self, bytes: usize) -> Self {
+        self.chunk_size = bytes.clamp(512, 64 * 1024);
+        self
+    }
+
+    /// Which end this mux is (default [`Role::Initiator`]). The two ends of a connection
+    /// must use different roles; dial paths pick the initiator and accept paths the responder.
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    pub fn scheduler(mut self, kind: SchedulerKind) -> Self {
+        self.scheduler = kind;
+        self
+    }
+
+    pub fn stealth_profile(mut self, profile: StealthProfile) -> Self {
+        self.stealth = profile;
+        self
+    }
+
+    /// Draw record sizes and delays from a profile learned off real traffic instead of
+    /// the built-in `sizing`/`jitter` ranges; the stealth profile's seeds still apply.
+    pub fn traffic_profile(mut self, profile: TrafficProfile) -> Self {
+        self.traffic_profile = Some(Arc::new(profile));
+        self
+    }
+
+    /// Frames still accepted under the previous receive key after a KEY_UPDATE.
+    pub fn old_key_overlap(mut self, frames: usize) -> Self {
+        self.old_key_overlap = frames;
+        self
+    }
+
+    /// When encrypted muxes rotate their send key on their own; see [`Mux::key_update`].
+    pub fn key_update_policy(mut self, policy: KeyUpdatePolicy) -> Self {
+        self.key_update = policy;
+        self
+    }
+
+    /// Send PINGs on a timer, track RTT and close the connection when the peer stops answering.
+    /// Off by default; incoming PINGs are answered either way.
+    pub fn keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Send on a fixed schedule: every slot carries one record of stream data or, when
+    /// nothing is queued, a PADDING frame of the same size, so idle periods and bursts
+    /// look alike. Stream data then always goes through the scheduler and waits for a
+    /// slot, which caps throughput at `record_size / interval`. Control frames are not
+    /// delayed. Datagrams wait for a slot too and take it ahead of stream data, unpadded.
+    /// Combine with [`RecordLayout::Hidden`] so the frame type is not visible.
+    pub fn cover_traffic(mut self, cover: CoverTraffic) -> Self {
+        self.cover = Some(CoverTraffic {
+            record_size: cover.record_size.clamp(64, 64 * 1024),
+            interval: cover.interval.max(Duration::from_millis(1)),
+            ..cover
+        });
+        self
+    }
+
+    /// Received datagrams held for the app before the oldest is dropped (floor 1). With
+    /// cover traffic the same bound applies to datagrams waiting for a send slot.
+    pub fn datagram_queue(mut self, datagrams: usize) -> Self {
+        self.datagram_queue = datagrams.max(1);
+        self
+    }
+
+    /// How encrypted frames are laid out on the wire. Both ends must agree; the secure
+    /// dial/accept paths exchange it in [`crate::inner::Caps`] and refuse a peer that
+    /// picked another one. Ignored by plaintext muxes.
+    pub fn record_layout(mut self, layout: RecordLayout) -> Self {
+        self.record_layout = layout;
+        self
+    }
+
+    pub(crate) fn layout(&self) -> RecordLayout {
+        self.record_layout
+    }
+
+    /// Run a `params` Noise handshake inside the outer TLS before the mux starts, and
+    /// key the mux from it as well as from the TLS exporter. `local` is this end's
+    /// static key; dialers pass the edge's static as `remote` when the pattern needs
+    /// one. The secure dial/accept paths exchange the pattern in [`crate::inner::Caps`]
+    /// and refuse a peer that picked another; without this they key the mux from the
+    /// exporter alone. Ignored by in-process pairs.
+    pub fn inner_handshake(
+        mut self,
+        params: HandshakeParams,
+        local: Scalar,
+        remote: Option<[u8; 32]>,
+    ) -> Self {
+        self.inner_handshake = Some(InnerHandshake {
+            params,
+            local,
+            remote,
+        });
+        self
+    }
+
+    #[cfg_attr(not(feature = "rustls-config"), allow(dead_code))]
+    pub(crate) fn handshake(&self) -> Option<&InnerHandshake> {
+        self.inner_handshake.as_ref()
+    }
+
+    /// Observe every frame sent, received or rejected. Runs on mux threads, so keep it cheap.
+    pub fn debug_hook(mut self, hook: impl Fn(&FrameEvent) + Send + Sync + 'static) -> Self {
+        self.debug_hook = Some(Arc::new(hook));
+        self
+    }
+}
+
 #[derive(Clone)]
 pub struct Mux {
     inner: Arc<Inner>,
@@ -22,49 +642,319 @@ pub struct Mux {
 
 struct Inner {
     // Wire channels
-    tx: mpsc::Sender<Bytes>,          // to peer
-    rx: Mutex<mpsc::Receiver<Bytes>>, // from peer (Mutex to make Inner Sync)
+    tx: Mutex<Option<mpsc::Sender<Bytes>>>, // to the wire writer; taken on close so the wire shuts
+    // Bytes handed to the wire writer and not yet written to the carrier
+    wire_backlog: Arc<AtomicUsize>,
+    transport: Arc<dyn FrameTransport>,
+    // Set once the reader loop exits (peer gone); wakes every blocked reader/writer
+    closed: AtomicBool,
     // Optional encryption state (L2 AEAD + KEY_UPDATE)
     enc: Mutex<Option<EncState>>,
     // Key epoch increments whenever tx or rx key rotates
     key_epoch: Mutex<u64>,
-    // Remote credit per stream (how many bytes we can send to peer)
-    remote_credit: Mutex<HashMap<StreamId, usize>>,
-    // Incoming data buffers per stream (to app)
-    incoming: Mutex<HashMap<StreamId, mpsc::Sender<Vec<u8>>>>,
+    // Remote credit per stream and for the connection (how many bytes we can send to peer)
+    remote_credit: Mutex<SendCredit>,
+    // Receive-side accounting against the connection window we granted
+    recv: Mutex<RecvWindow>,
+    // Incoming data buffers and close state per stream (to app)
+    incoming: Mutex<HashMap<StreamId, Arc<StreamState>>>,
+    // Signalled whenever a stream is released, so shutdown can wait for the map to empty
+    idle_cv: Condvar,
+    // GOAWAY bookkeeping for graceful drain
+    drain: Mutex<Drain>,
+    // Recently closed stream ids (bounded, oldest first)
+    retired: Mutex<VecDeque<StreamId>>,
     // New stream notifications
-    accept_tx: mpsc::Sender<StreamHandle>,
-    accept_rx: Mutex<mpsc::Receiver<StreamHandle>>,
-    // Notifier for writers awaiting credit
+    accept_q: WaitQueue<StreamHandle>,
+    // Notifier for writers awaiting credit (blocking writers use the condvar, async writers park a waker)
     credit_cv: Condvar,
-    // Stream ID allocator (even for one side, odd for the other could be better; use sequential)
+    credit_wakers: Mutex<HashMap<StreamId, Waker>>,
+    // Stream ID allocator, stepping by two within our role's half of the id space
+    role: Role,
     next_id: Mutex<StreamId>,
     // Control stream state (ID 0); when closed during rekey-close, data writes are ignored until resumed
     control_open: Mutex<bool>,
 
-    // Flow-control defaults (tunable for HTTP-friendly behavior)
+    // Flow-control settings (from MuxConfig), advertised in SETTINGS and enforced on receive
     initial_window: usize,
+    // min(connection_window, memory_cap)
+    conn_window: usize,
     base_chunk: usize,
+    // Old-key frames accepted after a KEY_UPDATE
+    old_key_overlap: usize,
+    key_policy: KeyUpdatePolicy,
+    started: Instant,
+    keepalive: Option<Keepalive>,
+    ping: Mutex<PingState>,
+    ping_cv: Condvar,
+    cover: Option<CoverTraffic>,
+    cover_stats: Mutex<CoverStats>,
+    traffic_profile: Option<Arc<TrafficProfile>>,
+    // Received datagrams, bounded by datagram_cap (oldest dropped first)
+    datagrams: WaitQueue<Datagram>,
+    datagram_cap: usize,
+    datagram_stats: Mutex<DatagramStats>,
+    // DATAGRAM payloads waiting for a cover slot, bounded by datagram_cap (oldest dropped first)
+    datagrams_out: Mutex<VecDeque<Vec<u8>>>,
+    // Resumption tickets from the peer's control stream, newest kept
+    tickets: WaitQueue<NewTicket>,
+    debug_hook: Option<DebugHook>,
 
-    // Round-robin writer scheduler
+    // Priority scheduler feeding the writer thread (RoundRobin); also holds stream priorities
     rr_enabled: bool,
-    rr_tx: mpsc::Sender<WriteReq>,
-    rr_rx: Mutex<mpsc::Receiver<WriteReq>>, // Mutex to make Inner Sync
-    rr_queues: Mutex<HashMap<StreamId, VecDeque<Vec<u8>>>>,
+    sched: Mutex<Scheduler>,
+    // Signalled when the scheduler may have something sendable
+    sched_cv: Condvar,
 
     #[cfg(feature = "stealth-mode")]
     shaper: Mutex<Option<StealthShaper>>, // record sizing + jitter
 }
 
-struct WriteReq {
-    id: StreamId,
-    chunk: Vec<u8>,
-}
-
+/// One multiplexed stream.
+///
+/// The blocking `read`/`write` methods suit plain threads; the same handle also
+/// implements tokio's `AsyncRead`/`AsyncWrite`, parking a waker instead of a
+/// thread while it waits for data or peer credit. The inherent methods shadow
+/// `AsyncReadExt::read`/`AsyncWriteExt::write`, so call those in qualified form.
+///
+/// Dropping the handle (or calling [`StreamHandle::close`]) sends FIN; the
+/// stream's state is released once both sides have finished or either reset it.
 pub struct StreamHandle {
     id: StreamId,
     mux: Mux,
-    rx: mpsc::Receiver<Vec<u8>>, // data chunks arriving for this stream
+    st: Arc<StreamState>,
+}
+
+// Per-stream state shared by the reader thread, the writers and the handle.
+struct StreamState {
+    rx: WaitQueue<Vec<u8>>, // data chunks arriving for this stream
+    ends: Mutex<Ends>,
+    local: bool, // opened by us (vs accepted from the peer)
+}
+
+struct SendCredit {
+    // Shared by every stream; replenished by WINDOW_UPDATE on stream 0
+    conn: usize,
+    streams: HashMap<StreamId, usize>,
+    // Credit the peer grants each new stream; MIN_WINDOW until its SETTINGS arrive
+    stream_window: usize,
+    settled: bool,
+}
+
+impl SendCredit {
+    // Take up to `needed` bytes of credit that both the stream and the connection can spare.
+    // None once the stream is gone; Some(0) means wait for a WINDOW_UPDATE.
+    fn take(&mut self, id: StreamId, needed: usize) -> Option<usize> {
+        let stream = self.streams.get_mut(&id)?;
+        let take = (*stream).min(self.conn).min(needed);
+        *stream -= take;
+        self.conn -= take;
+        Some(take)
+    }
+
+    // Apply the peer's SETTINGS. Everything so far assumed MIN_WINDOW, so top the
+    // connection and every open stream up to what the peer actually grants.
+    fn settle(&mut self, stream_window: usize, conn_window: usize) {
+        if std::mem::replace(&mut self.settled, true) {
+            return;
+        }
+        let extra = stream_window.saturating_sub(MIN_WINDOW);
+        for credit in self.streams.values_mut() {
+            *credit = credit.saturating_add(extra);
+        }
+        self.conn = self
+            .conn
+            .saturating_add(conn_window.saturating_sub(MIN_WINDOW));
+        self.stream_window = stream_window.max(MIN_WINDOW);
+    }
+}
+
+#[derive(Default)]
+struct RecvWindow {
+    // Received data not yet consumed (or discarded) by the app
+    buffered: usize,
+    // Consumed but not yet returned to the peer; sent in batches as connection credit
+    unacked: usize,
+}
+
+#[derive(Default)]
+struct Drain {
+    // Highest stream id the peer opened and we accepted; always in the peer's half of the
+    // id space, so it says nothing about streams we opened ourselves
+    last_accepted: StreamId,
+    // GOAWAY we sent (carrying last_accepted at the time); new peer streams are refused
+    sent: Option<StreamId>,
+    // GOAWAY the peer sent: our streams above this id were never served
+    received: Option<StreamId>,
+}
+
+#[derive(Default)]
+struct Ends {
+    // Set (before rx is closed) once no more data will arrive
+    read_end: Option<StreamEnd>,
+    // No further writes accepted (FIN queued or RST sent)
+    write_shut: bool,
+    // FIN/RST is on the wire
+    fin_sent: bool,
+    // Handle dropped: acknowledge and discard anything still arriving
+    detached: bool,
+    // Stream credit the peer still holds from our point of view
+    recv_window: usize,
+}
+
+impl StreamState {
+    fn new(local: bool, window: usize) -> Arc<Self> {
+        Arc::new(Self {
+            rx: WaitQueue::new(),
+            ends: Mutex::new(Ends {
+                recv_window: window,
+                ..Ends::default()
+            }),
+            local,
+        })
+    }
+
+    // Record how reads end and wake readers; a reset drops buffered data.
+    // Returns how many buffered bytes were dropped.
+    fn end_read(&self, end: StreamEnd) -> usize {
+        {
+            let mut e = self.ends.lock().unwrap();
+            match end {
+                StreamEnd::Reset(_) => {
+                    e.read_end = Some(end);
+                    e.write_shut = true;
+                    e.fin_sent = true;
+                }
+                _ if e.read_end.is_none() => e.read_end = Some(end),
+                _ => {}
+            }
+        }
+        let dropped = match end {
+            StreamEnd::Reset(_) => self.rx.clear().iter().map(Vec::len).sum(),
+            _ => 0,
+        };
+        self.rx.close();
+        dropped
+    }
+}
+
+/// FIFO shared between the mux reader thread and its consumers.
+///
+/// Consumers may block on it (`pop_timeout`) or poll it from an async task
+/// (`poll_pop`), so no helper thread is needed to bridge into a runtime.
+struct WaitQueue<T> {
+    state: Mutex<QueueState<T>>,
+    cv: Condvar,
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    wakers: Vec<Waker>,
+}
+
+impl<T> WaitQueue<T> {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                items: VecDeque::new(),
+                closed: false,
+                wakers: Vec::new(),
+            }),
+            cv: Condvar::new(),
+        }
+    }
+
+    // Items arriving after close are dropped (e.g. data racing a local reset);
+    // returns whether the item was queued.
+    fn push(&self, item: T) -> bool {
+        let mut st = self.state.lock().unwrap();
+        if st.closed {
+            return false;
+        }
+        st.items.push_back(item);
+        Self::notify(&mut st, &self.cv);
+        true
+    }
+
+    // Return an unconsumed remainder to the head of the queue (partial async reads).
+    fn push_front(&self, item: T) {
+        let mut st = self.state.lock().unwrap();
+        st.items.push_front(item);
+    }
+
+    fn close(&self) {
+        let mut st = self.state.lock().unwrap();
+        st.closed = true;
+        Self::notify(&mut st, &self.cv);
+    }
+
+    fn notify(st: &mut QueueState<T>, cv: &Condvar) {
+        cv.notify_all();
+        for w in st.wakers.drain(..) {
+            w.wake();
+        }
+    }
+
+    // Push, evicting the oldest item once `cap` are queued. Returns how many were evicted
+    // (or 1 if the queue is closed and the item itself was dropped).
+    fn push_bounded(&self, item: T, cap: usize) -> usize {
+        let mut st = self.state.lock().unwrap();
+        if st.closed {
+            return 1;
+        }
+        let mut evicted = 0;
+        while st.items.len() >= cap {
+            st.items.pop_front();
+            evicted += 1;
+        }
+        st.items.push_back(item);
+        Self::notify(&mut st, &self.cv);
+        evicted
+    }
+
+    // Drop everything queued, handing the discarded items back.
+    fn clear(&self) -> Vec<T> {
+        self.state.lock().unwrap().items.drain(..).collect()
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        self.state.lock().unwrap().items.pop_front()
+    }
+
+    // Blocks until an item arrives, the queue closes, or the timeout elapses.
+    fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut st = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = st.items.pop_front() {
+                return Some(item);
+            }
+            if st.closed {
+                return None;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            st = self.cv.wait_timeout(st, deadline - now).unwrap().0;
+        }
+    }
+
+    // Ready(None) means the queue is closed and drained.
+    fn poll_pop(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut st = self.state.lock().unwrap();
+        if let Some(item) = st.items.pop_front() {
+            return Poll::Ready(Some(item));
+        }
+        if st.closed {
+            return Poll::Ready(None);
+        }
+        if !st.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            st.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
 }
 
 #[cfg(feature = "stealth-mode")]
@@ -80,11 +970,91 @@ struct EncState {
     // transmit state
     tx_key: [u8; 32],
     tx_ctr: u64,
+    // frames and age under the current tx key, checked against the KeyUpdatePolicy
+    tx_policy: PolicyTracker,
+    tx_epoch: u64,
+    auto_updates: u64,
     // receive state (new/current)
     rx_key: [u8; 32],
     rx_ctr: u64,
+    rx_epoch: u64,
     // old key overlap window (accept with old key up to remaining frames)
     rx_old: Option<OldKey>,
+    layout: RecordLayout,
+}
+
+impl EncState {
+    fn seal(&mut self, frame: &framing::Frame) -> Bytes {
+        let nonce = Mux::ctr_to_nonce(self.tx_ctr);
+        let out = self
+            .layout
+            .seal(frame, framing::KeyCtx { key: self.tx_key }, nonce);
+        self.tx_ctr += 1;
+        self.tx_policy.on_frame_sent(frame);
+        out
+    }
+
+    fn rotate_tx(&mut self, now: u64) {
+        self.tx_key = next_key(&self.tx_key);
+        self.tx_ctr = 0;
+        self.tx_epoch += 1;
+        self.tx_policy.mark_updated(now);
+    }
+
+    fn rekey_due(&self, policy: KeyUpdatePolicy, now: u64) -> bool {
+        // The last nonce under a key is reserved for the KEY_UPDATE itself
+        self.tx_ctr >= NONCE_CEILING - 1 || tl::should_key_update(policy, &self.tx_policy, now)
+    }
+}
+
+fn next_key(key: &[u8; 32]) -> [u8; 32] {
+    let prk = crypto::hkdf::extract(key, b"qnet/mux/key_update/v1");
+    crypto::hkdf::expand(&prk, b"key")
+}
+
+/// Key rotation counters for one connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyMetrics {
+    /// Total rotations in both directions (same as [`Mux::encryption_epoch`]).
+    pub epoch: u64,
+    pub tx_epoch: u64,
+    pub rx_epoch: u64,
+    /// Send-key rotations triggered by the policy or nonce ceiling rather than the app.
+    pub auto_updates: u64,
+    pub tx_frames_since_update: u64,
+    pub rx_frames_since_update: u64,
+}
+
+/// An unreliable, unordered message on a connection; see [`Mux::send_datagram`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub flow: FlowId,
+    pub data: Vec<u8>,
+}
+
+/// Datagram counters for one connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatagramStats {
+    pub sent: u64,
+    pub received: u64,
+    /// Received datagrams discarded because the app fell behind the queue bound.
+    pub dropped: u64,
+    /// Datagrams discarded before sending: the carrier was backed up or, with cover
+    /// traffic, more were waiting for a slot than the queue bound allows.
+    pub send_dropped: u64,
+}
+
+// PING payload: flags (u8) | opaque (u64) | padding. A PONG echoes the whole payload with PING_ACK set.
+const PING_ACK: u8 = 0x1;
+const PING_HDR: usize = 9;
+
+#[derive(Default)]
+struct PingState {
+    next_opaque: u64,
+    // Sent and not yet answered, oldest first
+    outstanding: VecDeque<(u64, Instant)>,
+    missed: u32,
+    rtt: Option<RttStats>,
 }
 
 #[derive(Clone)]
@@ -95,61 +1065,129 @@ struct OldKey {
 }
 
 impl Mux {
-    pub fn new(tx: mpsc::Sender<Bytes>, rx: mpsc::Receiver<Bytes>) -> Self {
-        let (accept_tx, accept_rx) = mpsc::channel();
-        let (initial_window, base_chunk) = scheduler_defaults_from_env();
-        let rr_enabled = should_enable_rr_scheduler();
-        let (rr_tx, rr_rx) = mpsc::channel();
+    pub fn new(transport: impl FrameTransport + 'static, cfg: MuxConfig) -> Self {
+        Self::start(transport, cfg, None)
+    }
+
+    pub fn new_encrypted(
+        transport: impl FrameTransport + 'static,
+        tx_key: [u8; 32],
+        rx_key: [u8; 32],
+        cfg: MuxConfig,
+    ) -> Self {
+        let enc = EncState {
+            tx_key,
+            tx_ctr: 0,
+            tx_policy: PolicyTracker::new(0),
+            tx_epoch: 0,
+            auto_updates: 0,
+            rx_key,
+            rx_ctr: 0,
+            rx_epoch: 0,
+            rx_old: None,
+            layout: cfg.record_layout,
+        };
+        Self::start(transport, cfg, Some(enc))
+    }
+
+    // Encryption is in place before the reader runs or SETTINGS goes out, so the
+    // very first frame in each direction is already sealed.
+    fn start(
+        transport: impl FrameTransport + 'static,
+        cfg: MuxConfig,
+        enc: Option<EncState>,
+    ) -> Self {
+        let transport: Arc<dyn FrameTransport> = Arc::new(transport);
+        let (tx, rx) = mpsc::channel::<Bytes>();
+        let wire_backlog = Arc::new(AtomicUsize::new(0));
+        Self::spawn_wire_writer(transport.clone(), rx, wire_backlog.clone());
+        // Cover traffic sends stream data only in its slots, so it needs the queue
+        let rr_enabled = cfg.scheduler == SchedulerKind::RoundRobin || cfg.cover.is_some();
         let inner = Arc::new(Inner {
-            tx,
-            rx: Mutex::new(rx),
-            enc: Mutex::new(None),
+            tx: Mutex::new(Some(tx)),
+            wire_backlog,
+            transport,
+            closed: AtomicBool::new(false),
+            enc: Mutex::new(enc),
             key_epoch: Mutex::new(0),
-            remote_credit: Mutex::new(HashMap::new()),
+            remote_credit: Mutex::new(SendCredit {
+                conn: MIN_WINDOW,
+                streams: HashMap::new(),
+                stream_window: MIN_WINDOW,
+                settled: false,
+            }),
+            recv: Mutex::new(RecvWindow::default()),
             incoming: Mutex::new(HashMap::new()),
-            accept_tx,
-            accept_rx: Mutex::new(accept_rx),
+            idle_cv: Condvar::new(),
+            drain: Mutex::new(Drain::default()),
+            retired: Mutex::new(VecDeque::new()),
+            accept_q: WaitQueue::new(),
             credit_cv: Condvar::new(),
-            next_id: Mutex::new(1),
+            credit_wakers: Mutex::new(HashMap::new()),
+            role: cfg.role,
+            next_id: Mutex::new(cfg.role.first_id()),
             control_open: Mutex::new(true),
-            initial_window,
-            base_chunk,
+            initial_window: cfg.initial_window,
+            conn_window: cfg.connection_window.min(cfg.memory_cap),
+            base_chunk: cfg.chunk_size,
+            old_key_overlap: cfg.old_key_overlap,
+            key_policy: cfg.key_update,
+            started: Instant::now(),
+            keepalive: cfg.keepalive,
+            ping: Mutex::new(PingState::default()),
+            ping_cv: Condvar::new(),
+            cover: cfg.cover,
+            cover_stats: Mutex::new(CoverStats::default()),
+            traffic_profile: cfg.traffic_profile.clone(),
+            datagrams: WaitQueue::new(),
+            datagram_cap: cfg.datagram_queue,
+            datagram_stats: Mutex::new(DatagramStats::default()),
+            datagrams_out: Mutex::new(VecDeque::new()),
+            tickets: WaitQueue::new(),
+            debug_hook: cfg.debug_hook.clone(),
             rr_enabled,
-            rr_tx,
-            rr_rx: Mutex::new(rr_rx),
-            rr_queues: Mutex::new(HashMap::new()),
+            sched: Mutex::new(Scheduler::new(cfg.chunk_size)),
+            sched_cv: Condvar::new(),
             #[cfg(feature = "stealth-mode")]
-            shaper: Mutex::new(init_stealth_shaper_from_env()),
+            shaper: Mutex::new(Some(StealthShaper::new(
+                &cfg.stealth,
+                cfg.traffic_profile.clone(),
+            ))),
         });
 
         let mux = Mux {
             inner: inner.clone(),
         };
+        mux.send_settings();
         mux.spawn_reader();
-        if mux.inner.rr_enabled {
+        if let Some(cover) = mux.inner.cover {
+            mux.spawn_cover_writer(cover);
+        } else if mux.inner.rr_enabled {
             mux.spawn_rr_writer();
         }
+        if let Some(ka) = mux.inner.keepalive {
+            mux.spawn_keepalive(ka);
+        }
         mux
     }
 
-    pub fn new_encrypted(
-        tx: mpsc::Sender<Bytes>,
+    // Frames leave through a queue so senders never block on the carrier.
+    fn spawn_wire_writer(
+        transport: Arc<dyn FrameTransport>,
         rx: mpsc::Receiver<Bytes>,
-        tx_key: [u8; 32],
-        rx_key: [u8; 32],
-    ) -> Self {
-        let mux = Mux::new(tx, rx);
-        {
-            let mut enc = mux.inner.enc.lock().unwrap();
-            *enc = Some(EncState {
-                tx_key,
-                tx_ctr: 0,
-                rx_key,
-                rx_ctr: 0,
-                rx_old: None,
-            });
-        }
-        mux
+        backlog: Arc<AtomicUsize>,
+    ) {
+        thread::spawn(move || {
+            while let Ok(bytes) = rx.recv() {
+                let sent = transport.send_frame(&bytes);
+                backlog.fetch_sub(bytes.len(), Ordering::Relaxed);
+                if sent.is_err() {
+                    break;
+                }
+            }
+            // Mux closed its side (e.g. after a drain): pass the FIN on to the peer
+            let _ = transport.close();
+        });
     }
 
     fn spawn_reader(&self) {
@@ -157,15 +1195,36 @@ impl Mux {
         thread::spawn(move || {
             // Maintain local receive window and auto send WINDOW_UPDATE after app reads; we implement credit release in StreamHandle::read
             loop {
-                let bytes = {
-                    let rx = &mut *inner.rx.lock().unwrap();
-                    match rx.recv() {
-                        Ok(b) => b,
-                        Err(_) => break,
+                let bytes = match inner.transport.recv_frame() {
+                    Ok(Some(b)) => b,
+                    Ok(None) => break,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock
+                                | io::ErrorKind::TimedOut
+                                | io::ErrorKind::Interrupted
+                        ) =>
+                    {
+                        if inner.closed.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        continue;
                     }
+                    Err(_) => break,
                 };
-                if std::env::var("HTX_DEBUG_MUX").ok().as_deref() == Some("1") {
-                    eprintln!("htx::mux(rx): got wire bytes len={}", bytes.len());
+                if let Some(hook) = &inner.debug_hook {
+                    hook(&FrameEvent::Wire { len: bytes.len() });
+                }
+                // A peer that never rotates would eventually reuse nonces; stop before that
+                let exhausted = inner
+                    .enc
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .is_some_and(|st| st.rx_ctr >= NONCE_CEILING);
+                if exhausted {
+                    break;
                 }
                 // If encrypted, attempt AEAD decode with current/new key, else fall back to plain
                 let frame_res = {
@@ -174,10 +1233,11 @@ impl Mux {
                         // Try new/current key first
                         let nonce = Self::ctr_to_nonce(st.rx_ctr);
                         let keyctx = framing::KeyCtx { key: st.rx_key };
-                        match framing::decode(&bytes, keyctx, nonce) {
-                            Ok(f) => {
+                        match st.layout.open(&bytes, keyctx, nonce) {
+                            // An ignorable extension frame authenticated, so it used up its nonce
+                            res @ (Ok(_) | Err(framing::Error::Ignored(_))) => {
                                 st.rx_ctr = st.rx_ctr.saturating_add(1);
-                                Ok(f)
+                                res
                             }
                             Err(_) => {
                                 // Try old key if overlap window active
@@ -185,14 +1245,14 @@ impl Mux {
                                     if old.remaining > 0 {
                                         let nonce_old = Self::ctr_to_nonce(old.ctr);
                                         let keyctx_old = framing::KeyCtx { key: old.key };
-                                        match framing::decode(&bytes, keyctx_old, nonce_old) {
-                                            Ok(f) => {
+                                        match st.layout.open(&bytes, keyctx_old, nonce_old) {
+                                            res @ (Ok(_) | Err(framing::Error::Ignored(_))) => {
                                                 old.ctr = old.ctr.saturating_add(1);
                                                 old.remaining -= 1;
                                                 if old.remaining == 0 {
                                                     st.rx_old = None;
                                                 }
-                                                Ok(f)
+                                                res
                                             }
                                             Err(_) => Err(framing::Error::Crypto),
                                         }
@@ -210,30 +1270,12 @@ impl Mux {
                 };
                 match frame_res {
                     Ok(frame) => {
-                        if std::env::var("HTX_DEBUG_MUX").ok().as_deref() == Some("1") {
-                            let ty = match frame.ty {
-                                framing::FrameType::Stream => "Stream",
-                                framing::FrameType::WindowUpdate => "WindowUpdate",
-                                framing::FrameType::Ping => "Ping",
-                                framing::FrameType::KeyUpdate => "KeyUpdate",
-                                framing::FrameType::Close => "Close",
-                            };
-                            let id = if frame.payload.len() >= 4 {
-                                u32::from_be_bytes([
-                                    frame.payload[0],
-                                    frame.payload[1],
-                                    frame.payload[2],
-                                    frame.payload[3],
-                                ])
-                            } else {
-                                0
-                            };
-                            eprintln!(
-                                "htx::mux(rx): frame ty={} id={} payload_len={}",
-                                ty,
-                                id,
-                                frame.payload.len()
-                            );
+                        if let Some(hook) = &inner.debug_hook {
+                            hook(&FrameEvent::Received {
+                                ty: frame.ty,
+                                stream: leading_stream_id(&frame.payload),
+                                payload_len: frame.payload.len(),
+                            });
                         }
                         match frame.ty {
                             framing::FrameType::Stream => {
@@ -278,31 +1320,111 @@ impl Mux {
                                         // For simplicity, toggle control_open=false on any valid control message and set true after processing next KeyUpdate.
                                         let mut ctrl = inner.control_open.lock().unwrap();
                                         *ctrl = false;
+                                    } else if let Ok(nt) = cbor::from_slice::<NewTicket>(&data) {
+                                        inner.tickets.push_bounded(nt, MAX_QUEUED_TICKETS);
                                     }
                                     continue;
                                 }
 
-                                let mut incoming = inner.incoming.lock().unwrap();
-                                if let Some(tx) = incoming.get(&id) {
-                                    let _ = tx.send(data);
-                                } else {
-                                    // New stream: create channel and notify acceptor
-                                    let (tx_data, rx_data) = mpsc::channel();
-                                    let _ = tx_data.send(data);
-                                    incoming.insert(id, tx_data);
-                                    // Initialize remote credit so we can send back immediately
-                                    {
-                                        let mut rem = inner.remote_credit.lock().unwrap();
-                                        rem.insert(id, inner.initial_window);
-                                    }
-                                    let handle = StreamHandle {
-                                        id,
-                                        mux: Mux {
-                                            inner: inner.clone(),
-                                        },
-                                        rx: rx_data,
+                                let mux = Mux {
+                                    inner: inner.clone(),
+                                };
+                                if !mux.on_stream_data(id, data) {
+                                    // Peer overran the connection window; on_stream_data sent GOAWAY
+                                    break;
+                                }
+                            }
+                            framing::FrameType::Close => {
+                                // payload: stream_id (u32); the peer will send no more data on it
+                                if frame.payload.len() < 4 {
+                                    continue;
+                                }
+                                let id = u32::from_be_bytes([
+                                    frame.payload[0],
+                                    frame.payload[1],
+                                    frame.payload[2],
+                                    frame.payload[3],
+                                ]);
+                                let mux = Mux {
+                                    inner: inner.clone(),
+                                };
+                                let st = inner.incoming.lock().unwrap().get(&id).cloned();
+                                // A FIN for a stream we never saw data on still opens (and ends) it
+                                let st = match st {
+                                    Some(st) => st,
+                                    None => match mux.accept_remote(id) {
+                                        Some(st) => st,
+                                        None => continue,
+                                    },
+                                };
+                                st.end_read(StreamEnd::Fin);
+                                if st.ends.lock().unwrap().fin_sent {
+                                    mux.forget_stream(id);
+                                }
+                            }
+                            framing::FrameType::RstStream => {
+                                // payload: stream_id (u32) || reset code (u32)
+                                if frame.payload.len() < 8 {
+                                    continue;
+                                }
+                                let id = u32::from_be_bytes([
+                                    frame.payload[0],
+                                    frame.payload[1],
+                                    frame.payload[2],
+                                    frame.payload[3],
+                                ]);
+                                let code = u32::from_be_bytes([
+                                    frame.payload[4],
+                                    frame.payload[5],
+                                    frame.payload[6],
+                                    frame.payload[7],
+                                ]);
+                                let st = inner.incoming.lock().unwrap().get(&id).cloned();
+                                if let Some(st) = st {
+                                    let mux = Mux {
+                                        inner: inner.clone(),
                                     };
-                                    let _ = inner.accept_tx.send(handle);
+                                    mux.release_conn(
+                                        st.end_read(StreamEnd::Reset(ResetCode(code))),
+                                    );
+                                    mux.forget_stream(id);
+                                }
+                            }
+                            framing::FrameType::GoAway => {
+                                // payload: last_stream_id (u32) || code (u32)
+                                if frame.payload.len() < 8 {
+                                    continue;
+                                }
+                                let last = u32::from_be_bytes([
+                                    frame.payload[0],
+                                    frame.payload[1],
+                                    frame.payload[2],
+                                    frame.payload[3],
+                                ]);
+                                Mux {
+                                    inner: inner.clone(),
+                                }
+                                .on_goaway(last);
+                            }
+                            framing::FrameType::Priority => {
+                                // payload: stream_id (u32) || urgency (u8) || weight (u8)
+                                if frame.payload.len() < 6 {
+                                    continue;
+                                }
+                                let id = u32::from_be_bytes([
+                                    frame.payload[0],
+                                    frame.payload[1],
+                                    frame.payload[2],
+                                    frame.payload[3],
+                                ]);
+                                let prio = Priority::new(frame.payload[4], frame.payload[5]);
+                                let mux = Mux {
+                                    inner: inner.clone(),
+                                };
+                                // Like FIN, a PRIORITY may be the first frame of a new stream
+                                let known = inner.incoming.lock().unwrap().contains_key(&id);
+                                if known || (id != 0 && mux.accept_remote(id).is_some()) {
+                                    inner.sched.lock().unwrap().set_priority(id, prio);
                                 }
                             }
                             framing::FrameType::KeyUpdate => {
@@ -312,16 +1434,12 @@ impl Mux {
                                     let old = OldKey {
                                         key: st.rx_key,
                                         ctr: st.rx_ctr,
-                                        remaining: 3,
+                                        remaining: inner.old_key_overlap,
                                     };
-                                    let prk = crypto::hkdf::extract(
-                                        &st.rx_key,
-                                        b"qnet/mux/key_update/v1",
-                                    );
-                                    let newk: [u8; 32] = crypto::hkdf::expand(&prk, b"key");
                                     st.rx_old = Some(old);
-                                    st.rx_key = newk;
+                                    st.rx_key = next_key(&st.rx_key);
                                     st.rx_ctr = 0;
+                                    st.rx_epoch += 1;
                                 }
                                 // Bump epoch on rx rotation
                                 let mut ep = inner.key_epoch.lock().unwrap();
@@ -347,52 +1465,599 @@ impl Mux {
                                     frame.payload[6],
                                     frame.payload[7],
                                 ]) as usize;
-                                let mut rem = inner.remote_credit.lock().unwrap();
-                                let e = rem.entry(id).or_insert(0);
-                                *e = e.saturating_add(inc);
-                                inner.credit_cv.notify_all();
+                                {
+                                    let mut rem = inner.remote_credit.lock().unwrap();
+                                    if id == 0 {
+                                        rem.conn = rem.conn.saturating_add(inc);
+                                    } else if let Some(e) = rem.streams.get_mut(&id) {
+                                        // Updates for streams that are already gone are ignored
+                                        *e = e.saturating_add(inc);
+                                    }
+                                    inner.credit_cv.notify_all();
+                                }
+                                Mux {
+                                    inner: inner.clone(),
+                                }
+                                .wake_credit_waiters(id);
+                            }
+                            framing::FrameType::Settings => {
+                                // payload: role (u8) || stream window (u32) || connection window (u32)
+                                if frame.payload.len() < 9 {
+                                    continue;
+                                }
+                                let peer_role = match frame.payload[0] {
+                                    0 => Role::Initiator,
+                                    _ => Role::Responder,
+                                };
+                                let stream_window = u32::from_be_bytes([
+                                    frame.payload[1],
+                                    frame.payload[2],
+                                    frame.payload[3],
+                                    frame.payload[4],
+                                ]) as usize;
+                                let conn_window = u32::from_be_bytes([
+                                    frame.payload[5],
+                                    frame.payload[6],
+                                    frame.payload[7],
+                                    frame.payload[8],
+                                ]) as usize;
+                                let mux = Mux {
+                                    inner: inner.clone(),
+                                };
+                                if peer_role == inner.role {
+                                    // Both ends would allocate the same stream ids
+                                    mux.send_goaway(0, ResetCode::PROTOCOL);
+                                    break;
+                                }
+                                {
+                                    let mut rem = inner.remote_credit.lock().unwrap();
+                                    rem.settle(stream_window, conn_window);
+                                    inner.credit_cv.notify_all();
+                                }
+                                mux.wake_credit_waiters(0);
+                            }
+                            framing::FrameType::Datagram => {
+                                // payload: flow_id (u32) || data
+                                if frame.payload.len() < 4 {
+                                    continue;
+                                }
+                                let flow = u32::from_be_bytes([
+                                    frame.payload[0],
+                                    frame.payload[1],
+                                    frame.payload[2],
+                                    frame.payload[3],
+                                ]);
+                                let dg = Datagram {
+                                    flow,
+                                    data: frame.payload[4..].to_vec(),
+                                };
+                                let evicted = inner.datagrams.push_bounded(dg, inner.datagram_cap);
+                                let mut stats = inner.datagram_stats.lock().unwrap();
+                                stats.received += 1;
+                                stats.dropped += evicted as u64;
                             }
-                            _ => {}
+                            framing::FrameType::Ping => {
+                                if frame.payload.len() < PING_HDR {
+                                    continue;
+                                }
+                                let mux = Mux {
+                                    inner: inner.clone(),
+                                };
+                                if frame.payload[0] & PING_ACK == 0 {
+                                    let mut pong = frame.payload.clone();
+                                    pong[0] |= PING_ACK;
+                                    mux.send_frame(framing::Frame {
+                                        ty: framing::FrameType::Ping,
+                                        payload: pong,
+                                    });
+                                } else {
+                                    let mut opaque = [0u8; 8];
+                                    opaque.copy_from_slice(&frame.payload[1..PING_HDR]);
+                                    mux.on_pong(u64::from_be_bytes(opaque));
+                                }
+                            }
+                            framing::FrameType::Padding => {}
                         }
                     }
+                    // Extension from a newer peer that this build does not speak
+                    Err(framing::Error::Ignored(_)) => {}
                     Err(e) => {
-                        if std::env::var("HTX_DEBUG_FRAMES").ok().as_deref() == Some("1") {
-                            eprintln!(
-                                "htx::mux: decrypt_and_parse failed: {:?}; len={}",
-                                e,
-                                bytes.len()
-                            );
+                        if let Some(hook) = &inner.debug_hook {
+                            hook(&FrameEvent::Rejected {
+                                error: e,
+                                wire_len: bytes.len(),
+                            });
                         }
                     }
                 }
             }
+            // Peer is gone: end every stream and release anyone waiting on credit.
+            Mux { inner }.mark_closed();
         });
     }
 
+    fn mark_closed(&self) {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        // Dropping our sender lets the transport (and through it the peer) see the close
+        self.inner.tx.lock().unwrap().take();
+        for st in self.inner.incoming.lock().unwrap().values() {
+            st.end_read(StreamEnd::ConnectionLost);
+        }
+        self.inner.idle_cv.notify_all();
+        self.inner.ping_cv.notify_all();
+        self.inner.accept_q.close();
+        self.inner.datagrams.close();
+        self.inner.tickets.close();
+        {
+            let _sched = self.inner.sched.lock().unwrap();
+            self.inner.sched_cv.notify_all();
+        }
+        let _rem = self.inner.remote_credit.lock().unwrap();
+        self.inner.credit_cv.notify_all();
+        for (_, w) in self.inner.credit_wakers.lock().unwrap().drain() {
+            w.wake();
+        }
+    }
+
+    /// True once the underlying wire has gone away.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::SeqCst)
+    }
+
+    /// Open a new stream. Once either side has sent GOAWAY the returned handle is
+    /// already reset with [`ResetCode::REFUSED`], so callers can retry on another connection.
     pub fn open_stream(&self) -> StreamHandle {
+        self.open_stream_with_priority(Priority::default())
+    }
+
+    /// Open a stream whose data (in both directions) is scheduled with `prio`.
+    /// A non-default priority is announced to the peer with a PRIORITY frame.
+    ///
+    /// Priorities only order data on a mux running [`SchedulerKind::RoundRobin`] (or cover
+    /// traffic); with [`SchedulerKind::Inline`] writers still send as soon as they hold credit
+    /// and `prio` is merely recorded and announced.
+    pub fn open_stream_with_priority(&self, prio: Priority) -> StreamHandle {
         let mut idg = self.inner.next_id.lock().unwrap();
         let id = *idg;
-        *idg = id.saturating_add(1);
-        // initialize remote credit and incoming channel
+        *idg = id.saturating_add(2);
+        drop(idg);
+        if self.is_draining() {
+            let st = StreamState::new(true, self.inner.initial_window);
+            st.end_read(StreamEnd::Reset(ResetCode::REFUSED));
+            return StreamHandle {
+                id,
+                mux: self.clone(),
+                st,
+            };
+        }
+        // initialize remote credit and incoming queue
         {
             let mut rem = self.inner.remote_credit.lock().unwrap();
-            rem.insert(id, self.inner.initial_window);
+            let window = rem.stream_window;
+            rem.streams.insert(id, window);
         }
-        let (tx_data, rx_data) = mpsc::channel();
+        let st = StreamState::new(true, self.inner.initial_window);
         {
             let mut incoming = self.inner.incoming.lock().unwrap();
-            incoming.insert(id, tx_data);
+            incoming.insert(id, st.clone());
+        }
+        if self.is_closed() {
+            st.end_read(StreamEnd::ConnectionLost);
+        }
+        if prio != Priority::default() {
+            self.inner.sched.lock().unwrap().set_priority(id, prio);
+            self.send_priority(id, prio);
         }
         StreamHandle {
             id,
             mux: self.clone(),
-            rx: rx_data,
+            st,
+        }
+    }
+
+    // Register a stream the peer opened and queue it for accept. Returns None for ids
+    // we recently closed, whose late frames must not resurrect them, and for ids in our
+    // own half, which only ever name streams we opened and have since dropped.
+    fn accept_remote(&self, id: StreamId) -> Option<Arc<StreamState>> {
+        if self.inner.role.owns(id) || self.inner.retired.lock().unwrap().contains(&id) {
+            return None;
+        }
+        {
+            let mut drain = self.inner.drain.lock().unwrap();
+            if drain.sent.is_some() {
+                // Past our GOAWAY: the peer treats this as safe to retry elsewhere
+                drop(drain);
+                self.send_reset(id, ResetCode::REFUSED);
+                self.retire(id);
+                return None;
+            }
+            drain.last_accepted = drain.last_accepted.max(id);
+        }
+        let st = StreamState::new(false, self.inner.initial_window);
+        self.inner.incoming.lock().unwrap().insert(id, st.clone());
+        // Initialize remote credit so we can send back immediately
+        {
+            let mut rem = self.inner.remote_credit.lock().unwrap();
+            let window = rem.stream_window;
+            rem.streams.insert(id, window);
+        }
+        self.inner.accept_q.push(StreamHandle {
+            id,
+            mux: self.clone(),
+            st: st.clone(),
+        });
+        Some(st)
+    }
+
+    // Drop all per-stream bookkeeping once both directions are done (or the stream was reset).
+    fn forget_stream(&self, id: StreamId) {
+        {
+            let mut incoming = self.inner.incoming.lock().unwrap();
+            if incoming.remove(&id).is_none() {
+                return;
+            }
+            if incoming.is_empty() {
+                self.inner.idle_cv.notify_all();
+            }
+        }
+        self.inner.sched.lock().unwrap().remove(id);
+        self.retire(id);
+        // Writers blocked on this stream's credit see the missing entry and give up
+        self.inner.remote_credit.lock().unwrap().streams.remove(&id);
+        self.inner.credit_cv.notify_all();
+        if let Some(w) = self.inner.credit_wakers.lock().unwrap().remove(&id) {
+            w.wake();
+        }
+    }
+
+    fn retire(&self, id: StreamId) {
+        let mut retired = self.inner.retired.lock().unwrap();
+        if retired.len() >= RETIRED_IDS {
+            retired.pop_front();
+        }
+        retired.push_back(id);
+    }
+
+    /// True once either side has sent GOAWAY; no new streams will be served.
+    pub fn is_draining(&self) -> bool {
+        let drain = self.inner.drain.lock().unwrap();
+        drain.sent.is_some() || drain.received.is_some()
+    }
+
+    /// Gracefully drain the connection.
+    ///
+    /// Sends GOAWAY carrying the last stream we accepted, refuses any newer stream
+    /// from either side, and waits up to `grace` for in-flight streams to finish.
+    /// Streams still open after that are reset with [`ResetCode::CANCEL`] and the
+    /// wire is closed. Returns true if everything finished within the grace period.
+    pub fn shutdown(&self, grace: Duration) -> bool {
+        let goaway = {
+            let mut drain = self.inner.drain.lock().unwrap();
+            match drain.sent {
+                Some(_) => None,
+                None => {
+                    drain.sent = Some(drain.last_accepted);
+                    drain.sent
+                }
+            }
+        };
+        if let Some(last) = goaway {
+            self.send_goaway(last, ResetCode::NO_ERROR);
+        }
+        let deadline = Instant::now() + grace;
+        let clean = {
+            let mut incoming = self.inner.incoming.lock().unwrap();
+            loop {
+                if incoming.is_empty() {
+                    break true;
+                }
+                let now = Instant::now();
+                if now >= deadline || self.is_closed() {
+                    break false;
+                }
+                incoming = self
+                    .inner
+                    .idle_cv
+                    .wait_timeout(incoming, deadline - now)
+                    .unwrap()
+                    .0;
+            }
+        };
+        let left: Vec<(StreamId, Arc<StreamState>)> = self
+            .inner
+            .incoming
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, st)| (*id, st.clone()))
+            .collect();
+        for (id, st) in left {
+            self.release_conn(st.end_read(StreamEnd::Reset(ResetCode::CANCEL)));
+            self.send_reset(id, ResetCode::CANCEL);
+            self.forget_stream(id);
+        }
+        self.mark_closed();
+        clean
+    }
+
+    /// Send an unreliable datagram on `flow`. It bypasses stream flow control and is never
+    /// retransmitted; the peer drops it if its datagram queue is full.
+    ///
+    /// It is dropped here instead (and counted in [`DatagramStats::send_dropped`]) when
+    /// the carrier is already backed up. With cover traffic it waits for the next send
+    /// slot, which it takes ahead of stream data.
+    pub fn send_datagram(&self, flow: FlowId, data: &[u8]) -> io::Result<()> {
+        if data.len() > MAX_DATAGRAM_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "datagram of {} bytes exceeds {MAX_DATAGRAM_LEN}",
+                    data.len()
+                ),
+            ));
+        }
+        if self.is_closed() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        // payload: flow_id (u32) || data
+        let mut payload = BytesMut::with_capacity(4 + data.len());
+        payload.put_slice(&flow.to_be_bytes());
+        payload.put_slice(data);
+        if self.inner.cover.is_some() {
+            let mut out = self.inner.datagrams_out.lock().unwrap();
+            if out.len() >= self.inner.datagram_cap {
+                out.pop_front();
+                self.inner.datagram_stats.lock().unwrap().send_dropped += 1;
+            }
+            out.push_back(payload.to_vec());
+            return Ok(());
+        }
+        if self.inner.wire_backlog.load(Ordering::Relaxed) >= DATAGRAM_SEND_BACKLOG {
+            self.inner.datagram_stats.lock().unwrap().send_dropped += 1;
+            return Ok(());
+        }
+        self.send_datagram_frame(payload.to_vec());
+        Ok(())
+    }
+
+    fn send_datagram_frame(&self, payload: Vec<u8>) {
+        self.send_frame(framing::Frame {
+            ty: framing::FrameType::Datagram,
+            payload,
+        });
+        self.inner.datagram_stats.lock().unwrap().sent += 1;
+    }
+
+    /// Next received datagram, waiting up to `timeout`; `None` on timeout or once the
+    /// connection is gone and the queue is drained.
+    pub fn recv_datagram(&self, timeout: Duration) -> Option<Datagram> {
+        self.inner.datagrams.pop_timeout(timeout)
+    }
+
+    /// Wait for the next datagram; `None` once the connection is gone.
+    pub async fn next_datagram(&self) -> Option<Datagram> {
+        std::future::poll_fn(|cx| self.inner.datagrams.poll_pop(cx)).await
+    }
+
+    pub fn datagram_stats(&self) -> DatagramStats {
+        *self.inner.datagram_stats.lock().unwrap()
+    }
+
+    /// Slot and overhead counters; all zero unless cover traffic is on.
+    pub fn cover_stats(&self) -> CoverStats {
+        *self.inner.cover_stats.lock().unwrap()
+    }
+
+    /// Send a PING now; the answer feeds [`Mux::rtt`]. Returns its opaque value.
+    pub fn ping(&self) -> u64 {
+        let opaque = {
+            let mut p = self.inner.ping.lock().unwrap();
+            let opaque = p.next_opaque;
+            p.next_opaque += 1;
+            p.outstanding.push_back((opaque, Instant::now()));
+            opaque
+        };
+        let mut payload = vec![0u8; self.ping_len()];
+        payload[1..PING_HDR].copy_from_slice(&opaque.to_be_bytes());
+        self.send_frame(framing::Frame {
+            ty: framing::FrameType::Ping,
+            payload,
+        });
+        opaque
+    }
+
+    /// RTT estimate, once at least one PONG has come back.
+    pub fn rtt(&self) -> Option<RttStats> {
+        self.inner.ping.lock().unwrap().rtt
+    }
+
+    fn on_pong(&self, opaque: u64) {
+        let mut p = self.inner.ping.lock().unwrap();
+        let Some(pos) = p.outstanding.iter().position(|(o, _)| *o == opaque) else {
+            return;
+        };
+        let sent = p.outstanding[pos].1;
+        // Anything older was lost or overtaken; only count what is still in flight
+        p.outstanding.drain(..=pos);
+        p.missed = 0;
+        let sample = sent.elapsed();
+        match p.rtt.as_mut() {
+            Some(r) => r.update(sample),
+            None => p.rtt = Some(RttStats::first(sample)),
+        }
+    }
+
+    // Payload length for the next PING: minimal, or drawn like a data record when stealth-sized.
+    fn ping_len(&self) -> usize {
+        if !self.inner.keepalive.is_some_and(|k| k.stealth_sized) {
+            return PING_HDR;
+        }
+        #[cfg(feature = "stealth-mode")]
+        {
+            let mut guard = self.stealth_shaper_lock();
+            if let Some(sh) = guard.as_mut() {
+                return sh.sizer.choose_len(PING_HDR).clamp(PING_HDR, sh.max_record);
+            }
+        }
+        use rand::Rng;
+        rand::thread_rng().gen_range(PING_HDR.max(64)..=self.inner.base_chunk)
+    }
+
+    fn spawn_keepalive(&self, ka: Keepalive) {
+        let mux = self.clone();
+        thread::spawn(move || loop {
+            {
+                let p = mux.inner.ping.lock().unwrap();
+                let _ = mux.inner.ping_cv.wait_timeout(p, ka.interval).unwrap();
+            }
+            if mux.is_closed() {
+                return;
+            }
+            let dead = {
+                let mut p = mux.inner.ping.lock().unwrap();
+                if !p.outstanding.is_empty() {
+                    p.missed += 1;
+                }
+                p.missed >= ka.max_missed.max(1)
+            };
+            if dead {
+                mux.mark_closed();
+                return;
+            }
+            mux.ping();
+        });
+    }
+
+    // Peer sent GOAWAY: streams we opened above `last` were never served, so refuse them locally.
+    // `last` only ranks our own ids; streams the peer opened are its to finish or reset.
+    fn on_goaway(&self, last: StreamId) {
+        self.inner.drain.lock().unwrap().received = Some(last);
+        let unserved: Vec<(StreamId, Arc<StreamState>)> = self
+            .inner
+            .incoming
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, st)| st.local && **id > last)
+            .map(|(id, st)| (*id, st.clone()))
+            .collect();
+        for (id, st) in unserved {
+            self.release_conn(st.end_read(StreamEnd::Reset(ResetCode::REFUSED)));
+            self.forget_stream(id);
+        }
+    }
+
+    fn send_goaway(&self, last: StreamId, code: ResetCode) {
+        // payload: last_stream_id (u32) || code (u32)
+        let mut payload = BytesMut::with_capacity(8);
+        payload.put_slice(&last.to_be_bytes());
+        payload.put_slice(&code.0.to_be_bytes());
+        let frame = framing::Frame {
+            ty: framing::FrameType::GoAway,
+            payload: payload.to_vec(),
+        };
+        self.send_frame(frame);
+    }
+
+    // Put FIN for `id` on the wire, releasing the stream if the peer has already finished.
+    fn send_fin(&self, id: StreamId) {
+        // payload: stream_id (u32)
+        let frame = framing::Frame {
+            ty: framing::FrameType::Close,
+            payload: id.to_be_bytes().to_vec(),
+        };
+        self.send_frame(frame);
+        let st = self.inner.incoming.lock().unwrap().get(&id).cloned();
+        if let Some(st) = st {
+            let done = {
+                let mut e = st.ends.lock().unwrap();
+                e.fin_sent = true;
+                e.read_end.is_some()
+            };
+            if done {
+                self.forget_stream(id);
+            }
         }
     }
 
-    pub fn accept_stream(&self, timeout: Duration) -> Option<StreamHandle> {
-        let rx = self.inner.accept_rx.lock().unwrap();
-        rx.recv_timeout(timeout).ok()
+    fn send_priority(&self, id: StreamId, prio: Priority) {
+        // payload: stream_id (u32) || urgency (u8) || weight (u8)
+        let mut payload = BytesMut::with_capacity(6);
+        payload.put_slice(&id.to_be_bytes());
+        payload.put_u8(prio.urgency);
+        payload.put_u8(prio.weight);
+        let frame = framing::Frame {
+            ty: framing::FrameType::Priority,
+            payload: payload.to_vec(),
+        };
+        self.send_frame(frame);
+    }
+
+    // Queue a chunk or FIN for the writer thread.
+    fn enqueue(&self, id: StreamId, item: Outgoing) {
+        self.inner.sched.lock().unwrap().push(id, item);
+        self.inner.sched_cv.notify_one();
+    }
+
+    fn send_reset(&self, id: StreamId, code: ResetCode) {
+        // payload: stream_id (u32) || reset code (u32)
+        let mut payload = BytesMut::with_capacity(8);
+        payload.put_slice(&id.to_be_bytes());
+        payload.put_slice(&code.0.to_be_bytes());
+        let frame = framing::Frame {
+            ty: framing::FrameType::RstStream,
+            payload: payload.to_vec(),
+        };
+        self.send_frame(frame);
+    }
+
+    pub fn accept_stream(&self, timeout: Duration) -> Option<StreamHandle> {
+        self.inner.accept_q.pop_timeout(timeout)
+    }
+
+    /// Wait for the peer to open a stream; `None` once the connection is gone.
+    pub async fn accept(&self) -> Option<StreamHandle> {
+        std::future::poll_fn(|cx| self.inner.accept_q.poll_pop(cx)).await
+    }
+
+    // SETTINGS payload: role (u8) || stream window (u32) || connection window (u32).
+    // Sent once, ahead of anything else; windows beyond u32 are advertised as u32::MAX.
+    fn send_settings(&self) {
+        let window = |w: usize| u32::try_from(w).unwrap_or(u32::MAX);
+        let mut payload = BytesMut::with_capacity(9);
+        payload.put_u8(match self.inner.role {
+            Role::Initiator => 0,
+            Role::Responder => 1,
+        });
+        payload.put_u32(window(self.inner.initial_window));
+        payload.put_u32(window(self.inner.conn_window));
+        self.send_frame(framing::Frame {
+            ty: framing::FrameType::Settings,
+            payload: payload.to_vec(),
+        });
+    }
+
+    // Credit arrived for stream `id` (0: the connection, so every stream): wake its writers.
+    fn wake_credit_waiters(&self, id: StreamId) {
+        if id == 0 {
+            // Connection credit: any blocked stream may proceed now
+            for (_, w) in self.inner.credit_wakers.lock().unwrap().drain() {
+                w.wake();
+            }
+        } else if let Some(w) = self.inner.credit_wakers.lock().unwrap().remove(&id) {
+            w.wake();
+        }
+        // The writer thread takes the credit lock under the scheduler
+        // lock, so unpark only after releasing it
+        if self.inner.rr_enabled {
+            let mut sched = self.inner.sched.lock().unwrap();
+            if id == 0 {
+                sched.unpark_all();
+            } else {
+                sched.unpark(id);
+            }
+            self.inner.sched_cv.notify_one();
+        }
     }
 
     fn send_window_update(&self, id: StreamId, credit: usize) {
@@ -406,23 +2071,99 @@ impl Mux {
         self.send_frame(frame);
     }
 
-    fn send_data(&self, id: StreamId, data: &[u8]) {
-        // Enforce rekey-close: if control is closed, drop data frames until reopened
-        if id != 0 && !*self.inner.control_open.lock().unwrap() {
+    // Account for and queue STREAM data from the peer. Returns false when the peer overran
+    // the connection window, in which case the connection has been torn down.
+    fn on_stream_data(&self, id: StreamId, data: Vec<u8>) -> bool {
+        let len = data.len();
+        {
+            let mut recv = self.inner.recv.lock().unwrap();
+            if recv.buffered + recv.unacked + len > self.inner.conn_window {
+                drop(recv);
+                let last = self.inner.drain.lock().unwrap().last_accepted;
+                self.send_goaway(last, ResetCode::FLOW_CONTROL);
+                self.mark_closed();
+                return false;
+            }
+            recv.buffered += len;
+        }
+        let st = self.inner.incoming.lock().unwrap().get(&id).cloned();
+        let Some(st) = st.or_else(|| self.accept_remote(id)) else {
+            // Retired or refused: the data is dropped, so its connection credit comes straight back
+            self.release_conn(len);
+            return true;
+        };
+        let mut e = st.ends.lock().unwrap();
+        if e.detached {
+            // Nobody will read this; hand the credit straight back
+            drop(e);
+            self.send_window_update(id, len);
+            self.release_conn(len);
+        } else if e.read_end.is_some() {
+            drop(e);
+            self.release_conn(len);
+        } else if len > e.recv_window {
+            drop(e);
+            let dropped = st.end_read(StreamEnd::Reset(ResetCode::FLOW_CONTROL));
+            self.release_conn(dropped + len);
+            self.send_reset(id, ResetCode::FLOW_CONTROL);
+            self.forget_stream(id);
+        } else {
+            e.recv_window -= len;
+            // Queue under the ends lock so a concurrent drop either sees this chunk or discards it
+            let queued = st.rx.push(data);
+            drop(e);
+            if !queued {
+                self.release_conn(len);
+            }
+        }
+        true
+    }
+
+    // The app consumed `n` bytes of stream `id`: return stream and connection credit.
+    fn release(&self, id: StreamId, st: &StreamState, n: usize) {
+        if n == 0 {
             return;
         }
-        let mut payload = BytesMut::with_capacity(4 + data.len());
-        payload.put_slice(&id.to_be_bytes());
-        payload.put_slice(data);
-        let frame = framing::Frame {
-            ty: framing::FrameType::Stream,
-            payload: payload.to_vec(),
+        st.ends.lock().unwrap().recv_window += n;
+        self.send_window_update(id, n);
+        self.release_conn(n);
+    }
+
+    // `n` buffered bytes left the connection (read or discarded). Connection credit goes back
+    // in batches of a quarter window so small reads don't each cost a WINDOW_UPDATE.
+    fn release_conn(&self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let credit = {
+            let mut recv = self.inner.recv.lock().unwrap();
+            recv.buffered = recv.buffered.saturating_sub(n);
+            recv.unacked += n;
+            if recv.unacked >= self.inner.conn_window / 4 {
+                std::mem::take(&mut recv.unacked)
+            } else {
+                0
+            }
         };
-        self.send_frame(frame);
+        if credit > 0 {
+            self.send_window_update(0, credit);
+        }
     }
 
-    #[cfg(feature = "stealth-mode")]
+    /// Bytes received on this connection that the app has not read yet.
+    pub fn buffered_bytes(&self) -> usize {
+        self.inner.recv.lock().unwrap().buffered
+    }
+
+    fn send_data(&self, id: StreamId, data: &[u8]) {
+        self.send_data_padded(id, data, 0);
+    }
+
+    // Always carry the explicit data_len: the receiver only falls back to the legacy
+    // [id | data] layout when the declared length doesn't fit, so omitting it would
+    // let payloads that happen to start with a small u32 be misparsed.
     fn send_data_padded(&self, id: StreamId, data: &[u8], pad_len: usize) {
+        // Enforce rekey-close: if control is closed, drop data frames until reopened
         if id != 0 && !*self.inner.control_open.lock().unwrap() {
             return;
         }
@@ -442,35 +2183,132 @@ impl Mux {
         self.send_frame(frame);
     }
 
-    #[allow(dead_code)]
-    fn take_credit_blocking(&self, id: StreamId, needed: usize) -> usize {
-        let mut rem = self.inner.remote_credit.lock().unwrap();
-        loop {
-            let avail = *rem.get(&id).unwrap_or(&0);
-            if avail > 0 {
-                let take = avail.min(needed);
-                rem.insert(id, avail - take);
-                return take;
+    // Decide how many bytes of `remaining` go into the next record and how much padding follows them.
+    fn plan_record(&self, remaining: usize) -> (usize, usize) {
+        // Decide target record size
+        #[cfg(feature = "stealth-mode")]
+        {
+            // Default fallback
+            let mut target = self.inner.base_chunk;
+            let mut guard = self.stealth_shaper_lock();
+            if let Some(sh) = guard.as_mut() {
+                let maxr = sh.max_record.min(64 * 1024);
+                // Draw a target from the profile distribution; bound by remaining
+                target = sh.sizer.choose_len(remaining).clamp(1, maxr);
             }
-            rem = self.inner.credit_cv.wait(rem).unwrap();
+            // Interpret target as desired full STREAM payload (id+len+data+pad); reserve 8 bytes for (id+data_len)
+            let budget = target.saturating_sub(8);
+            let data_budget = remaining.min(budget);
+            (data_budget, budget.saturating_sub(data_budget))
         }
+        #[cfg(not(feature = "stealth-mode"))]
+        {
+            (remaining.min(self.inner.base_chunk), 0)
+        }
+    }
+
+    #[allow(dead_code)]
+    fn take_credit_blocking(&self, id: StreamId, needed: usize) -> usize {
+        self.take_credit_blocking_with_flag(id, needed).0
     }
 
     // Same as take_credit_blocking but also returns whether we blocked waiting for credit.
+    // Returns 0 only when the connection or the stream has closed.
     fn take_credit_blocking_with_flag(&self, id: StreamId, needed: usize) -> (usize, bool) {
         let mut rem = self.inner.remote_credit.lock().unwrap();
         let mut blocked = false;
         loop {
-            let avail = *rem.get(&id).unwrap_or(&0);
-            if avail > 0 {
-                let take = avail.min(needed);
-                rem.insert(id, avail - take);
+            // A missing entry means the stream was closed or reset underneath us
+            let Some(take) = rem.take(id, needed) else {
+                return (0, blocked);
+            };
+            if take > 0 {
                 return (take, blocked);
             }
+            if self.is_closed() {
+                return (0, blocked);
+            }
             blocked = true;
             rem = self.inner.credit_cv.wait(rem).unwrap();
         }
     }
+
+    // Writers in RR/cover mode only queue data; the writer thread takes credit as it sends.
+    // They may queue up to the stream's remaining credit and otherwise wait for a
+    // WINDOW_UPDATE like inline writers, so a peer that stops reading stops them too.
+    // Returns how much of `wanted` may be queued now (0 once the stream is gone).
+    fn reserve_queue_blocking(&self, id: StreamId, wanted: usize) -> (usize, bool) {
+        let mut blocked = false;
+        loop {
+            let (room, rem) = self.queue_room(id);
+            let Some(room) = room else {
+                return (0, blocked);
+            };
+            if room > 0 {
+                return (room.min(wanted), blocked);
+            }
+            if self.is_closed() {
+                return (0, blocked);
+            }
+            blocked = true;
+            drop(self.inner.credit_cv.wait(rem).unwrap());
+        }
+    }
+
+    // Credit `id` has beyond what is already queued (None once the stream is gone), with
+    // the credit lock it was read under. Locks in the writer thread's order, so the
+    // two numbers agree.
+    fn queue_room(&self, id: StreamId) -> (Option<usize>, MutexGuard<'_, SendCredit>) {
+        let sched = self.inner.sched.lock().unwrap();
+        let rem = self.inner.remote_credit.lock().unwrap();
+        let room = rem
+            .streams
+            .get(&id)
+            .map(|credit| credit.saturating_sub(sched.queued_bytes(id)));
+        (room, rem)
+    }
+
+    // Async counterpart of reserve_queue_blocking.
+    fn poll_reserve_queue(&self, id: StreamId, wanted: usize, cx: &mut Context<'_>) -> Poll<usize> {
+        let (room, _rem) = self.queue_room(id);
+        let Some(room) = room else {
+            return Poll::Ready(0);
+        };
+        if room > 0 {
+            return Poll::Ready(room.min(wanted));
+        }
+        if self.is_closed() {
+            return Poll::Ready(0);
+        }
+        // Register while still holding the credit lock so a concurrent update can't slip past us
+        self.inner
+            .credit_wakers
+            .lock()
+            .unwrap()
+            .insert(id, cx.waker().clone());
+        Poll::Pending
+    }
+
+    // Async counterpart of take_credit_blocking: parks the task's waker until a WINDOW_UPDATE arrives.
+    fn poll_take_credit(&self, id: StreamId, needed: usize, cx: &mut Context<'_>) -> Poll<usize> {
+        let mut rem = self.inner.remote_credit.lock().unwrap();
+        let Some(take) = rem.take(id, needed) else {
+            return Poll::Ready(0);
+        };
+        if take > 0 {
+            return Poll::Ready(take);
+        }
+        if self.is_closed() {
+            return Poll::Ready(0);
+        }
+        // Register while still holding the credit lock so a concurrent update can't slip past us
+        self.inner
+            .credit_wakers
+            .lock()
+            .unwrap()
+            .insert(id, cx.waker().clone());
+        Poll::Pending
+    }
 }
 
 impl StreamHandle {
@@ -481,69 +2319,31 @@ impl StreamHandle {
     // Write all data, respecting remote credit and chunking. When RR scheduler is enabled,
     // enqueue chunks to the scheduler; otherwise, send inline.
     pub fn write(&self, mut data: &[u8]) {
+        if self.st.ends.lock().unwrap().write_shut {
+            return;
+        }
         while !data.is_empty() {
-            // Decide target record size
-            #[cfg(feature = "stealth-mode")]
-            let target = {
-                // Default fallback
-                let mut t = self.mux.inner.base_chunk;
-                let mut guard = self.mux.stealth_shaper_lock();
-                if let Some(sh) = guard.as_mut() {
-                    let maxr = sh.max_record.min(64 * 1024);
-                    // Draw a target from the profile distribution; bound by remaining
-                    let draw = sh.sizer.choose_len(data.len()).max(1).min(maxr);
-                    t = draw.max(1);
-                }
-                t
-            };
-            #[cfg(not(feature = "stealth-mode"))]
-            let target = self.mux.inner.base_chunk;
-
-            // For stealth-mode, interpret target as desired full STREAM payload (id+len+data+pad). Otherwise, it's just data chunk size.
-            #[cfg(feature = "stealth-mode")]
-            let (data_budget, pad_len) = {
-                // Reserve 8 bytes for (id+data_len)
-                let budget = target.saturating_sub(8);
-                let data_budget = data.len().min(budget);
-                let pad = budget.saturating_sub(data_budget);
-                (data_budget.max(0), pad)
-            };
-            #[cfg(not(feature = "stealth-mode"))]
-            let (data_budget, _pad_len) = (data.len().min(target), 0usize);
+            let (data_budget, pad_len) = self.mux.plan_record(data.len());
 
-            // Credit is consumed at send time in RR mode; when inline, we must take credit here.
+            // Credit is consumed at send time in RR mode, but we only queue what it covers;
+            // when inline, we must take credit here.
             // When stealth-mode is enabled we also track whether we blocked waiting for credit
             // to decide if we should apply additional jitter (skip if we just blocked).
-            #[cfg(feature = "stealth-mode")]
-            let mut blocked = false;
-            let (chunk, rest) = if self.mux.inner.rr_enabled {
-                // Split by budget locally; scheduler will enforce credit per send
-                let take = data_budget;
-                let (c, r) = data.split_at(take);
-                (c, r)
+            let (take, _blocked) = if self.mux.inner.rr_enabled {
+                self.mux.reserve_queue_blocking(self.id, data_budget)
             } else {
-                #[cfg(feature = "stealth-mode")]
-                {
-                    let (take, b) = self
-                        .mux
-                        .take_credit_blocking_with_flag(self.id, data_budget);
-                    blocked = b;
-                    let (c, r) = data.split_at(take);
-                    (c, r)
-                }
-                #[cfg(not(feature = "stealth-mode"))]
-                {
-                    let (take, _blocked) = self
-                        .mux
-                        .take_credit_blocking_with_flag(self.id, data_budget);
-                    let (c, r) = data.split_at(take);
-                    (c, r)
-                }
+                self.mux
+                    .take_credit_blocking_with_flag(self.id, data_budget)
             };
+            if take == 0 {
+                // Connection or stream closed while waiting for credit
+                return;
+            }
+            let (chunk, rest) = data.split_at(take);
 
             // Apply bounded jitter only if we didn't just block on credit
             #[cfg(feature = "stealth-mode")]
-            if !blocked {
+            if !_blocked {
                 let mut guard = self.mux.stealth_shaper_lock();
                 if let Some(sh) = guard.as_mut() {
                     let d = sh.jitter.delay();
@@ -554,20 +2354,9 @@ impl StreamHandle {
             }
 
             if self.mux.inner.rr_enabled {
-                // Enqueue for RR scheduler
-                let _ = self.mux.inner.rr_tx.send(WriteReq {
-                    id: self.id,
-                    chunk: chunk.to_vec(),
-                });
+                self.mux.enqueue(self.id, Outgoing::Data(chunk.to_vec()));
             } else {
-                #[cfg(feature = "stealth-mode")]
-                {
-                    self.mux.send_data_padded(self.id, chunk, pad_len);
-                }
-                #[cfg(not(feature = "stealth-mode"))]
-                {
-                    self.mux.send_data(self.id, chunk);
-                }
+                self.mux.send_data_padded(self.id, chunk, pad_len);
             }
             data = rest;
 
@@ -578,29 +2367,226 @@ impl StreamHandle {
         }
     }
 
-    // Read a chunk; returns None if sender dropped (end of stream)
+    // Read a chunk; returns None at end of stream, on reset, or after a 5s timeout.
+    // Use `recv` to tell those apart.
     pub fn read(&self) -> Option<Vec<u8>> {
-        match self.rx.recv_timeout(Duration::from_secs(5)) {
-            Ok(buf) => {
-                let len = buf.len();
-                // release credit back to peer
-                self.mux.send_window_update(self.id, len);
-                Some(buf)
+        let buf = self.st.rx.pop_timeout(Duration::from_secs(5))?;
+        // release credit back to peer
+        self.mux.release(self.id, &self.st, buf.len());
+        Some(buf)
+    }
+
+    pub fn try_read(&self) -> Option<Vec<u8>> {
+        let buf = self.st.rx.try_pop()?;
+        self.mux.release(self.id, &self.st, buf.len());
+        Some(buf)
+    }
+
+    /// Wait up to `timeout` for the next chunk.
+    ///
+    /// `Ok(None)` is a clean end of stream (the peer sent FIN). A reset surfaces as
+    /// `ConnectionReset`, a lost connection as `ConnectionAborted`, and an elapsed
+    /// timeout as `TimedOut`.
+    pub fn recv(&self, timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+        match self.st.rx.pop_timeout(timeout) {
+            Some(buf) => {
+                self.mux.release(self.id, &self.st, buf.len());
+                Ok(Some(buf))
             }
-            Err(_) => None,
+            None => self.read_end_result().map(|()| None),
         }
     }
 
-    pub fn try_read(&self) -> Option<Vec<u8>> {
-        match self.rx.try_recv() {
-            Ok(buf) => {
-                let len = buf.len();
-                self.mux.send_window_update(self.id, len);
-                Some(buf)
+    /// Reprioritize this stream locally and ask the peer to schedule its side the same way.
+    /// Has no effect on sending order unless the mux schedules with [`SchedulerKind::RoundRobin`].
+    pub fn set_priority(&self, prio: Priority) {
+        self.mux
+            .inner
+            .sched
+            .lock()
+            .unwrap()
+            .set_priority(self.id, prio);
+        self.mux.send_priority(self.id, prio);
+    }
+
+    /// Current scheduling priority; the peer may have changed it with a PRIORITY frame.
+    pub fn priority(&self) -> Priority {
+        self.mux.inner.sched.lock().unwrap().priority(self.id)
+    }
+
+    /// How the receive side ended, or `None` while data may still arrive.
+    pub fn end_state(&self) -> Option<StreamEnd> {
+        self.st.ends.lock().unwrap().read_end
+    }
+
+    /// Half-close: send FIN after any data already written. Reads keep working
+    /// until the peer finishes its side.
+    pub fn shutdown_write(&self) {
+        {
+            let mut e = self.st.ends.lock().unwrap();
+            if e.write_shut {
+                return;
+            }
+            e.write_shut = true;
+        }
+        if self.mux.inner.rr_enabled {
+            self.mux.enqueue(self.id, Outgoing::Fin);
+        } else {
+            self.mux.send_fin(self.id);
+        }
+    }
+
+    /// Abort the stream in both directions, telling the peer why. Unsent and
+    /// unread data is discarded.
+    pub fn reset(&self, code: ResetCode) {
+        if let Some(StreamEnd::Reset(_)) = self.end_state() {
+            return;
+        }
+        self.mux
+            .release_conn(self.st.end_read(StreamEnd::Reset(code)));
+        self.mux.send_reset(self.id, code);
+        self.mux.forget_stream(self.id);
+    }
+
+    /// Gracefully close: FIN our side and stop reading. Same as dropping the handle.
+    pub fn close(self) {}
+
+    // Map the recorded end of the read side onto the io result a reader should see.
+    fn read_end_result(&self) -> io::Result<()> {
+        match self.end_state() {
+            Some(StreamEnd::Fin) => Ok(()),
+            Some(StreamEnd::Reset(code)) => Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                format!("stream reset by peer (code {})", code.0),
+            )),
+            Some(StreamEnd::ConnectionLost) => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "connection lost",
+            )),
+            None => Err(io::ErrorKind::TimedOut.into()),
+        }
+    }
+
+    fn write_error(&self) -> io::Error {
+        match self.end_state() {
+            Some(StreamEnd::Reset(_)) => io::ErrorKind::ConnectionReset.into(),
+            _ => io::ErrorKind::BrokenPipe.into(),
+        }
+    }
+
+    // Async read of one whole chunk, releasing its credit. Ready(Ok(None)) at end of stream.
+    pub(crate) fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<Vec<u8>>>> {
+        loop {
+            match self.st.rx.poll_pop(cx) {
+                Poll::Ready(Some(buf)) if buf.is_empty() => continue,
+                Poll::Ready(Some(buf)) => {
+                    self.mux.release(self.id, &self.st, buf.len());
+                    return Poll::Ready(Ok(Some(buf)));
+                }
+                Poll::Ready(None) => return Poll::Ready(self.read_end_result().map(|()| None)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        // Stop buffering, return credit for whatever was left unread, and FIN our side.
+        // The state itself goes once the peer finishes too (see send_fin / the Close handler).
+        let ended = {
+            let mut e = self.st.ends.lock().unwrap();
+            e.detached = true;
+            e.read_end.is_some()
+        };
+        let unread: usize = self.st.rx.clear().iter().map(Vec::len).sum();
+        if ended {
+            self.mux.release_conn(unread);
+        } else {
+            self.mux.release(self.id, &self.st, unread);
+        }
+        self.shutdown_write();
+    }
+}
+
+impl AsyncRead for StreamHandle {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            match this.st.rx.poll_pop(cx) {
+                Poll::Ready(Some(mut chunk)) => {
+                    if chunk.is_empty() {
+                        continue;
+                    }
+                    let n = chunk.len().min(buf.remaining());
+                    buf.put_slice(&chunk[..n]);
+                    if n < chunk.len() {
+                        this.st.rx.push_front(chunk.split_off(n));
+                    }
+                    // Only release credit for what the caller actually consumed
+                    this.mux.release(this.id, &this.st, n);
+                    return Poll::Ready(Ok(()));
+                }
+                // FIN leaves buf untouched (EOF); reset and connection loss are errors
+                Poll::Ready(None) => return Poll::Ready(this.read_end_result()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for StreamHandle {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if data.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if this.mux.is_closed() || this.st.ends.lock().unwrap().write_shut {
+            return Poll::Ready(Err(this.write_error()));
+        }
+        // Jitter is not applied here: sleeping would stall the executor.
+        let (data_budget, pad_len) = this.mux.plan_record(data.len());
+        if this.mux.inner.rr_enabled {
+            return match this.mux.poll_reserve_queue(this.id, data_budget, cx) {
+                Poll::Ready(0) => Poll::Ready(Err(this.write_error())),
+                Poll::Ready(take) => {
+                    this.mux
+                        .enqueue(this.id, Outgoing::Data(data[..take].to_vec()));
+                    Poll::Ready(Ok(take))
+                }
+                Poll::Pending => Poll::Pending,
+            };
+        }
+        match this.mux.poll_take_credit(this.id, data_budget, cx) {
+            Poll::Ready(0) => Poll::Ready(Err(this.write_error())),
+            Poll::Ready(take) => {
+                this.mux.send_data_padded(this.id, &data[..take], pad_len);
+                Poll::Ready(Ok(take))
             }
-            Err(_) => None,
+            Poll::Pending => Poll::Pending,
         }
     }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Frames are handed to the wire as soon as they are written
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.shutdown_write();
+        Poll::Ready(Ok(()))
+    }
 }
 
 #[cfg(feature = "stealth-mode")]
@@ -611,152 +2597,230 @@ impl Mux {
 }
 
 #[cfg(feature = "stealth-mode")]
-fn init_stealth_shaper_from_env() -> Option<StealthShaper> {
-    use std::env;
-    // Profiles
-    let s_prof = match env::var("STEALTH_SIZING_PROFILE")
-        .unwrap_or_else(|_| "webby".into())
-        .to_lowercase()
-        .as_str()
-    {
-        "small" => sizing_mod::Profile::Small,
-        "bursty" => sizing_mod::Profile::Bursty,
-        _ => sizing_mod::Profile::Webby,
-    };
-    let j_prof = match env::var("STEALTH_JITTER_PROFILE")
-        .unwrap_or_else(|_| "webby".into())
-        .to_lowercase()
-        .as_str()
-    {
-        "small" => jitter_mod::Profile::Small,
-        _ => jitter_mod::Profile::Webby,
-    };
-    let s_seed = env::var("STEALTH_SIZER_SEED")
-        .ok()
-        .and_then(|v| v.parse::<u64>().ok());
-    let j_seed = env::var("STEALTH_JITTER_SEED")
-        .ok()
-        .and_then(|v| v.parse::<u64>().ok());
-
-    let sizer = sizing_mod::Sizer::new(s_prof, s_seed);
-    let jitter = jitter_mod::Jitter::new(j_prof, j_seed);
-    Some(StealthShaper {
-        sizer,
-        jitter,
-        max_record: 64 * 1024 - 8, // leave headroom for headers/tags
-    })
+impl StealthShaper {
+    fn new(p: &StealthProfile, learned: Option<Arc<TrafficProfile>>) -> Self {
+        if let Some(t) = learned {
+            return StealthShaper {
+                sizer: sizing_mod::Sizer::from_profile(t.clone(), p.sizer_seed),
+                jitter: jitter_mod::Jitter::from_profile(t, p.jitter_seed),
+                max_record: 64 * 1024 - 8,
+            };
+        }
+        let s_prof = match p.sizing {
+            SizingProfile::Small => sizing_mod::Profile::Small,
+            SizingProfile::Webby => sizing_mod::Profile::Webby,
+            SizingProfile::Bursty => sizing_mod::Profile::Bursty,
+        };
+        let j_prof = match p.jitter {
+            JitterProfile::Small => jitter_mod::Profile::Small,
+            JitterProfile::Webby => jitter_mod::Profile::Webby,
+        };
+        StealthShaper {
+            sizer: sizing_mod::Sizer::new(s_prof, p.sizer_seed),
+            jitter: jitter_mod::Jitter::new(j_prof, p.jitter_seed),
+            max_record: 64 * 1024 - 8, // leave headroom for headers/tags
+        }
+    }
 }
 
-pub fn pair() -> (Mux, Mux) {
-    let (a_tx, a_rx_peer) = mpsc::channel::<Bytes>();
-    let (b_tx, b_rx_peer) = mpsc::channel::<Bytes>();
-    let a = Mux::new(a_tx, b_rx_peer);
-    let b = Mux::new(b_tx, a_rx_peer);
+/// Two connected in-memory muxes sharing `cfg`.
+/// Two muxes joined in memory; `a` is the initiator and `b` the responder.
+pub fn pair(cfg: MuxConfig) -> (Mux, Mux) {
+    let (ta, tb) = memory_pair();
+    let a = Mux::new(ta, cfg.clone().role(Role::Initiator));
+    let b = Mux::new(tb, cfg.role(Role::Responder));
     (a, b)
 }
 
+/// [`pair`] with AEAD on both sides; each side's tx key must be the other's rx key.
 pub fn pair_encrypted(
     a_tx_key: [u8; 32],
     a_rx_key: [u8; 32],
     b_tx_key: [u8; 32],
     b_rx_key: [u8; 32],
+    cfg: MuxConfig,
 ) -> (Mux, Mux) {
-    let (a_tx, a_rx_peer) = mpsc::channel::<Bytes>();
-    let (b_tx, b_rx_peer) = mpsc::channel::<Bytes>();
-    let a = Mux::new_encrypted(a_tx, b_rx_peer, a_tx_key, a_rx_key);
-    let b = Mux::new_encrypted(b_tx, a_rx_peer, b_tx_key, b_rx_key);
+    let (ta, tb) = memory_pair();
+    let a = Mux::new_encrypted(ta, a_tx_key, a_rx_key, cfg.clone().role(Role::Initiator));
+    let b = Mux::new_encrypted(tb, b_tx_key, b_rx_key, cfg.role(Role::Responder));
     (a, b)
 }
 
+// Stream id at the front of a frame payload (0 when too short to carry one).
+fn leading_stream_id(payload: &[u8]) -> StreamId {
+    match payload {
+        [a, b, c, d, ..] => u32::from_be_bytes([*a, *b, *c, *d]),
+        _ => 0,
+    }
+}
+
 impl Mux {
     // Send a transition control message on stream 0 (CBOR-encoded SignedControl)
     pub fn send_control(&self, sc: &SignedControl) {
         let data = cbor::to_vec(sc).expect("cbor");
         self.send_data(0, &data);
     }
+
+    /// Hand the peer a resumption ticket on stream 0.
+    pub fn send_ticket(&self, nt: &NewTicket) {
+        let data = cbor::to_vec(nt).expect("cbor");
+        self.send_data(0, &data);
+    }
+
+    /// Next resumption ticket from the peer, waiting up to `timeout`.
+    pub fn recv_ticket(&self, timeout: Duration) -> Option<NewTicket> {
+        self.inner.tickets.pop_timeout(timeout)
+    }
+    // Writer thread for SchedulerKind::RoundRobin: sends whatever the scheduler picks next.
+    // Streams out of credit are parked there instead of stalling everyone else.
     fn spawn_rr_writer(&self) {
-        let inner = self.inner.clone();
-        thread::spawn(move || {
-            // Round-robin over streams, sending one chunk per turn
-            loop {
-                // Receive new write requests (non-blocking drain to queues)
-                {
-                    let rx = &mut *inner.rr_rx.lock().unwrap();
-                    while let Ok(req) = rx.try_recv() {
-                        let mut qmap = inner.rr_queues.lock().unwrap();
-                        qmap.entry(req.id)
-                            .or_insert_with(VecDeque::new)
-                            .push_back(req.chunk);
+        let mux = self.clone();
+        thread::spawn(move || loop {
+            let next = {
+                let mut sched = mux.inner.sched.lock().unwrap();
+                loop {
+                    if mux.is_closed() {
+                        return;
                     }
-                }
-                // Build a static list of stream ids to iterate fairly
-                let ids: Vec<StreamId> = {
-                    let qmap = inner.rr_queues.lock().unwrap();
-                    qmap.keys().copied().collect()
-                };
-                if ids.is_empty() {
-                    // Block briefly waiting for new work to avoid busy spin
-                    let rx = &mut *inner.rr_rx.lock().unwrap();
-                    if let Ok(req) = rx.recv_timeout(Duration::from_millis(5)) {
-                        let mut qmap = inner.rr_queues.lock().unwrap();
-                        qmap.entry(req.id)
-                            .or_insert_with(VecDeque::new)
-                            .push_back(req.chunk);
+                    match mux.next_sendable(&mut sched, usize::MAX) {
+                        Some(next) => break next,
+                        None => {
+                            sched = mux
+                                .inner
+                                .sched_cv
+                                .wait_timeout(sched, Duration::from_millis(50))
+                                .unwrap()
+                                .0;
+                        }
                     }
-                    continue;
                 }
-                for id in ids {
-                    // Pop one chunk, if any
-                    let maybe_chunk = {
-                        let mut qmap = inner.rr_queues.lock().unwrap();
-                        if let Some(q) = qmap.get_mut(&id) {
-                            q.pop_front()
-                        } else {
-                            None
-                        }
-                    };
-                    if let Some(chunk) = maybe_chunk {
-                        // Respect remote credit here
-                        let needed = chunk.len();
-                        let (take, _blocked) = {
-                            // Use the existing credit mechanism
-                            // If not enough credit to send the whole chunk, split and requeue remainder
-                            let (t, _b) = Mux {
-                                inner: inner.clone(),
-                            }
-                            .take_credit_blocking_with_flag(id, needed);
-                            (t, _b)
-                        };
-                        if take == 0 {
-                            continue;
-                        }
-                        let (to_send, remainder) = if take < needed {
-                            (chunk[..take].to_vec(), Some(chunk[take..].to_vec()))
-                        } else {
-                            (chunk, None)
-                        };
-                        #[cfg(feature = "stealth-mode")]
-                        {
-                            Mux {
-                                inner: inner.clone(),
-                            }
-                            .send_data_padded(id, &to_send, 0);
+            };
+            match next {
+                Next::Fin(id) => mux.send_fin(id),
+                Next::Data(id, chunk) => mux.send_data(id, &chunk),
+            }
+        });
+    }
+
+    // Next FIN, or up to `max` bytes of data the peer has credit for; None when nothing
+    // queued can go out now.
+    fn next_sendable(&self, sched: &mut Scheduler, max: usize) -> Option<Next> {
+        loop {
+            match sched.next()? {
+                Next::Fin(id) => return Some(Next::Fin(id)),
+                Next::Data(id, mut chunk) => {
+                    // Credit is taken under the scheduler lock so a WINDOW_UPDATE
+                    // can't land between finding none and parking the stream
+                    let taken = self
+                        .inner
+                        .remote_credit
+                        .lock()
+                        .unwrap()
+                        .take(id, chunk.len().min(max));
+                    match taken {
+                        // Stream was reset or closed: drop whatever is still queued for it
+                        None => sched.remove(id),
+                        Some(0) => {
+                            sched.push_front(id, chunk);
+                            sched.park(id);
                         }
-                        #[cfg(not(feature = "stealth-mode"))]
-                        {
-                            Mux {
-                                inner: inner.clone(),
+                        Some(take) => {
+                            if take < chunk.len() {
+                                sched.push_front(id, chunk.split_off(take));
                             }
-                            .send_data(id, &to_send);
-                        }
-                        if let Some(rem) = remainder {
-                            let mut qmap = inner.rr_queues.lock().unwrap();
-                            qmap.entry(id).or_insert_with(VecDeque::new).push_front(rem);
+                            return Some(Next::Data(id, chunk));
                         }
                     }
                 }
-                // Small yield to let readers/processors run
-                std::thread::yield_now();
+            }
+        }
+    }
+
+    // Writer for cover traffic: wakes once per slot and sends exactly one record, stream
+    // data padded to the slot size if any is sendable, otherwise a PADDING frame.
+    fn spawn_cover_writer(&self, cover: CoverTraffic) {
+        use rand::SeedableRng;
+        let mux = self.clone();
+        thread::spawn(move || {
+            let profile = mux
+                .inner
+                .traffic_profile
+                .clone()
+                .filter(|_| cover.follow_profile);
+            let mut rng = rand::rngs::StdRng::from_entropy();
+            // Token bucket for PADDING-only slots, refilled at `budget` bytes per second
+            let mut tokens = cover.budget.unwrap_or(0) as f64;
+            let mut refilled = Instant::now();
+            let mut next_slot = Instant::now();
+            loop {
+                let (gap, size) = match &profile {
+                    Some(p) => (
+                        p.sample_gap(&mut rng).max(Duration::from_millis(1)),
+                        p.sample_size(&mut rng).clamp(64, 64 * 1024),
+                    ),
+                    None => (cover.interval, cover.record_size),
+                };
+                next_slot += gap;
+                let now = Instant::now();
+                if next_slot > now {
+                    thread::sleep(next_slot - now);
+                } else if now - next_slot > gap * 4 {
+                    // Far behind (suspended, overloaded): don't burst to catch up
+                    next_slot = now;
+                }
+                if mux.is_closed() {
+                    return;
+                }
+                // A waiting datagram takes the slot whole; it carries no padding of its own
+                let datagram = mux.inner.datagrams_out.lock().unwrap().pop_front();
+                if let Some(payload) = datagram {
+                    let mut stats = mux.inner.cover_stats.lock().unwrap();
+                    stats.slots += 1;
+                    stats.datagram_slots += 1;
+                    stats.data_bytes += (payload.len() - 4) as u64;
+                    drop(stats);
+                    mux.send_datagram_frame(payload);
+                    continue;
+                }
+                // STREAM payload: id (u32) | data_len (u32) | data | padding
+                let room = size - 8;
+                let data = loop {
+                    let next = mux.next_sendable(&mut mux.inner.sched.lock().unwrap(), room);
+                    match next {
+                        // FIN is control, not payload: send it and keep filling the slot
+                        Some(Next::Fin(id)) => mux.send_fin(id),
+                        Some(Next::Data(id, chunk)) => break Some((id, chunk)),
+                        None => break None,
+                    }
+                };
+                let mut stats = mux.inner.cover_stats.lock().unwrap();
+                stats.slots += 1;
+                if let Some((id, chunk)) = data {
+                    stats.data_slots += 1;
+                    stats.data_bytes += chunk.len() as u64;
+                    stats.padding_bytes += (room - chunk.len()) as u64;
+                    drop(stats);
+                    mux.send_data_padded(id, &chunk, room - chunk.len());
+                    continue;
+                }
+                if let Some(budget) = cover.budget {
+                    let now = Instant::now();
+                    tokens = (tokens + (now - refilled).as_secs_f64() * budget as f64)
+                        .min(budget as f64);
+                    refilled = now;
+                    if tokens < size as f64 {
+                        stats.skipped_slots += 1;
+                        continue;
+                    }
+                    tokens -= size as f64;
+                }
+                stats.padding_slots += 1;
+                stats.padding_bytes += size as u64;
+                drop(stats);
+                mux.send_frame(framing::Frame {
+                    ty: framing::FrameType::Padding,
+                    payload: vec![0u8; size],
+                });
             }
         });
     }
@@ -764,68 +2828,42 @@ impl Mux {
         // We may clone locally to zeroize sensitive plaintext after encoding without borrowing issues
         #[cfg(feature = "stealth-mode")]
         let mut frame = frame;
-        #[cfg(not(feature = "stealth-mode"))]
-        let frame = frame;
+        let stream = leading_stream_id(&frame.payload);
+        // The lock is held until the frame is queued so wire order matches nonce order
         let mut enc = self.inner.enc.lock().unwrap();
-        if let Some(st) = enc.as_mut() {
-            let nonce = Self::ctr_to_nonce(st.tx_ctr);
-            let keyctx = framing::KeyCtx { key: st.tx_key };
-            let out = framing::encode(&frame, keyctx, nonce);
-            st.tx_ctr = st.tx_ctr.saturating_add(1);
-            // Zeroize plaintext payload for STREAM frames (stealth builds)
-            #[cfg(feature = "stealth-mode")]
-            if matches!(frame.ty, framing::FrameType::Stream) {
-                frame.payload.fill(0);
-            }
-            if std::env::var("HTX_DEBUG_MUX").ok().as_deref() == Some("1") {
-                let ty = match frame.ty {
-                    framing::FrameType::Stream => "Stream",
-                    framing::FrameType::WindowUpdate => "WindowUpdate",
-                    framing::FrameType::Ping => "Ping",
-                    framing::FrameType::KeyUpdate => "KeyUpdate",
-                    framing::FrameType::Close => "Close",
-                };
-                let id = if out.len() >= 8 {
-                    u32::from_be_bytes([out[4], out[5], out[6], out[7]])
-                } else {
-                    0
-                };
-                eprintln!(
-                    "htx::mux(tx,enc): frame ty={} id={} wire_len={}",
-                    ty,
-                    id,
-                    out.len()
-                );
+        let encrypted = enc.is_some();
+        let out = if let Some(st) = enc.as_mut() {
+            let now = self.now_secs();
+            if st.rekey_due(self.inner.key_policy, now) {
+                self.rotate_tx_locked(st, now);
+                st.auto_updates += 1;
             }
-            let _ = self.inner.tx.send(out);
+            st.seal(&frame)
         } else {
-            let out = frame.encode_plain();
-            // Zeroize plaintext payload for STREAM frames (stealth builds)
-            #[cfg(feature = "stealth-mode")]
-            if matches!(frame.ty, framing::FrameType::Stream) {
-                frame.payload.fill(0);
-            }
-            if std::env::var("HTX_DEBUG_MUX").ok().as_deref() == Some("1") {
-                let ty = match frame.ty {
-                    framing::FrameType::Stream => "Stream",
-                    framing::FrameType::WindowUpdate => "WindowUpdate",
-                    framing::FrameType::Ping => "Ping",
-                    framing::FrameType::KeyUpdate => "KeyUpdate",
-                    framing::FrameType::Close => "Close",
-                };
-                let id = if out.len() >= 8 {
-                    u32::from_be_bytes([out[4], out[5], out[6], out[7]])
-                } else {
-                    0
-                };
-                eprintln!(
-                    "htx::mux(tx,plain): frame ty={} id={} wire_len={}",
-                    ty,
-                    id,
-                    out.len()
-                );
+            frame.encode_plain()
+        };
+        // Zeroize plaintext payload for STREAM frames (stealth builds)
+        #[cfg(feature = "stealth-mode")]
+        if matches!(frame.ty, framing::FrameType::Stream) {
+            frame.payload.fill(0);
+        }
+        if let Some(hook) = &self.inner.debug_hook {
+            hook(&FrameEvent::Sent {
+                ty: frame.ty,
+                stream,
+                wire_len: out.len(),
+                encrypted,
+            });
+        }
+        self.send_wire(out);
+    }
+
+    fn send_wire(&self, bytes: Bytes) {
+        if let Some(tx) = self.inner.tx.lock().unwrap().as_ref() {
+            let len = bytes.len();
+            if tx.send(bytes).is_ok() {
+                self.inner.wire_backlog.fetch_add(len, Ordering::Relaxed);
             }
-            let _ = self.inner.tx.send(out);
         }
     }
 
@@ -835,100 +2873,63 @@ impl Mux {
         n
     }
 
+    /// Rotate the send key now: a KEY_UPDATE goes out under the current key and
+    /// later frames use the next one. Encrypted muxes also do this on their own
+    /// per [`MuxConfig::key_update_policy`].
     pub fn key_update(&self) {
-        // Derive new tx key and reset tx ctr; send a KEY_UPDATE frame encrypted under old key
-        let enc = self.inner.enc.lock().unwrap();
-        if enc.is_some() {
-            // Send notification first (under current key)
-            drop(enc);
-            let frame = framing::Frame {
-                ty: framing::FrameType::KeyUpdate,
-                payload: Vec::new(),
-            };
-            self.send_frame(frame);
-            // Now rotate the tx key
-            let mut enc2 = self.inner.enc.lock().unwrap();
-            if let Some(st2) = enc2.as_mut() {
-                let prk = crypto::hkdf::extract(&st2.tx_key, b"qnet/mux/key_update/v1");
-                let newk: [u8; 32] = crypto::hkdf::expand(&prk, b"key");
-                st2.tx_key = newk;
-                st2.tx_ctr = 0;
-            }
-            // Bump epoch on tx rotation
-            let mut ep = self.inner.key_epoch.lock().unwrap();
-            *ep = ep.saturating_add(1);
+        let mut enc = self.inner.enc.lock().unwrap();
+        if let Some(st) = enc.as_mut() {
+            let now = self.now_secs();
+            self.rotate_tx_locked(st, now);
         }
     }
 
-    pub fn encryption_epoch(&self) -> u64 {
-        *self.inner.key_epoch.lock().unwrap()
-    }
-}
-
-// Determine scheduler defaults from environment.
-// HTX_SCHEDULER_PROFILE=http -> initial_window=256KiB, base_chunk=16KiB (HTTP-friendly)
-// Otherwise defaults: 64KiB window, 4KiB chunk.
-// Overrides: HTX_INITIAL_WINDOW, HTX_CHUNK (bytes, usize), if set.
-fn scheduler_defaults_from_env() -> (usize, usize) {
-    use std::env;
-    // Compatibility flag: if PREFER_QUIC is set truthy and no explicit HTX_SCHEDULER_PROFILE is provided,
-    // default to the HTTP-friendly profile.
-    let prefer_quic = env::var("PREFER_QUIC")
-        .ok()
-        .map(|v| {
-            let v = v.to_ascii_lowercase();
-            matches!(v.as_str(), "1" | "true" | "yes" | "on")
-        })
-        .unwrap_or(false);
-
-    let profile = env::var("HTX_SCHEDULER_PROFILE").unwrap_or_else(|_| {
-        if prefer_quic {
-            String::from("http")
-        } else {
-            String::from("default")
-        }
-    });
-
-    let (mut iw, mut ch) = if profile.eq_ignore_ascii_case("http") {
-        (256 * 1024, 16 * 1024)
-    } else {
-        (64 * 1024, 4096)
-    };
-    if let Ok(v) = env::var("HTX_INITIAL_WINDOW") {
-        if let Ok(parsed) = v.parse::<usize>() {
-            iw = parsed.max(1024); // sane floor
+    // Caller holds the `enc` lock, which keeps the KEY_UPDATE ahead of any frame sealed under the new key.
+    fn rotate_tx_locked(&self, st: &mut EncState, now: u64) {
+        let frame = framing::Frame {
+            ty: framing::FrameType::KeyUpdate,
+            payload: Vec::new(),
+        };
+        let out = st.seal(&frame);
+        if let Some(hook) = &self.inner.debug_hook {
+            hook(&FrameEvent::Sent {
+                ty: frame.ty,
+                stream: 0,
+                wire_len: out.len(),
+                encrypted: true,
+            });
         }
+        self.send_wire(out);
+        st.rotate_tx(now);
+        let mut ep = self.inner.key_epoch.lock().unwrap();
+        *ep = ep.saturating_add(1);
     }
-    if let Ok(v) = env::var("HTX_CHUNK") {
-        if let Ok(parsed) = v.parse::<usize>() {
-            ch = parsed.clamp(512, 64 * 1024);
-        }
+
+    fn now_secs(&self) -> u64 {
+        self.inner.started.elapsed().as_secs()
     }
-    (iw, ch)
-}
 
-fn should_enable_rr_scheduler() -> bool {
-    use std::env;
-    if let Ok(v) = env::var("HTX_SCHEDULER_RR") {
-        let v = v.to_ascii_lowercase();
-        return matches!(v.as_str(), "1" | "true" | "yes" | "on");
-    }
-    // Enable by default when HTTP profile is set or PREFER_QUIC is truthy
-    let prefer_quic = env::var("PREFER_QUIC")
-        .ok()
-        .map(|v| {
-            let v = v.to_ascii_lowercase();
-            matches!(v.as_str(), "1" | "true" | "yes" | "on")
-        })
-        .unwrap_or(false);
-    let profile = env::var("HTX_SCHEDULER_PROFILE").unwrap_or_else(|_| {
-        if prefer_quic {
-            String::from("http")
-        } else {
-            String::from("default")
+    pub fn key_metrics(&self) -> KeyMetrics {
+        let epoch = self.encryption_epoch();
+        match self.inner.enc.lock().unwrap().as_ref() {
+            Some(st) => KeyMetrics {
+                epoch,
+                tx_epoch: st.tx_epoch,
+                rx_epoch: st.rx_epoch,
+                auto_updates: st.auto_updates,
+                tx_frames_since_update: st.tx_ctr,
+                rx_frames_since_update: st.rx_ctr,
+            },
+            None => KeyMetrics {
+                epoch,
+                ..KeyMetrics::default()
+            },
         }
-    });
-    profile.eq_ignore_ascii_case("http")
+    }
+
+    pub fn encryption_epoch(&self) -> u64 {
+        *self.inner.key_epoch.lock().unwrap()
+    }
 }
 
 #[cfg(test)]
@@ -939,7 +2940,7 @@ mod tests {
 
     #[test]
     fn many_concurrent_streams_echo() {
-        let (a, b) = pair();
+        let (a, b) = pair(MuxConfig::default());
         let streams = 100;
         let payload = vec![7u8; 8 * 1024]; // 8 KiB each
 
@@ -988,13 +2989,525 @@ mod tests {
         server.join().unwrap();
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn async_streams_echo_with_partial_reads() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let (a, b) = pair(MuxConfig::default());
+        tokio::spawn(async move {
+            let mut sh = b.accept().await.expect("accept");
+            let mut buf = [0u8; 1000];
+            loop {
+                match AsyncReadExt::read(&mut sh, &mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => sh.write_all(&buf[..n]).await.unwrap(),
+                }
+            }
+        });
+        // Larger than the initial window so the writer has to park on credit
+        let payload: Vec<u8> = (0..300 * 1024u32).map(|i| (i % 253) as u8).collect();
+        let mut sh = a.open_stream();
+        let (mut rd, mut wr) = tokio::io::split(&mut sh);
+        let send = async {
+            wr.write_all(&payload).await.unwrap();
+        };
+        let recv = async {
+            let mut got = vec![0u8; payload.len()];
+            rd.read_exact(&mut got).await.unwrap();
+            got
+        };
+        let (_, got) =
+            tokio::time::timeout(Duration::from_secs(10), async { tokio::join!(send, recv) })
+                .await
+                .expect("async echo timed out");
+        assert_eq!(got, payload);
+    }
+
+    #[test]
+    fn data_resembling_length_prefix_is_not_truncated() {
+        let (a, b) = pair(MuxConfig::default());
+        let sh = a.open_stream();
+        // First four bytes read as a plausible data_len under the legacy layout
+        let data = [0u8, 0, 0, 1, 9, 9, 9, 9, 9];
+        sh.write(&data);
+        let got = b
+            .accept_stream(Duration::from_secs(1))
+            .and_then(|s| s.read())
+            .expect("data");
+        assert_eq!(got, data);
+    }
+
+    #[test]
+    fn closed_wire_ends_streams() {
+        let (ta, tb) = memory_pair();
+        let a = Mux::new(ta, MuxConfig::default());
+        let sh = a.open_stream();
+        // Dropping the peer's end closes the carrier and ends a's reader loop
+        drop(tb);
+        let start = std::time::Instant::now();
+        assert!(sh.read().is_none());
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(a.is_closed());
+        assert!(a.accept_stream(Duration::from_secs(1)).is_none());
+        // A vanished connection is an error, not a clean EOF
+        let err = sh.recv(Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert_eq!(sh.end_state(), Some(StreamEnd::ConnectionLost));
+    }
+
+    fn stream_state_is_empty(m: &Mux) -> bool {
+        m.inner.incoming.lock().unwrap().is_empty()
+            && m.inner.remote_credit.lock().unwrap().streams.is_empty()
+            && m.inner.sched.lock().unwrap().is_empty()
+    }
+
+    fn wait_until(mut cond: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if cond() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        cond()
+    }
+
+    #[test]
+    fn both_ends_open_streams_at_once() {
+        let (a, b) = pair(MuxConfig::default());
+        let open = |m: Mux, tag: u8| {
+            thread::spawn(move || {
+                (0..20u8)
+                    .map(|i| {
+                        let sh = m.open_stream();
+                        sh.write(&[tag, i]);
+                        sh.shutdown_write();
+                        sh
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        let (ha, hb) = (open(a.clone(), b'a'), open(b.clone(), b'b'));
+        let (sa, sb) = (ha.join().unwrap(), hb.join().unwrap());
+        assert!(sa.iter().all(|sh| sh.id() % 2 == 1));
+        assert!(sb.iter().all(|sh| sh.id() % 2 == 0));
+        // Each side accepts exactly the peer's streams, none of its own
+        for (m, peer_tag) in [(&a, b'b'), (&b, b'a')] {
+            let mut seen = Vec::new();
+            while let Some(sh) = m.accept_stream(Duration::from_millis(500)) {
+                let msg = sh.read().expect("data");
+                assert_eq!(msg[0], peer_tag);
+                seen.push(msg[1]);
+            }
+            seen.sort_unstable();
+            assert_eq!(seen, (0..20).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn half_close_delivers_eof_and_releases_state() {
+        let (a, b) = pair(MuxConfig::default());
+        let sa = a.open_stream();
+        sa.write(b"request");
+        sa.shutdown_write();
+        let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        assert_eq!(
+            sb.recv(Duration::from_secs(1)).unwrap().unwrap(),
+            b"request"
+        );
+        // FIN is a clean end of stream, distinct from a timeout
+        assert!(sb.recv(Duration::from_secs(1)).unwrap().is_none());
+        assert_eq!(sb.end_state(), Some(StreamEnd::Fin));
+        // The other direction stays open after the half-close
+        sb.write(b"response");
+        sb.close();
+        assert_eq!(
+            sa.recv(Duration::from_secs(1)).unwrap().unwrap(),
+            b"response"
+        );
+        assert!(sa.recv(Duration::from_secs(1)).unwrap().is_none());
+        assert!(wait_until(
+            || stream_state_is_empty(&a) && stream_state_is_empty(&b)
+        ));
+    }
+
+    #[test]
+    fn reset_surfaces_code_and_releases_state() {
+        let (a, b) = pair(MuxConfig::default());
+        let sa = a.open_stream();
+        sa.write(b"hello");
+        let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        sb.reset(ResetCode::REFUSED);
+        let err = sa.recv(Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(sa.end_state(), Some(StreamEnd::Reset(ResetCode::REFUSED)));
+        assert!(sb.recv(Duration::from_millis(50)).is_err());
+        assert!(wait_until(
+            || stream_state_is_empty(&a) && stream_state_is_empty(&b)
+        ));
+        // Late data for the reset stream must not show up as a new stream
+        a.send_data(sa.id(), b"late");
+        assert!(b.accept_stream(Duration::from_millis(200)).is_none());
+        assert!(stream_state_is_empty(&b));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn async_shutdown_is_eof_and_reset_is_error() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let (a, b) = pair(MuxConfig::default());
+        let mut sa = a.open_stream();
+        AsyncWriteExt::write_all(&mut sa, b"ping").await.unwrap();
+        AsyncWriteExt::shutdown(&mut sa).await.unwrap();
+        let mut sb = b.accept().await.expect("accept");
+        let mut got = Vec::new();
+        sb.read_to_end(&mut got).await.unwrap();
+        assert_eq!(got, b"ping");
+
+        sb.reset(ResetCode::CANCEL);
+        let mut buf = [0u8; 16];
+        let err = AsyncReadExt::read(&mut sa, &mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        let err = AsyncWriteExt::write_all(&mut sa, b"more")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn dropped_handle_acks_and_discards_until_peer_finishes() {
+        let (a, b) = pair(MuxConfig::default());
+        let sa = a.open_stream();
+        sa.write(b"x");
+        let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        drop(sb);
+        // b sent FIN; keep writing well past the initial window to prove credit still flows
+        let big = vec![7u8; a.inner.initial_window * 2];
+        sa.write(&big);
+        assert!(sa.recv(Duration::from_secs(1)).unwrap().is_none());
+        sa.close();
+        assert!(wait_until(
+            || stream_state_is_empty(&a) && stream_state_is_empty(&b)
+        ));
+    }
+
+    #[test]
+    fn shutdown_drains_in_flight_and_refuses_new_streams() {
+        let (a, b) = pair(MuxConfig::default());
+        let s1 = a.open_stream();
+        s1.write(b"req");
+        let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        // Opened before GOAWAY but never seen by b: must come back refused
+        let unseen = a.open_stream();
+        let drainer = {
+            let b = b.clone();
+            thread::spawn(move || b.shutdown(Duration::from_secs(5)))
+        };
+        assert!(wait_until(|| a.is_draining()));
+        assert_eq!(
+            unseen.end_state(),
+            Some(StreamEnd::Reset(ResetCode::REFUSED))
+        );
+        let late = a.open_stream();
+        assert_eq!(late.end_state(), Some(StreamEnd::Reset(ResetCode::REFUSED)));
+        // The in-flight stream still completes in both directions
+        assert_eq!(sb.recv(Duration::from_secs(1)).unwrap().unwrap(), b"req");
+        sb.write(b"resp");
+        sb.close();
+        assert_eq!(s1.recv(Duration::from_secs(1)).unwrap().unwrap(), b"resp");
+        assert!(s1.recv(Duration::from_secs(1)).unwrap().is_none());
+        s1.close();
+        assert!(drainer.join().unwrap());
+        assert!(b.is_closed());
+        assert!(wait_until(|| a.is_closed()));
+    }
+
+    #[test]
+    fn goaway_spares_streams_opened_by_the_sender() {
+        let (a, b) = pair(MuxConfig::default());
+        // b opens first, so its stream id (2) is above a's first one (1)
+        let from_b = b.open_stream();
+        from_b.write(b"b-req");
+        let at_a = a.accept_stream(Duration::from_secs(1)).expect("accept");
+        let from_a = a.open_stream();
+        from_a.write(b"a-req");
+        let at_b = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        assert!(from_a.id() < from_b.id());
+        // Never seen by b, and above the last stream of a's that b accepted
+        let unseen = a.open_stream();
+        let drainer = {
+            let b = b.clone();
+            thread::spawn(move || b.shutdown(Duration::from_secs(5)))
+        };
+        assert!(wait_until(|| a.is_draining()));
+        assert_eq!(
+            unseen.end_state(),
+            Some(StreamEnd::Reset(ResetCode::REFUSED))
+        );
+        assert_eq!(from_a.end_state(), None);
+        assert_eq!(at_a.end_state(), None);
+        // Both in-flight streams still complete in both directions
+        for (opener, acceptor, req) in [(from_a, at_b, b"a-req"), (from_b, at_a, b"b-req")] {
+            assert_eq!(acceptor.recv(Duration::from_secs(1)).unwrap().unwrap(), req);
+            acceptor.write(b"resp");
+            acceptor.close();
+            assert_eq!(
+                opener.recv(Duration::from_secs(1)).unwrap().unwrap(),
+                b"resp"
+            );
+            assert!(opener.recv(Duration::from_secs(1)).unwrap().is_none());
+            opener.close();
+        }
+        assert!(drainer.join().unwrap());
+        assert!(wait_until(|| a.is_closed()));
+    }
+
+    #[test]
+    fn shutdown_resets_streams_left_open_after_grace() {
+        let (a, b) = pair(MuxConfig::default());
+        let s1 = a.open_stream();
+        s1.write(b"req");
+        let _held = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        assert!(!b.shutdown(Duration::from_millis(100)));
+        let err = s1.recv(Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(s1.end_state(), Some(StreamEnd::Reset(ResetCode::CANCEL)));
+        assert!(wait_until(|| a.is_closed()));
+    }
+
+    #[test]
+    fn mux_runs_over_tcp_transport() {
+        use crate::transport::TcpTransport;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dial = thread::spawn(move || std::net::TcpStream::connect(addr).unwrap());
+        let (sock, _) = listener.accept().unwrap();
+        let cfg = MuxConfig::default();
+        let a = Mux::new_encrypted(
+            TcpTransport::new(dial.join().unwrap()).unwrap(),
+            [1u8; 32],
+            [2u8; 32],
+            cfg.clone(),
+        );
+        let b = Mux::new_encrypted(
+            TcpTransport::new(sock).unwrap(),
+            [2u8; 32],
+            [1u8; 32],
+            cfg.role(Role::Responder),
+        );
+        let data = vec![5u8; 200 * 1024];
+        let sh = a.open_stream();
+        let sent = data.clone();
+        // More than one window's worth: the writer needs the peer's WINDOW_UPDATEs
+        let writer = thread::spawn(move || {
+            sh.write(&sent);
+            sh.shutdown_write();
+        });
+        let peer = b.accept_stream(Duration::from_secs(2)).expect("accept");
+        let mut got = Vec::new();
+        while let Some(chunk) = peer.read() {
+            got.extend(chunk);
+        }
+        assert_eq!(got, data);
+        writer.join().unwrap();
+        drop(peer);
+        // Draining one side closes the socket; the other side sees the connection end
+        assert!(a.shutdown(Duration::from_secs(1)));
+        assert!(wait_until(|| b.is_closed()));
+    }
+
+    #[test]
+    fn lossy_carrier_loses_streams_not_the_connection() {
+        use crate::transport::LossyTransport;
+        let (ta, tb) = memory_pair();
+        let a = Mux::new(LossyTransport::new(ta, 0.3, 11), MuxConfig::default());
+        let b = Mux::new(tb, MuxConfig::default().role(Role::Responder));
+        let handles: Vec<_> = (0..50)
+            .map(|_| {
+                let sh = a.open_stream();
+                sh.write(b"probe");
+                sh
+            })
+            .collect();
+        let mut delivered = 0;
+        while let Some(s) = b.accept_stream(Duration::from_millis(200)) {
+            if s.try_read().as_deref() == Some(&b"probe"[..]) {
+                delivered += 1;
+            }
+        }
+        assert!(delivered > 0 && delivered < handles.len(), "{delivered}");
+        assert!(!a.is_closed() && !b.is_closed());
+    }
+
+    // Write `n` one-chunk messages a -> b and check they all arrive intact.
+    fn exchange(a: &Mux, b: &Mux, n: usize) {
+        let sh = a.open_stream();
+        let peer_side = b.clone();
+        let reader = thread::spawn(move || {
+            let peer = peer_side
+                .accept_stream(Duration::from_secs(1))
+                .expect("accept");
+            let mut got = Vec::new();
+            while let Some(chunk) = peer.read() {
+                got.extend(chunk);
+            }
+            got
+        });
+        let mut sent = Vec::new();
+        for i in 0..n {
+            let msg = [i as u8; 100];
+            sh.write(&msg);
+            sent.extend_from_slice(&msg);
+        }
+        sh.shutdown_write();
+        assert_eq!(reader.join().unwrap(), sent);
+    }
+
+    #[test]
+    fn policy_rotates_send_key_without_app_involvement() {
+        let cfg = MuxConfig::default().key_update_policy(KeyUpdatePolicy {
+            max_frames: 8,
+            max_seconds: 0,
+        });
+        let (a, b) = pair_encrypted([1u8; 32], [2u8; 32], [2u8; 32], [1u8; 32], cfg);
+        exchange(&a, &b, 60);
+        let ma = a.key_metrics();
+        assert!(ma.tx_epoch >= 6, "{ma:?}");
+        assert_eq!(ma.auto_updates, ma.tx_epoch);
+        assert!(ma.tx_frames_since_update <= 8);
+        assert!(wait_until(
+            || b.key_metrics().rx_epoch == a.key_metrics().tx_epoch
+        ));
+        assert!(b.encryption_epoch() >= ma.tx_epoch);
+    }
+
+    #[test]
+    fn nonce_ceiling_forces_rotation_even_when_policy_is_disabled() {
+        let cfg = MuxConfig::default().key_update_policy(KeyUpdatePolicy::DISABLED);
+        let (a, b) = pair_encrypted([1u8; 32], [2u8; 32], [2u8; 32], [1u8; 32], cfg);
+        // Fast-forward both ends of a -> b to just below the ceiling
+        a.inner.enc.lock().unwrap().as_mut().unwrap().tx_ctr = NONCE_CEILING - 2;
+        b.inner.enc.lock().unwrap().as_mut().unwrap().rx_ctr = NONCE_CEILING - 2;
+        exchange(&a, &b, 5);
+        let ma = a.key_metrics();
+        assert_eq!(ma.tx_epoch, 1);
+        assert_eq!(ma.auto_updates, 1);
+        assert!(ma.tx_frames_since_update < 10);
+        assert_eq!(b.key_metrics().rx_epoch, 1);
+        assert!(!b.is_closed());
+    }
+
+    #[test]
+    fn peer_that_never_rotates_is_cut_off_at_the_ceiling() {
+        let (a, b) = pair_encrypted(
+            [1u8; 32],
+            [2u8; 32],
+            [2u8; 32],
+            [1u8; 32],
+            MuxConfig::default(),
+        );
+        b.inner.enc.lock().unwrap().as_mut().unwrap().rx_ctr = NONCE_CEILING;
+        a.open_stream().write(b"x");
+        assert!(wait_until(|| b.is_closed()));
+    }
+
+    #[test]
+    fn rtt_estimator_smooths_and_tracks_min() {
+        let ms = Duration::from_millis;
+        let mut r = RttStats::first(ms(80));
+        assert_eq!((r.smoothed, r.variance, r.min), (ms(80), ms(40), ms(80)));
+        r.update(ms(40));
+        assert_eq!(r.smoothed, ms(75));
+        assert_eq!(r.variance, ms(40));
+        assert_eq!(r.min, ms(40));
+        r.update(ms(120));
+        assert_eq!(r.latest, ms(120));
+        assert_eq!(r.min, ms(40));
+        assert_eq!(r.samples, 3);
+    }
+
+    #[test]
+    fn keepalive_pings_are_answered_and_measure_rtt() {
+        let cfg = MuxConfig::default().keepalive(Keepalive {
+            interval: Duration::from_millis(20),
+            ..Keepalive::default()
+        });
+        let (a, _b) = pair(cfg);
+        assert!(wait_until(|| a.rtt().is_some_and(|r| r.samples >= 3)));
+        let r = a.rtt().unwrap();
+        assert!(r.min <= r.smoothed && r.min <= r.latest);
+        assert!(!a.is_closed());
+    }
+
+    #[test]
+    fn silent_peer_is_declared_dead_after_missed_pongs() {
+        use crate::transport::LossyTransport;
+        let (ta, tb) = memory_pair();
+        let a = Mux::new(
+            ta,
+            MuxConfig::default().keepalive(Keepalive {
+                interval: Duration::from_millis(20),
+                max_missed: 3,
+                stealth_sized: false,
+            }),
+        );
+        // b answers, but every PONG is lost on the way back
+        let _b = Mux::new(
+            LossyTransport::new(tb, 1.0, 1),
+            MuxConfig::default().role(Role::Responder),
+        );
+        let sh = a.open_stream();
+        assert!(wait_until(|| a.is_closed()));
+        assert!(a.rtt().is_none());
+        assert_eq!(
+            sh.recv(Duration::from_millis(10)).unwrap_err().kind(),
+            io::ErrorKind::ConnectionAborted
+        );
+    }
+
+    #[test]
+    fn stealth_sized_pings_vary_in_size_and_echo_in_full() {
+        let sizes = Arc::new(Mutex::new(Vec::new()));
+        let log = sizes.clone();
+        let cfg = MuxConfig::default()
+            .keepalive(Keepalive {
+                interval: Duration::from_secs(3600),
+                max_missed: 3,
+                stealth_sized: true,
+            })
+            .debug_hook(move |ev| {
+                if let FrameEvent::Received {
+                    ty: framing::FrameType::Ping,
+                    payload_len,
+                    ..
+                } = ev
+                {
+                    log.lock().unwrap().push(*payload_len);
+                }
+            });
+        let (a, _b) = pair(cfg);
+        for _ in 0..8 {
+            a.ping();
+        }
+        assert!(wait_until(|| a.rtt().is_some_and(|r| r.samples > 0)));
+        // Each PING and its PONG show up once on the receiving side, with equal sizes
+        assert!(wait_until(|| sizes.lock().unwrap().len() == 16));
+        let mut sizes = sizes.lock().unwrap().clone();
+        assert!(sizes.iter().all(|&n| n >= 64));
+        sizes.sort_unstable();
+        sizes.dedup();
+        assert!(sizes.len() > 1);
+    }
+
     #[test]
     fn key_update_rotation_continues_flow() {
         // Build encrypted pair
-        let (a_tx, a_rx_peer) = mpsc::channel::<Bytes>();
-        let (b_tx, b_rx_peer) = mpsc::channel::<Bytes>();
-        let a = Mux::new_encrypted(a_tx, b_rx_peer, [1u8; 32], [2u8; 32]);
-        let b = Mux::new_encrypted(b_tx, a_rx_peer, [2u8; 32], [1u8; 32]);
+        let (ta, tb) = memory_pair();
+        let a = Mux::new_encrypted(ta, [1u8; 32], [2u8; 32], MuxConfig::default());
+        let b = Mux::new_encrypted(
+            tb,
+            [2u8; 32],
+            [1u8; 32],
+            MuxConfig::default().role(Role::Responder),
+        );
 
         // Start echo server on b
         let server = thread::spawn(move || {
@@ -1030,7 +3543,13 @@ mod tests {
     #[test]
     fn key_update_accepts_up_to_3_old_then_rejects() {
         // Create encrypted pair with known keys
-        let (a, b) = super::pair_encrypted([1u8; 32], [2u8; 32], [2u8; 32], [1u8; 32]);
+        let (a, b) = super::pair_encrypted(
+            [1u8; 32],
+            [2u8; 32],
+            [2u8; 32],
+            [1u8; 32],
+            MuxConfig::default(),
+        );
 
         // Helper to build nonce from counter
         fn nonce_from_ctr(ctr: u64) -> [u8; 12] {
@@ -1040,9 +3559,9 @@ mod tests {
         }
 
         // We'll inject frames directly into B via A's tx (they are cross-connected)
-        let stream_id: u32 = 42;
+        let stream_id: u32 = 43; // odd: an id the initiator `a` could have opened
 
-        // 1) Send KEY_UPDATE encoded under current key ([1;32]) with ctr=0
+        // 1) Send KEY_UPDATE encoded under current key ([1;32]) with ctr=1 (a's SETTINGS used 0)
         let key_old = [1u8; 32];
         let frame_ku = framing::Frame {
             ty: framing::FrameType::KeyUpdate,
@@ -1051,15 +3570,12 @@ mod tests {
         let bytes_ku = framing::encode(
             &frame_ku,
             framing::KeyCtx { key: key_old },
-            nonce_from_ctr(0),
+            nonce_from_ctr(1),
         );
-        {
-            let tx = &a.inner.tx; // child module can access
-            tx.send(bytes_ku).unwrap();
-        }
+        a.send_wire(bytes_ku);
 
-        // 2) Send three STREAM frames under old key with ctr 1,2,3 (accepted)
-        for (i, ctr) in [1u64, 2, 3].into_iter().enumerate() {
+        // 2) Send three STREAM frames under old key with ctr 2,3,4 (accepted)
+        for (i, ctr) in [2u64, 3, 4].into_iter().enumerate() {
             let mut payload = Vec::new();
             payload.extend_from_slice(&stream_id.to_be_bytes());
             payload.extend_from_slice(&[b'a' + (i as u8)]);
@@ -1068,10 +3584,10 @@ mod tests {
                 payload,
             };
             let bytes = framing::encode(&f, framing::KeyCtx { key: key_old }, nonce_from_ctr(ctr));
-            a.inner.tx.send(bytes).unwrap();
+            a.send_wire(bytes);
         }
 
-        // 3) Send a 4th STREAM under old key with ctr=4 (should be rejected)
+        // 3) Send a 4th STREAM under old key with ctr=5 (should be rejected)
         {
             let mut payload = Vec::new();
             payload.extend_from_slice(&stream_id.to_be_bytes());
@@ -1080,8 +3596,8 @@ mod tests {
                 ty: framing::FrameType::Stream,
                 payload,
             };
-            let bytes = framing::encode(&f, framing::KeyCtx { key: key_old }, nonce_from_ctr(4));
-            a.inner.tx.send(bytes).unwrap();
+            let bytes = framing::encode(&f, framing::KeyCtx { key: key_old }, nonce_from_ctr(5));
+            a.send_wire(bytes);
         }
 
         // 4) Send a STREAM under the new key (derived as HKDF(old,"key")) with ctr=0 (accepted)
@@ -1096,7 +3612,7 @@ mod tests {
                 payload,
             };
             let bytes = framing::encode(&f, framing::KeyCtx { key: key_new }, nonce_from_ctr(0));
-            a.inner.tx.send(bytes).unwrap();
+            a.send_wire(bytes);
         }
 
         // Now, on B, accept the stream and read up to 5 messages; expect exactly 4 bytes: a,b,c,n
@@ -1117,7 +3633,13 @@ mod tests {
     #[test]
     fn control_rekey_close_blocks_data_until_keyupdate() {
         // Encrypted pair
-        let (a, b) = super::pair_encrypted([1u8; 32], [2u8; 32], [2u8; 32], [1u8; 32]);
+        let (a, b) = super::pair_encrypted(
+            [1u8; 32],
+            [2u8; 32],
+            [2u8; 32],
+            [1u8; 32],
+            MuxConfig::default(),
+        );
 
         // Spawn server to accept data on stream 1 and count bytes
         let server = thread::spawn(move || {
@@ -1164,11 +3686,51 @@ mod tests {
         assert_eq!(total, 128);
     }
 
+    #[cfg(feature = "stealth-mode")]
+    #[test]
+    fn learned_traffic_profile_sets_record_sizes() {
+        use core_framing::profile::TraceRecord;
+        let trace: Vec<TraceRecord> = (0..50)
+            .map(|i| TraceRecord {
+                at: i as f64 * 0.0005,
+                len: 1000,
+            })
+            .collect();
+        let profile = TrafficProfile::learn("fixed", &trace).unwrap();
+        let sizes = Arc::new(Mutex::new(Vec::new()));
+        let log = sizes.clone();
+        let cfg = MuxConfig::default()
+            .traffic_profile(profile)
+            .debug_hook(move |ev| {
+                if let FrameEvent::Sent {
+                    ty: framing::FrameType::Stream,
+                    stream: 1..,
+                    wire_len,
+                    ..
+                } = ev
+                {
+                    log.lock().unwrap().push(*wire_len);
+                }
+            });
+        let (a, b) = pair(cfg);
+        let sh = a.open_stream();
+        sh.write(&[7u8; 4500]);
+        let peer = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        let mut got = 0;
+        while got < 4500 {
+            got += peer.read().expect("data").len();
+        }
+        // Every record is the learned 1000-byte payload plus the 4-byte plain header
+        let sizes = sizes.lock().unwrap();
+        assert_eq!(sizes.len(), 5);
+        assert!(sizes.iter().all(|&n| n == 1004), "{sizes:?}");
+    }
+
     #[cfg(feature = "stealth-mode")]
     #[test]
     fn padded_stream_decode_backward_compat_plain() {
         // Build a plain (unencrypted) mux pair
-        let (a, b) = super::pair();
+        let (a, b) = pair(MuxConfig::default());
         let sh = a.open_stream();
         // Manually send a padded frame using internal API
         let data = b"hi";
@@ -1191,7 +3753,13 @@ mod tests {
     #[test]
     fn padded_stream_decode_backward_compat_encrypted() {
         // Encrypted pair
-        let (a, b) = super::pair_encrypted([1u8; 32], [2u8; 32], [2u8; 32], [1u8; 32]);
+        let (a, b) = super::pair_encrypted(
+            [1u8; 32],
+            [2u8; 32],
+            [2u8; 32],
+            [1u8; 32],
+            MuxConfig::default(),
+        );
         let sh = a.open_stream();
         let data = b"hello-world";
         a.send_data_padded(sh.id(), data, 17);
@@ -1207,55 +3775,577 @@ mod tests {
     }
 
     #[test]
-    fn scheduler_env_overrides_apply() {
-        // Ensure defaults
-        std::env::remove_var("HTX_SCHEDULER_PROFILE");
-        std::env::remove_var("PREFER_QUIC");
-        std::env::remove_var("HTX_INITIAL_WINDOW");
-        std::env::remove_var("HTX_CHUNK");
-        let (a_tx, a_rx_peer) = mpsc::channel::<Bytes>();
-        let (b_tx, b_rx_peer) = mpsc::channel::<Bytes>();
-        let a = Mux::new(a_tx, b_rx_peer);
-        let _b = Mux::new(b_tx, a_rx_peer);
+    fn config_builder_applies_and_clamps() {
+        let (a, _b) = pair(MuxConfig::default());
         assert_eq!(a.inner.initial_window, 64 * 1024);
         assert_eq!(a.inner.base_chunk, 4096);
+        assert_eq!(a.inner.conn_window, 1024 * 1024);
+        assert!(!a.inner.rr_enabled);
+
+        let (h, _hb) = pair(MuxConfig::http());
+        assert_eq!(h.inner.initial_window, 256 * 1024);
+        assert_eq!(h.inner.base_chunk, 16 * 1024);
+        assert_eq!(h.inner.conn_window, 4 * 1024 * 1024);
+        assert!(h.inner.rr_enabled);
+
+        let cfg = MuxConfig::http()
+            .initial_window(128 * 1024)
+            .chunk_size(8192)
+            .old_key_overlap(5);
+        let (c, _cb) = pair(cfg);
+        assert_eq!(c.inner.initial_window, 128 * 1024);
+        assert_eq!(c.inner.base_chunk, 8192);
+        assert_eq!(c.inner.old_key_overlap, 5);
+
+        let (tiny, _tb) = pair(MuxConfig::default().initial_window(1).chunk_size(1 << 20));
+        assert_eq!(tiny.inner.initial_window, 1024);
+        assert_eq!(tiny.inner.base_chunk, 64 * 1024);
+
+        // The memory cap bounds the connection window we grant
+        let (capped, _cb) = pair(MuxConfig::default().memory_cap(256 * 1024));
+        assert_eq!(capped.inner.conn_window, 256 * 1024);
+    }
+
+    #[test]
+    fn config_from_env_vars() {
+        let from = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            MuxConfig::from_vars(|k| vars.get(k).cloned())
+        };
+        let cfg = from(&[]);
+        assert_eq!(cfg.scheduler, SchedulerKind::Inline);
+        assert!(cfg.debug_hook.is_none());
+
+        // Flags take yes/on in any case, as they always did
+        let cfg = from(&[("PREFER_QUIC", "Yes"), ("HTX_CHUNK", "8192")]);
+        assert_eq!((cfg.initial_window, cfg.chunk_size), (256 * 1024, 8192));
+        assert_eq!(cfg.scheduler, SchedulerKind::RoundRobin);
+        let cfg = from(&[
+            ("HTX_SCHEDULER_PROFILE", "HTTP"),
+            ("HTX_SCHEDULER_RR", "off"),
+        ]);
+        assert_eq!(cfg.scheduler, SchedulerKind::Inline);
+        assert_eq!(
+            from(&[("HTX_SCHEDULER_RR", "ON")]).scheduler,
+            SchedulerKind::RoundRobin
+        );
+
+        let cfg = from(&[
+            ("STEALTH_SIZING_PROFILE", "Bursty"),
+            ("STEALTH_JITTER_PROFILE", "SMALL"),
+            ("HTX_HIDDEN_RECORDS", "1024"),
+            ("HTX_DEBUG_FRAMES", "1"),
+        ]);
+        assert_eq!(cfg.stealth.sizing, SizingProfile::Bursty);
+        assert_eq!(cfg.stealth.jitter, JitterProfile::Small);
+        assert_eq!(cfg.record_layout, RecordLayout::hidden(1024));
+        assert!(cfg.debug_hook.is_some());
+    }
+
+    #[test]
+    fn slow_reader_buffers_at_most_the_connection_window() {
+        let window = 128 * 1024;
+        let cfg = MuxConfig::default().connection_window(window);
+        let (a, b) = pair(cfg);
+        let per_stream = 256 * 1024;
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    let sh = a.open_stream();
+                    sh.write(&vec![3u8; per_stream]);
+                    sh.shutdown_write();
+                    sh
+                })
+            })
+            .collect();
+        // Nobody accepts or reads: the writers stall once the shared window is used up
+        assert!(wait_until(|| b.buffered_bytes() == window));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(b.buffered_bytes(), window);
+        assert!(writers.iter().all(|w| !w.is_finished()));
+
+        // Reading returns credit and every stream completes; streams that never got
+        // connection credit only show up on the peer now
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let sh = b.accept_stream(Duration::from_secs(5)).expect("accept");
+                thread::spawn(move || {
+                    let mut got = 0;
+                    while let Some(buf) = sh.recv(Duration::from_secs(5)).unwrap() {
+                        got += buf.len();
+                    }
+                    got
+                })
+            })
+            .collect();
+        for r in readers {
+            assert_eq!(r.join().unwrap(), per_stream);
+        }
+        for w in writers {
+            w.join().unwrap();
+        }
+        assert_eq!(b.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn overrunning_the_stream_window_resets_with_flow_control() {
+        let (a, b) = pair(MuxConfig::default());
+        let sa = a.open_stream();
+        // Bypass credit accounting the way a misbehaving peer would
+        a.send_data(sa.id, &vec![0u8; a.inner.initial_window + 1]);
+        let err = sa.recv(Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            sa.end_state(),
+            Some(StreamEnd::Reset(ResetCode::FLOW_CONTROL))
+        );
+        // Only the stream is lost; its bytes no longer count against the connection
+        assert!(!b.is_closed());
+        assert!(wait_until(|| b.buffered_bytes() == 0));
+    }
+
+    #[test]
+    fn peer_overrunning_the_memory_cap_loses_the_connection() {
+        let (ta, tb) = memory_pair();
+        let a = Mux::new(ta, MuxConfig::default());
+        let b = Mux::new(
+            tb,
+            MuxConfig::default()
+                .memory_cap(16 * 1024)
+                .role(Role::Responder),
+        );
+        // `b` advertises only 16 KiB; this `a` ignores its credit and sends 48 KiB at once
+        let sa = a.open_stream();
+        a.send_data(sa.id(), &vec![1u8; 48 * 1024]);
+        assert!(wait_until(|| b.is_closed() && a.is_closed()));
+        assert!(b.buffered_bytes() <= 16 * 1024);
+        // Refused if the very first record already overran the cap, lost otherwise
+        assert!(matches!(
+            sa.end_state(),
+            Some(StreamEnd::ConnectionLost | StreamEnd::Reset(ResetCode::REFUSED))
+        ));
+    }
+
+    #[test]
+    fn advertised_windows_let_differently_sized_peers_talk() {
+        let (ta, tb) = memory_pair();
+        let a = Mux::new(ta, MuxConfig::default());
+        let b = Mux::new(
+            tb,
+            MuxConfig::default()
+                .initial_window(4 * 1024)
+                .memory_cap(16 * 1024)
+                .role(Role::Responder),
+        );
+        let data = vec![3u8; 200 * 1024];
+        let sa = a.open_stream();
+        let writer = {
+            let data = data.clone();
+            thread::spawn(move || {
+                sa.write(&data);
+                sa
+            })
+        };
+        let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        let mut got = Vec::new();
+        while got.len() < data.len() {
+            got.extend(sb.read().expect("data"));
+            assert!(b.buffered_bytes() <= 16 * 1024);
+        }
+        assert_eq!(got, data);
+        // The other way round `b` sends within the default windows `a` advertised
+        let sa = writer.join().unwrap();
+        let echo = thread::spawn(move || sb.write(&data));
+        let mut back = 0;
+        while back < 200 * 1024 {
+            back += sa.read().expect("data").len();
+        }
+        echo.join().unwrap();
+        assert!(!a.is_closed() && !b.is_closed());
+    }
+
+    #[test]
+    fn peers_claiming_the_same_role_are_cut_off() {
+        let (ta, tb) = memory_pair();
+        let a = Mux::new(ta, MuxConfig::default());
+        let b = Mux::new(tb, MuxConfig::default());
+        assert!(wait_until(|| a.is_closed() && b.is_closed()));
+        let sa = a.open_stream();
+        assert!(sa.read().is_none());
+    }
+
+    #[test]
+    fn muxes_with_different_configs_coexist() {
+        let (a, b) = pair(MuxConfig::default());
+        let (c, d) = pair(MuxConfig::http().chunk_size(1024));
+        assert!(!a.inner.rr_enabled && c.inner.rr_enabled);
+        let data = vec![9u8; 40 * 1024];
+        for (tx, rx) in [(&a, &b), (&c, &d)] {
+            let sh = tx.open_stream();
+            sh.write(&data);
+            let peer = rx.accept_stream(Duration::from_secs(1)).expect("accept");
+            let mut got = Vec::new();
+            while got.len() < data.len() {
+                got.extend(peer.read().expect("data"));
+            }
+            assert_eq!(got, data);
+        }
+    }
+
+    #[test]
+    fn debug_hook_sees_sent_and_received_frames() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let cfg = MuxConfig::default().debug_hook(move |ev| log.lock().unwrap().push(*ev));
+        let (a, b) = super::pair_encrypted([1u8; 32], [2u8; 32], [2u8; 32], [1u8; 32], cfg);
+        let sh = a.open_stream();
+        sh.write(b"hook");
+        let peer = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        assert_eq!(peer.read().expect("data"), b"hook");
+        let seen = seen.lock().unwrap();
+        assert!(seen.iter().any(|ev| matches!(
+            ev,
+            FrameEvent::Sent { ty: framing::FrameType::Stream, stream, encrypted: true, .. }
+                if *stream == sh.id
+        )));
+        assert!(seen.iter().any(|ev| matches!(
+            ev,
+            FrameEvent::Received { ty: framing::FrameType::Stream, stream, .. } if *stream == sh.id
+        )));
+    }
+
+    #[test]
+    fn hidden_record_layout_pads_every_frame_to_the_record_size() {
+        let wire = Arc::new(Mutex::new(Vec::new()));
+        let log = wire.clone();
+        let cfg = MuxConfig::default()
+            .record_layout(RecordLayout::hidden(512))
+            .debug_hook(move |ev| {
+                if let FrameEvent::Wire { len } = ev {
+                    log.lock().unwrap().push(*len);
+                }
+            });
+        let (a, b) = super::pair_encrypted([1u8; 32], [2u8; 32], [2u8; 32], [1u8; 32], cfg);
+        let sa = a.open_stream();
+        sa.write(&[9u8; 3000]);
+        sa.shutdown_write();
+        a.send_datagram(1, b"dg").unwrap();
+        let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        let mut got = 0;
+        while let Some(buf) = sb.recv(Duration::from_secs(1)).unwrap() {
+            got += buf.len();
+        }
+        assert_eq!(got, 3000);
+        assert_eq!(b.recv_datagram(Duration::from_secs(1)).unwrap().data, b"dg");
+        let wire = wire.lock().unwrap();
+        assert!(!wire.is_empty());
+        // u24 length + ciphertext + 16-byte tag, ciphertext a whole number of records
+        assert!(wire.iter().all(|len| (len - 3 - 16) % 512 == 0), "{wire:?}");
+    }
+
+    #[test]
+    fn mismatched_record_layouts_never_deliver_data() {
+        let (ta, tb) = memory_pair();
+        let a = Mux::new_encrypted(
+            ta,
+            [1u8; 32],
+            [2u8; 32],
+            MuxConfig::default().record_layout(RecordLayout::hidden(256)),
+        );
+        let b = Mux::new_encrypted(
+            tb,
+            [2u8; 32],
+            [1u8; 32],
+            MuxConfig::default().role(Role::Responder),
+        );
+        let sa = a.open_stream();
+        sa.write(b"lost in translation");
+        assert!(b.accept_stream(Duration::from_millis(200)).is_none());
+    }
+
+    #[test]
+    fn cover_traffic_fills_idle_slots_and_absorbs_data() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let log = sent.clone();
+        let cover = CoverTraffic::constant_rate(Duration::from_millis(5), 1024);
+        let cfg = MuxConfig::default()
+            .cover_traffic(cover)
+            .debug_hook(move |ev| {
+                if let FrameEvent::Sent { ty, wire_len, .. } = ev {
+                    log.lock().unwrap().push((*ty, *wire_len));
+                }
+            });
+        let (a, b) = pair(cfg);
+        // An idle connection still sends a record per slot
+        assert!(wait_until(|| a.cover_stats().padding_slots >= 5));
 
-        // PREFER_QUIC should bias to HTTP profile when no explicit profile is set
-        std::env::set_var("PREFER_QUIC", "1");
-        let (a_tx_pq, a_rx_pq) = mpsc::channel::<Bytes>();
-        let (b_tx_pq, b_rx_pq) = mpsc::channel::<Bytes>();
-        let a_pq = Mux::new(a_tx_pq, b_rx_pq);
-        let _b_pq = Mux::new(b_tx_pq, a_rx_pq);
-        assert_eq!(a_pq.inner.initial_window, 256 * 1024);
-        assert_eq!(a_pq.inner.base_chunk, 16 * 1024);
-        std::env::remove_var("PREFER_QUIC");
-
-        // HTTP profile
-        std::env::set_var("HTX_SCHEDULER_PROFILE", "http");
-        let (a_tx2, a_rx2) = mpsc::channel::<Bytes>();
-        let (b_tx2, b_rx2) = mpsc::channel::<Bytes>();
-        let a2 = Mux::new(a_tx2, b_rx2);
-        let _b2 = Mux::new(b_tx2, a_rx2);
-        assert_eq!(a2.inner.initial_window, 256 * 1024);
-        assert_eq!(a2.inner.base_chunk, 16 * 1024);
-
-        // Explicit overrides
-        std::env::set_var("HTX_INITIAL_WINDOW", "131072"); // 128KiB
-        std::env::set_var("HTX_CHUNK", "8192"); // 8KiB
-        let (a_tx3, a_rx3) = mpsc::channel::<Bytes>();
-        let (b_tx3, b_rx3) = mpsc::channel::<Bytes>();
-        let a3 = Mux::new(a_tx3, b_rx3);
-        let _b3 = Mux::new(b_tx3, a_rx3);
-        assert_eq!(a3.inner.initial_window, 128 * 1024);
-        assert_eq!(a3.inner.base_chunk, 8192);
+        let sa = a.open_stream();
+        sa.write(&[3u8; 5000]);
+        sa.shutdown_write();
+        let sb = b.accept_stream(Duration::from_secs(2)).expect("accept");
+        let mut got = 0;
+        while let Some(buf) = sb.recv(Duration::from_secs(2)).unwrap() {
+            got += buf.len();
+        }
+        assert_eq!(got, 5000);
+
+        let stats = a.cover_stats();
+        assert_eq!(stats.data_bytes, 5000);
+        assert!(stats.data_slots >= 5, "{stats:?}");
+        assert!(stats.overhead() > 0.0 && stats.overhead() < 1.0);
+        // Data records and filler are the same size on the wire
+        let sent = sent.lock().unwrap();
+        let slot_records: Vec<usize> = sent
+            .iter()
+            .filter(|(ty, _)| {
+                matches!(ty, framing::FrameType::Stream | framing::FrameType::Padding)
+            })
+            .map(|&(_, len)| len)
+            .collect();
+        assert!(
+            slot_records.iter().all(|&len| len == 4 + 1024),
+            "{slot_records:?}"
+        );
+    }
+
+    #[test]
+    fn cover_traffic_budget_limits_padding() {
+        let cover = CoverTraffic {
+            budget: Some(2048),
+            ..CoverTraffic::constant_rate(Duration::from_millis(5), 1024)
+        };
+        let (a, _b) = pair(MuxConfig::default().cover_traffic(cover));
+        thread::sleep(Duration::from_millis(300));
+        let stats = a.cover_stats();
+        // Two slots from the full bucket plus what 300 ms of refill allows
+        assert!(stats.padding_slots <= 3, "{stats:?}");
+        assert!(stats.skipped_slots >= 10, "{stats:?}");
+        assert_eq!(stats.data_slots, 0);
+    }
+
+    #[test]
+    fn datagrams_round_trip_per_flow() {
+        let (a, b) = super::pair_encrypted(
+            [1u8; 32],
+            [2u8; 32],
+            [2u8; 32],
+            [1u8; 32],
+            MuxConfig::default(),
+        );
+        a.send_datagram(53, b"dns query").unwrap();
+        a.send_datagram(7, &[]).unwrap();
+        let d = b.recv_datagram(Duration::from_secs(1)).expect("datagram");
+        assert_eq!((d.flow, d.data.as_slice()), (53, &b"dns query"[..]));
+        let d = b.recv_datagram(Duration::from_secs(1)).expect("datagram");
+        assert_eq!((d.flow, d.data.len()), (7, 0));
+        // Streams are unaffected
+        let sa = a.open_stream();
+        sa.write(b"still reliable");
+        let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        assert_eq!(sb.read().unwrap(), b"still reliable");
+        assert_eq!(a.datagram_stats().sent, 2);
+        assert_eq!(b.datagram_stats().received, 2);
+    }
+
+    #[test]
+    fn full_datagram_queue_drops_the_oldest() {
+        let (a, b) = pair(MuxConfig::default().datagram_queue(4));
+        for i in 0..10u8 {
+            a.send_datagram(1, &[i]).unwrap();
+        }
+        assert!(wait_until(|| b.datagram_stats().received == 10));
+        assert_eq!(b.datagram_stats().dropped, 6);
+        let kept: Vec<u8> = std::iter::from_fn(|| b.recv_datagram(Duration::from_millis(10)))
+            .map(|d| d.data[0])
+            .collect();
+        assert_eq!(kept, vec![6, 7, 8, 9]);
+    }
+
+    // Carrier whose writes never complete, like a peer that stopped reading its socket
+    struct StuckTransport;
+
+    impl FrameTransport for StuckTransport {
+        fn send_frame(&self, _frame: &[u8]) -> io::Result<()> {
+            loop {
+                thread::park();
+            }
+        }
+        fn recv_frame(&self) -> io::Result<Option<Bytes>> {
+            thread::sleep(Duration::from_millis(10));
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+        fn close(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn datagrams_are_dropped_at_send_once_the_carrier_backs_up() {
+        let a = Mux::new(StuckTransport, MuxConfig::default());
+        let sent = 2 * DATAGRAM_SEND_BACKLOG / 1024;
+        for _ in 0..sent {
+            a.send_datagram(1, &[0u8; 1024]).unwrap();
+        }
+        let stats = a.datagram_stats();
+        assert_eq!(stats.sent + stats.send_dropped, sent as u64);
+        assert!(stats.send_dropped > 0);
+        assert!(stats.sent < sent as u64 / 2 + 1);
+        a.shutdown(Duration::ZERO);
+    }
+
+    #[test]
+    fn cover_traffic_sends_datagrams_in_slots() {
+        let cover = CoverTraffic::constant_rate(Duration::from_millis(20), 512);
+        let (a, b) = pair(MuxConfig::default().cover_traffic(cover).datagram_queue(4));
+        for i in 0..10u8 {
+            a.send_datagram(7, &[i]).unwrap();
+        }
+        // Only the newest four fit the send queue; they go out one per slot
+        assert_eq!(a.datagram_stats().send_dropped, 6);
+        let got: Vec<u8> = std::iter::from_fn(|| b.recv_datagram(Duration::from_millis(200)))
+            .map(|d| d.data[0])
+            .collect();
+        assert_eq!(got, [6, 7, 8, 9]);
+        assert_eq!(a.datagram_stats().sent, 4);
+        assert_eq!(a.cover_stats().datagram_slots, 4);
+    }
+
+    #[test]
+    fn oversized_datagrams_and_closed_connections_are_errors() {
+        let (a, b) = pair(MuxConfig::default());
+        let err = a
+            .send_datagram(1, &vec![0u8; MAX_DATAGRAM_LEN + 1])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        a.send_datagram(1, &vec![0u8; MAX_DATAGRAM_LEN]).unwrap();
+        assert_eq!(
+            b.recv_datagram(Duration::from_secs(1)).unwrap().data.len(),
+            MAX_DATAGRAM_LEN
+        );
+        a.shutdown(Duration::ZERO);
+        assert!(wait_until(|| b.is_closed()));
+        assert!(b.recv_datagram(Duration::from_secs(1)).is_none());
+        assert_eq!(
+            a.send_datagram(1, b"late").unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+    }
+
+    #[test]
+    fn priority_frame_reaches_peer_and_can_change() {
+        let (a, b) = pair(MuxConfig::http());
+        let sa = a.open_stream_with_priority(Priority::INTERACTIVE);
+        sa.write(b"GET /");
+        let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        assert_eq!(sa.priority(), Priority::INTERACTIVE);
+        assert!(wait_until(|| sb.priority() == Priority::INTERACTIVE));
+        sa.set_priority(Priority::BULK);
+        assert!(wait_until(|| sb.priority() == Priority::BULK));
+        // Out-of-range values off the wire are clamped
+        assert_eq!(Priority::new(42, 0), Priority::new(7, 1));
+    }
+
+    #[test]
+    fn unknown_ignorable_and_padding_frames_are_skipped() {
+        let (a, b) = pair_encrypted(
+            [1u8; 32],
+            [2u8; 32],
+            [2u8; 32],
+            [1u8; 32],
+            MuxConfig::default(),
+        );
+        {
+            // Seal an extension frame from a hypothetical newer peer in the nonce sequence
+            let mut enc = a.inner.enc.lock().unwrap();
+            let st = enc.as_mut().unwrap();
+            let nonce = Mux::ctr_to_nonce(st.tx_ctr);
+            let out = framing::encode_type(0x5A, b"ext", framing::KeyCtx { key: st.tx_key }, nonce);
+            st.tx_ctr += 1;
+            a.send_wire(out);
+        }
+        a.send_frame(framing::Frame {
+            ty: framing::FrameType::Padding,
+            payload: vec![0u8; 300],
+        });
+        let sa = a.open_stream();
+        sa.write(b"still here");
+        let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        assert_eq!(
+            sb.recv(Duration::from_secs(1)).unwrap().as_deref(),
+            Some(&b"still here"[..])
+        );
+    }
+
+    #[test]
+    fn queued_writers_wait_for_credit() {
+        for cfg in [
+            MuxConfig::http(),
+            MuxConfig::default().cover_traffic(CoverTraffic::constant_rate(
+                Duration::from_millis(1),
+                16 * 1024,
+            )),
+        ] {
+            let window = cfg.initial_window;
+            let (a, b) = pair(cfg);
+            let sa = a.open_stream();
+            let id = sa.id();
+            let writer = thread::spawn(move || sa.write(&vec![4u8; 8 * window]));
+            let sb = b.accept_stream(Duration::from_secs(1)).expect("accept");
+            // The peer is not reading: the writer may get one window out, no more
+            thread::sleep(Duration::from_millis(200));
+            assert!(!writer.is_finished());
+            assert!(a.inner.sched.lock().unwrap().queued_bytes(id) <= window);
+            assert!(b.buffered_bytes() <= window);
+            let mut got = 0;
+            while got < 8 * window {
+                got += sb.read().expect("data").len();
+            }
+            writer.join().unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn async_queued_writes_pend_without_credit() {
+        use tokio::io::AsyncWriteExt;
+        let (a, _b) = pair(MuxConfig::http());
+        let mut sa = a.open_stream();
+        let big = vec![5u8; 1024 * 1024];
+        let write = tokio::time::timeout(
+            Duration::from_millis(300),
+            AsyncWriteExt::write_all(&mut sa, &big),
+        );
+        assert!(
+            write.await.is_err(),
+            "write_all finished with no credit left"
+        );
+        assert!(a.inner.sched.lock().unwrap().queued_bytes(sa.id()) <= 256 * 1024);
+    }
+
+    #[test]
+    fn stream_out_of_credit_does_not_stall_the_writer() {
+        let (a, b) = pair(MuxConfig::http());
+        let bulk = a.open_stream_with_priority(Priority::INTERACTIVE);
+        // The peer never reads `bulk`, so its writer stalls after one stream window
+        let _bulk_writer = thread::spawn(move || bulk.write(&vec![1u8; 1024 * 1024]));
+        let _bulk_peer = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        let small = a.open_stream_with_priority(Priority::BULK);
+        small.write(&[2u8; 10_000]);
+        small.shutdown_write();
+        let peer = b.accept_stream(Duration::from_secs(1)).expect("accept");
+        let mut got = 0;
+        while let Some(buf) = peer.recv(Duration::from_secs(2)).unwrap() {
+            got += buf.len();
+        }
+        assert_eq!(got, 10_000);
     }
 
     #[test]
     fn small_stream_finishes_before_large_under_contention_rr() {
-        // Force RR scheduler and HTTP profile like defaults
-        std::env::set_var("HTX_SCHEDULER_RR", "1");
-        std::env::set_var("HTX_SCHEDULER_PROFILE", "http");
-        let (a, b) = pair();
+        let (a, b) = pair(MuxConfig::http());
 
         // Echo server on b
         let server = thread::spawn(move || {
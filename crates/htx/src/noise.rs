@@ -0,0 +1,229 @@
+//! Noise handshake patterns understood by [`crate::Handshake`].
+//!
+//! Each pattern is a list of messages made of tokens, as in the Noise spec,
+//! and every message ends with an empty payload. `XK` pins the responder's
+//! static as a pre-message, `XX` exchanges both statics without a pin, and
+//! `IK` finishes in one round trip when the edge's key is already known.
+//! The `psk2` modifier mixes a pre-shared key at the end of message 2. The
+//! classical patterns match other Noise implementations byte for byte.
+//!
+//! The `hfs` modifier makes any pattern hybrid post-quantum, following the
+//! Noise HFS extension with ML-KEM-768: the initiator adds a KEM public key
+//! (`e1`) after its first `e`, and the responder answers with a ciphertext
+//! (`ekem1`) after `ee` whose shared secret is mixed into the chaining key.
+
+use core_crypto::mlkem::{CIPHERTEXT_LEN, ENCAPS_KEY_LEN};
+use rand::{CryptoRng, RngCore};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Token {
+    E,
+    S,
+    Ee,
+    Es,
+    Se,
+    Ss,
+    Psk,
+    E1,
+    Ekem1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Pattern {
+    /// Responder static known in advance; three messages.
+    #[default]
+    Xk,
+    /// Responder static known in advance; initiator static sent in message 1.
+    Ik,
+    /// No static known in advance; both are exchanged encrypted.
+    Xx,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 3] = [Pattern::Xk, Pattern::Ik, Pattern::Xx];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Pattern::Xk => "XK",
+            Pattern::Ik => "IK",
+            Pattern::Xx => "XX",
+        }
+    }
+
+    /// Whether the initiator must know the responder's static key up front.
+    pub fn needs_remote_static(self) -> bool {
+        matches!(self, Pattern::Xk | Pattern::Ik)
+    }
+
+    pub(crate) fn messages(self) -> &'static [&'static [Token]] {
+        use Token::*;
+        match self {
+            Pattern::Xk => &[&[E, Es], &[E, Ee], &[S, Se]],
+            Pattern::Xx => &[&[E], &[E, Ee, S, Es], &[S, Se]],
+            Pattern::Ik => &[&[E, Es, S, Ss], &[E, Ee, Se]],
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Pattern plus optional `psk2` key and `hfs` hybrid mode: what both ends must agree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HandshakeParams {
+    pub pattern: Pattern,
+    pub psk: Option<[u8; 32]>,
+    /// Add an ML-KEM-768 exchange (`hfs`). A hybrid responder still accepts a
+    /// classical initiator and falls back to the plain pattern, so upgrade
+    /// responders first; check [`crate::Handshake::params`] afterwards to
+    /// refuse the fallback.
+    pub hybrid: bool,
+}
+
+impl HandshakeParams {
+    pub fn new(pattern: Pattern) -> Self {
+        Self {
+            pattern,
+            psk: None,
+            hybrid: false,
+        }
+    }
+
+    /// Mix an ML-KEM-768 shared secret into the chaining key (the `hfs` modifier).
+    pub fn hybrid(mut self) -> Self {
+        self.hybrid = true;
+        self
+    }
+
+    /// Mix `psk` at the end of message 2 (the `psk2` modifier).
+    pub fn psk2(mut self, psk: [u8; 32]) -> Self {
+        self.psk = Some(psk);
+        self
+    }
+
+    /// Pattern name with modifiers, e.g. `IKpsk2` or `XKhfs+psk2`. This is what
+    /// the inner caps carry.
+    pub fn name(&self) -> String {
+        let psk = match (self.psk.is_some(), self.hybrid) {
+            (false, _) => "",
+            (true, false) => "psk2",
+            (true, true) => "+psk2",
+        };
+        let hfs = if self.hybrid { "hfs" } else { "" };
+        format!("{}{hfs}{psk}", self.pattern)
+    }
+
+    pub fn protocol_name(&self) -> String {
+        let kem = if self.hybrid { "+MLKEM768" } else { "" };
+        format!("Noise_{}_25519{kem}_ChaChaPoly_SHA256", self.name())
+    }
+
+    pub(crate) fn message(&self, i: usize) -> Vec<Token> {
+        let mut m = Vec::new();
+        for &t in self.pattern.messages()[i] {
+            m.push(t);
+            match t {
+                // e1 follows the initiator's e, ekem1 follows ee
+                Token::E if self.hybrid && i == 0 => m.push(Token::E1),
+                Token::Ee if self.hybrid => m.push(Token::Ekem1),
+                _ => {}
+            }
+        }
+        if self.psk.is_some() && i == 1 {
+            m.push(Token::Psk);
+        }
+        m
+    }
+
+    /// Wire length of message `i`. Fields and the empty payload are encrypted,
+    /// and gain a tag, once a key exists.
+    pub(crate) fn message_len(&self, i: usize) -> usize {
+        let (mut keyed, mut len) = (false, 0);
+        for j in 0..=i {
+            len = 0;
+            for t in self.message(j) {
+                let tag = if keyed { 16 } else { 0 };
+                match t {
+                    Token::E => {
+                        len += 32;
+                        keyed |= self.psk.is_some();
+                    }
+                    Token::S => len += 32 + tag,
+                    Token::E1 => len += ENCAPS_KEY_LEN + tag,
+                    Token::Ekem1 => {
+                        len += CIPHERTEXT_LEN + tag;
+                        keyed = true;
+                    }
+                    Token::Ee | Token::Es | Token::Se | Token::Ss | Token::Psk => keyed = true,
+                }
+            }
+            if keyed {
+                len += 16;
+            }
+        }
+        len
+    }
+
+    pub(crate) fn message_count(&self) -> usize {
+        self.pattern.messages().len()
+    }
+}
+
+/// Parse a name from [`HandshakeParams::name`]. The key is not part of the name,
+/// so the result has no PSK and the flag says whether `psk2` was asked for.
+pub fn parse_name(name: &str) -> Option<(HandshakeParams, bool)> {
+    let (rest, wants_psk) = match name.strip_suffix("psk2") {
+        Some(rest) => (rest.strip_suffix('+').unwrap_or(rest), true),
+        None => (name, false),
+    };
+    let (pattern, hybrid) = match rest.strip_suffix("hfs") {
+        Some(p) => (p, true),
+        None => (rest, false),
+    };
+    let params = HandshakeParams {
+        pattern: pattern.parse().ok()?,
+        psk: None,
+        hybrid,
+    };
+    Some((params, wants_psk))
+}
+
+/// Parse `XK`, `IK` or `XX`, ignoring case.
+impl FromStr for Pattern {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Pattern::ALL
+            .into_iter()
+            .find(|p| p.as_str().eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
+
+/// Hands out one fixed 32-byte value for every draw (ephemeral, then KEM seeds),
+/// as published test vectors do. Never use it for a real handshake.
+#[doc(hidden)]
+pub struct FixedRng(pub [u8; 32]);
+
+impl RngCore for FixedRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        dest.copy_from_slice(&self.0[..dest.len()]);
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for FixedRng {}
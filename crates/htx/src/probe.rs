@@ -0,0 +1,352 @@
+//! Active-probing resistance for the edge.
+//!
+//! An HTX client proves itself with a knock. The first [`KNOCK_LEN`] bytes it
+//! sends after the TLS handshake are an HMAC, under a key shared with the edge,
+//! of a TLS exporter value. The exporter ties the knock to one session, so a
+//! recorded knock is useless on any other connection.
+//!
+//! Anything that does not knock is spliced to a cover origin: a browser, a
+//! scanner or a censor's probe. TLS clients have their decrypted bytes
+//! forwarded. Clients that do not open with a TLS handshake, or say nothing,
+//! are forwarded raw. Either way the prober ends up talking to an ordinary web
+//! server. With a guard set the edge offers only the ALPN the cover speaks, so a
+//! prober never negotiates a protocol the cover then fails to answer.
+
+use crate::api::ApiError;
+use ring::hmac;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Length of the knock a client sends right after the TLS handshake.
+pub const KNOCK_LEN: usize = 32;
+/// How long the edge waits for a knock before treating the client as a prober.
+pub const DEFAULT_KNOCK_TIMEOUT: Duration = Duration::from_secs(3);
+/// Probers spliced to the cover at once before further ones are closed.
+pub const DEFAULT_MAX_SPLICES: usize = 64;
+
+const KNOCK_LABEL: &[u8] = b"qnet knock";
+// First byte of a TLS handshake record
+const TLS_HANDSHAKE: u8 = 0x16;
+// Read timeout while splicing a TLS session, so queued origin bytes get written
+const SPLICE_POLL: Duration = Duration::from_millis(20);
+
+/// Edge policy: the knock key, and where clients without it are sent.
+#[derive(Clone)]
+pub struct ProbeGuard {
+    key: [u8; 32],
+    cover: String,
+    cover_alpn: Vec<Vec<u8>>,
+    knock_timeout: Duration,
+    max_splices: usize,
+    // Shared by clones, so one listener's splices count against one limit
+    splices: Arc<AtomicUsize>,
+}
+
+impl ProbeGuard {
+    /// `cover` is the `host:port` of a plain-HTTP origin serving the cover site.
+    /// It is assumed to speak HTTP/1.1 only; see [`ProbeGuard::cover_alpn`].
+    pub fn new(key: [u8; 32], cover: impl Into<String>) -> Self {
+        Self {
+            key,
+            cover: cover.into(),
+            cover_alpn: vec![b"http/1.1".to_vec()],
+            knock_timeout: DEFAULT_KNOCK_TIMEOUT,
+            max_splices: DEFAULT_MAX_SPLICES,
+            splices: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn knock_timeout(mut self, timeout: Duration) -> Self {
+        self.knock_timeout = timeout;
+        self
+    }
+
+    /// ALPN protocols the edge offers, in preference order. Only list `h2` when the
+    /// cover answers HTTP/2 with prior knowledge.
+    pub fn cover_alpn(mut self, protocols: &[&str]) -> Self {
+        self.cover_alpn = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self
+    }
+
+    /// Splices running at once (floor 1). Probers beyond that are closed.
+    pub fn max_splices(mut self, n: usize) -> Self {
+        self.max_splices = n.max(1);
+        self
+    }
+
+    /// Guard from `HTX_KNOCK_KEY` and `HTX_COVER_ORIGIN`, or `None` when either is unset.
+    pub fn from_env() -> Result<Option<Self>, ApiError> {
+        let (Some(key), Ok(cover)) = (knock_key_from_env()?, std::env::var("HTX_COVER_ORIGIN"))
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Self::new(key, cover)))
+    }
+}
+
+/// `HTX_KNOCK_KEY` as 64 hex characters, or `None` when unset.
+pub fn knock_key_from_env() -> Result<Option<[u8; 32]>, ApiError> {
+    let Ok(v) = std::env::var("HTX_KNOCK_KEY") else {
+        return Ok(None);
+    };
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(v.trim(), &mut key).map_err(|_| {
+        ApiError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "HTX_KNOCK_KEY must be 64 hex characters",
+        ))
+    })?;
+    Ok(Some(key))
+}
+
+/// The knock for the session whose knock exporter value is `ekm`.
+pub fn knock(key: &[u8; 32], ekm: &[u8; 32]) -> [u8; KNOCK_LEN] {
+    let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), ekm);
+    let mut out = [0u8; KNOCK_LEN];
+    out.copy_from_slice(tag.as_ref());
+    out
+}
+
+/// Client: the knock to send first on `conn`.
+pub(crate) fn client_knock(
+    conn: &rustls::ClientConnection,
+    key: &[u8; 32],
+) -> Result<[u8; KNOCK_LEN], ApiError> {
+    let ekm = conn
+        .export_keying_material([0u8; 32], KNOCK_LABEL, None)
+        .map_err(|_| ApiError::Tls)?;
+    Ok(knock(key, &ekm))
+}
+
+/// A connection to hand to the cover origin; [`Splice::run`] blocks until both
+/// sides are done.
+pub(crate) struct Splice {
+    tcp: TcpStream,
+    // Terminated TLS session and the plaintext already read from it
+    tls: Option<(rustls::ServerConnection, Vec<u8>)>,
+    cover: String,
+    // None when the guard was already at its splice limit
+    slot: Option<SpliceSlot>,
+}
+
+// One of the guard's splices; gives the place back on drop
+struct SpliceSlot(Arc<AtomicUsize>);
+
+impl Drop for SpliceSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl ProbeGuard {
+    /// Offer only the cover's ALPN, so a prober that negotiated one gets an origin
+    /// speaking it.
+    pub(crate) fn restrict_alpn(&self, tls: &mut rustls::ServerConfig) {
+        tls.alpn_protocols = self.cover_alpn.clone();
+    }
+
+    /// Before TLS: a splice when the client should go to the cover raw, because its
+    /// first byte is not a TLS handshake record or nothing came before `deadline`.
+    pub(crate) fn check_hello(
+        &self,
+        tcp: TcpStream,
+        deadline: Instant,
+    ) -> io::Result<Result<TcpStream, Splice>> {
+        let mut first = [0u8; 1];
+        let is_tls = loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break false;
+            }
+            tcp.set_read_timeout(Some(left))?;
+            match tcp.peek(&mut first) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => break first[0] == TLS_HANDSHAKE,
+                Err(e) if is_retry(&e) => {}
+                Err(e) => return Err(e),
+            }
+        };
+        tcp.set_read_timeout(None)?;
+        Ok(match is_tls {
+            true => Ok(tcp),
+            false => Err(self.splice(tcp, None)),
+        })
+    }
+
+    /// After TLS: read the knock. A client whose bytes stop matching it, or who
+    /// sends nothing in time, goes to the cover with whatever it sent.
+    pub(crate) fn check_knock(
+        &self,
+        mut conn: rustls::ServerConnection,
+        mut tcp: TcpStream,
+    ) -> io::Result<Result<(rustls::ServerConnection, TcpStream), Splice>> {
+        let ekm = conn
+            .export_keying_material([0u8; 32], KNOCK_LABEL, None)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let want = knock(&self.key, &ekm);
+        let deadline = Instant::now() + self.knock_timeout;
+        let mut got = Vec::with_capacity(KNOCK_LEN);
+        let mut buf = [0u8; KNOCK_LEN];
+        // Stop at the first byte that differs so a short HTTP request is
+        // forwarded at once. The knock is per session, so this teaches a
+        // prober nothing it can reuse.
+        while got.len() < KNOCK_LEN && want.starts_with(&got) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            tcp.set_read_timeout(Some(left))?;
+            let want_more = KNOCK_LEN - got.len();
+            match rustls::Stream::new(&mut conn, &mut tcp).read(&mut buf[..want_more]) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => got.extend_from_slice(&buf[..n]),
+                Err(e) if is_retry(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        tcp.set_read_timeout(None)?;
+        let ok = got.len() == KNOCK_LEN
+            && got.iter().zip(&want).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0;
+        Ok(match ok {
+            true => Ok((conn, tcp)),
+            false => Err(self.splice(tcp, Some((conn, got)))),
+        })
+    }
+
+    fn splice(&self, tcp: TcpStream, tls: Option<(rustls::ServerConnection, Vec<u8>)>) -> Splice {
+        let slot = self
+            .splices
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max_splices).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| SpliceSlot(self.splices.clone()));
+        Splice {
+            tcp,
+            tls,
+            cover: self.cover.clone(),
+            slot,
+        }
+    }
+}
+
+impl Splice {
+    /// Forward to the cover, or close the client when the guard is at its limit.
+    pub(crate) fn run(self) -> io::Result<()> {
+        let Some(_slot) = &self.slot else {
+            return Err(io::Error::other("too many cover splices"));
+        };
+        let mut origin = TcpStream::connect(&self.cover)?;
+        origin.set_nodelay(true)?;
+        match self.tls {
+            None => splice_raw(self.tcp, origin),
+            Some((conn, first)) => {
+                origin.write_all(&first)?;
+                splice_tls(conn, self.tcp, origin)
+            }
+        }
+    }
+}
+
+fn is_retry(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    )
+}
+
+fn splice_raw(client: TcpStream, origin: TcpStream) -> io::Result<()> {
+    let (mut c_rd, mut o_wr) = (client.try_clone()?, origin.try_clone()?);
+    let up = thread::spawn(move || {
+        let _ = io::copy(&mut c_rd, &mut o_wr);
+        let _ = o_wr.shutdown(Shutdown::Write);
+    });
+    let (mut o_rd, mut c_wr) = (origin, client);
+    let _ = io::copy(&mut o_rd, &mut c_wr);
+    let _ = c_wr.shutdown(Shutdown::Write);
+    let _ = up.join();
+    Ok(())
+}
+
+// A TLS session cannot be split, so origin bytes arrive over a channel and
+// client reads poll with a short timeout in between.
+fn splice_tls(
+    conn: rustls::ServerConnection,
+    client: TcpStream,
+    mut origin: TcpStream,
+) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    let mut o_rd = origin.try_clone()?;
+    thread::spawn(move || {
+        let mut buf = [0u8; 16 * 1024];
+        while let Ok(n @ 1..) = o_rd.read(&mut buf) {
+            if tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    client.set_read_timeout(Some(SPLICE_POLL))?;
+    let mut tls = rustls::StreamOwned::new(conn, client);
+    let mut buf = [0u8; 16 * 1024];
+    let mut client_open = true;
+    loop {
+        let mut origin_open = true;
+        if client_open {
+            loop {
+                match rx.try_recv() {
+                    Ok(b) => tls.write_all(&b)?,
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        origin_open = false;
+                        break;
+                    }
+                }
+            }
+        } else {
+            // Nothing more to read from the client: just wait on the origin
+            match rx.recv() {
+                Ok(b) => tls.write_all(&b)?,
+                Err(_) => origin_open = false,
+            }
+        }
+        tls.flush()?;
+        if !origin_open {
+            tls.conn.send_close_notify();
+            tls.flush()?;
+            return tls.sock.shutdown(Shutdown::Write);
+        }
+        if client_open {
+            match tls.read(&mut buf) {
+                Ok(0) => {
+                    client_open = false;
+                    origin.shutdown(Shutdown::Write)?;
+                }
+                Ok(n) => origin.write_all(&buf[..n])?,
+                Err(e) if is_retry(&e) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    client_open = false;
+                    origin.shutdown(Shutdown::Write)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn knock_depends_on_key_and_session() {
+        let (k1, k2) = ([1u8; 32], [2u8; 32]);
+        let (s1, s2) = ([3u8; 32], [4u8; 32]);
+        assert_eq!(knock(&k1, &s1), knock(&k1, &s1));
+        assert_ne!(knock(&k1, &s1), knock(&k2, &s1));
+        assert_ne!(knock(&k1, &s1), knock(&k1, &s2));
+    }
+}
@@ -0,0 +1,471 @@
+//! Session resumption tickets for fast reconnects.
+//!
+//! After a full handshake the edge hands out [`NewTicket`]s on the control stream
+//! (stream 0). The ticket itself is opaque to the client: the edge seals the ticket
+//! id, expiry and secret under a key only it holds ([`TicketIssuer`]). Both ends
+//! derive the secret from their session's resumption secret and the ticket nonce,
+//! so it never travels. Clients keep tickets per origin in a [`TicketCache`].
+//!
+//! To resume, the client sends a [`ResumeHello`] carrying the ticket and a fresh
+//! X25519 ephemeral; the edge opens the ticket, refuses it when expired or already
+//! redeemed, and answers with its own ephemeral in a [`ResumeAccept`]. Keys come
+//! from the ticket secret and the ephemeral DH together, so every resumed session
+//! gets fresh keys and a stolen ticket key alone does not open recorded sessions.
+
+use core_cbor as cbor;
+use core_crypto as crypto;
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use rand::{rngs::OsRng, RngCore};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long an issued ticket can be redeemed.
+pub const DEFAULT_TICKET_LIFETIME: Duration = Duration::from_secs(24 * 3600);
+/// Tickets a [`TicketCache`] keeps per origin; the oldest is dropped first.
+pub const TICKETS_PER_ORIGIN: usize = 4;
+
+// AAD for sealed tickets
+const TICKET_AAD: &[u8] = b"qnet/ticket/v1";
+const TICKET_ID_LEN: usize = 16;
+const TICKET_NONCE_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeError {
+    /// Not a ticket or resumption message this build understands.
+    Malformed,
+    /// Sealed under another ticket key, or tampered with.
+    BadTicket,
+    Expired,
+    /// The ticket was already redeemed once.
+    Replayed,
+    /// The ephemeral exchange failed or the edge did not prove the ticket secret.
+    Handshake,
+}
+
+/// Seconds since the Unix epoch, the clock tickets are stamped with.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Ticket announcement sent by the edge on the control stream.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NewTicket {
+    #[serde(rename = "TICKET", with = "serde_bytes")]
+    pub ticket: Vec<u8>,
+    #[serde(rename = "TNONCE", with = "serde_bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(rename = "LIFETIME")]
+    pub lifetime: u64, // seconds
+}
+
+// What the edge seals into a ticket
+#[derive(Serialize, Deserialize)]
+struct TicketBody {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    secret: Vec<u8>,
+    expires: u64,
+}
+
+fn ticket_secret(resumption: &[u8; 32], nonce: &[u8]) -> [u8; 32] {
+    let prk = crypto::hkdf::extract(resumption, nonce);
+    crypto::hkdf::expand(&prk, b"qnet/resume/v1|ticket")
+}
+
+/// Edge side: seals tickets under its ticket key and redeems each at most once.
+pub struct TicketIssuer {
+    key: [u8; 32],
+    lifetime: Duration,
+    // Redeemed ticket ids and their expiry; dropped once the ticket would be expired anyway
+    redeemed: Mutex<HashMap<[u8; TICKET_ID_LEN], u64>>,
+}
+
+impl TicketIssuer {
+    /// Issuer sealing with `key`. Edges that share a key accept each other's tickets,
+    /// but each keeps its own replay set.
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            key,
+            lifetime: DEFAULT_TICKET_LIFETIME,
+            redeemed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Issuer with a fresh random key; its tickets die with it.
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self::new(key)
+    }
+
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// Issue a ticket for a session whose resumption secret is `resumption`.
+    pub fn issue(&self, resumption: &[u8; 32]) -> NewTicket {
+        self.issue_at(resumption, unix_now())
+    }
+
+    pub fn issue_at(&self, resumption: &[u8; 32], now: u64) -> NewTicket {
+        let mut id = [0u8; TICKET_ID_LEN];
+        let mut nonce = [0u8; TICKET_NONCE_LEN];
+        let mut seal_nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut id);
+        OsRng.fill_bytes(&mut nonce);
+        OsRng.fill_bytes(&mut seal_nonce);
+        let lifetime = self.lifetime.as_secs();
+        let body = TicketBody {
+            id: id.to_vec(),
+            secret: ticket_secret(resumption, &nonce).to_vec(),
+            expires: now.saturating_add(lifetime),
+        };
+        let pt = cbor::to_det_cbor(&body).expect("det-cbor");
+        let mut ticket = seal_nonce.to_vec();
+        ticket.extend_from_slice(&crypto::aead::seal(&self.key, &seal_nonce, TICKET_AAD, &pt));
+        NewTicket {
+            ticket,
+            nonce: nonce.to_vec(),
+            lifetime,
+        }
+    }
+
+    /// Open `ticket` and mark it used, returning its secret.
+    pub fn redeem(&self, ticket: &[u8], now: u64) -> Result<[u8; 32], ResumeError> {
+        if ticket.len() < 12 {
+            return Err(ResumeError::Malformed);
+        }
+        let (seal_nonce, ct) = ticket.split_at(12);
+        let seal_nonce: [u8; 12] = seal_nonce.try_into().expect("12 bytes");
+        let pt = crypto::aead::open(&self.key, &seal_nonce, TICKET_AAD, ct)
+            .map_err(|_| ResumeError::BadTicket)?;
+        let body: TicketBody = cbor::from_det_cbor(&pt).map_err(|_| ResumeError::Malformed)?;
+        let id: [u8; TICKET_ID_LEN] = body.id.try_into().map_err(|_| ResumeError::Malformed)?;
+        let secret: [u8; 32] = body.secret.try_into().map_err(|_| ResumeError::Malformed)?;
+        if now >= body.expires {
+            return Err(ResumeError::Expired);
+        }
+        let mut redeemed = self.redeemed.lock().unwrap();
+        redeemed.retain(|_, expires| *expires > now);
+        if redeemed.insert(id, body.expires).is_some() {
+            return Err(ResumeError::Replayed);
+        }
+        Ok(secret)
+    }
+
+    /// Answer a [`ResumeHello`]: redeem its ticket and key the resumed session.
+    /// Returns the [`ResumeAccept`] bytes for the client and the edge's keys.
+    pub fn accept(&self, hello: &[u8], now: u64) -> Result<(Vec<u8>, ResumedKeys), ResumeError> {
+        let h: ResumeHello = cbor::from_det_cbor(hello).map_err(|_| ResumeError::Malformed)?;
+        let ce: [u8; 32] = h.ephemeral.try_into().map_err(|_| ResumeError::Malformed)?;
+        let secret = self.redeem(&h.ticket, now)?;
+        let (sk, se) = ephemeral();
+        let sched = Schedule::new(&secret, &dh(&sk, &ce)?, hello, &se);
+        let accept = cbor::to_det_cbor(&ResumeAccept {
+            ephemeral: se.to_vec(),
+            confirm: sched.confirm.to_vec(),
+        })
+        .expect("det-cbor");
+        Ok((accept, sched.keys(false)))
+    }
+}
+
+/// A ticket as the client keeps it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ticket {
+    pub ticket: Vec<u8>,
+    secret: [u8; 32],
+    /// Unix seconds on the client's clock after which the edge refuses it.
+    pub expires: u64,
+}
+
+impl Ticket {
+    /// The client's view of `nt`, keyed from its own session's resumption secret.
+    pub fn from_new_ticket(nt: &NewTicket, resumption: &[u8; 32], now: u64) -> Self {
+        Self {
+            ticket: nt.ticket.clone(),
+            secret: ticket_secret(resumption, &nt.nonce),
+            expires: now.saturating_add(nt.lifetime),
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires
+    }
+}
+
+/// Client-side tickets per origin. Each ticket is handed out once.
+#[derive(Debug, Default)]
+pub struct TicketCache {
+    origins: Mutex<HashMap<String, VecDeque<Ticket>>>,
+}
+
+impl TicketCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, origin: &str, ticket: Ticket) {
+        let mut origins = self.origins.lock().unwrap();
+        let q = origins.entry(origin.to_string()).or_default();
+        q.push_back(ticket);
+        while q.len() > TICKETS_PER_ORIGIN {
+            q.pop_front();
+        }
+    }
+
+    /// Newest unexpired ticket for `origin`, removed so it is never offered twice.
+    pub fn take(&self, origin: &str, now: u64) -> Option<Ticket> {
+        let mut origins = self.origins.lock().unwrap();
+        let q = origins.get_mut(origin)?;
+        q.retain(|t| !t.is_expired(now));
+        let t = q.pop_back();
+        if q.is_empty() {
+            origins.remove(origin);
+        }
+        t
+    }
+
+    /// Tickets held for `origin`, expired ones included.
+    pub fn len(&self, origin: &str) -> usize {
+        self.origins
+            .lock()
+            .unwrap()
+            .get(origin)
+            .map_or(0, |q| q.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.origins.lock().unwrap().is_empty()
+    }
+}
+
+/// First message of a resumed session: the ticket and the client's ephemeral.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeHello {
+    #[serde(with = "serde_bytes")]
+    pub ticket: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub ephemeral: Vec<u8>,
+}
+
+/// The edge's answer: its ephemeral and proof that it opened the ticket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeAccept {
+    #[serde(with = "serde_bytes")]
+    pub ephemeral: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub confirm: Vec<u8>,
+}
+
+/// Keys of a resumed session, before binding to the outer TLS
+/// (see [`crate::inner::open_inner_resumed`]).
+#[derive(Debug, Clone)]
+pub struct ResumedKeys {
+    pub tx_key: [u8; 32],
+    pub rx_key: [u8; 32],
+    /// Resumption secret of the new session, for its own tickets.
+    pub resumption: [u8; 32],
+}
+
+/// Client side of a resumption; send [`ResumeClient::hello`], then [`ResumeClient::finish`]
+/// with the edge's answer.
+pub struct ResumeClient {
+    secret: [u8; 32],
+    sk: Scalar,
+    hello: Vec<u8>,
+}
+
+impl ResumeClient {
+    pub fn new(ticket: &Ticket) -> Self {
+        let (sk, ce) = ephemeral();
+        let hello = cbor::to_det_cbor(&ResumeHello {
+            ticket: ticket.ticket.clone(),
+            ephemeral: ce.to_vec(),
+        })
+        .expect("det-cbor");
+        Self {
+            secret: ticket.secret,
+            sk,
+            hello,
+        }
+    }
+
+    pub fn hello(&self) -> &[u8] {
+        &self.hello
+    }
+
+    pub fn finish(self, accept: &[u8]) -> Result<ResumedKeys, ResumeError> {
+        let a: ResumeAccept = cbor::from_det_cbor(accept).map_err(|_| ResumeError::Malformed)?;
+        let se: [u8; 32] = a.ephemeral.try_into().map_err(|_| ResumeError::Malformed)?;
+        let sched = Schedule::new(&self.secret, &dh(&self.sk, &se)?, &self.hello, &se);
+        // Constant-time compare
+        let diff = sched
+            .confirm
+            .iter()
+            .zip(&a.confirm)
+            .fold(0u8, |d, (x, y)| d | (x ^ y));
+        if a.confirm.len() != sched.confirm.len() || diff != 0 {
+            return Err(ResumeError::Handshake);
+        }
+        Ok(sched.keys(true))
+    }
+}
+
+fn ephemeral() -> (Scalar, [u8; 32]) {
+    let mut b = [0u8; 32];
+    OsRng.fill_bytes(&mut b);
+    let sk = Scalar::from_bytes_mod_order(b);
+    (sk, (sk * X25519_BASEPOINT).to_bytes())
+}
+
+// Refuses low-order points, whose shared secret is all zeros
+fn dh(sk: &Scalar, pk: &[u8; 32]) -> Result<[u8; 32], ResumeError> {
+    let shared = (sk * MontgomeryPoint(*pk)).to_bytes();
+    if shared == [0u8; 32] {
+        return Err(ResumeError::Handshake);
+    }
+    Ok(shared)
+}
+
+// Key schedule: prk = HKDF-Extract(ticket secret, DH); every output is bound to the
+// transcript hash of the hello and the edge's ephemeral.
+struct Schedule {
+    c2s: [u8; 32],
+    s2c: [u8; 32],
+    confirm: [u8; 32],
+    resumption: [u8; 32],
+}
+
+impl Schedule {
+    fn new(secret: &[u8; 32], shared: &[u8; 32], hello: &[u8], se: &[u8; 32]) -> Self {
+        let mut transcript = hello.to_vec();
+        transcript.extend_from_slice(se);
+        let th = digest(&SHA256, &transcript);
+        let prk = crypto::hkdf::extract(secret, shared);
+        let expand = |label: &[u8]| -> [u8; 32] {
+            let mut info = label.to_vec();
+            info.extend_from_slice(th.as_ref());
+            crypto::hkdf::expand(&prk, &info)
+        };
+        Self {
+            c2s: expand(b"qnet/resume/v1|c2s|"),
+            s2c: expand(b"qnet/resume/v1|s2c|"),
+            confirm: expand(b"qnet/resume/v1|confirm|"),
+            resumption: expand(b"qnet/resume/v1|resumption|"),
+        }
+    }
+
+    fn keys(&self, is_client: bool) -> ResumedKeys {
+        let (tx_key, rx_key) = if is_client {
+            (self.c2s, self.s2c)
+        } else {
+            (self.s2c, self.c2s)
+        };
+        ResumedKeys {
+            tx_key,
+            rx_key,
+            resumption: self.resumption,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn resume(
+        issuer: &TicketIssuer,
+        t: &Ticket,
+        now: u64,
+    ) -> Result<(ResumedKeys, ResumedKeys), ResumeError> {
+        let client = ResumeClient::new(t);
+        let (accept, server) = issuer.accept(client.hello(), now)?;
+        Ok((client.finish(&accept)?, server))
+    }
+
+    #[test]
+    fn resumed_sessions_get_fresh_matching_keys() {
+        let issuer = TicketIssuer::generate();
+        let resumption = [3u8; 32];
+        let nt = issuer.issue_at(&resumption, NOW);
+        let cbor_nt = cbor::to_det_cbor(&nt).unwrap();
+        assert_eq!(cbor::from_det_cbor::<NewTicket>(&cbor_nt).unwrap(), nt);
+        let t = Ticket::from_new_ticket(&nt, &resumption, NOW);
+
+        let (c, s) = resume(&issuer, &t, NOW + 10).unwrap();
+        assert_eq!((c.tx_key, c.rx_key), (s.rx_key, s.tx_key));
+        assert_eq!(c.resumption, s.resumption);
+        assert_ne!(c.resumption, resumption);
+
+        // A second ticket from the same session resumes with different keys
+        let t2 = Ticket::from_new_ticket(&issuer.issue_at(&resumption, NOW), &resumption, NOW);
+        let (c2, _) = resume(&issuer, &t2, NOW + 10).unwrap();
+        assert_ne!(c2.tx_key, c.tx_key);
+    }
+
+    #[test]
+    fn tickets_are_single_use_and_expire() {
+        let issuer = TicketIssuer::generate().with_lifetime(Duration::from_secs(60));
+        let resumption = [3u8; 32];
+        let t = Ticket::from_new_ticket(&issuer.issue_at(&resumption, NOW), &resumption, NOW);
+        assert!(resume(&issuer, &t, NOW + 1).is_ok());
+        assert_eq!(
+            resume(&issuer, &t, NOW + 2).err(),
+            Some(ResumeError::Replayed)
+        );
+
+        let t = Ticket::from_new_ticket(&issuer.issue_at(&resumption, NOW), &resumption, NOW);
+        assert!(t.is_expired(NOW + 60));
+        assert_eq!(
+            resume(&issuer, &t, NOW + 60).err(),
+            Some(ResumeError::Expired)
+        );
+
+        // Another edge key, or a secret from another session, gets nowhere
+        let other = TicketIssuer::generate();
+        assert_eq!(resume(&other, &t, NOW).err(), Some(ResumeError::BadTicket));
+        let wrong = Ticket::from_new_ticket(&issuer.issue_at(&resumption, NOW), &[4u8; 32], NOW);
+        assert_eq!(
+            resume(&issuer, &wrong, NOW).err(),
+            Some(ResumeError::Handshake)
+        );
+        assert_eq!(issuer.redeem(b"short", NOW), Err(ResumeError::Malformed));
+    }
+
+    #[test]
+    fn cache_hands_out_newest_ticket_once() {
+        let cache = TicketCache::new();
+        let issuer = TicketIssuer::generate();
+        let mk = |now| Ticket::from_new_ticket(&issuer.issue_at(&[1; 32], now), &[1; 32], now);
+        for i in 0..6 {
+            cache.insert("https://edge.example", mk(NOW + i));
+        }
+        assert_eq!(cache.len("https://edge.example"), TICKETS_PER_ORIGIN);
+        let newest = cache.take("https://edge.example", NOW).unwrap();
+        assert_eq!(newest.expires, NOW + 5 + DEFAULT_TICKET_LIFETIME.as_secs());
+        assert_eq!(cache.len("https://edge.example"), TICKETS_PER_ORIGIN - 1);
+        assert!(cache.take("https://other.example", NOW).is_none());
+        // Expired tickets are dropped rather than offered
+        assert!(cache
+            .take("https://edge.example", NOW + 365 * 24 * 3600)
+            .is_none());
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,284 @@
+//! Weighted deficit round-robin over streams with queued writes.
+//!
+//! Streams are grouped by [`Priority::urgency`]; the writer always serves the
+//! most urgent group that has something sendable. Inside a group every visit
+//! tops a stream's deficit up by a quantum proportional to its weight, and a
+//! chunk goes out once the deficit covers it, so bandwidth splits by weight
+//! whatever the chunk sizes are.
+//!
+//! Streams waiting on peer credit are parked rather than blocking the writer;
+//! a WINDOW_UPDATE puts them back in their round.
+
+use std::collections::{HashMap, VecDeque};
+
+pub type StreamId = u32;
+
+/// Scheduling hint for a stream's outgoing data, in the spirit of RFC 9218.
+///
+/// Lower `urgency` is served strictly first (0..=7). Streams of equal urgency
+/// share bandwidth in proportion to `weight` (1..=255).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Priority {
+    pub urgency: u8,
+    pub weight: u8,
+}
+
+impl Priority {
+    pub const LEVELS: usize = 8;
+    /// Page loads, API calls and anything a user is waiting on.
+    pub const INTERACTIVE: Priority = Priority {
+        urgency: 1,
+        weight: 64,
+    };
+    /// Large transfers that should only use what is left over.
+    pub const BULK: Priority = Priority {
+        urgency: 5,
+        weight: 16,
+    };
+
+    /// Clamp out-of-range fields instead of rejecting them (PRIORITY frames come off the wire).
+    pub fn new(urgency: u8, weight: u8) -> Self {
+        Self {
+            urgency: urgency.min(Self::LEVELS as u8 - 1),
+            weight: weight.max(1),
+        }
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self {
+            urgency: 3,
+            weight: 16,
+        }
+    }
+}
+
+// FIN rides the scheduler queue so it is never sent ahead of queued data.
+pub(crate) enum Outgoing {
+    Data(Vec<u8>),
+    Fin,
+}
+
+#[derive(Default)]
+struct Entry {
+    prio: Priority,
+    queue: VecDeque<Outgoing>,
+    // Data bytes in `queue`
+    queued: usize,
+    deficit: usize,
+    // Listed in its group's round
+    active: bool,
+    // Out of peer credit; rejoins the round on WINDOW_UPDATE
+    parked: bool,
+}
+
+/// What the writer should do next.
+pub(crate) enum Next {
+    Data(StreamId, Vec<u8>),
+    Fin(StreamId),
+}
+
+pub(crate) struct Scheduler {
+    // Deficit added per visit for the default weight
+    quantum: usize,
+    streams: HashMap<StreamId, Entry>,
+    rounds: [VecDeque<StreamId>; Priority::LEVELS],
+}
+
+impl Scheduler {
+    pub(crate) fn new(quantum: usize) -> Self {
+        Self {
+            quantum: quantum.max(1),
+            streams: HashMap::new(),
+            rounds: Default::default(),
+        }
+    }
+
+    pub(crate) fn set_priority(&mut self, id: StreamId, prio: Priority) {
+        let e = self.streams.entry(id).or_default();
+        if e.prio == prio {
+            return;
+        }
+        let was_active = e.active;
+        if was_active {
+            let round = &mut self.rounds[e.prio.urgency as usize];
+            round.retain(|&s| s != id);
+        }
+        e.prio = prio;
+        e.deficit = 0;
+        if was_active {
+            self.rounds[prio.urgency as usize].push_back(id);
+        }
+    }
+
+    pub(crate) fn priority(&self, id: StreamId) -> Priority {
+        self.streams.get(&id).map(|e| e.prio).unwrap_or_default()
+    }
+
+    pub(crate) fn push(&mut self, id: StreamId, item: Outgoing) {
+        let e = self.streams.entry(id).or_default();
+        if let Outgoing::Data(d) = &item {
+            e.queued += d.len();
+        }
+        e.queue.push_back(item);
+        self.activate(id);
+    }
+
+    // Hand back the unsent tail of a chunk; it goes out before anything queued after it.
+    pub(crate) fn push_front(&mut self, id: StreamId, data: Vec<u8>) {
+        if let Some(e) = self.streams.get_mut(&id) {
+            e.deficit += data.len();
+            e.queued += data.len();
+            e.queue.push_front(Outgoing::Data(data));
+        }
+    }
+
+    /// Data bytes waiting to be sent on `id`.
+    pub(crate) fn queued_bytes(&self, id: StreamId) -> usize {
+        self.streams.get(&id).map_or(0, |e| e.queued)
+    }
+
+    /// Take `id` out of rotation until [`Scheduler::unpark`].
+    pub(crate) fn park(&mut self, id: StreamId) {
+        if let Some(e) = self.streams.get_mut(&id) {
+            e.parked = true;
+        }
+    }
+
+    pub(crate) fn unpark(&mut self, id: StreamId) {
+        if let Some(e) = self.streams.get_mut(&id) {
+            e.parked = false;
+            self.activate(id);
+        }
+    }
+
+    pub(crate) fn unpark_all(&mut self) {
+        let ids: Vec<StreamId> = self.streams.keys().copied().collect();
+        for id in ids {
+            self.unpark(id);
+        }
+    }
+
+    /// Drop a stream and anything still queued for it.
+    pub(crate) fn remove(&mut self, id: StreamId) {
+        if let Some(e) = self.streams.remove(&id) {
+            if e.active {
+                self.rounds[e.prio.urgency as usize].retain(|&s| s != id);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Next item to send, or `None` when every queue is empty or parked.
+    pub(crate) fn next(&mut self) -> Option<Next> {
+        for level in 0..Priority::LEVELS {
+            while let Some(&id) = self.rounds[level].front() {
+                let e = self
+                    .streams
+                    .get_mut(&id)
+                    .expect("round lists only live streams");
+                if e.parked || e.queue.is_empty() {
+                    // Idle streams leave the round and lose their saved deficit
+                    self.rounds[level].pop_front();
+                    e.active = false;
+                    e.deficit = 0;
+                    continue;
+                }
+                match e.queue.front() {
+                    Some(Outgoing::Fin) => {
+                        e.queue.pop_front();
+                        return Some(Next::Fin(id));
+                    }
+                    Some(Outgoing::Data(d)) if d.len() <= e.deficit => {
+                        e.deficit -= d.len();
+                        e.queued -= d.len();
+                        let Some(Outgoing::Data(d)) = e.queue.pop_front() else {
+                            unreachable!()
+                        };
+                        return Some(Next::Data(id, d));
+                    }
+                    _ => {
+                        // Not enough saved up: top up and let the rest of the round go first
+                        e.deficit += self.quantum * e.prio.weight as usize / 16 + 1;
+                        self.rounds[level].rotate_left(1);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn activate(&mut self, id: StreamId) {
+        let Some(e) = self.streams.get_mut(&id) else {
+            return;
+        };
+        if !e.active && !e.parked && !e.queue.is_empty() {
+            e.active = true;
+            self.rounds[e.prio.urgency as usize].push_back(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(s: &mut Scheduler) -> Vec<StreamId> {
+        let mut order = Vec::new();
+        while let Some(n) = s.next() {
+            match n {
+                Next::Data(id, _) | Next::Fin(id) => order.push(id),
+            }
+        }
+        order
+    }
+
+    #[test]
+    fn urgent_streams_go_first() {
+        let mut s = Scheduler::new(1024);
+        s.set_priority(2, Priority::INTERACTIVE);
+        for _ in 0..4 {
+            s.push(1, Outgoing::Data(vec![0; 1024]));
+        }
+        s.push(2, Outgoing::Data(vec![0; 1024]));
+        s.push(2, Outgoing::Fin);
+        assert_eq!(drain(&mut s), vec![2, 2, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn equal_urgency_splits_bandwidth_by_weight() {
+        let mut s = Scheduler::new(1024);
+        s.set_priority(1, Priority::new(3, 48));
+        s.set_priority(3, Priority::new(3, 16));
+        for _ in 0..400 {
+            s.push(1, Outgoing::Data(vec![0; 512]));
+            s.push(3, Outgoing::Data(vec![0; 512]));
+        }
+        // While both are backlogged, stream 1 gets three times the bytes
+        let first: Vec<StreamId> = drain(&mut s).into_iter().take(400).collect();
+        let heavy = first.iter().filter(|&&id| id == 1).count();
+        assert!(
+            (290..310).contains(&heavy),
+            "heavy stream got {heavy} of 400"
+        );
+    }
+
+    #[test]
+    fn parked_streams_do_not_block_others() {
+        let mut s = Scheduler::new(1024);
+        s.push(1, Outgoing::Data(vec![0; 100]));
+        s.push(2, Outgoing::Data(vec![0; 100]));
+        s.park(1);
+        assert_eq!(drain(&mut s), vec![2]);
+        s.unpark(1);
+        assert_eq!(drain(&mut s), vec![1]);
+        s.remove(1);
+        s.remove(2);
+        assert!(s.is_empty());
+    }
+}
@@ -0,0 +1,822 @@
+//! Concurrent HTX edge listener.
+//!
+//! [`HtxServer`] owns the TCP listener. An acceptor thread hands each client to
+//! its own handshake thread, which drives the outer TLS handshake under a
+//! deadline and derives the inner keys. Finished connections wait in a bounded
+//! queue until [`HtxServer::accept`] takes them. The certificate can be swapped
+//! with [`HtxServer::reload`]: new handshakes use the new one, and connections
+//! already up are not touched. With a [`ProbeGuard`] set, clients that do not
+//! knock are spliced to the cover origin instead of being queued.
+
+use crate::api::{
+    load_tls_config, load_tls_files, server_conn, tls_paths_from_env, ApiError, Conn, MuxConfig,
+    TicketIssuer,
+};
+use crate::probe::{ProbeGuard, Splice};
+use crate::HandshakeError;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Listener settings; start from [`HtxServerConfig::default`].
+#[derive(Clone)]
+pub struct HtxServerConfig {
+    handshake_timeout: Duration,
+    max_handshakes: usize,
+    accept_queue: usize,
+    mux: MuxConfig,
+    guard: Option<ProbeGuard>,
+    tickets: Option<Arc<TicketIssuer>>,
+}
+
+impl Default for HtxServerConfig {
+    fn default() -> Self {
+        Self {
+            handshake_timeout: Duration::from_secs(10),
+            max_handshakes: 64,
+            accept_queue: 128,
+            mux: MuxConfig::default(),
+            guard: None,
+            tickets: None,
+        }
+    }
+}
+
+impl HtxServerConfig {
+    /// Time a client gets to finish the TLS handshake before it is dropped.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Handshakes run at once (floor 1). Further clients wait in the kernel backlog.
+    pub fn max_handshakes(mut self, n: usize) -> Self {
+        self.max_handshakes = n.max(1);
+        self
+    }
+
+    /// Ready connections held for [`HtxServer::accept`] (floor 1). A connection
+    /// finishing while the queue is full is sent GOAWAY and closed.
+    pub fn accept_queue(mut self, n: usize) -> Self {
+        self.accept_queue = n.max(1);
+        self
+    }
+
+    /// Mux settings for every accepted connection.
+    pub fn mux(mut self, cfg: MuxConfig) -> Self {
+        self.mux = cfg;
+        self
+    }
+
+    /// Only clients that knock with the guard's key get HTX; the rest go to its
+    /// cover origin.
+    pub fn probe_guard(mut self, guard: ProbeGuard) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    /// Let clients holding a ticket from `issuer` resume instead of running the
+    /// full handshake. Hand out tickets with [`Conn::send_ticket`].
+    pub fn ticket_issuer(mut self, issuer: Arc<TicketIssuer>) -> Self {
+        self.tickets = Some(issuer);
+        self
+    }
+}
+
+/// Listener counters since bind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Connections that reached the accept queue.
+    pub accepted: u64,
+    /// Handshakes that failed for a reason other than the deadline.
+    pub failed: u64,
+    pub timed_out: u64,
+    /// Connections closed because the accept queue was full.
+    pub queue_full: u64,
+    /// Clients without a valid knock, spliced to the cover origin.
+    pub covered: u64,
+    /// Successful certificate reloads.
+    pub reloads: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    accepted: AtomicU64,
+    failed: AtomicU64,
+    timed_out: AtomicU64,
+    queue_full: AtomicU64,
+    covered: AtomicU64,
+    reloads: AtomicU64,
+}
+
+struct Shared {
+    tls: RwLock<Arc<rustls::ServerConfig>>,
+    files: Option<(PathBuf, PathBuf)>,
+    cfg: HtxServerConfig,
+    in_flight: Mutex<usize>,
+    slot_free: Condvar,
+    closed: AtomicBool,
+    counters: Counters,
+}
+
+/// Edge listener that handshakes many clients at once; see the module docs.
+pub struct HtxServer {
+    shared: Arc<Shared>,
+    ready: Mutex<mpsc::Receiver<Conn>>,
+    local_addr: SocketAddr,
+}
+
+impl HtxServer {
+    /// Bind `addr` and serve with the certificate chain and key in `cert_pem` / `key_pem`.
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        cert_pem: &[u8],
+        key_pem: &[u8],
+        cfg: HtxServerConfig,
+    ) -> Result<Self, ApiError> {
+        let tls = load_tls_config(cert_pem, key_pem)?;
+        Self::start(addr, tls, None, cfg)
+    }
+
+    /// Bind `addr` with the certificate and key read from files. [`HtxServer::reload`]
+    /// reads the same files again.
+    pub fn bind_files<A: ToSocketAddrs>(
+        addr: A,
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+        cfg: HtxServerConfig,
+    ) -> Result<Self, ApiError> {
+        let files = (cert_path.into(), key_path.into());
+        let tls = load_tls_files(&files.0, &files.1)?;
+        Self::start(addr, tls, Some(files), cfg)
+    }
+
+    /// Like [`HtxServer::bind_files`], with the paths in `HTX_TLS_CERT` / `HTX_TLS_KEY`.
+    pub fn bind_from_env<A: ToSocketAddrs>(
+        addr: A,
+        cfg: HtxServerConfig,
+    ) -> Result<Self, ApiError> {
+        let (cert_path, key_path) = tls_paths_from_env()?;
+        Self::bind_files(addr, cert_path, key_path, cfg)
+    }
+
+    fn start<A: ToSocketAddrs>(
+        addr: A,
+        mut tls: rustls::ServerConfig,
+        files: Option<(PathBuf, PathBuf)>,
+        cfg: HtxServerConfig,
+    ) -> Result<Self, ApiError> {
+        if let Some(guard) = &cfg.guard {
+            guard.restrict_alpn(&mut tls);
+        }
+        let listener = TcpListener::bind(addr).map_err(ApiError::Io)?;
+        let local_addr = listener.local_addr().map_err(ApiError::Io)?;
+        let (tx, rx) = mpsc::sync_channel(cfg.accept_queue);
+        let shared = Arc::new(Shared {
+            tls: RwLock::new(Arc::new(tls)),
+            files,
+            cfg,
+            in_flight: Mutex::new(0),
+            slot_free: Condvar::new(),
+            closed: AtomicBool::new(false),
+            counters: Counters::default(),
+        });
+        let acceptor = shared.clone();
+        thread::spawn(move || accept_loop(&acceptor, listener, tx));
+        Ok(Self {
+            shared,
+            ready: Mutex::new(rx),
+            local_addr,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Next handshaken connection. `None` once the server is closed and the
+    /// queue has drained.
+    pub fn accept(&self) -> Option<Conn> {
+        self.ready.lock().unwrap().recv().ok()
+    }
+
+    /// Like [`HtxServer::accept`], giving up after `timeout`.
+    pub fn accept_timeout(&self, timeout: Duration) -> Option<Conn> {
+        self.ready.lock().unwrap().recv_timeout(timeout).ok()
+    }
+
+    /// Swap in a new certificate chain and key. Handshakes already running finish
+    /// with the old one.
+    pub fn reload_pem(&self, cert_pem: &[u8], key_pem: &[u8]) -> Result<(), ApiError> {
+        let tls = load_tls_config(cert_pem, key_pem)?;
+        self.swap(tls);
+        Ok(())
+    }
+
+    /// Read the certificate files given at bind again. On error the current
+    /// certificate stays in use.
+    pub fn reload(&self) -> Result<(), ApiError> {
+        let (cert_path, key_path) = self.shared.files.as_ref().ok_or_else(|| {
+            ApiError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "server was not bound from certificate files",
+            ))
+        })?;
+        let tls = load_tls_files(cert_path, key_path)?;
+        self.swap(tls);
+        Ok(())
+    }
+
+    fn swap(&self, mut tls: rustls::ServerConfig) {
+        if let Some(guard) = &self.shared.cfg.guard {
+            guard.restrict_alpn(&mut tls);
+        }
+        *self.shared.tls.write().unwrap() = Arc::new(tls);
+        self.shared.counters.reloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> ServerStats {
+        let c = &self.shared.counters;
+        ServerStats {
+            accepted: c.accepted.load(Ordering::Relaxed),
+            failed: c.failed.load(Ordering::Relaxed),
+            timed_out: c.timed_out.load(Ordering::Relaxed),
+            queue_full: c.queue_full.load(Ordering::Relaxed),
+            covered: c.covered.load(Ordering::Relaxed),
+            reloads: c.reloads.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stop accepting clients. Handshakes in flight still complete and queued
+    /// connections can still be taken.
+    pub fn close(&self) {
+        if self.shared.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // Wake the acceptor out of accept() so it sees the flag
+        let mut wake = self.local_addr;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake {
+                SocketAddr::V4(_) => [127, 0, 0, 1].into(),
+                SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&wake, Duration::from_secs(1));
+    }
+}
+
+impl Drop for HtxServer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn accept_loop(shared: &Arc<Shared>, listener: TcpListener, ready: mpsc::SyncSender<Conn>) {
+    loop {
+        // Hold further clients in the kernel backlog while every slot is busy
+        {
+            let mut n = shared.in_flight.lock().unwrap();
+            while *n >= shared.cfg.max_handshakes {
+                n = shared.slot_free.wait(n).unwrap();
+            }
+        }
+        if shared.closed.load(Ordering::SeqCst) {
+            return;
+        }
+        let tcp = match listener.accept() {
+            Ok((tcp, _peer)) => tcp,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => {
+                // Out of descriptors and the like: back off rather than spin
+                thread::sleep(Duration::from_millis(5));
+                continue;
+            }
+        };
+        if shared.closed.load(Ordering::SeqCst) {
+            return;
+        }
+        *shared.in_flight.lock().unwrap() += 1;
+        let tls = shared.tls.read().unwrap().clone();
+        let shared = shared.clone();
+        let ready = ready.clone();
+        thread::spawn(move || {
+            let deadline = Instant::now() + shared.cfg.handshake_timeout;
+            let cfg = &shared.cfg;
+            let res = handshake(
+                tls,
+                tcp,
+                deadline,
+                cfg.mux.clone(),
+                cfg.guard.as_ref(),
+                cfg.tickets.as_deref(),
+            );
+            *shared.in_flight.lock().unwrap() -= 1;
+            shared.slot_free.notify_one();
+            let c = &shared.counters;
+            match res {
+                Ok(Admitted::Cover(splice)) => {
+                    c.covered.fetch_add(1, Ordering::Relaxed);
+                    let _ = splice.run();
+                }
+                Ok(Admitted::Htx(conn)) => match ready.try_send(conn) {
+                    Ok(()) => {
+                        c.accepted.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(mpsc::TrySendError::Full(conn)) => {
+                        c.queue_full.fetch_add(1, Ordering::Relaxed);
+                        conn.shutdown(Duration::ZERO);
+                    }
+                    Err(mpsc::TrySendError::Disconnected(conn)) => {
+                        conn.shutdown(Duration::ZERO);
+                    }
+                },
+                Err(ApiError::Io(e)) if e.kind() == io::ErrorKind::TimedOut => {
+                    c.timed_out.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(ApiError::Handshake(HandshakeError::Timeout)) => {
+                    c.timed_out.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(_) => {
+                    c.failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+    }
+}
+
+pub(crate) enum Admitted {
+    Htx(Conn),
+    Cover(Box<Splice>),
+}
+
+/// Outer TLS handshake before `deadline`, then the knock when `guard` is set.
+pub(crate) fn handshake(
+    tls: Arc<rustls::ServerConfig>,
+    mut tcp: TcpStream,
+    deadline: Instant,
+    mux_cfg: MuxConfig,
+    guard: Option<&ProbeGuard>,
+    tickets: Option<&TicketIssuer>,
+) -> Result<Admitted, ApiError> {
+    tcp.set_nodelay(true).map_err(ApiError::Io)?;
+    if let Some(guard) = guard {
+        tcp = match guard.check_hello(tcp, deadline).map_err(ApiError::Io)? {
+            Ok(tcp) => tcp,
+            Err(splice) => return Ok(Admitted::Cover(Box::new(splice))),
+        };
+    }
+    let mut conn = rustls::ServerConnection::new(tls).map_err(|_| ApiError::Tls)?;
+    while conn.is_handshaking() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(ApiError::Io(io::ErrorKind::TimedOut.into()));
+        }
+        tcp.set_read_timeout(Some(left)).map_err(ApiError::Io)?;
+        tcp.set_write_timeout(Some(left)).map_err(ApiError::Io)?;
+        match conn.complete_io(&mut tcp) {
+            Ok(_) => {}
+            // Timed-out reads come back as WouldBlock on Unix; the deadline check decides
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(e) => return Err(ApiError::Io(e)),
+        }
+    }
+    tcp.set_read_timeout(None).map_err(ApiError::Io)?;
+    tcp.set_write_timeout(None).map_err(ApiError::Io)?;
+    if let Some(guard) = guard {
+        (conn, tcp) = match guard.check_knock(conn, tcp).map_err(ApiError::Io)? {
+            Ok(admitted) => admitted,
+            Err(splice) => return Ok(Admitted::Cover(Box::new(splice))),
+        };
+    }
+    server_conn(conn, tcp, deadline, mux_cfg, tickets).map(Admitted::Htx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{client_conn, edge_template, RecordLayout, Ticket};
+    use crate::probe::ProbeGuard;
+    use crate::resume::{unix_now, ResumeError};
+    use crate::{x25519_scalar, HandshakeParams, Pattern};
+    use curve25519_dalek::constants::X25519_BASEPOINT;
+    use std::io::{Read, Write};
+
+    struct Identity {
+        cert_pem: String,
+        key_pem: String,
+        der: Vec<u8>,
+    }
+
+    fn identity() -> Identity {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        Identity {
+            cert_pem: cert.serialize_pem().unwrap(),
+            key_pem: cert.serialize_private_key_pem(),
+            der: cert.serialize_der().unwrap(),
+        }
+    }
+
+    fn connect(addr: SocketAddr, der: &[u8]) -> Result<Conn, ApiError> {
+        connect_knocking(addr, der, None)
+    }
+
+    fn tls_client(der: &[u8]) -> rustls::ClientConnection {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&rustls::Certificate(der.to_vec())).unwrap();
+        let mut cfg = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let name = rustls::ServerName::try_from("localhost").unwrap();
+        rustls::ClientConnection::new(Arc::new(cfg), name).unwrap()
+    }
+
+    fn connect_knocking(
+        addr: SocketAddr,
+        der: &[u8],
+        knock_key: Option<&[u8; 32]>,
+    ) -> Result<Conn, ApiError> {
+        connect_with(addr, der, knock_key, MuxConfig::default(), None)
+    }
+
+    // Client that trusts exactly `der`, then the same inner setup as api::dial
+    fn connect_with(
+        addr: SocketAddr,
+        der: &[u8],
+        knock_key: Option<&[u8; 32]>,
+        mux_cfg: MuxConfig,
+        ticket: Option<&Ticket>,
+    ) -> Result<Conn, ApiError> {
+        let mut conn = tls_client(der);
+        let mut tcp = TcpStream::connect(addr).map_err(ApiError::Io)?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut tcp).map_err(ApiError::Io)?;
+        }
+        client_conn(conn, tcp, &edge_template(), mux_cfg, knock_key, ticket)
+    }
+
+    // Stand-in cover site: answers every request on a connection with the same page
+    fn cover_origin() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for mut c in listener.incoming().flatten() {
+                thread::spawn(move || {
+                    let mut req = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while let Ok(n @ 1..) = c.read(&mut buf) {
+                        req.extend_from_slice(&buf[..n]);
+                        if req.ends_with(b"\r\n\r\n") {
+                            req.clear();
+                            let _ =
+                                c.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\ncover");
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    fn read_response(r: &mut impl Read) -> String {
+        let mut resp = Vec::new();
+        let mut buf = [0u8; 256];
+        while !resp.ends_with(b"cover") {
+            let n = r.read(&mut buf).unwrap();
+            assert!(n > 0, "eof after {:?}", String::from_utf8_lossy(&resp));
+            resp.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8(resp).unwrap()
+    }
+
+    fn echo_once(server: &HtxServer) {
+        let conn = server.accept_timeout(Duration::from_secs(5)).unwrap();
+        let s = conn.accept_stream(5000).unwrap();
+        let buf = s.read().unwrap();
+        s.write(&buf);
+    }
+
+    #[test]
+    fn handshakes_clients_concurrently() {
+        let id = identity();
+        let server = HtxServer::bind(
+            "127.0.0.1:0",
+            id.cert_pem.as_bytes(),
+            id.key_pem.as_bytes(),
+            HtxServerConfig::default().handshake_timeout(Duration::from_secs(5)),
+        )
+        .unwrap();
+        let addr = server.local_addr();
+        // A client that connects and says nothing must not hold up the others
+        let _stalled = TcpStream::connect(addr).unwrap();
+        let clients: Vec<_> = (0..4u8)
+            .map(|i| {
+                let der = id.der.clone();
+                thread::spawn(move || {
+                    let conn = connect(addr, &der).unwrap();
+                    let s = conn.open_stream();
+                    s.write(&[i; 3]);
+                    assert_eq!(s.read().unwrap(), vec![i; 3]);
+                })
+            })
+            .collect();
+        for _ in 0..4 {
+            echo_once(&server);
+        }
+        for c in clients {
+            c.join().unwrap();
+        }
+        assert_eq!(server.stats().accepted, 4);
+    }
+
+    #[test]
+    fn slow_handshakes_time_out() {
+        let id = identity();
+        let server = HtxServer::bind(
+            "127.0.0.1:0",
+            id.cert_pem.as_bytes(),
+            id.key_pem.as_bytes(),
+            HtxServerConfig::default()
+                .handshake_timeout(Duration::from_millis(200))
+                .max_handshakes(1),
+        )
+        .unwrap();
+        let addr = server.local_addr();
+        // Half a ClientHello record header, then nothing
+        let mut stalled = TcpStream::connect(addr).unwrap();
+        stalled.write_all(&[0x16, 0x03]).unwrap();
+        // Only one handshake slot: this client waits for the stalled one to expire
+        let der = id.der.clone();
+        let t = thread::spawn(move || connect(addr, &der).map(|_| ()));
+        assert!(server.accept_timeout(Duration::from_secs(5)).is_some());
+        t.join().unwrap().unwrap();
+        assert_eq!(server.stats().timed_out, 1);
+    }
+
+    #[test]
+    fn reload_swaps_the_certificate_for_new_handshakes() {
+        let dir = std::env::temp_dir().join(format!("htx-server-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let old = identity();
+        std::fs::write(&cert_path, &old.cert_pem).unwrap();
+        std::fs::write(&key_path, &old.key_pem).unwrap();
+        let server = HtxServer::bind_files(
+            "127.0.0.1:0",
+            &cert_path,
+            &key_path,
+            HtxServerConfig::default().handshake_timeout(Duration::from_secs(5)),
+        )
+        .unwrap();
+        let addr = server.local_addr();
+        let before = connect(addr, &old.der).unwrap();
+        let before_srv = server.accept_timeout(Duration::from_secs(5)).unwrap();
+
+        let new = identity();
+        std::fs::write(&cert_path, &new.cert_pem).unwrap();
+        std::fs::write(&key_path, &new.key_pem).unwrap();
+        server.reload().unwrap();
+        assert!(connect(addr, &old.der).is_err());
+        connect(addr, &new.der).unwrap();
+        assert!(server.accept_timeout(Duration::from_secs(5)).is_some());
+        assert_eq!(server.stats().reloads, 1);
+
+        // A broken file leaves the current certificate in place
+        std::fs::write(&key_path, "not a key").unwrap();
+        assert!(server.reload().is_err());
+        connect(addr, &new.der).unwrap();
+
+        // The connection from before the reload still works
+        let s = before.open_stream();
+        s.write(b"still here");
+        let r = before_srv.accept_stream(5000).unwrap();
+        assert_eq!(r.read().unwrap(), b"still here");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mismatched_record_layouts_fail_the_handshake() {
+        let id = identity();
+        let server = HtxServer::bind(
+            "127.0.0.1:0",
+            id.cert_pem.as_bytes(),
+            id.key_pem.as_bytes(),
+            HtxServerConfig::default(),
+        )
+        .unwrap();
+        let hidden = MuxConfig::default().record_layout(RecordLayout::hidden(1024));
+        match connect_with(server.local_addr(), &id.der, None, hidden, None) {
+            Err(ApiError::LayoutMismatch { ours, theirs }) => {
+                assert_eq!(
+                    (ours, theirs),
+                    (RecordLayout::hidden(1024), RecordLayout::Clear)
+                );
+            }
+            other => panic!("expected a layout mismatch, got {:?}", other.map(|_| ())),
+        }
+        assert!(server.accept_timeout(Duration::from_millis(500)).is_none());
+        assert_eq!(server.stats().failed, 1);
+    }
+
+    #[test]
+    fn caps_select_a_noise_handshake_inside_tls() {
+        let id = identity();
+        let (ci, edge) = (x25519_scalar([1; 32]), x25519_scalar([2; 32]));
+        let edge_pub = (edge * X25519_BASEPOINT).to_bytes();
+        let ik = HandshakeParams::new(Pattern::Ik).psk2([9; 32]);
+        let server = HtxServer::bind(
+            "127.0.0.1:0",
+            id.cert_pem.as_bytes(),
+            id.key_pem.as_bytes(),
+            HtxServerConfig::default().mux(MuxConfig::default().inner_handshake(ik, edge, None)),
+        )
+        .unwrap();
+        let addr = server.local_addr();
+        let cfg = MuxConfig::default().inner_handshake(ik, ci, Some(edge_pub));
+        let client = connect_with(addr, &id.der, None, cfg, None).unwrap();
+        let conn = server.accept_timeout(Duration::from_secs(5)).unwrap();
+        let s = client.open_stream();
+        s.write(b"noise");
+        assert_eq!(conn.accept_stream(5000).unwrap().read().unwrap(), b"noise");
+
+        // A client keying from the exporter alone is refused at the caps exchange
+        assert!(matches!(
+            connect(addr, &id.der),
+            Err(ApiError::Handshake(HandshakeError::PatternMismatch))
+        ));
+        assert!(server.accept_timeout(Duration::from_millis(500)).is_none());
+        // With the wrong psk the client cannot read the edge's IK reply
+        let wrong = ik.psk2([8; 32]);
+        let cfg = MuxConfig::default().inner_handshake(wrong, ci, Some(edge_pub));
+        assert!(matches!(
+            connect_with(addr, &id.der, None, cfg, None),
+            Err(ApiError::Handshake(HandshakeError::Decrypt))
+        ));
+    }
+
+    #[test]
+    fn tickets_resume_over_tls_once() {
+        let id = identity();
+        let issuer = Arc::new(TicketIssuer::generate());
+        let server = HtxServer::bind(
+            "127.0.0.1:0",
+            id.cert_pem.as_bytes(),
+            id.key_pem.as_bytes(),
+            HtxServerConfig::default().ticket_issuer(issuer.clone()),
+        )
+        .unwrap();
+        let addr = server.local_addr();
+        let first = connect(addr, &id.der).unwrap();
+        let edge = server.accept_timeout(Duration::from_secs(5)).unwrap();
+        edge.send_ticket(&issuer);
+        let ticket = first.recv_ticket(5000).unwrap();
+
+        let resumed = connect_with(addr, &id.der, None, MuxConfig::default(), Some(&ticket));
+        let t = thread::spawn({
+            let resumed = resumed.unwrap();
+            move || {
+                let s = resumed.open_stream();
+                s.write(b"resumed");
+                assert_eq!(s.read().unwrap(), b"resumed");
+            }
+        });
+        echo_once(&server);
+        t.join().unwrap();
+        // The edge redeemed the ticket rather than running a full handshake
+        assert_eq!(
+            issuer.redeem(&ticket.ticket, unix_now()),
+            Err(ResumeError::Replayed)
+        );
+
+        // A spent ticket is refused and the client falls back to a full handshake
+        let again = connect_with(addr, &id.der, None, MuxConfig::default(), Some(&ticket)).unwrap();
+        let t = thread::spawn(move || {
+            let s = again.open_stream();
+            s.write(b"full");
+            assert_eq!(s.read().unwrap(), b"full");
+        });
+        echo_once(&server);
+        t.join().unwrap();
+        assert_eq!(server.stats().accepted, 3);
+    }
+
+    #[test]
+    fn close_ends_accept() {
+        let id = identity();
+        let server = HtxServer::bind(
+            "127.0.0.1:0",
+            id.cert_pem.as_bytes(),
+            id.key_pem.as_bytes(),
+            HtxServerConfig::default(),
+        )
+        .unwrap();
+        server.close();
+        assert!(server.accept().is_none());
+    }
+
+    #[test]
+    fn probers_are_spliced_to_the_cover_origin() {
+        let id = identity();
+        let key = [7u8; 32];
+        let guard = ProbeGuard::new(key, cover_origin().to_string())
+            .knock_timeout(Duration::from_millis(300));
+        let server = HtxServer::bind(
+            "127.0.0.1:0",
+            id.cert_pem.as_bytes(),
+            id.key_pem.as_bytes(),
+            HtxServerConfig::default().probe_guard(guard),
+        )
+        .unwrap();
+        let addr = server.local_addr();
+        let get = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        // A browser-like TLS client sees the cover site, request after request
+        let mut tcp = TcpStream::connect(addr).unwrap();
+        let mut conn = tls_client(&id.der);
+        let mut tls = rustls::Stream::new(&mut conn, &mut tcp);
+        tls.flush().unwrap();
+        // The edge offers only what the cover speaks, though the client asked for h2
+        assert_eq!(tls.conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+        for _ in 0..2 {
+            tls.write_all(get).unwrap();
+            assert!(read_response(&mut tls).starts_with("HTTP/1.1 200 OK"));
+        }
+
+        // So does a plain-HTTP probe, and a TLS client that waits for the server
+        let mut plain = TcpStream::connect(addr).unwrap();
+        plain.write_all(get).unwrap();
+        assert!(read_response(&mut plain).starts_with("HTTP/1.1 200 OK"));
+        let mut tcp = TcpStream::connect(addr).unwrap();
+        let mut conn = tls_client(&id.der);
+        let mut tls = rustls::Stream::new(&mut conn, &mut tcp);
+        tls.flush().unwrap();
+        thread::sleep(Duration::from_millis(500));
+        tls.write_all(get).unwrap();
+        assert!(read_response(&mut tls).starts_with("HTTP/1.1 200 OK"));
+
+        // The wrong key gets nowhere (its caps offer goes to the cover, which never
+        // answers); the right one gets HTX
+        let der = id.der.clone();
+        thread::spawn(move || connect_knocking(addr, &der, Some(&[8u8; 32])));
+        assert!(server.accept_timeout(Duration::from_millis(500)).is_none());
+        let client = connect_knocking(addr, &id.der, Some(&key)).unwrap();
+        let conn = server.accept_timeout(Duration::from_secs(5)).unwrap();
+        let s = client.open_stream();
+        s.write(b"htx");
+        assert_eq!(conn.accept_stream(5000).unwrap().read().unwrap(), b"htx");
+        let stats = server.stats();
+        assert_eq!((stats.covered, stats.accepted), (4, 1));
+    }
+
+    #[test]
+    fn splices_beyond_the_limit_are_closed() {
+        let id = identity();
+        let guard = ProbeGuard::new([7u8; 32], cover_origin().to_string()).max_splices(1);
+        let server = HtxServer::bind(
+            "127.0.0.1:0",
+            id.cert_pem.as_bytes(),
+            id.key_pem.as_bytes(),
+            HtxServerConfig::default().probe_guard(guard),
+        )
+        .unwrap();
+        let addr = server.local_addr();
+        let get = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let probe = || {
+            let mut plain = TcpStream::connect(addr).unwrap();
+            plain.write_all(get).unwrap();
+            plain
+        };
+
+        // The first prober holds the only splice; the next one is closed unanswered
+        let mut first = probe();
+        assert!(read_response(&mut first).starts_with("HTTP/1.1 200 OK"));
+        let mut second = probe();
+        assert_eq!(second.read(&mut [0u8; 64]).unwrap_or(0), 0);
+
+        // Once the first hangs up its place is free again
+        drop(first);
+        let served = (0..50).any(|_| {
+            let mut p = probe();
+            let mut buf = [0u8; 64];
+            match p.read(&mut buf) {
+                Ok(n) if n > 0 => true,
+                _ => {
+                    thread::sleep(Duration::from_millis(20));
+                    false
+                }
+            }
+        });
+        assert!(served);
+    }
+}
@@ -6,12 +6,30 @@ pub enum CompatMode {
     V1_1,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// When to rotate traffic keys; a zero limit disables that trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct KeyUpdatePolicy {
     pub max_frames: usize,
     pub max_seconds: u64,
 }
 
+impl Default for KeyUpdatePolicy {
+    fn default() -> Self {
+        Self {
+            max_frames: 1 << 20,
+            max_seconds: 3600,
+        }
+    }
+}
+
+impl KeyUpdatePolicy {
+    /// Never rotate on count or age (the mux's nonce ceiling still applies).
+    pub const DISABLED: KeyUpdatePolicy = KeyUpdatePolicy {
+        max_frames: 0,
+        max_seconds: 0,
+    };
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct PolicyTracker {
     frames_since_update: usize,
@@ -28,6 +46,9 @@ impl PolicyTracker {
     pub fn on_frame_sent(&mut self, _frame: &framing::Frame) {
         self.frames_since_update = self.frames_since_update.saturating_add(1);
     }
+    pub fn frames_since_update(&self) -> usize {
+        self.frames_since_update
+    }
     pub fn mark_updated(&mut self, now: u64) {
         self.frames_since_update = 0;
         self.last_update_ts = now;
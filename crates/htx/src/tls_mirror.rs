@@ -4,6 +4,7 @@ use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+use crate::fingerprint::ClientFingerprint;
 use core_cbor as cbor; // for TemplateID (DET-CBOR)
 use once_cell::sync::Lazy;
 use std::sync::{
@@ -14,7 +15,7 @@ use std::sync::{
 static GLOBAL_CACHE: Lazy<std::sync::Mutex<MirrorCache>> =
     Lazy::new(|| std::sync::Mutex::new(MirrorCache::new(Duration::from_secs(24 * 60 * 60))));
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AllowEntry {
     pub host_pattern: String, // exact, "*" or "*.suffix"
     pub template: Template,
@@ -24,13 +25,158 @@ pub struct AllowEntry {
 
 static ROT_IDX: AtomicUsize = AtomicUsize::new(0); // legacy/global (kept for fallback)
 static ROT_PER_HOST: Lazy<Mutex<HashMap<String, usize>>> = Lazy::new(|| Mutex::new(HashMap::new()));
-static ALLOWLIST: Lazy<Mutex<Option<Vec<AllowEntry>>>> = Lazy::new(|| {
-    let v = std::env::var("STEALTH_TPL_ALLOWLIST")
-        .ok()
-        .and_then(|s| serde_json::from_str::<Vec<AllowEntry>>(&s).ok());
-    Mutex::new(v)
+static ALLOWLIST: Lazy<Mutex<Option<TemplateCatalog>>> = Lazy::new(|| {
+    let catalog = template_catalog_from_env().unwrap_or_else(|e| {
+        eprintln!("htx::tls_mirror: template catalog not loaded, dials will calibrate: {e}");
+        None
+    });
+    Mutex::new(catalog)
 });
 
+/// Allow-list of templates per host, signed by the operator.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TemplateCatalog {
+    /// Must grow with every update; an older catalog is never loaded over a newer one.
+    pub version: u32,
+    pub updated_at: u64,
+    /// Unix seconds after which the catalog is ignored.
+    pub expires_at: u64,
+    pub entries: Vec<AllowEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedTemplates {
+    pub catalog: TemplateCatalog,
+    /// Ed25519 over the DET-CBOR of `catalog`, as for bootstrap seed catalogs.
+    pub signature_hex: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CatalogError {
+    #[error("catalog signature does not verify")]
+    Signature,
+    #[error("STEALTH_TPL_CATALOG_PATH is set without STEALTH_TPL_PUBKEY_HEX")]
+    NoKey,
+    #[error("catalog expired at {0}")]
+    Expired(u64),
+    #[error("catalog version {offered} does not replace loaded version {loaded}")]
+    Stale { loaded: u32, offered: u32 },
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl TemplateCatalog {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= crate::resume::unix_now()
+    }
+}
+
+/// Check the signature and expiry of a template catalog.
+pub fn verify_signed_templates(
+    pk_hex: &str,
+    signed: &SignedTemplates,
+) -> Result<TemplateCatalog, CatalogError> {
+    let pk = hex::decode(pk_hex.trim()).map_err(|_| CatalogError::Signature)?;
+    let sig = hex::decode(signed.signature_hex.trim()).map_err(|_| CatalogError::Signature)?;
+    let det = cbor::to_det_cbor(&signed.catalog).map_err(|_| CatalogError::Signature)?;
+    core_crypto::ed25519::verify(&pk, &det, &sig).map_err(|_| CatalogError::Signature)?;
+    if signed.catalog.is_expired() {
+        return Err(CatalogError::Expired(signed.catalog.expires_at));
+    }
+    Ok(signed.catalog.clone())
+}
+
+/// Read and verify a [`SignedTemplates`] JSON file.
+pub fn read_signed_templates(
+    path: impl AsRef<std::path::Path>,
+    pk_hex: &str,
+) -> Result<TemplateCatalog, CatalogError> {
+    let signed = serde_json::from_slice(&std::fs::read(path)?)?;
+    verify_signed_templates(pk_hex, &signed)
+}
+
+/// Verify `signed` and make it the allow-list for new dials. A catalog older
+/// than the loaded one is refused, as is a different catalog under the same
+/// version; loading the same catalog again is a no-op. Returns the version in use.
+pub fn install_signed_templates(
+    pk_hex: &str,
+    signed: &SignedTemplates,
+) -> Result<u32, CatalogError> {
+    install_catalog(verify_signed_templates(pk_hex, signed)?)
+}
+
+/// [`install_signed_templates`] from a file, e.g. after an operator pushed an update.
+pub fn load_template_catalog(
+    path: impl AsRef<std::path::Path>,
+    pk_hex: &str,
+) -> Result<u32, CatalogError> {
+    install_catalog(read_signed_templates(path, pk_hex)?)
+}
+
+/// Version of the allow-list in use; 0 for an unsigned one.
+pub fn loaded_catalog_version() -> Option<u32> {
+    let guard = ALLOWLIST.lock().unwrap_or_else(|e| e.into_inner());
+    guard.as_ref().map(|c| c.version)
+}
+
+fn install_catalog(catalog: TemplateCatalog) -> Result<u32, CatalogError> {
+    let mut guard = ALLOWLIST.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(loaded) = guard.as_ref() {
+        let stale = catalog.version < loaded.version
+            || (catalog.version == loaded.version && catalog != *loaded);
+        if stale {
+            return Err(CatalogError::Stale {
+                loaded: loaded.version,
+                offered: catalog.version,
+            });
+        }
+    }
+    let version = catalog.version;
+    *guard = Some(catalog);
+    Ok(version)
+}
+
+/// Startup allow-list: the signed catalog at STEALTH_TPL_CATALOG_PATH, verified
+/// with STEALTH_TPL_PUBKEY_HEX. An unsigned STEALTH_TPL_ALLOWLIST is only used
+/// with STEALTH_TPL_ALLOW_UNSIGNED=1; without it the list is skipped with a
+/// warning. The allow-list loads this on first use and logs any error; call it
+/// at startup to fail on a bad catalog instead.
+pub fn template_catalog_from_env() -> Result<Option<TemplateCatalog>, CatalogError> {
+    template_catalog_from_vars(|k| std::env::var(k).ok())
+}
+
+fn template_catalog_from_vars(
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<Option<TemplateCatalog>, CatalogError> {
+    if let Some(path) = var("STEALTH_TPL_CATALOG_PATH") {
+        let pk_hex = var("STEALTH_TPL_PUBKEY_HEX").ok_or(CatalogError::NoKey)?;
+        return read_signed_templates(path, &pk_hex).map(Some);
+    }
+    let Some(json) = var("STEALTH_TPL_ALLOWLIST") else {
+        return Ok(None);
+    };
+    if var("STEALTH_TPL_ALLOW_UNSIGNED").as_deref() != Some("1") {
+        eprintln!(
+            "htx::tls_mirror: ignoring unsigned STEALTH_TPL_ALLOWLIST; set STEALTH_TPL_CATALOG_PATH \
+             or STEALTH_TPL_ALLOW_UNSIGNED=1"
+        );
+        return Ok(None);
+    }
+    Ok(Some(unsigned_catalog(serde_json::from_str(&json)?)))
+}
+
+// Unsigned lists load as version 0 so that any signed catalog replaces them
+fn unsigned_catalog(entries: Vec<AllowEntry>) -> TemplateCatalog {
+    TemplateCatalog {
+        version: 0,
+        updated_at: 0,
+        expires_at: u64::MAX,
+        entries,
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Template {
     pub alpn: Vec<String>,
@@ -45,6 +191,7 @@ pub struct TemplateId(#[serde(with = "serde_bytes")] pub Vec<u8>);
 #[derive(Debug, Clone)]
 pub struct ClientConfig {
     pub ja3: String,
+    pub ja4: String,
     pub template_id: TemplateId,
     #[cfg(feature = "rustls-config")]
     pub rustls: std::sync::Arc<rustls::ClientConfig>,
@@ -89,19 +236,58 @@ pub fn compute_template_id(tpl: &Template) -> TemplateId {
     TemplateId(id.to_vec())
 }
 
+/// JA3 of the ClientHello the dialer sends for `tpl`.
 pub fn compute_ja3(tpl: &Template) -> String {
-    // JA3 = SSLVersion,CipherSuites,Extensions,EllipticCurves,EllipticCurvePointFormats
-    // We approximate using extensions and groups; version/ciphers omitted in this PoC.
-    let exts = tpl
-        .extensions
-        .iter()
-        .map(|e| e.to_string())
-        .collect::<Vec<_>>()
-        .join("-");
-    let groups = tpl.groups.join("-");
-    let base = format!("{},,{},{}", "771", exts, groups); // TLS1.2/1.3ish placeholder
-    let hash = md5::compute(base.as_bytes());
-    format!("{:x}", hash)
+    dialer_fingerprint(tpl).ja3
+}
+
+/// JA3 and JA4 of the ClientHello the dialer sends for `tpl`.
+pub fn dialer_fingerprint(tpl: &Template) -> ClientFingerprint {
+    ClientFingerprint::from_bytes(&dialer_client_hello(tpl)).expect("rustls writes a valid hello")
+}
+
+/// The TLS records of the ClientHello the dialer sends for `tpl`, written
+/// without a socket. The server name only changes the SNI bytes, not the
+/// fingerprint.
+pub fn dialer_client_hello(tpl: &Template) -> Vec<u8> {
+    let cfg = std::sync::Arc::new(dialer_config(tpl, rustls::RootCertStore::empty()));
+    let name = rustls::ServerName::try_from("example.com").expect("valid name");
+    let mut conn = rustls::ClientConnection::new(cfg, name).expect("valid config");
+    let mut out = Vec::new();
+    while conn.wants_write() {
+        conn.write_tls(&mut out).expect("write to Vec");
+    }
+    out
+}
+
+// What the template controls in the dialer's rustls config: key exchange
+// groups, in order, and ALPN. rustls has no knobs for the rest of the hello.
+fn dialer_config(tpl: &Template, roots: rustls::RootCertStore) -> rustls::ClientConfig {
+    use rustls::kx_group::{SECP256R1, SECP384R1, X25519};
+    let mut groups: Vec<&'static rustls::SupportedKxGroup> = Vec::new();
+    for name in &tpl.groups {
+        let g = match name.as_str() {
+            "x25519" => &X25519,
+            "secp256r1" => &SECP256R1,
+            "secp384r1" => &SECP384R1,
+            _ => continue,
+        };
+        if !groups.iter().any(|have| have.name == g.name) {
+            groups.push(g);
+        }
+    }
+    if groups.is_empty() {
+        groups.extend(rustls::ALL_KX_GROUPS);
+    }
+    let mut cfg = rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_kx_groups(&groups)
+        .with_safe_default_protocol_versions()
+        .expect("safe defaults are consistent")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    cfg.alpn_protocols = tpl.alpn.iter().map(|s| s.as_bytes().to_vec()).collect();
+    cfg
 }
 
 #[derive(Debug, Clone, Default)]
@@ -133,10 +319,11 @@ pub fn choose_template_rotating(
             return Ok((id, t.clone()));
         }
     }
-    // allow-list env
+    // allow-list catalog, unless it has expired
     if let Ok(guard) = ALLOWLIST.lock() {
-        if let Some(list) = guard.as_ref() {
-            let matches: Vec<&AllowEntry> = list
+        if let Some(catalog) = guard.as_ref().filter(|c| !c.is_expired()) {
+            let matches: Vec<&AllowEntry> = catalog
+                .entries
                 .iter()
                 .filter(|e| host_matches(&e.host_pattern, &host))
                 .collect();
@@ -166,7 +353,9 @@ pub fn choose_template_rotating(
 #[cfg(test)]
 pub fn __test_set_allowlist(json: &str) {
     if let Ok(mut g) = ALLOWLIST.lock() {
-        *g = serde_json::from_str::<Vec<AllowEntry>>(json).ok();
+        *g = serde_json::from_str::<Vec<AllowEntry>>(json)
+            .ok()
+            .map(unsigned_catalog);
     }
 }
 
@@ -245,13 +434,14 @@ pub fn calibrate(
 }
 
 pub fn build_client_hello(tpl: &Template) -> ClientConfig {
-    let ja3 = compute_ja3(tpl);
+    let ClientFingerprint { ja3, ja4, .. } = dialer_fingerprint(tpl);
     let tid = compute_template_id(tpl);
     #[cfg(feature = "rustls-config")]
     {
         let cfg = build_rustls_config(tpl);
         ClientConfig {
             ja3,
+            ja4,
             template_id: tid,
             rustls: cfg,
         }
@@ -260,6 +450,7 @@ pub fn build_client_hello(tpl: &Template) -> ClientConfig {
     {
         ClientConfig {
             ja3,
+            ja4,
             template_id: tid,
         }
     }
@@ -267,7 +458,7 @@ pub fn build_client_hello(tpl: &Template) -> ClientConfig {
 
 #[cfg(feature = "rustls-config")]
 fn build_rustls_config(tpl: &Template) -> std::sync::Arc<rustls::ClientConfig> {
-    use rustls::{ClientConfig as RClientConfig, RootCertStore};
+    use rustls::RootCertStore;
     use std::sync::Arc;
     let mut roots = RootCertStore::empty();
     for cert in rustls_native_certs::load_native_certs().expect("roots") {
@@ -292,10 +483,7 @@ fn build_rustls_config(tpl: &Template) -> std::sync::Arc<rustls::ClientConfig> {
             }
         }
     }
-    let mut cfg = RClientConfig::builder()
-        .with_safe_defaults()
-        .with_root_certificates(roots)
-        .with_no_client_auth();
+    let mut cfg = dialer_config(tpl, roots);
     // DEV ONLY: allow bypassing certificate verification for local testing
     if std::env::var("HTX_INSECURE_NO_VERIFY").ok().as_deref() == Some("1") {
         struct NoVerifier;
@@ -333,8 +521,6 @@ fn build_rustls_config(tpl: &Template) -> std::sync::Arc<rustls::ClientConfig> {
         cfg.dangerous()
             .set_certificate_verifier(Arc::new(NoVerifier));
     }
-    // ALPN
-    cfg.alpn_protocols = tpl.alpn.iter().map(|s| s.as_bytes().to_vec()).collect();
     std::sync::Arc::new(cfg)
 }
 
@@ -390,12 +576,16 @@ mod tests {
             groups: vec!["x25519".into(), "secp256r1".into()],
             extensions: vec![0, 11, 10, 35, 16, 23, 43, 51],
         };
-        // Compute expected JA3 using the same logic
         let ja3 = compute_ja3(&tpl);
         // The value should be stable and equal to the fixture hash for this template
         assert_eq!(ja3, compute_ja3(&tpl));
-        assert_eq!(ja3, "fd2e42d63964a4223054e5e71e8250e4");
+        assert_eq!(ja3, "fe0b98098e8355b8d06ae3d636f6f63a");
         assert_eq!(ja3.len(), 32); // md5 hex length
+
+        // Hashed from what rustls writes: real version, ciphers and point formats
+        let fp = dialer_fingerprint(&tpl);
+        assert!(fp.ja3_string.starts_with("771,4866-4865-4867-"));
+        assert!(fp.ja3_string.ends_with(",29-23,0"));
     }
 
     #[test]
@@ -420,4 +610,128 @@ mod tests {
         assert_eq!(vals.len(), 3);
         assert!(vals[2] - vals[0] <= 1, "distribution skewed: {:?}", counts);
     }
+    fn signed(seed: &[u8; 32], version: u32, expires_at: u64, alpn: &str) -> SignedTemplates {
+        let catalog = TemplateCatalog {
+            version,
+            updated_at: 1_725_000_000,
+            expires_at,
+            entries: vec![AllowEntry {
+                host_pattern: "*.catalog.example".into(),
+                template: Template {
+                    alpn: vec![alpn.into()],
+                    sig_algs: vec!["ecdsa_secp256r1_sha256".into()],
+                    groups: vec!["x25519".into()],
+                    extensions: vec![0, 10, 11, 16, 43, 51],
+                },
+                weight: None,
+            }],
+        };
+        let det = cbor::to_det_cbor(&catalog).unwrap();
+        SignedTemplates {
+            catalog,
+            signature_hex: hex::encode(core_crypto::ed25519::sign(seed, &det)),
+        }
+    }
+
+    #[test]
+    fn signed_catalog_replaces_the_allowlist_and_refuses_rollback() {
+        use ring::signature::{Ed25519KeyPair, KeyPair};
+        let _g = TEST_MUTEX.lock().unwrap();
+        let seed = [5u8; 32];
+        let kp = Ed25519KeyPair::from_seed_unchecked(&seed).unwrap();
+        let pk_hex = hex::encode(kp.public_key().as_ref());
+        let later = crate::resume::unix_now() + 3600;
+        let origin = "https://www.catalog.example";
+        __test_set_allowlist(r#"[]"#);
+        assert_eq!(loaded_catalog_version(), Some(0));
+
+        let v2 = signed(&seed, 2, later, "h2");
+        assert_eq!(install_signed_templates(&pk_hex, &v2).unwrap(), 2);
+        assert_eq!(
+            choose_template_rotating(origin, None).unwrap().1.alpn,
+            ["h2"]
+        );
+        // Same catalog again is fine; older or rewritten under the same version is not
+        assert_eq!(install_signed_templates(&pk_hex, &v2).unwrap(), 2);
+        for (version, alpn) in [(1, "h2"), (2, "http/1.1")] {
+            assert!(matches!(
+                install_signed_templates(&pk_hex, &signed(&seed, version, later, alpn)),
+                Err(CatalogError::Stale { loaded: 2, .. })
+            ));
+        }
+        // Wrong key, tampered entries and expired catalogs are refused
+        assert!(matches!(
+            install_signed_templates(&pk_hex, &signed(&[6u8; 32], 3, later, "h2")),
+            Err(CatalogError::Signature)
+        ));
+        let mut tampered = signed(&seed, 3, later, "h2");
+        tampered.catalog.entries[0].host_pattern = "*".into();
+        assert!(matches!(
+            install_signed_templates(&pk_hex, &tampered),
+            Err(CatalogError::Signature)
+        ));
+        assert!(matches!(
+            install_signed_templates(&pk_hex, &signed(&seed, 3, 1_000, "h2")),
+            Err(CatalogError::Expired(1_000))
+        ));
+        assert_eq!(loaded_catalog_version(), Some(2));
+
+        // An update pushed to disk swaps in for the next dial
+        let path =
+            std::env::temp_dir().join(format!("htx-tpl-catalog-{}.json", std::process::id()));
+        let v3 = signed(&seed, 3, later, "http/1.1");
+        std::fs::write(&path, serde_json::to_vec(&v3).unwrap()).unwrap();
+        assert_eq!(load_template_catalog(&path, &pk_hex).unwrap(), 3);
+        assert_eq!(
+            choose_template_rotating(origin, None).unwrap().1.alpn,
+            ["http/1.1"]
+        );
+        std::fs::write(&path, serde_json::to_vec(&v2).unwrap()).unwrap();
+        assert!(matches!(
+            load_template_catalog(&path, &pk_hex),
+            Err(CatalogError::Stale {
+                loaded: 3,
+                offered: 2
+            })
+        ));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn bad_catalog_from_env_is_an_error() {
+        let from = |vars: &[(&str, &str)]| {
+            let vars: std::collections::HashMap<String, String> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            template_catalog_from_vars(|k| vars.get(k).cloned())
+        };
+        let seed = [5u8; 32];
+        let path = std::env::temp_dir().join(format!("htx-tpl-env-{}.json", std::process::id()));
+        let v1 = signed(&seed, 1, crate::resume::unix_now() + 3600, "h2");
+        std::fs::write(&path, serde_json::to_vec(&v1).unwrap()).unwrap();
+        let path = path.to_str().unwrap();
+        assert!(matches!(
+            from(&[("STEALTH_TPL_CATALOG_PATH", path)]),
+            Err(CatalogError::NoKey)
+        ));
+        // Signed by another key
+        let wrong_key = hex::encode([9u8; 32]);
+        assert!(matches!(
+            from(&[
+                ("STEALTH_TPL_CATALOG_PATH", path),
+                ("STEALTH_TPL_PUBKEY_HEX", &wrong_key)
+            ]),
+            Err(CatalogError::Signature)
+        ));
+        let _ = std::fs::remove_file(path);
+
+        // An unsigned list is skipped unless allowed
+        assert!(from(&[("STEALTH_TPL_ALLOWLIST", "[]")]).unwrap().is_none());
+        let allowed = from(&[
+            ("STEALTH_TPL_ALLOWLIST", "[]"),
+            ("STEALTH_TPL_ALLOW_UNSIGNED", "1"),
+        ]);
+        assert_eq!(allowed.unwrap().unwrap().version, 0);
+    }
 }
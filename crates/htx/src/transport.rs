@@ -0,0 +1,302 @@
+//! Byte carriers underneath the HTX mux.
+//!
+//! The mux hands complete wire frames (`[Len(u24) | Type | payload]`, plain or
+//! AEAD-sealed) to a [`FrameTransport`] and gets complete frames back; where the
+//! frame boundaries fall in a byte stream is worked out by
+//! [`core_framing::FrameDecoder`], which the mux then decrypts itself.
+
+use bytes::Bytes;
+use core_framing::FrameDecoder;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+
+/// Largest wire frame accepted from a peer. Mux frames stay well under 128 KiB;
+/// anything bigger is refused before it is buffered.
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+// Upper bound on how long a read may hold a non-splittable carrier (TLS) before
+// giving queued writes a turn.
+#[cfg(feature = "rustls-config")]
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(5);
+
+/// A carrier of whole mux frames.
+///
+/// `send_frame` and `recv_frame` are called from different threads at the same
+/// time; implementations synchronize internally.
+pub trait FrameTransport: Send + Sync {
+    /// Write one complete wire frame.
+    fn send_frame(&self, frame: &[u8]) -> io::Result<()>;
+    /// Next complete wire frame; `Ok(None)` once the peer has closed.
+    ///
+    /// `WouldBlock`, `TimedOut` and `Interrupted` errors mean nothing arrived
+    /// yet and the call should be retried.
+    fn recv_frame(&self) -> io::Result<Option<Bytes>>;
+    /// Tell the peer no more frames follow. Further sends fail.
+    fn close(&self) -> io::Result<()>;
+}
+
+fn frame_decoder() -> FrameDecoder {
+    FrameDecoder::plain().max_frame_len(MAX_FRAME_LEN)
+}
+
+// Pull frames out of `io`, reading at most once per call so a timed-out read
+// hands control back to the caller.
+fn read_frame<R: Read>(io: &mut R, fb: &mut FrameDecoder) -> io::Result<Option<Bytes>> {
+    let mut tmp = [0u8; 4096];
+    loop {
+        if let Some(frame) = next_raw(fb)? {
+            return Ok(Some(frame));
+        }
+        match io.read(&mut tmp)? {
+            0 => return Ok(None),
+            n => fb.extend(&tmp[..n]),
+        }
+    }
+}
+
+fn next_raw(fb: &mut FrameDecoder) -> io::Result<Option<Bytes>> {
+    fb.next_raw()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("bad frame: {e:?}")))
+}
+
+/// Plain TCP carrier: read and write halves run independently.
+pub struct TcpTransport {
+    reader: Mutex<(TcpStream, FrameDecoder)>,
+    writer: Mutex<TcpStream>,
+}
+
+impl TcpTransport {
+    pub fn new(sock: TcpStream) -> io::Result<Self> {
+        sock.set_nodelay(true)?;
+        let reader = sock.try_clone()?;
+        Ok(Self {
+            reader: Mutex::new((reader, frame_decoder())),
+            writer: Mutex::new(sock),
+        })
+    }
+}
+
+impl FrameTransport for TcpTransport {
+    fn send_frame(&self, frame: &[u8]) -> io::Result<()> {
+        self.writer.lock().unwrap().write_all(frame)
+    }
+
+    fn recv_frame(&self) -> io::Result<Option<Bytes>> {
+        let (sock, fb) = &mut *self.reader.lock().unwrap();
+        read_frame(sock, fb)
+    }
+
+    fn close(&self) -> io::Result<()> {
+        // Half-close: the peer sees EOF while our reader keeps draining its frames
+        self.writer.lock().unwrap().shutdown(Shutdown::Write)
+    }
+}
+
+/// rustls carrier. A TLS session cannot be split, so reads are bounded by a
+/// short socket timeout to let queued writes through.
+#[cfg(feature = "rustls-config")]
+pub struct TlsTransport<C> {
+    io: Mutex<rustls::StreamOwned<C, TcpStream>>,
+    buf: Mutex<FrameDecoder>,
+}
+
+#[cfg(feature = "rustls-config")]
+impl<C> TlsTransport<C> {
+    pub fn new(io: rustls::StreamOwned<C, TcpStream>) -> io::Result<Self> {
+        io.sock.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Self {
+            io: Mutex::new(io),
+            buf: Mutex::new(frame_decoder()),
+        })
+    }
+}
+
+#[cfg(feature = "rustls-config")]
+impl<C, S> FrameTransport for TlsTransport<C>
+where
+    C: std::ops::DerefMut + std::ops::Deref<Target = rustls::ConnectionCommon<S>> + Send,
+    S: rustls::SideData,
+{
+    fn send_frame(&self, frame: &[u8]) -> io::Result<()> {
+        let mut io = self.io.lock().unwrap();
+        io.write_all(frame)?;
+        io.flush()
+    }
+
+    fn recv_frame(&self) -> io::Result<Option<Bytes>> {
+        let mut fb = self.buf.lock().unwrap();
+        if let Some(frame) = next_raw(&mut fb)? {
+            return Ok(Some(frame));
+        }
+        let mut io = self.io.lock().unwrap();
+        read_frame(&mut *io, &mut fb)
+    }
+
+    fn close(&self) -> io::Result<()> {
+        let mut io = self.io.lock().unwrap();
+        io.conn.send_close_notify();
+        io.flush()?;
+        io.sock.shutdown(Shutdown::Write)
+    }
+}
+
+/// In-process carrier; see [`memory_pair`].
+pub struct MemoryTransport {
+    tx: Mutex<Option<mpsc::Sender<Bytes>>>,
+    rx: Mutex<mpsc::Receiver<Bytes>>,
+}
+
+/// Two connected in-memory transports.
+pub fn memory_pair() -> (MemoryTransport, MemoryTransport) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    let a = MemoryTransport {
+        tx: Mutex::new(Some(a_tx)),
+        rx: Mutex::new(a_rx),
+    };
+    let b = MemoryTransport {
+        tx: Mutex::new(Some(b_tx)),
+        rx: Mutex::new(b_rx),
+    };
+    (a, b)
+}
+
+impl FrameTransport for MemoryTransport {
+    fn send_frame(&self, frame: &[u8]) -> io::Result<()> {
+        match self.tx.lock().unwrap().as_ref() {
+            Some(tx) => tx
+                .send(Bytes::copy_from_slice(frame))
+                .map_err(|_| io::ErrorKind::BrokenPipe.into()),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn recv_frame(&self) -> io::Result<Option<Bytes>> {
+        // The peer dropping or closing its sender is our EOF
+        Ok(self.rx.lock().unwrap().recv().ok())
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.tx.lock().unwrap().take();
+        Ok(())
+    }
+}
+
+/// Test carrier that silently drops a fraction of outgoing frames.
+pub struct LossyTransport<T> {
+    inner: T,
+    loss: f64,
+    rng: Mutex<StdRng>,
+    dropped: AtomicU64,
+}
+
+impl<T: FrameTransport> LossyTransport<T> {
+    /// Drop each sent frame with probability `loss` (0.0..=1.0), reproducibly for a given `seed`.
+    pub fn new(inner: T, loss: f64, seed: u64) -> Self {
+        Self {
+            inner,
+            loss: loss.clamp(0.0, 1.0),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Frames dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: FrameTransport> FrameTransport for LossyTransport<T> {
+    fn send_frame(&self, frame: &[u8]) -> io::Result<()> {
+        if self.rng.lock().unwrap().gen_bool(self.loss) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        self.inner.send_frame(frame)
+    }
+
+    fn recv_frame(&self) -> io::Result<Option<Bytes>> {
+        self.inner.recv_frame()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        self.inner.close()
+    }
+}
+
+impl<T: FrameTransport + ?Sized> FrameTransport for std::sync::Arc<T> {
+    fn send_frame(&self, frame: &[u8]) -> io::Result<()> {
+        (**self).send_frame(frame)
+    }
+
+    fn recv_frame(&self) -> io::Result<Option<Bytes>> {
+        (**self).recv_frame()
+    }
+
+    fn close(&self) -> io::Result<()> {
+        (**self).close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn frame(ty: u8, payload: &[u8]) -> Vec<u8> {
+        let len = payload.len() + 1;
+        let mut v = vec![(len >> 16) as u8, (len >> 8) as u8, len as u8, ty];
+        v.extend_from_slice(payload);
+        v
+    }
+
+    #[test]
+    fn tcp_transport_refuses_oversized_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || TcpStream::connect(addr).unwrap());
+        let (server, _) = listener.accept().unwrap();
+        let mut raw = client.join().unwrap();
+        let b = TcpTransport::new(server).unwrap();
+        // Announce a 16 MiB frame and send only its header
+        raw.write_all(&[0xFF, 0xFF, 0xFF, 0x10]).unwrap();
+        let err = b.recv_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn tcp_transport_round_trip_and_close_is_eof() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || TcpStream::connect(addr).unwrap());
+        let (server, _) = listener.accept().unwrap();
+        let a = TcpTransport::new(client.join().unwrap()).unwrap();
+        let b = TcpTransport::new(server).unwrap();
+        let f = frame(0x10, &[1u8; 10_000]);
+        a.send_frame(&f).unwrap();
+        a.close().unwrap();
+        assert_eq!(b.recv_frame().unwrap().as_deref(), Some(&f[..]));
+        assert_eq!(b.recv_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn lossy_transport_drops_at_roughly_the_configured_rate() {
+        let (a, b) = memory_pair();
+        let a = LossyTransport::new(a, 0.25, 7);
+        for i in 0..1000u32 {
+            a.send_frame(&frame(0x10, &i.to_be_bytes())).unwrap();
+        }
+        a.close().unwrap();
+        let mut got = 0;
+        while b.recv_frame().unwrap().is_some() {
+            got += 1;
+        }
+        assert_eq!(got + a.dropped(), 1000);
+        assert!((150..350).contains(&a.dropped()), "dropped {}", a.dropped());
+    }
+}
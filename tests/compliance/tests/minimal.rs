@@ -1,6 +1,6 @@
 use core_framing as framing;
 use core_routing as routing;
-use htx::mux::pair_encrypted;
+use htx::mux::{pair_encrypted, MuxConfig};
 use htx::transition::ControlRecord;
 
 // MINIMAL profile:
@@ -50,7 +50,13 @@ fn framing_aead_semantics_and_negative() {
 #[test]
 fn mux_keyupdate_overlap_and_rekey_close_resume() {
     // Encrypted pair with known keys
-    let (a, b) = pair_encrypted([1u8; 32], [2u8; 32], [2u8; 32], [1u8; 32]);
+    let (a, b) = pair_encrypted(
+        [1u8; 32],
+        [2u8; 32],
+        [2u8; 32],
+        [1u8; 32],
+        MuxConfig::default(),
+    );
 
     // Server thread: accept, echo and count
     let server = std::thread::spawn(move || {
@@ -60,32 +60,45 @@ func generateTemplates() {
 		tlsutls.HelloFirefox_Auto,
 	}
 
+	failed := 0
 	for i, id := range ids {
-		// Create a uTLS connection to get the ClientHello
-		uconn := tlsutls.UClient(nil, &tlsutls.Config{InsecureSkipVerify: true}, id)
+		// Create a uTLS connection and build the browser's ClientHello. Without
+		// BuildHandshakeState the hello is empty: no cipher suites, no extensions.
+		uconn := tlsutls.UClient(nil, &tlsutls.Config{ServerName: "www.example.com", InsecureSkipVerify: true}, id)
+		if err := uconn.BuildHandshakeState(); err != nil {
+			fmt.Printf("Error building template %d: %v\n", i, err)
+			failed++
+			continue
+		}
 		hello := uconn.HandshakeState.Hello
 
-		// Make deterministic
+		// Make deterministic (key shares stay random; htx replaces them per connection)
 		hello.Random = make([]byte, 32)
 		hello.SessionId = make([]byte, 32)
 		copy(hello.Random, []byte("qnet-deterministic-random-1234567"))
 		copy(hello.SessionId, []byte("qnet-session-12345678901234567"))
 
-		data, err := hello.Marshal()
-		if err != nil {
+		// Re-marshal with the fixed random and session ID, keeping extension order and padding
+		if err := uconn.MarshalClientHello(); err != nil {
 			fmt.Printf("Error marshaling template %d: %v\n", i, err)
+			failed++
 			continue
 		}
+		data := uconn.HandshakeState.Hello.Raw
 
 		filename := fmt.Sprintf("template_%d.bin", i)
-		err = os.WriteFile(filename, data, 0644)
-		if err != nil {
+		if err := os.WriteFile(filename, data, 0644); err != nil {
 			fmt.Printf("Error writing %s: %v\n", filename, err)
+			failed++
 			continue
 		}
 		fmt.Printf("Generated %s for %s\n", filename, id.Str())
 	}
 
+	if failed > 0 {
+		fmt.Printf("%d of %d templates failed.\n", failed, len(ids))
+		os.Exit(1)
+	}
 	fmt.Println("Templates generated successfully.")
 }
 
@@ -137,23 +150,40 @@ func selfTest() {
 
 	if templateCount == 0 {
 		fmt.Println("No templates found. Run 'generate' first.")
-		return
+		os.Exit(1)
 	}
 
 	fmt.Printf("Found %d templates.\n", templateCount)
 
 	// Test parsing
+	bad := 0
 	for i := 0; i < templateCount; i++ {
 		filename := fmt.Sprintf("template_%d.bin", i)
 		data, err := os.ReadFile(filename)
 		if err != nil {
 			fmt.Printf("Error reading %s: %v\n", filename, err)
+			bad++
 			continue
 		}
 
-		// For self-test, just check file sizes or something simple
+		// A ClientHello handshake message: type 1, then a 24-bit body length
+		if len(data) < 4 || data[0] != 1 || int(data[1])<<16|int(data[2])<<8|int(data[3]) != len(data)-4 {
+			fmt.Printf("Template %d: not a ClientHello handshake message\n", i)
+			bad++
+			continue
+		}
+		// A built hello is a few hundred bytes; 74 means no cipher suites or extensions
+		if len(data) < 128 {
+			fmt.Printf("Template %d: only %d bytes; regenerate with 'generate'\n", i, len(data))
+			bad++
+			continue
+		}
 		fmt.Printf("Template %d: file size %d bytes\n", i, len(data))
 	}
 
+	if bad > 0 {
+		fmt.Printf("Self-test failed: %d of %d templates unusable.\n", bad, templateCount)
+		os.Exit(1)
+	}
 	fmt.Println("Self-test passed!")
 }